@@ -0,0 +1,116 @@
+//! Measures the round trip of small JSON-RPC messages between a stdio client and server,
+//! connected via in-memory pipes. The amount of allocations per round trip is printed
+//! before the timing results, since buffer reuse in the comm tasks is intended to reduce it.
+
+#[allow(dead_code, clippy::enum_variant_names)]
+#[path = "../examples/protocol/mod.rs"]
+mod protocol;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::future::poll_fn;
+use multilink::{
+    stdio::{client::StdioClient, server::StdioServer},
+    ServiceError, ServiceFuture, ServiceResponse,
+};
+use protocol::{GreetingResponse, Request, Response, SayHelloRequest};
+use tokio::{io::duplex, runtime::Runtime};
+use tower::Service;
+
+const ROUND_TRIPS_PER_SAMPLE: usize = 1000;
+
+/// Counts allocations made by the whole process, including the comm tasks.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Responds to each request with the name from the request.
+struct EchoService;
+
+impl Service<Request> for EchoService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let result = match req {
+            Request::SayHello(request) => request.name,
+            _ => String::new(),
+        };
+        Box::pin(async move {
+            Ok(ServiceResponse::Single(Response::SayHello(
+                GreetingResponse { result },
+            )))
+        })
+    }
+}
+
+async fn round_trips(client: &mut StdioClient<Request, Response>, count: usize) {
+    for _ in 0..count {
+        let request = Request::SayHello(SayHelloRequest {
+            name: "multilink".to_string(),
+        });
+        poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+        client.call(request).await.unwrap();
+    }
+}
+
+fn small_messages(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut client = runtime.block_on(async {
+        let (client_io, server_io) = duplex(64 * 1024);
+        let (server_reader, server_writer) = tokio::io::split(server_io);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let server = StdioServer::with_io(
+            EchoService,
+            Default::default(),
+            server_reader,
+            server_writer,
+        );
+        tokio::spawn(server.run());
+        StdioClient::with_io(client_reader, client_writer, Default::default())
+    });
+
+    // Warm up, so that reused buffers have reached their capacity
+    runtime.block_on(round_trips(&mut client, ROUND_TRIPS_PER_SAMPLE));
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    runtime.block_on(round_trips(&mut client, ROUND_TRIPS_PER_SAMPLE));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "allocations per round trip: {:.1}",
+        allocations as f64 / ROUND_TRIPS_PER_SAMPLE as f64
+    );
+
+    let mut group = c.benchmark_group("stdio");
+    group.throughput(Throughput::Elements(ROUND_TRIPS_PER_SAMPLE as u64));
+    group.bench_function("small_message_round_trips", |b| {
+        b.iter(|| runtime.block_on(round_trips(&mut client, ROUND_TRIPS_PER_SAMPLE)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, small_messages);
+criterion_main!(benches);
@@ -3,8 +3,13 @@ use std::io::{stdout, Write};
 use clap::{command, Parser};
 use futures::StreamExt;
 use multilink::{
-    http::client::HttpClientConfig, stdio::client::StdioClientConfig,
-    util::service::build_service_from_config, ServiceResponse,
+    http::client::HttpClientConfig,
+    stdio::client::StdioClientConfig,
+    util::{
+        await_progress_result,
+        service::{build_service, ClientTransportConfig},
+    },
+    ServiceResponse,
 };
 use protocol::{GreetingResponse, Request, Response, SayCustomGreetingRequest, SayHelloRequest};
 use tracing_subscriber::{filter::LevelFilter, EnvFilter};
@@ -40,6 +45,10 @@ struct Cli {
     #[arg(long)]
     stream_hello: bool,
 
+    /// Report progress from the server before receiving the greeting. Custom greetings not supported.
+    #[arg(long)]
+    hello_with_progress: bool,
+
     /// The name of the person that the greeting should greet.
     #[arg(long, default_value = "Bob")]
     name: String,
@@ -58,27 +67,27 @@ async fn main() {
 
     let cli = Cli::parse();
 
-    let stdio_config = Some(StdioClientConfig {
-        bin_path: cli.stdio_bin_path,
-        ..Default::default()
-    });
-
-    let http_config = match cli.use_http {
-        true => Some(HttpClientConfig {
+    let transport_config = match cli.use_http {
+        true => ClientTransportConfig::Http(HttpClientConfig {
             base_url: cli.http_base_url,
             ..Default::default()
         }),
-        false => None,
+        false => ClientTransportConfig::Stdio {
+            command_name: SERVER_STDIO_COMMAND.to_string(),
+            command_arguments: SERVER_STDIO_COMMAND_ARGS
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+            config: StdioClientConfig {
+                bin_path: cli.stdio_bin_path,
+                ..Default::default()
+            },
+        },
     };
 
-    let mut client_service = build_service_from_config::<Request, Response>(
-        SERVER_STDIO_COMMAND,
-        &SERVER_STDIO_COMMAND_ARGS,
-        stdio_config,
-        http_config,
-    )
-    .await
-    .expect("should be able to create client service");
+    let mut client_service = build_service::<Request, Response>(transport_config)
+        .await
+        .expect("should be able to create client service");
 
     let request = match cli.custom_greeting {
         Some(greeting) => Request::SayCustomGreeting(SayCustomGreetingRequest {
@@ -87,9 +96,10 @@ async fn main() {
         }),
         None => {
             let request = SayHelloRequest { name: cli.name };
-            match cli.stream_hello {
-                false => Request::SayHello(request),
-                true => Request::SayHelloStream(request),
+            match (cli.stream_hello, cli.hello_with_progress) {
+                (true, _) => Request::SayHelloStream(request),
+                (false, true) => Request::SayHelloWithProgress(request),
+                (false, false) => Request::SayHello(request),
             }
         }
     };
@@ -100,6 +110,19 @@ async fn main() {
         .expect("client request should succeed");
 
     match response {
+        ServiceResponse::Multiple(response_stream) if cli.hello_with_progress => {
+            let GreetingResponse { result } = await_progress_result(
+                response_stream,
+                |response| match response {
+                    Response::SayHelloWithProgress(event) => Some(event),
+                    _ => None,
+                },
+                |progress| println!("Progress: {}%", progress.percent),
+            )
+            .await
+            .expect("client progress request should succeed");
+            println!("Server says: {}", result);
+        }
         ServiceResponse::Single(response) => {
             let result = match response {
                 Response::SayCustomGreeting(GreetingResponse { result }) => result,
@@ -11,11 +11,12 @@ use futures::StreamExt;
 use multilink::{
     http::server::{HttpServer, HttpServerConfig},
     stdio::server::StdioServer,
+    util::ProgressEvent,
     ServiceError, ServiceFuture, ServiceResponse,
 };
-use protocol::{GreetingResponse, GreetingStreamResponse, Request, Response};
+use protocol::{GreetingProgress, GreetingResponse, GreetingStreamResponse, Request, Response};
 use tokio::time::sleep;
-use tower::Service;
+use tower::{limit::RateLimitLayer, Service};
 use tracing_subscriber::{filter::LevelFilter, EnvFilter};
 
 #[derive(Debug, Subcommand)]
@@ -37,6 +38,11 @@ struct Cli {
     /// The port that the HTTP server should listen on.
     #[arg(long, default_value_t = 8080)]
     http_listen_port: u16,
+
+    /// The maximum amount of requests per second that the stdio server should process.
+    /// Requests past the rate will be delayed.
+    #[arg(long, default_value_t = 10)]
+    stdio_requests_per_sec: u64,
 }
 
 #[derive(Clone)]
@@ -74,6 +80,18 @@ impl Service<Request> for GreetingService {
                     }
                     .boxed(),
                 ),
+                Request::SayHelloWithProgress(request) => ServiceResponse::Multiple(
+                    stream! {
+                        for percent in [0, 25, 50, 75] {
+                            yield Ok(Response::SayHelloWithProgress(ProgressEvent::Progress(GreetingProgress { percent })));
+                            sleep(Duration::from_millis(300)).await;
+                        }
+                        yield Ok(Response::SayHelloWithProgress(ProgressEvent::Result(GreetingResponse {
+                            result: format!("Hello, {}!", request.name),
+                        })));
+                    }
+                    .boxed(),
+                ),
             })
         })
     }
@@ -106,6 +124,10 @@ async fn main() {
         .await
         .expect("http server should not fail"),
         Command::StdioServer => StdioServer::new(service, Default::default())
+            .with_layer(RateLimitLayer::new(
+                cli.stdio_requests_per_sec,
+                Duration::from_secs(1),
+            ))
             .run()
             .await
             .expect("stdio server should not fail"),
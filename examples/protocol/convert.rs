@@ -1,12 +1,15 @@
 use async_trait::async_trait;
 use hyper::{Body, Method, StatusCode};
 use multilink::{
+    format::JSON_CONTENT_TYPE,
     http::{
         util::{
-            notification_sse_response, notification_sse_stream, parse_request, parse_response,
-            serialize_to_http_request, serialize_to_http_response, validate_method,
+            notification_sse_response, notification_sse_stream, parse_request, parse_request_query,
+            parse_response, serialize_to_http_request, serialize_to_http_request_query,
+            serialize_to_http_response, serialize_to_http_response_with_etag,
+            validate_content_type, validate_method,
         },
-        ModalHttpResponse, RequestHttpConvert, ResponseHttpConvert,
+        ModalHttpResponse, RequestHttpConvert, ResponseHttpConvert, SseEvent,
     },
     jsonrpc::{JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse},
     stdio::{RequestJsonRpcConvert, ResponseJsonRpcConvert},
@@ -20,10 +23,12 @@ use super::{Request, Response};
 const SAY_HELLO_HTTP_PATH: &str = "/say_hello";
 const SAY_GREETING_HTTP_PATH: &str = "/say_greeting";
 const SAY_HELLO_STREAM_HTTP_PATH: &str = "/say_hello_stream";
+const SAY_HELLO_PROGRESS_HTTP_PATH: &str = "/say_hello_progress";
 
 const SAY_HELLO_JSONRPC_METHOD: &str = "sayHello";
 const SAY_GREETING_JSONRPC_METHOD: &str = "sayGreeting";
 const SAY_HELLO_STREAM_JSONRPC_METHOD: &str = "sayHelloStream";
+const SAY_HELLO_PROGRESS_JSONRPC_METHOD: &str = "sayHelloWithProgress";
 
 #[async_trait]
 impl RequestHttpConvert<Request> for Request {
@@ -34,16 +39,23 @@ impl RequestHttpConvert<Request> for Request {
         let request = match path {
             SAY_HELLO_HTTP_PATH => {
                 validate_method(&request, Method::GET)?;
-                Self::SayHello(parse_request(request).await?)
+                Self::SayHello(parse_request_query(&request)?)
             }
             SAY_GREETING_HTTP_PATH => {
                 validate_method(&request, Method::POST)?;
+                validate_content_type(&request, JSON_CONTENT_TYPE)?;
                 Self::SayCustomGreeting(parse_request(request).await?)
             }
             SAY_HELLO_STREAM_HTTP_PATH => {
                 validate_method(&request, Method::POST)?;
+                validate_content_type(&request, JSON_CONTENT_TYPE)?;
                 Self::SayHelloStream(parse_request(request).await?)
             }
+            SAY_HELLO_PROGRESS_HTTP_PATH => {
+                validate_method(&request, Method::POST)?;
+                validate_content_type(&request, JSON_CONTENT_TYPE)?;
+                Self::SayHelloWithProgress(parse_request(request).await?)
+            }
             _ => return Ok(None),
         };
         Ok(Some(request))
@@ -54,9 +66,12 @@ impl RequestHttpConvert<Request> for Request {
         base_url: &hyper::Uri,
     ) -> Result<Option<hyper::Request<Body>>, ProtocolError> {
         let request = match self {
-            Self::SayHello(request) => {
-                serialize_to_http_request(base_url, SAY_HELLO_HTTP_PATH, Method::GET, &request)?
-            }
+            Self::SayHello(request) => serialize_to_http_request_query(
+                base_url,
+                SAY_HELLO_HTTP_PATH,
+                Method::GET,
+                &request,
+            )?,
             Self::SayCustomGreeting(request) => {
                 serialize_to_http_request(base_url, SAY_GREETING_HTTP_PATH, Method::POST, &request)?
             }
@@ -66,9 +81,24 @@ impl RequestHttpConvert<Request> for Request {
                 Method::POST,
                 &request,
             )?,
+            Self::SayHelloWithProgress(request) => serialize_to_http_request(
+                base_url,
+                SAY_HELLO_PROGRESS_HTTP_PATH,
+                Method::POST,
+                &request,
+            )?,
         };
         Ok(Some(request))
     }
+
+    fn operation_name(&self) -> Option<&str> {
+        Some(match self {
+            Self::SayHello(_) => SAY_HELLO_JSONRPC_METHOD,
+            Self::SayCustomGreeting(_) => SAY_GREETING_JSONRPC_METHOD,
+            Self::SayHelloStream(_) => SAY_HELLO_STREAM_JSONRPC_METHOD,
+            Self::SayHelloWithProgress(_) => SAY_HELLO_PROGRESS_JSONRPC_METHOD,
+        })
+    }
 }
 
 #[async_trait]
@@ -85,15 +115,24 @@ impl ResponseHttpConvert<Request, Response> for Response {
                 Request::SayCustomGreeting(_) => ServiceResponse::Single(Self::SayCustomGreeting(
                     parse_response(response).await?,
                 )),
-                Request::SayHelloStream(_) => ServiceResponse::Multiple(notification_sse_stream(
-                    original_request.clone(),
-                    response,
-                )),
+                Request::SayHelloStream(_) | Request::SayHelloWithProgress(_) => {
+                    ServiceResponse::Multiple(notification_sse_stream(
+                        original_request.clone(),
+                        response,
+                    ))
+                }
             },
-            ModalHttpResponse::Event(event) => ServiceResponse::Single(match original_request {
-                Request::SayHelloStream(_) => Self::SayHelloStream(parse_from_value(event)?),
-                _ => return Ok(None),
-            }),
+            ModalHttpResponse::Event(event)
+            | ModalHttpResponse::SseEvent(SseEvent { value: event, .. }) => {
+                ServiceResponse::Single(match original_request {
+                    Request::SayHelloStream(_) => Self::SayHelloStream(parse_from_value(event)?),
+                    Request::SayHelloWithProgress(_) => {
+                        Self::SayHelloWithProgress(parse_from_value(event)?)
+                    }
+                    _ => return Ok(None),
+                })
+            }
+            _ => return Ok(None),
         }))
     }
 
@@ -102,16 +141,19 @@ impl ResponseHttpConvert<Request, Response> for Response {
     ) -> Result<Option<ModalHttpResponse>, ProtocolError> {
         let response = match response {
             ServiceResponse::Single(response) => match response {
-                Self::SayHello(response) => ModalHttpResponse::Single(serialize_to_http_response(
-                    &response,
-                    StatusCode::OK,
-                )?),
+                // Greetings are cacheable, so the server can respond to conditional requests
+                Self::SayHello(response) => ModalHttpResponse::Single(
+                    serialize_to_http_response_with_etag(&response, StatusCode::OK)?,
+                ),
                 Self::SayCustomGreeting(response) => ModalHttpResponse::Single(
                     serialize_to_http_response(&response, StatusCode::OK)?,
                 ),
                 Self::SayHelloStream(response) => {
                     ModalHttpResponse::Event(serde_json::to_value(response).unwrap())
                 }
+                Self::SayHelloWithProgress(event) => {
+                    ModalHttpResponse::Event(serde_json::to_value(event).unwrap())
+                }
             },
             ServiceResponse::Multiple(stream) => {
                 // Output a single server-side event HTTP response
@@ -128,6 +170,7 @@ impl RequestJsonRpcConvert<Request> for Request {
             SAY_HELLO_JSONRPC_METHOD => Self::SayHello(value.parse_params()?),
             SAY_GREETING_JSONRPC_METHOD => Self::SayCustomGreeting(value.parse_params()?),
             SAY_HELLO_STREAM_JSONRPC_METHOD => Self::SayHelloStream(value.parse_params()?),
+            SAY_HELLO_PROGRESS_JSONRPC_METHOD => Self::SayHelloWithProgress(value.parse_params()?),
             _ => return Ok(None),
         }))
     }
@@ -146,6 +189,10 @@ impl RequestJsonRpcConvert<Request> for Request {
                 SAY_HELLO_STREAM_JSONRPC_METHOD,
                 Some(serde_json::to_value(request).unwrap()),
             ),
+            Self::SayHelloWithProgress(request) => (
+                SAY_HELLO_PROGRESS_JSONRPC_METHOD,
+                Some(serde_json::to_value(request).unwrap()),
+            ),
         };
         JsonRpcRequest::new(method.to_string(), params)
     }
@@ -171,6 +218,9 @@ impl ResponseJsonRpcConvert<Request, Response> for Response {
                 let result = resp.get_result()?;
                 Ok(Some(match original_request {
                     Request::SayHelloStream(_) => Self::SayHelloStream(parse_from_value(result)?),
+                    Request::SayHelloWithProgress(_) => {
+                        Self::SayHelloWithProgress(parse_from_value(result)?)
+                    }
                     _ => return Ok(None),
                 }))
             }
@@ -187,6 +237,10 @@ impl ResponseJsonRpcConvert<Request, Response> for Response {
                 is_notification = true;
                 serde_json::to_value(response).unwrap()
             }
+            Response::SayHelloWithProgress(event) => {
+                is_notification = true;
+                serde_json::to_value(event).unwrap()
+            }
         });
         match is_notification {
             true => JsonRpcNotification::new_with_result_params(result, id.to_string()).into(),
@@ -1,5 +1,6 @@
 mod convert;
 
+use multilink::util::ProgressEvent;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -18,6 +19,7 @@ pub enum Request {
     SayHello(SayHelloRequest),
     SayCustomGreeting(SayCustomGreetingRequest),
     SayHelloStream(SayHelloRequest),
+    SayHelloWithProgress(SayHelloRequest),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -30,10 +32,16 @@ pub struct GreetingStreamResponse {
     pub character: char,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GreetingProgress {
+    pub percent: u8,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Response {
     SayHello(GreetingResponse),
     SayCustomGreeting(GreetingResponse),
     SayHelloStream(GreetingStreamResponse),
+    SayHelloWithProgress(ProgressEvent<GreetingProgress, GreetingResponse>),
 }
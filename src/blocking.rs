@@ -0,0 +1,193 @@
+use std::{future::Future, sync::Arc};
+
+use futures::{future::poll_fn, StreamExt};
+use thiserror::Error;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tower::Service;
+
+use crate::{
+    error::ProtocolErrorType, BoxedService, NotificationStream, ProtocolError, ServiceError,
+    ServiceFuture, ServiceResponse,
+};
+
+/// Errors that are specific to blocking clients.
+#[derive(Debug, Error)]
+pub enum BlockingError {
+    #[error("blocking client cannot be used within an async runtime")]
+    WithinRuntime,
+    #[error("unable to create runtime for blocking client: {0}")]
+    CreateRuntime(std::io::Error),
+}
+
+impl From<BlockingError> for ProtocolError {
+    fn from(error: BlockingError) -> Self {
+        ProtocolError::new(ProtocolErrorType::Internal, Box::new(error))
+    }
+}
+
+/// Returns an error if called within an async runtime, since the runtime
+/// of the blocking client cannot be driven from another runtime.
+fn ensure_outside_runtime() -> Result<(), ProtocolError> {
+    match Handle::try_current() {
+        Ok(_) => Err(BlockingError::WithinRuntime.into()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Owns the runtime of a blocking client. Dropping a runtime within an async context
+/// would panic, so the runtime is shut down in the background instead, without waiting
+/// for its tasks to complete.
+struct BlockingRuntime(Option<Runtime>);
+
+impl BlockingRuntime {
+    fn new() -> Result<Self, ProtocolError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(BlockingError::CreateRuntime)?;
+        Ok(Self(Some(runtime)))
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.0
+            .as_ref()
+            .expect("runtime should exist until dropped")
+            .block_on(future)
+    }
+}
+
+impl Drop for BlockingRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// A response container returned by a [`BlockingClient`].
+pub enum BlockingServiceResponse<Response> {
+    /// Contains a single response returned by the service.
+    Single(Response),
+    /// Contains an iterator of multiple responses returned by the service.
+    Multiple(BlockingNotificationIter<Response>),
+}
+
+/// A blocking iterator of multiple response results returned by the service.
+/// Each call to `next` blocks until the next result is received. If called within
+/// an async runtime, a [`BlockingError::WithinRuntime`] error is returned,
+/// and the iterator ends.
+pub struct BlockingNotificationIter<Response> {
+    runtime: Arc<BlockingRuntime>,
+    stream: Option<NotificationStream<Response>>,
+}
+
+impl<Response> Iterator for BlockingNotificationIter<Response> {
+    type Item = Result<Response, ProtocolError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stream = self.stream.as_mut()?;
+        if let Err(e) = ensure_outside_runtime() {
+            self.stream = None;
+            return Some(Err(e));
+        }
+        let result = self.runtime.block_on(stream.next());
+        if result.is_none() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+/// A client that performs requests synchronously, for applications that do not use
+/// an async runtime. Wraps a multilink client (i.e. [`HttpClient`](crate::http::client::HttpClient)
+/// or [`StdioClient`](crate::stdio::client::StdioClient)), and drives it with a current-thread runtime
+/// that is owned by the blocking client. Background tasks of the inner client
+/// (i.e. the stdio comm task) only run while a request or response iterator is blocking.
+///
+/// The blocking client must not be created or used within an async runtime;
+/// a [`BlockingError::WithinRuntime`] error will be returned if so. The client and
+/// its response iterators may be dropped anywhere.
+pub struct BlockingClient<Request, Response> {
+    // The service is dropped before the runtime, so that it may cleanly stop its tasks
+    service: BoxedService<Request, Response>,
+    runtime: Arc<BlockingRuntime>,
+}
+
+impl<Request, Response> BlockingClient<Request, Response>
+where
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    /// Creates a blocking client for a service that can be created outside of a runtime
+    /// (i.e. [`HttpClient`](crate::http::client::HttpClient)).
+    pub fn new<S>(service: S) -> Result<Self, ProtocolError>
+    where
+        S: Service<
+                Request,
+                Response = ServiceResponse<Response>,
+                Error = ServiceError,
+                Future = ServiceFuture<ServiceResponse<Response>>,
+            > + Send
+            + Sync
+            + 'static,
+    {
+        ensure_outside_runtime()?;
+        Ok(Self {
+            service: Box::new(service),
+            runtime: Arc::new(BlockingRuntime::new()?),
+        })
+    }
+
+    /// Creates a blocking client for a service that must be created within a runtime
+    /// (i.e. [`StdioClient`](crate::stdio::client::StdioClient), which spawns the child process
+    /// and comm task when created). The future is resolved by the runtime of the blocking client.
+    pub fn build<S, E, F>(future: F) -> Result<Self, ProtocolError>
+    where
+        S: Service<
+                Request,
+                Response = ServiceResponse<Response>,
+                Error = ServiceError,
+                Future = ServiceFuture<ServiceResponse<Response>>,
+            > + Send
+            + Sync
+            + 'static,
+        E: Into<ServiceError>,
+        F: Future<Output = Result<S, E>>,
+    {
+        ensure_outside_runtime()?;
+        let runtime = BlockingRuntime::new()?;
+        let service = runtime
+            .block_on(future)
+            .map_err(|e| ProtocolError::from(e.into()))?;
+        Ok(Self {
+            service: Box::new(service),
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Sends the request, and blocks until the response is received.
+    /// Multiple responses are returned as a blocking iterator.
+    pub fn call(
+        &mut self,
+        request: Request,
+    ) -> Result<BlockingServiceResponse<Response>, ProtocolError> {
+        ensure_outside_runtime()?;
+        let service = &mut self.service;
+        let response = self
+            .runtime
+            .block_on(async {
+                poll_fn(|cx| service.poll_ready(cx)).await?;
+                service.call(request).await
+            })
+            .map_err(ProtocolError::from)?;
+        Ok(match response {
+            ServiceResponse::Single(response) => BlockingServiceResponse::Single(response),
+            ServiceResponse::Multiple(stream) => {
+                BlockingServiceResponse::Multiple(BlockingNotificationIter {
+                    runtime: self.runtime.clone(),
+                    stream: Some(stream),
+                })
+            }
+        })
+    }
+}
@@ -1,25 +1,65 @@
 use std::error::Error;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower::timeout::error::Elapsed;
 
 /// The error type of the [`ProtocolError`].
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum ProtocolErrorType {
     NotFound,
     HttpMethodNotAllowed,
     BadRequest,
     Unauthorized,
     Internal,
+    TooManyRequests,
+    ServiceUnavailable,
+    Forbidden,
+    Timeout,
+    /// The request was not received by the server in time (i.e. the client sent the request too slowly).
+    RequestTimeout,
+    UnsupportedMediaType,
+    /// The request or message exceeded the maximum size accepted by the receiver.
+    PayloadTooLarge,
+    /// A secure connection could not be established with the server
+    /// (i.e. the server certificate could not be verified).
+    Tls,
+    /// The connection was closed before a response was received. The server may
+    /// have processed the request, so it is not safe to retry.
+    ConnectionClosed,
+}
+
+impl ProtocolErrorType {
+    /// Returns true if a request that failed with this error type may succeed
+    /// if retried later. "Too many requests", "service unavailable", "timeout" and "request timeout"
+    /// errors are considered retryable; the clients return "timeout" errors when a request times out,
+    /// and "service unavailable" errors when a connection could not be established.
+    /// All other error types are not retryable, including TLS errors and connections that
+    /// were closed after the request was sent.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProtocolErrorType::TooManyRequests
+                | ProtocolErrorType::ServiceUnavailable
+                | ProtocolErrorType::Timeout
+                | ProtocolErrorType::RequestTimeout
+        )
+    }
 }
 
 /// A "one size fits all" error type for the protocol.
-/// Contains a boxed error, and the error type.
+/// Contains a boxed error, and the error type. An optional error code and
+/// optional structured error data may be attached via [`ProtocolError::with_code`]
+/// and [`ProtocolError::with_data`].
 #[derive(Debug, thiserror::Error)]
 #[error("{error}")]
 pub struct ProtocolError {
     pub error_type: ProtocolErrorType,
     #[source]
     pub error: Box<dyn Error + Send + Sync + 'static>,
+    code: Option<String>,
+    data: Option<Value>,
 }
 
 impl ProtocolError {
@@ -27,7 +67,66 @@ impl ProtocolError {
         error_type: ProtocolErrorType,
         error: Box<dyn Error + Send + Sync + 'static>,
     ) -> Self {
-        Self { error_type, error }
+        Self {
+            error_type,
+            error,
+            code: None,
+            data: None,
+        }
+    }
+
+    /// Returns true if the request may succeed if retried later.
+    /// See [`ProtocolErrorType::is_retryable`].
+    pub fn is_retryable(&self) -> bool {
+        self.error_type.is_retryable()
+    }
+
+    /// Returns the stable, machine-readable error code (i.e. `"request.invalid_name"`)
+    /// that clients can use to localize the error. The error description
+    /// should be used as a fallback.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// Returns the optional machine-readable error payload (i.e. `{"field": "name"}`),
+    /// preserved across HTTP and JSON-RPC.
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+
+    /// Returns the original error, i.e. to downcast it to its type.
+    /// Equivalent to the `error` field.
+    pub fn inner_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+        self.error.as_ref()
+    }
+
+    /// Sets the stable error code for the error.
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Sets the structured error data for the error.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Sets the error code and structured data, if provided.
+    pub(crate) fn with_optional_details(self, code: Option<String>, data: Option<Value>) -> Self {
+        Self { code, data, ..self }
+    }
+
+    /// Splits the error into its type, the original error, the error code and the error data.
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        ProtocolErrorType,
+        Box<dyn Error + Send + Sync + 'static>,
+        Option<String>,
+        Option<Value>,
+    ) {
+        (self.error_type, self.error, self.code, self.data)
     }
 }
 
@@ -35,34 +134,102 @@ impl From<Box<dyn Error + Send + Sync + 'static>> for ProtocolError {
     fn from(error: Box<dyn Error + Send + Sync + 'static>) -> Self {
         match error.downcast::<Self>() {
             Ok(e) => *e,
-            Err(e) => ProtocolError::new(ProtocolErrorType::Internal, e),
+            Err(e) => {
+                let error_type =
+                    transport_error_type(e.as_ref()).unwrap_or(ProtocolErrorType::Internal);
+                ProtocolError::new(error_type, e)
+            }
         }
     }
 }
 
+/// Returns the error type for timeouts and transport errors (i.e. connection failures),
+/// or `None` if the error is not a transport error.
+pub(crate) fn transport_error_type(error: &(dyn Error + 'static)) -> Option<ProtocolErrorType> {
+    if error.is::<Elapsed>() {
+        return Some(ProtocolErrorType::Timeout);
+    }
+    #[cfg(any(feature = "http-client", feature = "http-server"))]
+    if let Some(error) = error.downcast_ref::<hyper::Error>() {
+        return hyper_error_type(error);
+    }
+    None
+}
+
+#[cfg(any(feature = "http-client", feature = "http-server"))]
+fn hyper_error_type(error: &hyper::Error) -> Option<ProtocolErrorType> {
+    #[cfg(feature = "http-client")]
+    if is_tls_error(error) {
+        return Some(ProtocolErrorType::Tls);
+    }
+    if error.is_timeout() {
+        Some(ProtocolErrorType::Timeout)
+    } else if error.is_connect() {
+        Some(ProtocolErrorType::ServiceUnavailable)
+    } else if error.is_closed() || error.is_canceled() || error.is_incomplete_message() {
+        Some(ProtocolErrorType::ConnectionClosed)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the TLS handshake failed while connecting.
+#[cfg(feature = "http-client")]
+fn is_tls_error(error: &hyper::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if error.is::<rustls::Error>() {
+            return true;
+        }
+        // IO errors do not expose the wrapped error as their source
+        source = match error.downcast_ref::<std::io::Error>() {
+            Some(e) => e.get_ref().map(|e| e as &(dyn Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
 /// A serializable variant of the protocol error.
-/// Contains a description of the error and the error type.
+/// Contains a description of the error, the error type, an optional error code
+/// and optional structured error data.
 #[derive(Clone, Debug, thiserror::Error, Serialize, Deserialize)]
 #[error("{description}")]
 pub struct SerializableProtocolError {
     pub error_type: ProtocolErrorType,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl SerializableProtocolError {
+    pub fn new(error_type: ProtocolErrorType, description: String) -> Self {
+        Self {
+            error_type,
+            description,
+            code: None,
+            data: None,
+        }
+    }
 }
 
 impl From<ProtocolError> for SerializableProtocolError {
     fn from(value: ProtocolError) -> Self {
         Self {
-            error_type: value.error_type,
+            code: value.code().map(str::to_string),
+            data: value.data().cloned(),
             description: value.error.to_string(),
+            error_type: value.error_type,
         }
     }
 }
 
 impl From<SerializableProtocolError> for ProtocolError {
     fn from(value: SerializableProtocolError) -> Self {
-        Self {
-            error_type: value.error_type.clone(),
-            error: Box::new(value),
-        }
+        let (code, data) = (value.code.clone(), value.data.clone());
+        ProtocolError::new(value.error_type.clone(), Box::new(value))
+            .with_optional_details(code, data)
     }
 }
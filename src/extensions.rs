@@ -0,0 +1,103 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use tokio::task::futures::TaskLocalFuture;
+
+tokio::task_local! {
+    static REQUEST_EXTENSIONS: Extensions;
+}
+
+/// The socket address of the peer of a TCP or WebSocket connection.
+/// Inserted into the extensions of each request received on the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// A typed map of values attached to a request, keyed by type. Allows servers,
+/// interceptors and services to share per-request context (i.e. the identity of the caller,
+/// or connection details) without including it in the protocol-agnostic request type.
+///
+/// Servers populate the extensions with connection details, along with any values provided
+/// via `with_extension` on the server (i.e. shared state such as a database pool). While the
+/// service future is being resolved, the extensions of the request can be read via
+/// [`request_extension`] or [`with_request_extensions`].
+///
+/// The extensions are scoped to the task that calls the service, so they are not available
+/// in tasks spawned by the service, or in services behind a layer that dispatches requests
+/// to a separate task (i.e. `tower::buffer::Buffer`). In those cases, the required values
+/// should be read in `Service::call` (or before the layer), and moved into the spawned task.
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+impl Extensions {
+    /// Creates an empty extensions map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value, replacing any previous value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns a reference to the value of type `T`, if present.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Removes the value of type `T`. Returns true if a value was present.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> bool {
+        self.map.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if a value of type `T` is present.
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Inserts all values of `other`, replacing values of the same type.
+    pub fn extend(&mut self, other: Extensions) {
+        self.map.extend(other.map);
+    }
+}
+
+/// Returns a clone of the extension of type `T`, for the request that is being handled
+/// by the current service future. Returns `None` if the value is not present, or if called
+/// outside of a service future (i.e. from a spawned task, from a service behind a buffer,
+/// or while a stream response is being consumed). See [`Extensions`].
+pub fn request_extension<T: Clone + Send + Sync + 'static>() -> Option<T> {
+    with_request_extensions(|extensions| extensions.get::<T>().cloned()).flatten()
+}
+
+/// Invokes `f` with the extensions of the request that is being handled by the current
+/// service future. Returns `None` if called outside of a service future.
+pub fn with_request_extensions<R>(f: impl FnOnce(&Extensions) -> R) -> Option<R> {
+    REQUEST_EXTENSIONS.try_with(f).ok()
+}
+
+/// Makes the extensions available to the service while the service is called,
+/// and while the returned future is resolved.
+pub(crate) fn with_extensions<F: Future>(
+    extensions: Extensions,
+    call: impl FnOnce() -> F,
+) -> TaskLocalFuture<Extensions, F> {
+    let future = REQUEST_EXTENSIONS.sync_scope(extensions.clone(), call);
+    REQUEST_EXTENSIONS.scope(extensions, future)
+}
@@ -0,0 +1,163 @@
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{error::ProtocolErrorType, ProtocolError, ServiceError};
+
+/// The content type of JSON payloads.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+/// The content type of MessagePack payloads.
+#[cfg(feature = "msgpack")]
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+/// The content type of CBOR payloads.
+#[cfg(feature = "cbor")]
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Errors that occur while encoding or decoding payloads.
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("failed to serialize {format} payload: {source}")]
+    Serialize {
+        format: SerializationFormat,
+        source: ServiceError,
+    },
+    #[error("failed to deserialize {format} payload: {source}")]
+    Deserialize {
+        format: SerializationFormat,
+        source: ServiceError,
+    },
+    #[error("unsupported content type: {0}")]
+    UnsupportedContentType(String),
+}
+
+impl From<FormatError> for ProtocolError {
+    fn from(error: FormatError) -> Self {
+        let error_type = match &error {
+            FormatError::Serialize { .. } => ProtocolErrorType::Internal,
+            FormatError::Deserialize { .. } => ProtocolErrorType::BadRequest,
+            FormatError::UnsupportedContentType(_) => ProtocolErrorType::UnsupportedMediaType,
+        };
+        ProtocolError::new(error_type, Box::new(error))
+    }
+}
+
+/// The format used to encode message payloads. The shape of messages
+/// (i.e. the fields of JSON-RPC messages) is the same for all formats,
+/// only the encoding differs. Binary formats require the `msgpack` or `cbor` features.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SerializationFormat {
+    /// JSON, the default format.
+    #[default]
+    Json,
+    /// MessagePack, encoded via `rmp-serde`. Structs are encoded as maps.
+    #[cfg(feature = "msgpack")]
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// CBOR, encoded via `ciborium`.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Display for SerializationFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SerializationFormat::Json => "json",
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => "msgpack",
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => "cbor",
+        })
+    }
+}
+
+impl SerializationFormat {
+    /// Returns the HTTP content type for the format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SerializationFormat::Json => JSON_CONTENT_TYPE,
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => MSGPACK_CONTENT_TYPE,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+
+    /// Returns the format for an HTTP content type, ignoring any parameters
+    /// (i.e. `charset`). Structured syntax suffixes (i.e. `application/problem+json`) are
+    /// treated as the associated format. Returns an "unsupported content type" error if the
+    /// content type is unknown, or if the associated feature is not enabled.
+    pub fn from_content_type(content_type: &str) -> Result<Self, FormatError> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match mime.as_str() {
+            JSON_CONTENT_TYPE => Ok(SerializationFormat::Json),
+            mime if mime.ends_with("+json") => Ok(SerializationFormat::Json),
+            #[cfg(feature = "msgpack")]
+            MSGPACK_CONTENT_TYPE | "application/x-msgpack" => Ok(SerializationFormat::MessagePack),
+            #[cfg(feature = "cbor")]
+            CBOR_CONTENT_TYPE => Ok(SerializationFormat::Cbor),
+            _ => Err(FormatError::UnsupportedContentType(
+                content_type.to_string(),
+            )),
+        }
+    }
+
+    /// Returns true if the encoded payloads may contain any bytes (including newlines).
+    pub fn is_binary(&self) -> bool {
+        !matches!(self, SerializationFormat::Json)
+    }
+
+    /// Serializes `value`, and appends the payload to `dst`.
+    pub fn serialize_into<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+        dst: &mut Vec<u8>,
+    ) -> Result<(), FormatError> {
+        let result: Result<(), ServiceError> = match self {
+            SerializationFormat::Json => {
+                serde_json::to_writer(&mut *dst, value).map_err(Into::into)
+            }
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => {
+                rmp_serde::encode::write_named(dst, value).map_err(Into::into)
+            }
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => {
+                ciborium::ser::into_writer(value, &mut *dst).map_err(Into::into)
+            }
+        };
+        result.map_err(|source| FormatError::Serialize {
+            format: *self,
+            source,
+        })
+    }
+
+    /// Serializes `value` into a new payload.
+    pub fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+        let mut bytes = Vec::new();
+        self.serialize_into(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Deserializes a payload into `T`.
+    pub fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FormatError> {
+        let result: Result<T, ServiceError> = match self {
+            SerializationFormat::Json => serde_json::from_slice(bytes).map_err(Into::into),
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => rmp_serde::from_slice(bytes).map_err(Into::into),
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => ciborium::de::from_reader(bytes).map_err(Into::into),
+        };
+        result.map_err(|source| FormatError::Deserialize {
+            format: *self,
+            source,
+        })
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{error::ProtocolErrorType, ProtocolError};
+
+/// The version of the multilink wire protocol. Incremented on incompatible
+/// changes to the message envelopes or reserved methods.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The stable error code of errors caused by an incompatible peer.
+pub const HANDSHAKE_ERROR_CODE: &str = "handshake.incompatible";
+/// The feature that contains the serialization format of message payloads (i.e. `json`).
+pub const SERIALIZATION_FORMAT_FEATURE: &str = "serialization_format";
+/// The feature that contains the framing of stdio messages (i.e. `line` or `length_prefixed`).
+pub const FRAMING_FEATURE: &str = "framing";
+/// The feature that contains the encodings used to compress HTTP response bodies, as a
+/// comma-separated list in order of preference (i.e. `gzip,deflate`), or `identity` if
+/// responses are not compressed. Unlike other features, the values of the peers do not need
+/// to be equal; an encoding is selected via [`Handshake::negotiate_compression`].
+pub const COMPRESSION_FEATURE: &str = "compression";
+
+/// Describes a peer during the optional handshake, so that incompatible
+/// clients and servers fail upfront, rather than with confusing per-request errors.
+/// Each peer sends its own handshake, and checks the handshake of the other peer
+/// via [`Handshake::negotiate`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    /// The version of the wire protocol. See [`PROTOCOL_VERSION`].
+    pub protocol_version: u32,
+    /// An application-defined version of the request and response schemas.
+    /// Only compared if provided by both peers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<String>,
+    /// Transport-specific features (i.e. [`FRAMING_FEATURE`]).
+    /// Only features provided by both peers are compared.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, String>,
+}
+
+/// Errors caused by an incompatible peer during the handshake.
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    #[error("peer uses protocol version {peer}, expected {local}")]
+    ProtocolVersion { local: u32, peer: u32 },
+    #[error("peer uses schema version `{peer}`, expected `{local}`")]
+    SchemaVersion { local: String, peer: String },
+    #[error("peer uses `{peer}` for feature `{name}`, expected `{local}`")]
+    Feature {
+        name: String,
+        local: String,
+        peer: String,
+    },
+    #[error("peer rejected the handshake: {0}")]
+    Rejected(String),
+    #[error("peer sent an invalid handshake: {0}")]
+    Invalid(String),
+}
+
+impl From<HandshakeError> for ProtocolError {
+    fn from(error: HandshakeError) -> Self {
+        ProtocolError::new(ProtocolErrorType::BadRequest, Box::new(error))
+            .with_code(HANDSHAKE_ERROR_CODE)
+    }
+}
+
+impl Handshake {
+    /// Creates a handshake for the current protocol version, without any features.
+    pub fn new(schema_version: Option<String>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            schema_version,
+            features: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a feature to the handshake, replacing any previous value.
+    pub fn with_feature(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.features.insert(name.into(), value.into());
+        self
+    }
+
+    /// Checks that the peer is compatible: the protocol versions must be equal, along
+    /// with the schema versions and features that are provided by both peers
+    /// (except for [`COMPRESSION_FEATURE`]).
+    /// Returns a [`HandshakeError`] describing the first mismatch.
+    pub fn negotiate(&self, peer: &Handshake) -> Result<(), HandshakeError> {
+        if self.protocol_version != peer.protocol_version {
+            return Err(HandshakeError::ProtocolVersion {
+                local: self.protocol_version,
+                peer: peer.protocol_version,
+            });
+        }
+        if let (Some(local), Some(peer)) = (&self.schema_version, &peer.schema_version) {
+            if local != peer {
+                return Err(HandshakeError::SchemaVersion {
+                    local: local.clone(),
+                    peer: peer.clone(),
+                });
+            }
+        }
+        let compared_features = self
+            .features
+            .iter()
+            .filter(|(name, _)| *name != COMPRESSION_FEATURE);
+        for (name, local) in compared_features {
+            match peer.features.get(name) {
+                Some(peer) if peer != local => {
+                    return Err(HandshakeError::Feature {
+                        name: name.clone(),
+                        local: local.clone(),
+                        peer: peer.clone(),
+                    })
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Selects the first encoding of the local [`COMPRESSION_FEATURE`] that is also
+    /// supported by the peer. Returns `None` if either peer does not provide the feature,
+    /// or if the peers do not share an encoding.
+    pub fn negotiate_compression(&self, peer: &Handshake) -> Option<&str> {
+        let peer_encodings = peer.features.get(COMPRESSION_FEATURE)?;
+        self.features
+            .get(COMPRESSION_FEATURE)?
+            .split(',')
+            .map(str::trim)
+            .find(|encoding| {
+                peer_encodings
+                    .split(',')
+                    .any(|peer_encoding| peer_encoding.trim() == *encoding)
+            })
+    }
+}
@@ -0,0 +1,127 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::{util::config::ConfigError, ProtocolError};
+
+use super::{
+    super::{RequestHttpConvert, ResponseHttpConvert},
+    HttpClient, HttpClientConfig, HttpVersion, RequestInterceptor,
+};
+
+/// Builds an [`HttpClient`], starting from the default configuration.
+/// Created via [`HttpClient::builder`].
+pub struct HttpClientBuilder<Request, Response> {
+    config: HttpClientConfig,
+    request_interceptor: Option<RequestInterceptor<Request>>,
+    request_phantom: PhantomData<Request>,
+    response_phantom: PhantomData<Response>,
+}
+
+impl<Request, Response> HttpClientBuilder<Request, Response>
+where
+    Request: RequestHttpConvert<Request> + Clone + Send + 'static,
+    Response: ResponseHttpConvert<Request, Response> + Send + 'static,
+{
+    pub(super) fn new() -> Self {
+        Self {
+            config: Default::default(),
+            request_interceptor: None,
+            request_phantom: Default::default(),
+            response_phantom: Default::default(),
+        }
+    }
+
+    /// Replaces the entire configuration. Subsequent calls will modify the provided configuration.
+    pub fn config(mut self, config: HttpClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See [`HttpClientConfig::base_url`].
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.config.base_url = base_url.into();
+        self
+    }
+
+    /// See [`HttpClientConfig::base_urls`].
+    pub fn base_urls(mut self, base_urls: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.base_urls = base_urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// See [`HttpClientConfig::api_key`].
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.config.api_key = Some(api_key.into());
+        self
+    }
+
+    /// See [`HttpClientConfig::api_key_header`].
+    pub fn api_key_header(mut self, api_key_header: impl Into<String>) -> Self {
+        self.config.api_key_header = api_key_header.into();
+        self
+    }
+
+    /// See [`HttpClientConfig::hmac_secret`].
+    pub fn hmac_secret(mut self, hmac_secret: impl Into<String>) -> Self {
+        self.config.hmac_secret = Some(hmac_secret.into());
+        self
+    }
+
+    /// See [`HttpClientConfig::timeout_secs`].
+    pub fn timeout_secs(mut self, timeout_secs: u64) -> Self {
+        self.config.timeout_secs = timeout_secs;
+        self
+    }
+
+    /// See [`HttpClientConfig::pool_idle_timeout_secs`].
+    pub fn pool_idle_timeout_secs(mut self, pool_idle_timeout_secs: u64) -> Self {
+        self.config.pool_idle_timeout_secs = Some(pool_idle_timeout_secs);
+        self
+    }
+
+    /// See [`HttpClientConfig::pool_max_idle_per_host`].
+    pub fn pool_max_idle_per_host(mut self, pool_max_idle_per_host: usize) -> Self {
+        self.config.pool_max_idle_per_host = Some(pool_max_idle_per_host);
+        self
+    }
+
+    /// See [`HttpClientConfig::http2_keep_alive_interval_secs`].
+    pub fn http2_keep_alive_interval_secs(mut self, http2_keep_alive_interval_secs: u64) -> Self {
+        self.config.http2_keep_alive_interval_secs = Some(http2_keep_alive_interval_secs);
+        self
+    }
+
+    /// See [`HttpClientConfig::http_version`].
+    pub fn http_version(mut self, http_version: HttpVersion) -> Self {
+        self.config.http_version = http_version;
+        self
+    }
+
+    /// See [`HttpClientConfig::handshake`].
+    pub fn handshake(mut self, handshake: bool) -> Self {
+        self.config.handshake = handshake;
+        self
+    }
+
+    /// See [`HttpClientConfig::schema_version`].
+    pub fn schema_version(mut self, schema_version: impl Into<String>) -> Self {
+        self.config.schema_version = Some(schema_version.into());
+        self
+    }
+
+    /// See [`HttpClient::with_request_interceptor`].
+    pub fn request_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut Request) -> Result<(), ProtocolError> + Send + Sync + 'static,
+    {
+        self.request_interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Creates the client. A [`ConfigError`] will be returned
+    /// if the configuration is invalid.
+    pub fn build(self) -> Result<HttpClient<Request, Response>, ConfigError> {
+        let mut client = HttpClient::try_new(self.config)?;
+        client.request_interceptor = self.request_interceptor;
+        Ok(client)
+    }
+}
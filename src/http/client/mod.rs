@@ -1,70 +1,283 @@
+mod builder;
+
+pub use builder::HttpClientBuilder;
+
 use std::{
     marker::PhantomData,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use hyper::{
+    body::to_bytes,
     client::HttpConnector,
-    http::{uri::InvalidUri, HeaderValue},
-    Client, Uri,
+    header::{ACCEPT_ENCODING, ETAG},
+    http::{uri::InvalidUri, HeaderName, HeaderValue},
+    Body, Client, Request as HttpRequest, Response as HttpResponse, StatusCode, Uri,
 };
 use hyper_rustls::HttpsConnector;
 use serde::{Deserialize, Serialize};
-use tower::{timeout::Timeout, Service};
+use tower::{
+    timeout::{error::Elapsed, Timeout},
+    Service,
+};
+use tracing::warn;
 
 use crate::{
-    error::{ProtocolError, ProtocolErrorType},
-    ConfigExampleSnippet, ServiceError, ServiceFuture, ServiceResponse, DEFAULT_TIMEOUT_SECS,
+    error::{transport_error_type, ProtocolError, ProtocolErrorType},
+    handshake::{Handshake, HandshakeError, COMPRESSION_FEATURE},
+    metrics, trace,
+    util::config::{ensure_header_name, ensure_header_value, ensure_non_zero, ConfigError},
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ServiceError, ServiceFuture,
+    ServiceResponse, DEFAULT_TIMEOUT_SECS,
 };
 
-use super::util::parse_response;
+use super::{
+    signature::{sign, unix_timestamp, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    util::parse_response,
+};
 
 use super::{
     generic_error, ModalHttpResponse, ProtocolHttpError, RequestHttpConvert, ResponseHttpConvert,
-    API_KEY_HEADER,
+    API_KEY_HEADER, DEADLINE_HEADER, HANDSHAKE_HEADER,
 };
 
+/// The encodings accepted for compressed responses, in order of preference.
+const ACCEPTED_ENCODINGS: &str = "gzip, deflate";
+
+/// The HTTP version used by the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpVersion {
+    /// Only HTTP/1.1 is used.
+    #[default]
+    Http1,
+    /// Only HTTP/2 is used. For cleartext connections, HTTP/2 is used with
+    /// prior knowledge (h2c), so the server must support HTTP/2.
+    Http2,
+    /// HTTP/2 is used if negotiated with the server via ALPN for TLS connections,
+    /// otherwise HTTP/1.1 is used.
+    Auto,
+}
+
 /// Configuration for the HTTP client.
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct HttpClientConfig {
-    /// Base URL/prefix for all outgoing requests.
+    /// Base URL/prefix for all outgoing requests. Any path included in the
+    /// URL (i.e. `/api/v1`) will be prepended to the path of each request.
     pub base_url: String,
+    /// Optional base URLs for multiple replicas of the server. If provided,
+    /// requests will be distributed across the URLs in round-robin order,
+    /// and `base_url` will be ignored. If a connection to one replica fails,
+    /// the request will be sent to the next replica. Requests are not resent if the
+    /// connection is closed after the request was sent.
+    pub base_urls: Vec<String>,
     /// API key to append to requests.
-    /// The key will be inserted into the `X-API-Key` header.
+    /// The key will be inserted into the header named by `api_key_header`.
     pub api_key: Option<String>,
-    /// Timeout for client requests in seconds.
+    /// The name of the header that contains the API key. Defaults to `X-API-Key`.
+    /// Must match the header expected by the server.
+    pub api_key_header: String,
+    /// Secret for signing requests with HMAC-SHA256. If provided, the signature will
+    /// be inserted into the `X-Signature` header, and the signing time will be inserted
+    /// into the `X-Timestamp` header. The signature covers the method, path, query and body.
+    pub hmac_secret: Option<String>,
+    /// Timeout for client requests in seconds. The timeout is sent to the server
+    /// in the `X-Deadline-Ms` header, so the server may abort requests that the
+    /// client will no longer wait for.
     pub timeout_secs: u64,
+    /// Duration in seconds after which idle pooled connections will be closed.
+    /// Uses the hyper default if unset.
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Maximum amount of idle connections to keep per host.
+    /// Uses the hyper default if unset.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Interval in seconds for sending HTTP/2 keepalive pings.
+    /// Keepalive pings are disabled if unset.
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    /// The HTTP version to use for requests.
+    pub http_version: HttpVersion,
+    /// If enabled, the [`Handshake`] of the client is sent with each request in the
+    /// `X-Multilink-Handshake` header, since requests may be served by different connections or
+    /// replicas. Multilink servers reject requests from incompatible clients, and include their
+    /// own handshake in responses, which is checked by the client. Requests fail with a
+    /// "bad request" error if the peers are incompatible. The handshake includes the
+    /// encodings accepted for compressed responses, which the server uses instead of
+    /// the `Accept-Encoding` header.
+    pub handshake: bool,
+    /// An application-defined version of the request and response schemas,
+    /// which is sent in the handshake. Only used if `handshake` is enabled.
+    pub schema_version: Option<String>,
 }
 
 impl ConfigExampleSnippet for HttpClientConfig {
     fn config_example_snippet() -> String {
-        r#"# The base URL for the HttpClient.
+        r#"# The base URL for the HttpClient. May include a path prefix
+# for all requests, i.e. "https://example.com/api/v1".
 # base_url = "https://example.com"
 
+# Base URLs for multiple replicas of the server (optional). Requests will be
+# distributed across the replicas, and base_url will be ignored.
+# base_urls = ["https://a.example.com", "https://b.example.com"]
+
 # The API key for authenticating requests made by the HttpClient (optional).
 # This field can be omitted if an API key is not required.
 # api_key = "YOUR_API_KEY"
 
+# The name of the header that contains the API key.
+# api_key_header = "X-API-Key"
+
+# The secret for signing requests made by the HttpClient (optional).
+# Only required if the server verifies request signatures.
+# hmac_secret = "YOUR_SECRET"
+
 # The timeout duration in seconds for the HttpClient.
-# timeout_secs = 60"#
+# timeout_secs = 60
+
+# Close idle pooled connections after this duration in seconds (optional).
+# pool_idle_timeout_secs = 90
+
+# The maximum amount of idle connections to keep per host (optional).
+# pool_max_idle_per_host = 32
+
+# Send HTTP/2 keepalive pings at this interval in seconds (optional).
+# http2_keep_alive_interval_secs = 30
+
+# The HTTP version to use: "http1", "http2" or "auto". "http2" uses
+# prior knowledge for cleartext connections. "auto" negotiates the
+# version via ALPN for TLS connections.
+# http_version = "http1"
+
+# Send a handshake with each request, so that requests to an incompatible
+# server fail upfront.
+# handshake = false
+
+# The version of the request and response schemas, sent in the handshake.
+# schema_version = "1""#
             .into()
     }
 }
 
+impl ConfigDeprecatedKeys for HttpClientConfig {}
+
+impl ConfigEnvPrefix for HttpClientConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_HTTP_CLIENT"
+    }
+}
+
+impl HttpClientConfig {
+    /// Checks that the base URLs contain a scheme and authority, that the API key header
+    /// name and schema version are valid, and that the timeout is non-zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ensure_header_name("api_key_header", &self.api_key_header)?;
+        if let Some(schema_version) = self.schema_version.as_ref() {
+            ensure_header_value("schema_version", schema_version)?;
+        }
+        match self.base_urls.is_empty() {
+            true => {
+                parse_base_url("base_url", &self.base_url)?;
+            }
+            false => {
+                for base_url in &self.base_urls {
+                    parse_base_url("base_urls", base_url)?;
+                }
+            }
+        }
+        ensure_non_zero("timeout_secs", self.timeout_secs)
+    }
+}
+
 impl Default for HttpClientConfig {
     fn default() -> Self {
         Self {
             base_url: String::new(),
+            base_urls: Vec::new(),
             api_key: None,
+            api_key_header: API_KEY_HEADER.to_string(),
+            hmac_secret: None,
             timeout_secs: DEFAULT_TIMEOUT_SECS,
+            pool_idle_timeout_secs: None,
+            pool_max_idle_per_host: None,
+            http2_keep_alive_interval_secs: None,
+            http_version: HttpVersion::Http1,
+            handshake: false,
+            schema_version: None,
         }
     }
 }
 
+/// Parses a base URL, and ensures that it contains a scheme and authority.
+fn parse_base_url(key: &'static str, base_url: &str) -> Result<Uri, ConfigError> {
+    let uri = Uri::from_str(base_url).map_err(|e| ConfigError::new(key, e.to_string()))?;
+    if uri.scheme().is_none() {
+        return Err(ConfigError::new(
+            key,
+            format!("base url `{base_url}` should contain a scheme"),
+        ));
+    }
+    if uri.authority().is_none() {
+        return Err(ConfigError::new(
+            key,
+            format!("base url `{base_url}` should contain an authority"),
+        ));
+    }
+    Ok(uri)
+}
+
+/// Buffers the body of the request, and inserts the HMAC signature and timestamp headers.
+async fn sign_request(
+    request: HttpRequest<Body>,
+    secret: &str,
+) -> Result<HttpRequest<Body>, ServiceError> {
+    let (mut parts, body) = request.into_parts();
+    let body = to_bytes(body).await?;
+    let timestamp = unix_timestamp();
+    let signature = sign(secret, timestamp, &parts.method, &parts.uri, &body);
+    parts
+        .headers
+        .insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature)?);
+    parts
+        .headers
+        .insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+    Ok(HttpRequest::from_parts(parts, body.into()))
+}
+
+/// The handshake of the client, along with the header value that it is sent in.
+struct ClientHandshake {
+    local: Handshake,
+    header: HeaderValue,
+}
+
+/// Checks the handshake of the server, if it is included in the response.
+fn check_server_handshake(
+    local: &Handshake,
+    response: &HttpResponse<Body>,
+) -> Result<(), ProtocolError> {
+    let Some(value) = response.headers().get(HANDSHAKE_HEADER) else {
+        return Ok(());
+    };
+    let peer = value
+        .to_str()
+        .map_err(|e| HandshakeError::Invalid(e.to_string()))
+        .and_then(|value| {
+            serde_json::from_str::<Handshake>(value)
+                .map_err(|e| HandshakeError::Invalid(e.to_string()))
+        })?;
+    Ok(local.negotiate(&peer)?)
+}
+
+/// Invoked for each request before it is converted into an HTTP request.
+/// See [`HttpClient::with_request_interceptor`].
+type RequestInterceptor<Request> =
+    Arc<dyn Fn(&mut Request) -> Result<(), ProtocolError> + Send + Sync>;
+
 /// Client for HTTP communication with a remote host.
 #[derive(Clone)]
 pub struct HttpClient<Request, Response>
@@ -72,9 +285,15 @@ where
     Request: RequestHttpConvert<Request> + Clone + Send + 'static,
     Response: ResponseHttpConvert<Request, Response> + Send + 'static,
 {
-    base_url: Arc<Uri>,
+    base_urls: Arc<Vec<Uri>>,
+    api_key_header: Option<HeaderName>,
+    next_base_url: Arc<AtomicUsize>,
     config: Arc<HttpClientConfig>,
+    /// Returned for each request, if the client was created with an invalid configuration.
+    config_error: Option<ConfigError>,
+    handshake: Option<Arc<ClientHandshake>>,
     client: Timeout<Client<HttpsConnector<HttpConnector>>>,
+    request_interceptor: Option<RequestInterceptor<Request>>,
     request_phantom: PhantomData<Request>,
     response_phantom: PhantomData<Response>,
 }
@@ -86,24 +305,108 @@ where
 {
     /// Creates a new client for HTTP communication. An [`InvalidUri`]
     /// error will be returned if the base URL in the configuration is invalid.
+    /// Other invalid configuration values (i.e. a base URL without a scheme) are
+    /// returned as errors when a request is made; use [`HttpClient::try_new`]
+    /// to check the whole configuration upfront.
     pub fn new(config: HttpClientConfig) -> Result<Self, InvalidUri> {
-        let https = hyper_rustls::HttpsConnectorBuilder::new()
+        let base_urls = match config.base_urls.is_empty() {
+            true => vec![Uri::from_str(&config.base_url)?],
+            false => config
+                .base_urls
+                .iter()
+                .map(|base_url| Uri::from_str(base_url))
+                .collect::<Result<_, _>>()?,
+        };
+        let config_error = config.validate().err();
+        Ok(Self::with_base_urls(config, base_urls, config_error))
+    }
+
+    /// Creates a new client for HTTP communication. A [`ConfigError`]
+    /// will be returned if the configuration is invalid (i.e. the base URL lacks a scheme).
+    pub fn try_new(config: HttpClientConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let base_urls = match config.base_urls.is_empty() {
+            true => vec![parse_base_url("base_url", &config.base_url)?],
+            false => config
+                .base_urls
+                .iter()
+                .map(|base_url| parse_base_url("base_urls", base_url))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Self::with_base_urls(config, base_urls, None))
+    }
+
+    fn with_base_urls(
+        config: HttpClientConfig,
+        base_urls: Vec<Uri>,
+        config_error: Option<ConfigError>,
+    ) -> Self {
+        let connector_builder = hyper_rustls::HttpsConnectorBuilder::new()
             .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
+            .https_or_http();
+        let https = match config.http_version {
+            HttpVersion::Http1 => connector_builder.enable_http1().build(),
+            HttpVersion::Http2 => connector_builder.enable_http2().build(),
+            HttpVersion::Auto => connector_builder.enable_all_versions().build(),
+        };
+        let mut builder = Client::builder();
+        builder.http2_only(config.http_version == HttpVersion::Http2);
+        if let Some(pool_idle_timeout_secs) = config.pool_idle_timeout_secs {
+            builder.pool_idle_timeout(Duration::from_secs(pool_idle_timeout_secs));
+        }
+        if let Some(pool_max_idle_per_host) = config.pool_max_idle_per_host {
+            builder.pool_max_idle_per_host(pool_max_idle_per_host);
+        }
+        if let Some(http2_keep_alive_interval_secs) = config.http2_keep_alive_interval_secs {
+            builder.http2_keep_alive_interval(Duration::from_secs(http2_keep_alive_interval_secs));
+        }
         let client = Timeout::new(
-            Client::builder().build(https),
+            builder.build(https),
             Duration::from_secs(config.timeout_secs),
         );
-        let base_url = Arc::new(Uri::from_str(&config.base_url)?);
-        Ok(Self {
-            base_url,
+        // If the header name is invalid, requests fail with the configuration error
+        let api_key_header = HeaderName::from_bytes(config.api_key_header.as_bytes()).ok();
+        let handshake = config
+            .handshake
+            .then(|| {
+                let local = Handshake::new(config.schema_version.clone())
+                    .with_feature(COMPRESSION_FEATURE, ACCEPTED_ENCODINGS.replace(' ', ""));
+                let header = serde_json::to_string(&local).ok()?;
+                Some(Arc::new(ClientHandshake {
+                    header: HeaderValue::from_str(&header).ok()?,
+                    local,
+                }))
+            })
+            .flatten();
+        Self {
+            base_urls: Arc::new(base_urls),
+            api_key_header,
+            next_base_url: Default::default(),
             config: Arc::new(config),
+            config_error,
+            handshake,
             client,
+            request_interceptor: None,
             request_phantom: Default::default(),
             response_phantom: Default::default(),
-        })
+        }
+    }
+
+    /// Returns a builder for configuring the client fluently,
+    /// as an alternative to providing a [`HttpClientConfig`] to [`HttpClient::new`].
+    pub fn builder() -> HttpClientBuilder<Request, Response> {
+        HttpClientBuilder::new()
+    }
+
+    /// Sets an interceptor that will be invoked for each request before it is converted into
+    /// an HTTP request. The interceptor may modify the request (i.e. to stamp a field on every
+    /// request), or reject it by returning an error. Replaces any interceptor that was set previously.
+    pub fn with_request_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut Request) -> Result<(), ProtocolError> + Send + Sync + 'static,
+    {
+        self.request_interceptor = Some(Arc::new(interceptor));
+        self
     }
 }
 
@@ -116,33 +419,136 @@ where
     type Error = ServiceError;
     type Future = ServiceFuture<ServiceResponse<Response>>;
 
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.client.poll_ready(cx)
     }
 
     fn call(&mut self, request: Request) -> Self::Future {
-        let http_request = request.to_http_request(&self.base_url);
-        let mut client = self.client.clone();
+        // Use the client that was driven to readiness, and leave a clone for the next request
+        let clone = self.client.clone();
+        let mut client = std::mem::replace(&mut self.client, clone);
         let api_key = self.config.api_key.clone();
+        let api_key_header = self.api_key_header.clone();
+        let hmac_secret = self.config.hmac_secret.clone();
+        let timeout_ms = self.config.timeout_secs.saturating_mul(1000);
+        let base_urls = self.base_urls.clone();
+        let start_index = self.next_base_url.fetch_add(1, Ordering::Relaxed);
+        let request_interceptor = self.request_interceptor.clone();
+        let handshake = self.handshake.clone();
+        let config_error = self.config_error.clone();
         Box::pin(async move {
-            let mut http_request =
-                http_request?.ok_or_else(|| generic_error(ProtocolErrorType::NotFound))?;
-            if let Some(api_key) = api_key {
-                http_request
-                    .headers_mut()
-                    .insert(API_KEY_HEADER, HeaderValue::from_str(&api_key)?);
-            }
-            let response = client.call(http_request).await?;
-            let status = response.status();
-            if !status.is_success() {
-                return Err(Box::new(ProtocolError {
-                    error_type: response.status().into(),
-                    error: Box::new(parse_response::<ProtocolHttpError>(response).await?),
-                }))?;
+            let start = Instant::now();
+            let mut operation_name = None;
+            let mut request = request;
+            let result: Result<ServiceResponse<Response>, ServiceError> = async {
+                if let Some(e) = config_error {
+                    return Err(ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)).into());
+                }
+                if let Some(request_interceptor) = request_interceptor {
+                    request_interceptor(&mut request)?;
+                }
+                let mut attempt = 0;
+                let response = loop {
+                    let base_url = &base_urls[(start_index + attempt) % base_urls.len()];
+                    let mut http_request = request
+                        .to_http_request(base_url)?
+                        .ok_or_else(|| generic_error(ProtocolErrorType::NotFound))?;
+                    operation_name.get_or_insert_with(|| {
+                        request
+                            .operation_name()
+                            .unwrap_or(http_request.method().as_str())
+                            .to_string()
+                    });
+                    if let (Some(api_key), Some(api_key_header)) =
+                        (api_key.as_ref(), api_key_header.as_ref())
+                    {
+                        http_request
+                            .headers_mut()
+                            .insert(api_key_header, HeaderValue::from_str(api_key)?);
+                    }
+                    // The timeout applies to each attempt, so each attempt has the full budget
+                    http_request
+                        .headers_mut()
+                        .insert(DEADLINE_HEADER, HeaderValue::from(timeout_ms));
+                    if let Some(handshake) = handshake.as_ref() {
+                        http_request
+                            .headers_mut()
+                            .insert(HANDSHAKE_HEADER, handshake.header.clone());
+                    }
+                    if !http_request.headers().contains_key(ACCEPT_ENCODING) {
+                        http_request.headers_mut().insert(
+                            ACCEPT_ENCODING,
+                            HeaderValue::from_static(ACCEPTED_ENCODINGS),
+                        );
+                    }
+                    trace::inject_http_headers(http_request.headers_mut());
+                    if let Some(hmac_secret) = hmac_secret.as_ref() {
+                        http_request = sign_request(http_request, hmac_secret).await?;
+                    }
+                    match client.call(http_request).await {
+                        Ok(response) => break response,
+                        Err(e) if e.is::<Elapsed>() => {
+                            return Err(ProtocolError::new(ProtocolErrorType::Timeout, e).into())
+                        }
+                        Err(e) => {
+                            attempt += 1;
+                            let error_type = transport_error_type(e.as_ref())
+                                .unwrap_or(ProtocolErrorType::ServiceUnavailable);
+                            // The request may have been processed if the connection was closed
+                            // after it was sent, so it is not sent to another replica
+                            if attempt >= base_urls.len()
+                                || matches!(error_type, ProtocolErrorType::ConnectionClosed)
+                            {
+                                return Err(ProtocolError::new(error_type, e).into());
+                            }
+                            warn!(
+                                "failed to send http request to {}, trying next replica: {}",
+                                base_url, e
+                            );
+                        }
+                    }
+                };
+                // Checked before the status, so that rejections are described from the
+                // perspective of the client
+                if let Some(handshake) = handshake.as_ref() {
+                    check_server_handshake(&handshake.local, &response)?;
+                }
+                // Only client and server errors are treated as errors, so that the
+                // response converter can inspect any other status codes.
+                let status = response.status();
+                if status == StatusCode::NOT_MODIFIED {
+                    // The entity tag is provided so that the converter can match a cached response
+                    let etag = response
+                        .headers()
+                        .get(ETAG)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                    let response = Response::from_http_response(
+                        ModalHttpResponse::NotModified { etag },
+                        &request,
+                    )
+                    .await?;
+                    return Ok(response.ok_or_else(|| generic_error(ProtocolErrorType::NotFound))?);
+                }
+                if status.is_client_error() || status.is_server_error() {
+                    let error = parse_response::<ProtocolHttpError>(response).await?;
+                    // Converted via `into`, since `?` would box the boxed error again,
+                    // which prevents the caller from downcasting it
+                    return Err(error.into_protocol_error(status).into());
+                }
+                let response =
+                    Response::from_http_response(ModalHttpResponse::Single(response), &request)
+                        .await?;
+                Ok(response.ok_or_else(|| generic_error(ProtocolErrorType::NotFound))?)
             }
-            let response =
-                Response::from_http_response(ModalHttpResponse::Single(response), &request).await?;
-            Ok(response.ok_or_else(|| generic_error(ProtocolErrorType::NotFound))?)
+            .await;
+            metrics::record_result(
+                metrics::HTTP_CLIENT_TRANSPORT,
+                operation_name.as_deref().unwrap_or_default(),
+                start,
+                &result,
+            );
+            result
         })
     }
 }
@@ -1,8 +1,8 @@
 pub use hyper;
 
-use hyper::{Body, StatusCode, Uri};
+use hyper::{Body, Method, StatusCode, Uri};
 pub use hyper::{Request as HttpRequest, Response as HttpResponse};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 
@@ -14,20 +14,61 @@ use crate::{
 /// HTTP client components.
 #[cfg(any(feature = "http-client"))]
 pub mod client;
+mod query;
 /// HTTP server components
 #[cfg(any(feature = "http-server"))]
 pub mod server;
+mod signature;
 /// HTTP utilities for request/response conversion.
 pub mod util;
 
+/// The default header for API keys.
 const API_KEY_HEADER: &str = "X-API-Key";
-const SSE_DATA_PREFIX: &str = "data: ";
+/// Contains the remaining time in milliseconds that the client will wait for a response.
+const DEADLINE_HEADER: &str = "X-Deadline-Ms";
+/// Contains the [`Handshake`](crate::handshake::Handshake) of the client or server, as JSON.
+const HANDSHAKE_HEADER: &str = "X-Multilink-Handshake";
 
 /// Body for an HTTP error response.
 #[derive(Debug, Error, Serialize, Deserialize)]
 #[error("{error}")]
 pub struct ProtocolHttpError {
     pub error: String,
+    /// The type of the error, as provided by [`ProtocolError::error_type`]. Allows clients to
+    /// distinguish errors that share a status code. If omitted (i.e. for error responses
+    /// produced by a proxy), the type should be derived from the status code. Types unknown
+    /// to this version of the crate are ignored, so that newer servers remain compatible.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_lenient_error_type"
+    )]
+    pub error_type: Option<ProtocolErrorType>,
+    /// A stable, machine-readable error code, as provided by [`ProtocolError::code`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Structured error data, as provided by [`ProtocolError::data`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Deserializes an optional error type, treating unknown error types as omitted.
+fn deserialize_lenient_error_type<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ProtocolErrorType>, D::Error> {
+    let value = Option::<Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|value| serde_json::from_value(value).ok()))
+}
+
+impl ProtocolHttpError {
+    /// Converts the body of an error response into a protocol error. The type of the error
+    /// is derived from `status` if it is not included in the body.
+    #[cfg(feature = "http-client")]
+    pub(crate) fn into_protocol_error(self, status: StatusCode) -> ProtocolError {
+        let error_type = self.error_type.clone().unwrap_or_else(|| status.into());
+        let (code, data) = (self.code.clone(), self.data.clone());
+        ProtocolError::new(error_type, Box::new(self)).with_optional_details(code, data)
+    }
 }
 
 impl Into<StatusCode> for ProtocolErrorType {
@@ -38,6 +79,15 @@ impl Into<StatusCode> for ProtocolErrorType {
             ProtocolErrorType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             ProtocolErrorType::NotFound => StatusCode::NOT_FOUND,
             ProtocolErrorType::HttpMethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ProtocolErrorType::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ProtocolErrorType::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ProtocolErrorType::Forbidden => StatusCode::FORBIDDEN,
+            ProtocolErrorType::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ProtocolErrorType::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            ProtocolErrorType::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ProtocolErrorType::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ProtocolErrorType::Tls => StatusCode::BAD_GATEWAY,
+            ProtocolErrorType::ConnectionClosed => StatusCode::BAD_GATEWAY,
         }
     }
 }
@@ -50,18 +100,51 @@ impl From<StatusCode> for ProtocolErrorType {
             StatusCode::INTERNAL_SERVER_ERROR => ProtocolErrorType::Internal,
             StatusCode::NOT_FOUND => ProtocolErrorType::NotFound,
             StatusCode::METHOD_NOT_ALLOWED => ProtocolErrorType::HttpMethodNotAllowed,
+            StatusCode::TOO_MANY_REQUESTS => ProtocolErrorType::TooManyRequests,
+            StatusCode::SERVICE_UNAVAILABLE => ProtocolErrorType::ServiceUnavailable,
+            StatusCode::FORBIDDEN => ProtocolErrorType::Forbidden,
+            StatusCode::GATEWAY_TIMEOUT => ProtocolErrorType::Timeout,
+            StatusCode::REQUEST_TIMEOUT => ProtocolErrorType::RequestTimeout,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ProtocolErrorType::UnsupportedMediaType,
+            StatusCode::PAYLOAD_TOO_LARGE => ProtocolErrorType::PayloadTooLarge,
             _ => ProtocolErrorType::Internal,
         }
     }
 }
 
 /// A multilink HTTP response.
+#[non_exhaustive]
 pub enum ModalHttpResponse {
     /// Contains a single HTTP response returned by the server.
     Single(HttpResponse<Body>),
     /// Contains a single serializable event returned by the server,
     /// as part of a stream.
     Event(Value),
+    /// Contains a single serializable event returned by the server as part of a stream,
+    /// along with the SSE event name and/or id. Only used by
+    /// [`notification_sse_stream`](util::notification_sse_stream) if the server included an
+    /// `event:` or `id:` field, otherwise [`ModalHttpResponse::Event`] is used.
+    /// Converters may also return this variant for streamed responses, so that
+    /// [`notification_sse_response`](util::notification_sse_response) writes the name and id.
+    SseEvent(SseEvent),
+    /// A "304 Not Modified" response, which the server sends instead of a response with an
+    /// `ETag` header if a `GET` or `HEAD` request has a matching `If-None-Match` header (see
+    /// [`serialize_to_http_response_with_etag`](util::serialize_to_http_response_with_etag)).
+    /// Contains the entity tag of the unchanged resource, so that the client can use its
+    /// cached copy. Converters may also return this variant, to respond with a 304 directly.
+    NotModified { etag: Option<String> },
+}
+
+/// A server-sent event with an optional event name and id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SseEvent {
+    /// The name of the event, from the `event:` field.
+    pub name: Option<String>,
+    /// The id of the event, from the `id:` field.
+    /// Can be used by clients to resume a stream.
+    pub id: Option<String>,
+    /// The payload of the event.
+    pub value: Value,
 }
 
 /// A request that can convert to and from a [`HttpRequest<Body>`].
@@ -71,6 +154,10 @@ pub trait RequestHttpConvert<Request> {
     /// if the request conversion fails (i.e. request validation fails,
     /// unexpected error, etc.). Returns `None` if the request type is unknown or unsupported for remote host scenarios,
     /// which is synonymous with a "not found" error.
+    ///
+    /// When called by the server, the address of the client is available in the request
+    /// extensions, i.e. `request.extensions().get::<ClientAddr>()`. See
+    /// [`ClientAddr`](server::ClientAddr) and [`RemoteAddr`](server::RemoteAddr).
     async fn from_http_request(
         request: HttpRequest<Body>,
     ) -> Result<Option<Request>, ProtocolError>;
@@ -79,6 +166,13 @@ pub trait RequestHttpConvert<Request> {
     /// the request is unsupported for this protocol, which is synonymous with a
     /// "not found" error.
     fn to_http_request(&self, base_url: &Uri) -> Result<Option<HttpRequest<Body>>, ProtocolError>;
+
+    /// Returns the name of the operation (i.e. the route, such as `say_hello`), which is
+    /// used as the `method` label of the request metrics. Should not contain identifiers
+    /// or other unbounded values. The HTTP method is used as the label if `None` is returned.
+    fn operation_name(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// A response that can convert to and from a [`ModalHttpResponse`].
@@ -93,6 +187,10 @@ where
     /// response validation fails, unexpected error, etc.). A reference to the associated
     /// request is provided, in case it's helpful. Returns `None` if the response type is unknown or unsupported
     /// for remote host scenarios, which is synonymous with a "not found" error.
+    /// Responses with 4xx or 5xx status codes are handled by the client as errors, so
+    /// this method receives all other responses, including their status codes and headers
+    /// (i.e. `202 Accepted` or `204 No Content`). "304 Not Modified" responses are provided
+    /// as [`ModalHttpResponse::NotModified`].
     async fn from_http_response(
         response: ModalHttpResponse,
         original_request: &Request,
@@ -135,12 +233,35 @@ impl Into<Result<Value, ProtocolError>> for HttpNotificationPayload {
     }
 }
 
+/// The error of a [`ProtocolErrorType::HttpMethodNotAllowed`] error returned by
+/// [`validate_methods`](util::validate_methods). Contains the methods that are allowed for the
+/// path, which the server includes in the `Allow` header of the "405 Method Not Allowed" response.
+#[derive(Debug, Error)]
+#[error("{}", StatusCode::METHOD_NOT_ALLOWED)]
+pub struct MethodNotAllowedError {
+    pub allowed_methods: Vec<Method>,
+}
+
+impl MethodNotAllowedError {
+    /// Returns the value of the `Allow` header, i.e. `PUT, PATCH`.
+    pub fn allow_header_value(&self) -> String {
+        self.allowed_methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
 /// Creates a generic [`ProtocolError`] using the HTTP status code
 /// description (i.e. "Bad Request" or "Not Found") as the error text.
 pub fn generic_error(error_type: ProtocolErrorType) -> ProtocolError {
     let status: StatusCode = error_type.clone().into();
     let error = Box::new(ProtocolHttpError {
         error: status.to_string(),
+        error_type: None,
+        code: None,
+        data: None,
     });
-    ProtocolError { error_type, error }
+    ProtocolError::new(error_type, error)
 }
@@ -0,0 +1,178 @@
+use serde::{
+    de::{self, value::StringDeserializer, DeserializeOwned, IntoDeserializer, Visitor},
+    forward_to_deserialize_any, Deserializer, Serialize,
+};
+use serde_json::Value;
+
+/// Converts the fields of `value` into query pairs. Strings are used as-is, other scalars
+/// are formatted as JSON, and nested arrays/objects are encoded as JSON strings.
+/// Fields with null values are omitted. Returns an error if `value` is not a struct or map.
+pub(super) fn to_query_pairs<T: Serialize>(
+    value: &T,
+) -> Result<Vec<(String, String)>, serde_json::Error> {
+    let Value::Object(fields) = serde_json::to_value(value)? else {
+        return Err(serde::ser::Error::custom(
+            "only structs and maps can be serialized into a query string",
+        ));
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Null => None,
+            Value::String(value) => Some((key, value)),
+            value => Some((key, value.to_string())),
+        })
+        .collect())
+}
+
+/// Deserializes `T` from a percent-encoded query string, produced by [`to_query_pairs`].
+pub(super) fn from_query<T: DeserializeOwned>(query: &str) -> Result<T, serde_json::Error> {
+    let fields = form_urlencoded::parse(query.as_bytes())
+        .map(|(key, value)| (key.into_owned(), QueryValue(value.into_owned())))
+        .collect::<Vec<_>>();
+    T::deserialize(de::value::MapDeserializer::new(fields.into_iter()))
+}
+
+/// A query value, which is parsed according to the type requested by the deserialized type.
+struct QueryValue(String);
+
+impl QueryValue {
+    fn parse<V: std::str::FromStr>(&self) -> Result<V, serde_json::Error>
+    where
+        V::Err: std::fmt::Display,
+    {
+        self.0.parse().map_err(de::Error::custom)
+    }
+
+    fn into_json(self) -> Result<Value, serde_json::Error> {
+        serde_json::from_str(&self.0)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for QueryValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for QueryValue {
+    type Error = serde_json::Error;
+
+    /// Used when the type is not known upfront (i.e. for `serde_json::Value`, untagged enums
+    /// or flattened fields), so booleans and numbers are inferred from the value.
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let may_be_scalar = matches!(self.0.as_str(), "true" | "false")
+            || self.0.starts_with(|c: char| c == '-' || c.is_ascii_digit());
+        if may_be_scalar {
+            match serde_json::from_str(&self.0) {
+                Ok(Value::Bool(value)) => return visitor.visit_bool(value),
+                Ok(Value::Number(number)) => return number.deserialize_any(visitor),
+                _ => (),
+            }
+        }
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.into_json()?.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.into_json()?.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.into_json()?
+            .deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.into_json()?.deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.into_json()?.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // Unit variants are encoded as plain strings, other variants as JSON objects
+        match self.0.starts_with('{') {
+            true => self.into_json()?.deserialize_enum(name, variants, visitor),
+            false => StringDeserializer::<serde_json::Error>::new(self.0)
+                .deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
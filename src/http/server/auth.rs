@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use futures::{
+    future::{ready, BoxFuture},
+    Future, FutureExt,
+};
+use http_body::{LengthLimitError, Limited};
+use hyper::{body::to_bytes, Body, Request as HttpRequest};
+use ring::digest::{digest, SHA256};
+
+use crate::{
+    error::ProtocolErrorType,
+    http::{
+        generic_error,
+        signature::{unix_timestamp, verify, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    },
+    ProtocolError,
+};
+
+#[cfg(feature = "jwt")]
+use super::jwt::{get_bearer_token, JwtValidator};
+use super::HttpServerConfig;
+
+/// Authorizes incoming HTTP requests, after the API key check has passed.
+/// Can be provided to the server via [`HttpServer::with_authorizer`](super::HttpServer::with_authorizer).
+pub trait HttpAuthorizer: Send + Sync {
+    /// Returns an error if the request should be rejected. An error with
+    /// [`ProtocolErrorType::Unauthorized`] should be returned if the request lacks valid credentials,
+    /// and [`ProtocolErrorType::Forbidden`] should be returned if the request is authenticated,
+    /// but is not allowed to be performed.
+    fn authorize(&self, request: &HttpRequest<Body>) -> Result<(), ProtocolError>;
+}
+
+impl<F> HttpAuthorizer for F
+where
+    F: Fn(&HttpRequest<Body>) -> Result<(), ProtocolError> + Send + Sync,
+{
+    fn authorize(&self, request: &HttpRequest<Body>) -> Result<(), ProtocolError> {
+        self(request)
+    }
+}
+
+/// Authorizes incoming HTTP requests asynchronously (i.e. by validating a token against
+/// a database or an external auth service), after the API key check has passed. Can be
+/// provided to the server via [`HttpServer::with_async_authorizer`](super::HttpServer::with_async_authorizer).
+pub trait AsyncHttpAuthorizer: Send + Sync {
+    /// Returns a future that resolves to an error if the request should be rejected.
+    /// Since the future may not borrow the request, any required headers or extensions
+    /// should be extracted before the future is created. The error types should be used as
+    /// described in [`HttpAuthorizer::authorize`].
+    fn authorize(
+        &self,
+        request: &HttpRequest<Body>,
+    ) -> BoxFuture<'static, Result<(), ProtocolError>>;
+}
+
+impl<F, Fut> AsyncHttpAuthorizer for F
+where
+    F: Fn(&HttpRequest<Body>) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), ProtocolError>> + Send + 'static,
+{
+    fn authorize(
+        &self,
+        request: &HttpRequest<Body>,
+    ) -> BoxFuture<'static, Result<(), ProtocolError>> {
+        self(request).boxed()
+    }
+}
+
+/// Adapts a [`HttpAuthorizer`], so that synchronous and asynchronous
+/// authorizers can be invoked in the same manner.
+pub(super) struct SyncAuthorizer<A>(pub(super) A);
+
+impl<A: HttpAuthorizer> AsyncHttpAuthorizer for SyncAuthorizer<A> {
+    fn authorize(
+        &self,
+        request: &HttpRequest<Body>,
+    ) -> BoxFuture<'static, Result<(), ProtocolError>> {
+        ready(self.0.authorize(request)).boxed()
+    }
+}
+
+/// The authorization steps that are performed for each request,
+/// after the API key and signature checks.
+#[derive(Clone, Default)]
+pub(super) struct RequestAuthorizers {
+    #[cfg(feature = "jwt")]
+    pub(super) jwt_validator: Option<Arc<JwtValidator>>,
+    pub(super) authorizer: Option<Arc<dyn AsyncHttpAuthorizer>>,
+}
+
+impl RequestAuthorizers {
+    /// Validates the bearer token of the request if a JWT validator is set, and
+    /// inserts the verified claims into the request extensions, so that they are available
+    /// to the authorizer and the service. The authorizer is invoked afterwards.
+    pub(super) async fn authorize(
+        &self,
+        request: &mut HttpRequest<Body>,
+    ) -> Result<(), ProtocolError> {
+        #[cfg(feature = "jwt")]
+        if let Some(jwt_validator) = self.jwt_validator.as_ref() {
+            let token = get_bearer_token(request)
+                .map(str::to_string)
+                .ok_or_else(|| generic_error(ProtocolErrorType::Unauthorized))?;
+            let claims = jwt_validator.validate(&token).await?;
+            request.extensions_mut().insert(claims);
+        }
+        if let Some(authorizer) = self.authorizer.as_ref() {
+            authorizer.authorize(request).await?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn get_api_key<'a>(
+    config: &HttpServerConfig,
+    request: &'a HttpRequest<Body>,
+) -> Option<&'a str> {
+    request
+        .headers()
+        .get(config.api_key_header.as_str())
+        .map(|v| v.to_str().unwrap_or_default())
+}
+
+/// Compares two values in constant time. Both values are hashed beforehand,
+/// so that the comparison time does not depend on their lengths either.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (digest(&SHA256, a), digest(&SHA256, b));
+    a.as_ref()
+        .iter()
+        .zip(b.as_ref())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Checks the API key of the request against all configured keys. Every key is compared
+/// in constant time, so the time taken does not reveal how much of a key matched.
+pub(super) fn check_api_key(
+    config: &HttpServerConfig,
+    request: &HttpRequest<Body>,
+) -> Result<(), ProtocolError> {
+    if !config.api_keys.is_empty() {
+        let key_header = get_api_key(config, request).unwrap_or_default();
+        let is_valid = config.api_keys.iter().fold(false, |is_valid, key| {
+            // Non-short-circuiting, so that all keys are compared
+            is_valid | constant_time_eq(key.as_bytes(), key_header.as_bytes())
+        });
+        if !is_valid {
+            return Err(generic_error(ProtocolErrorType::Unauthorized));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the path is equal to the prefix, or is nested under it.
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Returns a "forbidden" error if the API key has configured scopes,
+/// and the path is not covered by any of them. Keys without scopes may access all paths.
+pub(super) fn check_api_key_scope(
+    config: &HttpServerConfig,
+    api_key: Option<&str>,
+    path: &str,
+) -> Result<(), ProtocolError> {
+    let Some(scopes) = api_key.and_then(|key| config.api_key_scopes.get(key)) else {
+        return Ok(());
+    };
+    if !scopes.iter().any(|prefix| path_has_prefix(path, prefix)) {
+        return Err(generic_error(ProtocolErrorType::Forbidden));
+    }
+    Ok(())
+}
+
+/// Verifies the HMAC signature of the request, if signing secrets are configured.
+/// The body is buffered in order to verify the signature, so the request is
+/// rebuilt with the buffered body. Requests with a missing or invalid signature,
+/// or a timestamp outside of the allowed window, are rejected as unauthorized.
+pub(super) async fn check_signature(
+    config: &HttpServerConfig,
+    request: HttpRequest<Body>,
+) -> Result<HttpRequest<Body>, ProtocolError> {
+    if config.hmac_secrets.is_empty() {
+        return Ok(request);
+    }
+    let get_header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| generic_error(ProtocolErrorType::Unauthorized))
+    };
+    let signature = get_header(SIGNATURE_HEADER)?;
+    let timestamp = get_header(TIMESTAMP_HEADER)?
+        .parse::<u64>()
+        .map_err(|_| generic_error(ProtocolErrorType::Unauthorized))?;
+    if unix_timestamp().abs_diff(timestamp) > config.hmac_max_age_secs {
+        return Err(generic_error(ProtocolErrorType::Unauthorized));
+    }
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(Limited::new(body, config.hmac_max_body_bytes))
+        .await
+        .map_err(|e| {
+            let error_type = match e.is::<LengthLimitError>() {
+                true => ProtocolErrorType::PayloadTooLarge,
+                false => ProtocolErrorType::BadRequest,
+            };
+            ProtocolError::new(error_type, e)
+        })?;
+    if !verify(
+        &config.hmac_secrets,
+        &signature,
+        timestamp,
+        &parts.method,
+        &parts.uri,
+        &body,
+    ) {
+        return Err(generic_error(ProtocolErrorType::Unauthorized));
+    }
+    Ok(HttpRequest::from_parts(parts, body.into()))
+}
@@ -1,38 +1,524 @@
 use std::{
-    marker::PhantomData,
-    net::SocketAddr,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use hyper::{Body, Request as HttpRequest, Response as HttpResponse};
+use async_stream::stream;
+use futures::{
+    future::{ready, Ready},
+    Future, StreamExt,
+};
+use hyper::{
+    body::{to_bytes, Bytes, HttpBody},
+    header::{
+        HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH,
+        ETAG, EXPIRES, IF_NONE_MATCH, LAST_MODIFIED, VARY,
+    },
+    server::conn::AddrStream,
+    Body, Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Version,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, timeout_at},
+};
+use tokio_util::sync::PollSemaphore;
 use tower::{timeout::Timeout, Service};
-use tracing::{debug, info, warn};
+use tracing::{debug, info, info_span, warn, Instrument};
 
 use crate::{
-    error::ProtocolErrorType, ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+    error::ProtocolErrorType,
+    extensions::{with_extensions, Extensions},
+    handshake::{Handshake, HandshakeError, COMPRESSION_FEATURE},
+    metrics, trace,
+    util::{deadline_exceeded_error, intercept_response, should_sample_log, with_deadline},
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
 };
 
 use super::{
-    generic_error, HttpServerConfig, ModalHttpResponse, RequestHttpConvert, ResponseHttpConvert,
-    API_KEY_HEADER,
+    super::{
+        util::{compress_body, ContentEncoding},
+        DEADLINE_HEADER, HANDSHAKE_HEADER,
+    },
+    auth::{check_api_key, check_api_key_scope, check_signature, get_api_key, RequestAuthorizers},
+    generic_error,
+    limit::{ConcurrencyPermit, RequestLimiters},
+    AccessLogField, ClientAddr, HttpServerConfig, Interceptors, ModalHttpResponse, RemoteAddr,
+    RequestContext, RequestHttpConvert, RequestInterceptor, ResponseHttpConvert, SseFlushMode,
 };
 
-fn check_api_key(
-    config: &HttpServerConfig,
-    request: &HttpRequest<Body>,
-) -> Result<(), ProtocolError> {
-    if !config.api_keys.is_empty() {
-        let key_header = request
-            .headers()
-            .get(API_KEY_HEADER)
-            .map(|v| v.to_str().unwrap_or_default())
+#[cfg(feature = "jwt")]
+use super::JwtClaims;
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const FORWARDED_HEADER: &str = "Forwarded";
+const X_FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// Waits for the request (or its body) to be read, until the read deadline elapses.
+/// Returns `None` if the deadline elapsed first. Only the portion of the body that is read
+/// by `future` is covered; a body that is converted into a stream is read afterwards.
+async fn before_read_deadline<F: Future>(
+    future: F,
+    deadline: Option<Instant>,
+) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline.into(), future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Creates the response for a request that was not received before the read deadline.
+/// HTTP/1 connections are closed once the response is sent, since the rest of the
+/// request may still be in transit.
+fn read_timeout_response(version: Version, read_timeout_secs: u64) -> HttpResponse<Body> {
+    warn!("request was not received within {read_timeout_secs}s, closing connection");
+    let mut response: HttpResponse<Body> = ProtocolError::new(
+        ProtocolErrorType::RequestTimeout,
+        format!("request was not received within {read_timeout_secs}s").into(),
+    )
+    .into();
+    if version < Version::HTTP_2 {
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+/// Parses a node from a forwarding header, which may be
+/// a bare IP address, or an address with a port (IPv6 addresses are bracketed).
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.split(']').next())
+        .and_then(|node| node.parse().ok())
+}
+
+/// Resolves the original client address from the `Forwarded` header,
+/// falling back to the `X-Forwarded-For` header.
+/// Returns the right-most forwarded address, since it was appended by the trusted proxy.
+/// Entries to the left of it were provided by the client, and may be spoofed.
+fn get_forwarded_addr(request: &HttpRequest<Body>) -> Option<IpAddr> {
+    let headers = request.headers();
+    let forwarded = headers
+        .get(FORWARDED_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .and_then(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                match key.trim().eq_ignore_ascii_case("for") {
+                    true => parse_forwarded_node(value),
+                    false => None,
+                }
+            })
+        });
+    forwarded.or_else(|| {
+        headers
+            .get(X_FORWARDED_FOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(parse_forwarded_node)
+    })
+}
+
+fn get_or_create_request_id(request: &HttpRequest<Body>) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Returns the remaining time that the client will wait for a response, if provided.
+fn get_deadline(request: &HttpRequest<Body>) -> Option<Duration> {
+    request
+        .headers()
+        .get(DEADLINE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_millis)
+}
+
+async fn compress_response(
+    response: HttpResponse<Body>,
+    encoding: ContentEncoding,
+    min_bytes: usize,
+) -> HttpResponse<Body> {
+    let is_eligible = !response.headers().contains_key(CONTENT_ENCODING)
+        && response
+            .body()
+            .size_hint()
+            .exact()
+            .map(|size| size >= min_bytes as u64)
             .unwrap_or_default();
-        if !config.api_keys.contains(key_header) {
-            return Err(generic_error(ProtocolErrorType::Unauthorized));
+    if !is_eligible {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let compressed = match to_bytes(body).await {
+        Ok(bytes) => compress_body(bytes.as_ref(), encoding),
+        Err(e) => Err(ProtocolError::new(ProtocolErrorType::Internal, Box::new(e))),
+    };
+    match compressed {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static(ACCEPT_ENCODING.as_str()));
+            HttpResponse::from_parts(parts, compressed.into())
         }
+        Err(e) => {
+            warn!("failed to compress http response: {}", e);
+            e.into()
+        }
+    }
+}
+
+/// Creates a body-less "not modified" response, with the entity tag of the unchanged resource.
+fn not_modified_response(etag: Option<HeaderValue>) -> HttpResponse<Body> {
+    let mut response = HttpResponse::new(Body::empty());
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    if let Some(etag) = etag {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+/// Replaces a successful response with a "not modified" response, if its `ETag` header matches
+/// any of the `If-None-Match` values of the request (or if a value is `*`).
+/// Tags are compared using weak comparison. Cache-related headers are preserved.
+fn check_if_none_match(
+    response: HttpResponse<Body>,
+    if_none_match: &[HeaderValue],
+) -> HttpResponse<Body> {
+    let Some(etag) = response
+        .headers()
+        .get(ETAG)
+        .filter(|_| response.status().is_success())
+        .and_then(|etag| etag.to_str().ok())
+    else {
+        return response;
+    };
+    let opaque_tag = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let matches = if_none_match
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque_tag(tag) == opaque_tag(etag));
+    if !matches {
+        return response;
+    }
+    let mut not_modified = not_modified_response(None);
+    for name in [ETAG, CACHE_CONTROL, EXPIRES, LAST_MODIFIED, VARY] {
+        if let Some(value) = response.headers().get(&name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+/// The handshake of the server, along with the header value that it is sent in.
+/// Created once per server.
+pub(super) struct ServerHandshake {
+    local: Handshake,
+    /// `None` if the schema version is not a valid header value
+    /// (see [`HttpServerConfig::validate`]).
+    header: Option<HeaderValue>,
+}
+
+impl ServerHandshake {
+    pub(super) fn new(config: &HttpServerConfig) -> Self {
+        let encodings = match config.enable_compression {
+            true => [ContentEncoding::Gzip, ContentEncoding::Deflate]
+                .map(|encoding| encoding.as_str())
+                .join(","),
+            false => "identity".to_string(),
+        };
+        let local = Handshake::new(config.schema_version.clone())
+            .with_feature(COMPRESSION_FEATURE, encodings);
+        let header = serde_json::to_string(&local)
+            .ok()
+            .and_then(|value| HeaderValue::from_str(&value).ok());
+        if header.is_none() {
+            warn!("schema version is not a valid header value, handshakes will not be included in responses");
+        }
+        Self { local, header }
+    }
+
+    /// Checks the handshake of the client, if it is included in the request. Returns the
+    /// handshake of the client if it is included, in which case the handshake of the server
+    /// should be included in the response. Requests from incompatible clients are rejected
+    /// with a "bad request" error.
+    fn check(&self, request: &HttpRequest<Body>) -> Result<Option<Handshake>, ProtocolError> {
+        let Some(value) = request.headers().get(HANDSHAKE_HEADER) else {
+            return Ok(None);
+        };
+        let peer = value
+            .to_str()
+            .map_err(|e| HandshakeError::Invalid(e.to_string()))
+            .and_then(|value| {
+                serde_json::from_str::<Handshake>(value)
+                    .map_err(|e| HandshakeError::Invalid(e.to_string()))
+            })
+            .and_then(|peer| self.local.negotiate(&peer).map(|_| peer))
+            .inspect_err(|e| warn!("rejecting handshake from client: {e}"))?;
+        Ok(Some(peer))
+    }
+
+    /// Selects the encoding for compressing the response, if the client provides the
+    /// compression feature. Returns `None` if the client does not provide the feature,
+    /// in which case the encoding is selected via the `Accept-Encoding` header.
+    fn negotiate_compression(&self, peer: &Handshake) -> Option<Option<ContentEncoding>> {
+        peer.features.get(COMPRESSION_FEATURE)?;
+        Some(
+            self.local
+                .negotiate_compression(peer)
+                .and_then(ContentEncoding::from_token),
+        )
+    }
+
+    /// Includes the handshake of the server in the response, so that the client can check it.
+    fn insert_header(&self, mut response: HttpResponse<Body>) -> HttpResponse<Body> {
+        if let Some(header) = self.header.as_ref() {
+            response
+                .headers_mut()
+                .insert(HANDSHAKE_HEADER, header.clone());
+        }
+        response
+    }
+}
+
+/// Converts the result of the service into an HTTP response. Errors, and responses that
+/// could not be converted, are converted into error responses.
+fn into_http_response<Request, Response>(
+    result: Result<ServiceResponse<Response>, ServiceError>,
+) -> HttpResponse<Body>
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+{
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return ProtocolError::from(e).into(),
+    };
+    match Response::to_http_response(response) {
+        Ok(Some(ModalHttpResponse::Single(response))) => response,
+        Ok(Some(ModalHttpResponse::NotModified { etag })) => {
+            not_modified_response(etag.and_then(|etag| HeaderValue::try_from(etag).ok()))
+        }
+        Ok(Some(ModalHttpResponse::Event(_) | ModalHttpResponse::SseEvent(_))) => {
+            warn!(
+                "unexpected event response returned from http response conversion, returning 404"
+            );
+            generic_error(ProtocolErrorType::NotFound).into()
+        }
+        Ok(None) => generic_error(ProtocolErrorType::NotFound).into(),
+        Err(e) => e.into(),
+    }
+}
+
+/// Invokes the request interceptor with the captured request context, if an interceptor is set.
+fn intercept_request<Request>(
+    mut request: Request,
+    context: &mut RequestContext,
+    interceptor: Option<&RequestInterceptor<Request>>,
+) -> Result<Request, ProtocolError> {
+    if let Some(interceptor) = interceptor {
+        interceptor(&mut request, context)?;
+    }
+    Ok(request)
+}
+
+/// A connection that has been accepted by the server.
+pub(super) struct AcceptedConnection {
+    pub(super) remote_addr: SocketAddr,
+    /// Released once the connection closes, if the amount of connections is limited.
+    pub(super) _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Holds the concurrency permit until the streaming response body
+/// has been fully consumed or dropped.
+fn hold_permit_for_stream(
+    response: HttpResponse<Body>,
+    permit: ConcurrencyPermit,
+) -> HttpResponse<Body> {
+    let (parts, body) = response.into_parts();
+    let body = Body::wrap_stream(body.map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    }));
+    HttpResponse::from_parts(parts, body)
+}
+
+/// Buffers the chunks of a streaming response that are produced within `window`
+/// of the first buffered chunk, and writes them as a single chunk.
+fn coalesce_stream(response: HttpResponse<Body>, window: Duration) -> HttpResponse<Body> {
+    let (parts, mut body) = response.into_parts();
+    let body = stream! {
+        while let Some(chunk) = body.next().await {
+            let mut buffer = match chunk {
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+                Ok(chunk) => chunk.to_vec(),
+            };
+            let deadline = sleep(window);
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    _ = &mut deadline => break,
+                    chunk = body.next() => match chunk {
+                        Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                        Some(Err(e)) => {
+                            yield Ok(Bytes::from(buffer));
+                            yield Err(e);
+                            return;
+                        }
+                        None => {
+                            yield Ok(Bytes::from(buffer));
+                            return;
+                        }
+                    }
+                }
+            }
+            yield Ok(Bytes::from(buffer));
+        }
+    };
+    HttpResponse::from_parts(parts, Body::wrap_stream(body))
+}
+
+/// The state shared by all connections of a server, created once per server.
+pub(super) struct ServerState<Request, Response> {
+    pub(super) config: Arc<HttpServerConfig>,
+    pub(super) limiters: RequestLimiters,
+    pub(super) authorizers: RequestAuthorizers,
+    pub(super) interceptors: Interceptors<Request, Response>,
+    /// The extensions provided to the server, which are included in the extensions of each request.
+    pub(super) extensions: Extensions,
+    pub(super) handshake: ServerHandshake,
+}
+
+/// Creates a [`HttpServerConnService`] for each accepted connection. If the amount of
+/// connections is limited, a permit is acquired before the server accepts the next
+/// connection, so that excess connections wait in the listen backlog instead of
+/// being accepted and held open.
+pub(super) struct HttpServerMakeService<Request, Response, S>
+where
+    Request: RequestHttpConvert<Request> + Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    state: Arc<ServerState<Request, Response>>,
+    service: Timeout<S>,
+    connection_limiter: Option<PollSemaphore>,
+    /// The permit for the next accepted connection, acquired while polling for readiness.
+    connection_permit: Option<OwnedSemaphorePermit>,
+    /// True while the connection limit is reached, so that it is only logged once.
+    is_at_limit: bool,
+}
+
+impl<Request, Response, S> HttpServerMakeService<Request, Response, S>
+where
+    Request: RequestHttpConvert<Request> + Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    pub(super) fn new(
+        state: Arc<ServerState<Request, Response>>,
+        service: Timeout<S>,
+        connection_limiter: Option<Arc<Semaphore>>,
+    ) -> Self {
+        Self {
+            state,
+            service,
+            connection_limiter: connection_limiter.map(PollSemaphore::new),
+            connection_permit: None,
+            is_at_limit: false,
+        }
+    }
+}
+
+impl<Request, Response, S> Service<&AddrStream> for HttpServerMakeService<Request, Response, S>
+where
+    Request: RequestHttpConvert<Request> + Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    type Response = HttpServerConnService<Request, Response, S>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let Some(connection_limiter) = &mut self.connection_limiter else {
+            return Poll::Ready(Ok(()));
+        };
+        if self.connection_permit.is_none() {
+            match connection_limiter.poll_acquire(cx) {
+                Poll::Pending => {
+                    if !self.is_at_limit {
+                        warn!("connection limit reached, waiting for a connection to close");
+                        self.is_at_limit = true;
+                    }
+                    return Poll::Pending;
+                }
+                Poll::Ready(permit) => {
+                    self.is_at_limit = false;
+                    self.connection_permit =
+                        Some(permit.expect("connection limiter should not be closed"));
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &AddrStream) -> Self::Future {
+        ready(Ok(HttpServerConnService::new(
+            self.state.clone(),
+            self.service.clone(),
+            AcceptedConnection {
+                remote_addr: conn.remote_addr(),
+                // The permit is held until the connection closes
+                _permit: self.connection_permit.take(),
+            },
+        )))
     }
-    Ok(())
 }
 
 pub(super) struct HttpServerConnService<Request, Response, S>
@@ -48,11 +534,9 @@ where
         + Clone
         + 'static,
 {
-    config: Arc<HttpServerConfig>,
+    state: Arc<ServerState<Request, Response>>,
     service: Timeout<S>,
-    remote_addr: SocketAddr,
-    request_phantom: PhantomData<Request>,
-    response_phantom: PhantomData<Response>,
+    connection: AcceptedConnection,
 }
 
 impl<Request, Response, S> HttpServerConnService<Request, Response, S>
@@ -69,16 +553,14 @@ where
         + 'static,
 {
     pub(super) fn new(
-        config: Arc<HttpServerConfig>,
+        state: Arc<ServerState<Request, Response>>,
         service: Timeout<S>,
-        remote_addr: SocketAddr,
+        connection: AcceptedConnection,
     ) -> Self {
         Self {
-            config,
+            state,
             service,
-            remote_addr,
-            request_phantom: Default::default(),
-            response_phantom: Default::default(),
+            connection,
         }
     }
 }
@@ -86,8 +568,8 @@ where
 impl<Request, Response, S> Service<HttpRequest<Body>>
     for HttpServerConnService<Request, Response, S>
 where
-    Request: RequestHttpConvert<Request> + Clone + Send,
-    Response: ResponseHttpConvert<Request, Response> + Send,
+    Request: RequestHttpConvert<Request> + Clone + Send + 'static,
+    Response: ResponseHttpConvert<Request, Response> + Send + 'static,
     S: Service<
             Request,
             Response = ServiceResponse<Response>,
@@ -106,54 +588,227 @@ where
     }
 
     fn call(&mut self, request: HttpRequest<Body>) -> Self::Future {
-        let config = self.config.clone();
+        let state = self.state.clone();
         let mut service = self.service.clone();
-        debug!("received http request from {}", self.remote_addr);
-        let remote_addr = self.remote_addr.clone();
+        debug!("received http request from {}", self.connection.remote_addr);
+        let remote_addr = self.connection.remote_addr;
         Box::pin(async move {
-            if let Err(e) = check_api_key(&config, &request) {
-                return Ok(e.into());
-            }
-
+            let config = &state.config;
+            let handshake = &state.handshake;
+            let start = Instant::now();
+            let request_id = get_or_create_request_id(&request);
+            let method = request.method().clone();
             let uri = request.uri().to_string();
-            let request_result = Request::from_http_request(request).await;
-            let response = match request_result {
-                Ok(request_option) => match request_option {
-                    Some(request) => {
-                        let response = service.call(request).await;
-                        response
-                            .map(|response| {
-                                // Map an Ok service response into an http response
-                                Response::to_http_response(response)
-                                    .map(|r| r.and_then(|r| match r {
-                                        ModalHttpResponse::Single(r) => Some(r),
-                                        ModalHttpResponse::Event(_) => {
-                                            warn!("unexpected event response returned from http response conversion, returning 404");
-                                            None
-                                        }
-                                    }))
-                                    .unwrap_or_else(|e| Some(e.into()))
-                                    .unwrap_or_else(|| {
-                                        generic_error(ProtocolErrorType::NotFound).into()
-                                    })
-                            })
-                            .unwrap_or_else(|e| {
-                                // Map service error into an http response
-                                ProtocolError::from(e).into()
-                            })
+            let client_addr = config
+                .trust_forwarded_headers
+                .then(|| get_forwarded_addr(&request))
+                .flatten()
+                .unwrap_or_else(|| remote_addr.ip());
+            let version = request.version();
+            let read_timeout_secs = config.request_read_timeout_secs.unwrap_or_default();
+            let read_deadline = config
+                .request_read_timeout_secs
+                .map(|secs| start + Duration::from_secs(secs));
+            let mut request = request;
+            request.extensions_mut().insert(ClientAddr(client_addr));
+            request.extensions_mut().insert(RemoteAddr(remote_addr));
+            let mut extensions = state.extensions.clone();
+            extensions.insert(ClientAddr(client_addr));
+            extensions.insert(RemoteAddr(remote_addr));
+            let mut encoding = config
+                .enable_compression
+                .then(|| ContentEncoding::from_accept_encoding(request.headers()))
+                .flatten();
+            // Conditional requests are checked once the response and its entity tag are known
+            let if_none_match = match method {
+                Method::GET | Method::HEAD => request
+                    .headers()
+                    .get_all(IF_NONE_MATCH)
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                _ => Vec::new(),
+            };
+            let request_bytes = config
+                .log_payload_sizes
+                .then(|| request.body().size_hint().exact())
+                .flatten();
+
+            // Set once the request is converted, otherwise the HTTP method is used as the metric label
+            let mut operation_name = None;
+            // Rejected requests are returned from the block, so that all responses
+            // include the request id and are recorded in the metrics and access log
+            let response = async {
+                if let Err(e) = check_api_key(config, &request) {
+                    return e.into();
+                }
+                // Rate limits are applied before the signature and authorizers, so that
+                // excess requests do not consume the resources of an async authorizer
+                if let Err(e) = state
+                    .limiters
+                    .rate_limiter
+                    .check(get_api_key(config, &request))
+                {
+                    return e.into();
+                }
+                // Verifying the signature reads the body
+                let mut request =
+                    match before_read_deadline(check_signature(config, request), read_deadline)
+                        .await
+                    {
+                        None => return read_timeout_response(version, read_timeout_secs),
+                        Some(Ok(request)) => request,
+                        Some(Err(e)) => return e.into(),
+                    };
+                if let Err(e) = state.authorizers.authorize(&mut request).await {
+                    return e.into();
+                }
+
+                let deadline = get_deadline(&request);
+                if deadline == Some(Duration::ZERO) {
+                    return deadline_exceeded_error().into();
+                }
+                let peer_handshake = match handshake.check(&request) {
+                    Ok(peer_handshake) => peer_handshake,
+                    Err(e) => return handshake.insert_header(e.into()),
+                };
+                if let Some(negotiated) = peer_handshake
+                    .as_ref()
+                    .and_then(|peer| handshake.negotiate_compression(peer))
+                {
+                    encoding = negotiated;
+                }
+
+                let span = info_span!(
+                    "http_request",
+                    method = %method,
+                    path = %request.uri().path(),
+                    request_id = %request_id,
+                );
+                trace::set_parent_from_http_headers(&span, request.headers());
+                let api_key = get_api_key(config, &request).map(str::to_string);
+                let path = request.uri().path().to_string();
+                #[cfg(feature = "jwt")]
+                if let Some(claims) = request.extensions().get::<JwtClaims>() {
+                    extensions.insert(claims.clone());
+                }
+                // The context is captured beforehand, since the conversion consumes the request
+                let mut context = RequestContext {
+                    method: method.clone(),
+                    path: path.clone(),
+                    headers: request.headers().clone(),
+                    request_id: request_id.clone(),
+                    client_addr,
+                    extensions,
+                };
+                // Responses to requests with a handshake include the handshake of the server,
+                // so requests rejected past this point are returned from the inner block
+                let response = async {
+                    let Some(request_result) =
+                        before_read_deadline(Request::from_http_request(request), read_deadline)
+                            .await
+                    else {
+                        return read_timeout_response(version, read_timeout_secs);
+                    };
+                    let request = match request_result {
+                        Ok(Some(request)) => request,
+                        // If option is None, we can assume that the request resulted
+                        // in Not Found
+                        Ok(None) => return generic_error(ProtocolErrorType::NotFound).into(),
+                        Err(e) => return e.into(),
+                    };
+                    operation_name = request.operation_name().map(str::to_string);
+                    let accepted = check_api_key_scope(config, api_key.as_deref(), &path)
+                        .and_then(|_| {
+                            intercept_request(
+                                request,
+                                &mut context,
+                                state.interceptors.request.as_ref(),
+                            )
+                        })
+                        .and_then(|request| {
+                            Ok((request, state.limiters.concurrency_limiter.try_acquire()?))
+                        });
+                    let (request, permit) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => return e.into(),
+                    };
+                    let response = with_extensions(context.extensions, || {
+                        span.in_scope(|| service.call(request))
+                    });
+                    let response = with_deadline(response, deadline)
+                        .instrument(span.clone())
+                        .await;
+                    let response =
+                        intercept_response(response, state.interceptors.response.as_ref());
+                    let is_stream = matches!(response, Ok(ServiceResponse::Multiple(_)));
+                    let response = into_http_response::<Request, Response>(response);
+                    if !is_stream {
+                        return response;
                     }
-                    // If option is None, we can assume that the request resulted
-                    // in Not Found
-                    None => generic_error(ProtocolErrorType::NotFound).into(),
-                },
-                Err(e) => e.into(),
+                    // hyper writes each chunk of the stream as soon as it is produced,
+                    // so events are delivered immediately unless coalescing is enabled
+                    let response = match config.sse_flush_mode {
+                        SseFlushMode::Immediate => response,
+                        SseFlushMode::Coalesced { window_ms } => {
+                            coalesce_stream(response, Duration::from_millis(window_ms))
+                        }
+                    };
+                    hold_permit_for_stream(response, permit)
+                }
+                .await;
+                match peer_handshake {
+                    Some(_) => handshake.insert_header(response),
+                    None => response,
+                }
+            }
+            .await;
+            let response = match if_none_match.is_empty() {
+                true => response,
+                false => check_if_none_match(response, &if_none_match),
+            };
+            let response = match encoding {
+                Some(encoding) => {
+                    compress_response(response, encoding, config.compression_min_bytes).await
+                }
+                None => response,
             };
-            info!(
-                uri = uri,
-                status = response.status().to_string(),
-                "handled http request from {}",
-                remote_addr,
+            let response_bytes = config
+                .log_payload_sizes
+                .then(|| response.body().size_hint().exact())
+                .flatten();
+            let mut response = response;
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            let status = response.status();
+            let is_error = status.is_client_error() || status.is_server_error();
+            metrics::record_request(
+                metrics::HTTP_SERVER_TRANSPORT,
+                operation_name.as_deref().unwrap_or(method.as_str()),
+                start,
+                is_error.then(|| status.into()).as_ref(),
             );
+            if (is_error && config.access_log_all_errors)
+                || should_sample_log(config.access_log_sample_rate)
+            {
+                let fields = &config.access_log_fields;
+                let has_field = |field: AccessLogField| fields.contains(&field);
+                info!(
+                    method = has_field(AccessLogField::Method).then(|| method.as_str()),
+                    uri = has_field(AccessLogField::Uri).then_some(uri.as_str()),
+                    status = has_field(AccessLogField::Status).then(|| status.as_u16()),
+                    latency_ms = has_field(AccessLogField::Latency)
+                        .then(|| start.elapsed().as_secs_f64() * 1000.0),
+                    remote_addr = has_field(AccessLogField::RemoteAddr)
+                        .then(|| tracing::field::display(client_addr)),
+                    request_id =
+                        has_field(AccessLogField::RequestId).then_some(request_id.as_str()),
+                    request_bytes,
+                    response_bytes,
+                    "handled http request",
+                );
+            }
             Ok(response)
         })
     }
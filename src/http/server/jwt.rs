@@ -0,0 +1,336 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use hyper::{body::to_bytes, client::HttpConnector, Body, Client, Request as HttpRequest, Uri};
+use hyper_rustls::HttpsConnector;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::timeout,
+};
+use tracing::warn;
+
+use crate::{
+    error::ProtocolErrorType,
+    http::generic_error,
+    util::config::{ensure_non_zero, ConfigError},
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ProtocolError, ServiceError,
+};
+
+const BEARER_PREFIX: &str = "Bearer ";
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// The minimum time between key set fetches, when a token refers to an unknown key.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The claims of a verified JWT. Inserted into the extensions of each
+/// [`HttpRequest`](super::super::HttpRequest) that passes validation, so they can be accessed by
+/// [`AsyncHttpAuthorizer`](super::AsyncHttpAuthorizer) implementations and
+/// [`RequestHttpConvert::from_http_request`](super::super::RequestHttpConvert::from_http_request).
+/// Also included in the request [`Extensions`](crate::extensions::Extensions) that are
+/// available to the service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JwtClaims(pub Map<String, Value>);
+
+/// Configuration for validating JWT bearer tokens.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// The URL of the JSON Web Key Set, containing the keys that tokens are signed with.
+    pub jwks_url: String,
+    /// The expected issuer of tokens (the `iss` claim). Not checked if omitted.
+    pub issuer: Option<String>,
+    /// The expected audience of tokens (the `aud` claim). Not checked if omitted.
+    pub audience: Option<String>,
+    /// Claims that must be present in each token. Tokens without
+    /// one of the claims are rejected with a "forbidden" error.
+    pub required_claims: HashSet<String>,
+    /// Scopes that must be granted by each token, via the space-delimited `scope` claim
+    /// or the `scp` claim. Tokens without one of the scopes are rejected with a "forbidden" error.
+    pub required_scopes: HashSet<String>,
+    /// The time in seconds that fetched keys are cached for. Keys are also fetched
+    /// if a token refers to an unknown key, to account for key rotation.
+    pub jwks_refresh_secs: u64,
+    /// The allowed clock skew in seconds, when checking the expiry and "not before" times.
+    pub leeway_secs: u64,
+}
+
+impl ConfigExampleSnippet for JwtConfig {
+    fn config_example_snippet() -> String {
+        r#"# The URL of the JSON Web Key Set used to verify token signatures.
+# jwks_url = "https://auth.example.com/.well-known/jwks.json"
+
+# The expected issuer of tokens. Not checked if omitted.
+# issuer = "https://auth.example.com/"
+
+# The expected audience of tokens. Not checked if omitted.
+# audience = "my-api"
+
+# Claims that must be present in each token.
+# required_claims = ["sub"]
+
+# Scopes that must be granted by each token.
+# required_scopes = ["greet"]
+
+# The time in seconds that fetched keys are cached for.
+# jwks_refresh_secs = 300
+
+# The allowed clock skew in seconds, when checking token expiry.
+# leeway_secs = 60"#
+            .into()
+    }
+}
+
+impl ConfigDeprecatedKeys for JwtConfig {}
+
+impl ConfigEnvPrefix for JwtConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_JWT"
+    }
+}
+
+impl JwtConfig {
+    /// Checks that the key set URL and refresh interval are usable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        parse_jwks_url(&self.jwks_url)?;
+        ensure_non_zero("jwks_refresh_secs", self.jwks_refresh_secs)
+    }
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            jwks_url: String::new(),
+            issuer: None,
+            audience: None,
+            required_claims: HashSet::new(),
+            required_scopes: HashSet::new(),
+            jwks_refresh_secs: 300,
+            leeway_secs: 60,
+        }
+    }
+}
+
+fn parse_jwks_url(url: &str) -> Result<Uri, ConfigError> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|e| ConfigError::new("jwks_url", e.to_string()))?;
+    match uri.scheme_str() {
+        Some("http" | "https") => Ok(uri),
+        _ => Err(ConfigError::new(
+            "jwks_url",
+            "url must use the http or https scheme",
+        )),
+    }
+}
+
+struct CachedKeys {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+impl CachedKeys {
+    /// Finds the key with the given id. Tokens without a key id
+    /// may only be used if the set contains a single key.
+    fn find(&self, kid: Option<&str>) -> Option<&Jwk> {
+        match kid {
+            Some(kid) => self.keys.find(kid),
+            None => match self.keys.keys.as_slice() {
+                [key] => Some(key),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Returns the bearer token from the `Authorization` header of the request.
+pub(super) fn get_bearer_token(request: &HttpRequest<Body>) -> Option<&str> {
+    request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix(BEARER_PREFIX))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// Returns the scopes granted via the `scope` and `scp` claims.
+fn granted_scopes(claims: &Map<String, Value>) -> HashSet<&str> {
+    let mut scopes = HashSet::new();
+    for key in ["scope", "scp"] {
+        match claims.get(key) {
+            Some(Value::String(value)) => scopes.extend(value.split_whitespace()),
+            Some(Value::Array(values)) => scopes.extend(values.iter().filter_map(Value::as_str)),
+            _ => (),
+        }
+    }
+    scopes
+}
+
+/// Validates JWT bearer tokens, using the keys from a JSON Web Key Set.
+/// The key set is fetched when the first token is validated, and cached for
+/// [`JwtConfig::jwks_refresh_secs`]. Can be provided to the server via
+/// [`HttpServer::with_jwt_validator`](super::HttpServer::with_jwt_validator).
+pub struct JwtValidator {
+    config: JwtConfig,
+    jwks_url: Uri,
+    client: Client<HttpsConnector<HttpConnector>>,
+    cache: RwLock<Option<CachedKeys>>,
+    /// Held while fetching the key set, so that only one fetch is in flight. The cache
+    /// is not locked during the fetch, so tokens signed with cached keys are not delayed.
+    fetch_lock: Mutex<()>,
+}
+
+impl JwtValidator {
+    /// Creates a new validator. A [`ConfigError`] will be returned if the
+    /// configuration is invalid (i.e. the key set URL lacks a scheme).
+    pub fn new(config: JwtConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let jwks_url = parse_jwks_url(&config.jwks_url)?;
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            config,
+            jwks_url,
+            client: Client::builder().build(https),
+            cache: RwLock::new(None),
+            fetch_lock: Mutex::new(()),
+        })
+    }
+
+    async fn fetch_keys(&self) -> Result<JwkSet, ServiceError> {
+        let response = timeout(JWKS_FETCH_TIMEOUT, self.client.get(self.jwks_url.clone()))
+            .await
+            .map_err(|_| "timed out while fetching key set")??;
+        if !response.status().is_success() {
+            return Err(format!("unexpected status {} for key set", response.status()).into());
+        }
+        let body = to_bytes(response.into_body()).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Returns the decoding key for the key id. The key set is fetched if the cached
+    /// keys are stale, or if the key is unknown and the keys were not fetched recently.
+    /// If the key set cannot be fetched, previously fetched keys are retained.
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, ProtocolError> {
+        let refresh_interval = Duration::from_secs(self.config.jwks_refresh_secs);
+        let unknown_key_error = || {
+            ProtocolError::new(
+                ProtocolErrorType::Unauthorized,
+                "token was signed with an unknown key".into(),
+            )
+        };
+        let to_decoding_key = |jwk: &Jwk| {
+            DecodingKey::from_jwk(jwk)
+                .map_err(|e| ProtocolError::new(ProtocolErrorType::Unauthorized, Box::new(e)))
+        };
+        let needs_fetch = |cached: &CachedKeys| match cached.find(kid) {
+            Some(_) => cached.fetched_at.elapsed() >= refresh_interval,
+            None => cached.fetched_at.elapsed() >= JWKS_MIN_REFRESH_INTERVAL,
+        };
+        if let Some(cached) = self.cache.read().await.as_ref() {
+            if !needs_fetch(cached) {
+                return cached
+                    .find(kid)
+                    .map(to_decoding_key)
+                    .unwrap_or_else(|| Err(unknown_key_error()));
+            }
+        }
+
+        let _fetch_guard = self.fetch_lock.lock().await;
+        // The keys may have been fetched by another request while waiting for the lock
+        if self
+            .cache
+            .read()
+            .await
+            .as_ref()
+            .map(needs_fetch)
+            .unwrap_or(true)
+        {
+            let result = self.fetch_keys().await;
+            let mut cache = self.cache.write().await;
+            match result {
+                Ok(keys) => {
+                    *cache = Some(CachedKeys {
+                        keys,
+                        fetched_at: Instant::now(),
+                    })
+                }
+                Err(e) => {
+                    warn!("failed to fetch key set from {}: {}", self.jwks_url, e);
+                    match cache.as_mut() {
+                        Some(cached) => cached.fetched_at = Instant::now(),
+                        None => return Err(generic_error(ProtocolErrorType::ServiceUnavailable)),
+                    }
+                }
+            }
+        }
+        self.cache
+            .read()
+            .await
+            .as_ref()
+            .and_then(|cached| cached.find(kid))
+            .map(to_decoding_key)
+            .unwrap_or_else(|| Err(unknown_key_error()))
+    }
+
+    /// Verifies the signature, expiry, issuer and audience of the token, and checks that
+    /// the required claims and scopes are present. Invalid tokens are rejected with an
+    /// "unauthorized" error, and valid tokens that lack a required claim or scope are
+    /// rejected with a "forbidden" error.
+    pub async fn validate(&self, token: &str) -> Result<JwtClaims, ProtocolError> {
+        let unauthorized = |e| ProtocolError::new(ProtocolErrorType::Unauthorized, Box::new(e));
+        let header = decode_header(token).map_err(unauthorized)?;
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        // The algorithm must match the family of the key, so that tokens
+        // cannot be signed with a public key
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway_secs;
+        if let Some(issuer) = self.config.issuer.as_ref() {
+            validation.set_issuer(&[issuer]);
+        }
+        match self.config.audience.as_ref() {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(unauthorized)?
+            .claims;
+
+        if let Some(claim) = self
+            .config
+            .required_claims
+            .iter()
+            .find(|claim| !claims.contains_key(claim.as_str()))
+        {
+            return Err(ProtocolError::new(
+                ProtocolErrorType::Forbidden,
+                format!("token is missing required claim `{claim}`").into(),
+            ));
+        }
+        let scopes = granted_scopes(&claims);
+        if let Some(scope) = self
+            .config
+            .required_scopes
+            .iter()
+            .find(|scope| !scopes.contains(scope.as_str()))
+        {
+            return Err(ProtocolError::new(
+                ProtocolErrorType::Forbidden,
+                format!("token is missing required scope `{scope}`").into(),
+            ));
+        }
+        Ok(JwtClaims(claims))
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{error::ProtocolErrorType, http::generic_error, ProtocolError};
+
+use super::HttpServerConfig;
+
+/// The request limiters shared by all connections of the server.
+#[derive(Clone)]
+pub(super) struct RequestLimiters {
+    pub(super) rate_limiter: Arc<ApiKeyRateLimiter>,
+    pub(super) concurrency_limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl RequestLimiters {
+    pub(super) fn new(config: &HttpServerConfig) -> Self {
+        Self {
+            rate_limiter: Arc::new(ApiKeyRateLimiter::new(config.api_key_rate_limits.clone())),
+            concurrency_limiter: Arc::new(ConcurrencyLimiter::new(config.max_concurrent_requests)),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Enforces per-API-key request budgets using token buckets.
+/// Each key may perform up to its configured amount of requests per second,
+/// with bursts of up to one second's worth of requests.
+pub(super) struct ApiKeyRateLimiter {
+    limits: HashMap<String, f64>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl ApiKeyRateLimiter {
+    pub(super) fn new(limits: HashMap<String, f64>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Consumes a token for the key, if the key has a configured budget.
+    /// Returns a "too many requests" error if the budget for the key has
+    /// been exhausted. Unknown and unlimited keys are always allowed.
+    pub(super) fn check(&self, api_key: Option<&str>) -> Result<(), ProtocolError> {
+        let Some((key, requests_per_sec)) = api_key.and_then(|key| self.limits.get_key_value(key))
+        else {
+            return Ok(());
+        };
+        let capacity = requests_per_sec.max(1.0);
+        let now = Instant::now();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("rate limit buckets lock poisoned");
+        let bucket = buckets.entry(key.clone()).or_insert(TokenBucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed: Duration = now - bucket.last_refill;
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * requests_per_sec).min(capacity);
+        bucket.last_refill = now;
+        if bucket.tokens < 1.0 {
+            return Err(generic_error(ProtocolErrorType::TooManyRequests));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Limits the amount of requests that may be processed by the service
+/// at the same time.
+pub(super) struct ConcurrencyLimiter {
+    max_concurrent_requests: Option<usize>,
+    active_requests: AtomicUsize,
+}
+
+/// Represents a request slot acquired from the [`ConcurrencyLimiter`].
+/// The slot is released when the permit is dropped.
+pub(super) struct ConcurrencyPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl ConcurrencyLimiter {
+    pub(super) fn new(max_concurrent_requests: Option<usize>) -> Self {
+        Self {
+            max_concurrent_requests,
+            active_requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquires a request slot. Returns a "service unavailable" error if
+    /// the maximum amount of concurrent requests has been reached.
+    pub(super) fn try_acquire(self: &Arc<Self>) -> Result<ConcurrencyPermit, ProtocolError> {
+        let max = self.max_concurrent_requests.unwrap_or(usize::MAX);
+        self.active_requests
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < max).then_some(active + 1)
+            })
+            .map_err(|_| generic_error(ProtocolErrorType::ServiceUnavailable))?;
+        Ok(ConcurrencyPermit {
+            limiter: self.clone(),
+        })
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.limiter.active_requests.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -1,29 +1,174 @@
+mod auth;
 mod conn;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod limit;
+
+pub use auth::{AsyncHttpAuthorizer, HttpAuthorizer};
+#[cfg(feature = "jwt")]
+pub use jwt::{JwtClaims, JwtConfig, JwtValidator};
 
 use std::{
-    collections::HashSet, convert::Infallible, marker::PhantomData, net::SocketAddr, sync::Arc,
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 
 use hyper::{
-    server::conn::AddrStream, service::make_service_fn, Body, Response as HttpResponse, Server,
+    header::{HeaderMap, HeaderValue, ALLOW},
+    server::conn::AddrIncoming,
+    Body, Method, Response as HttpResponse, Server,
 };
 use serde::{Deserialize, Serialize};
-use tower::{timeout::Timeout, Service};
+use tokio::{net::TcpListener, sync::Semaphore};
+use tower::{timeout::Timeout, Layer, Service};
 use tracing::info;
 
 use crate::{
-    http::{server::conn::HttpServerConnService, API_KEY_HEADER},
-    ConfigExampleSnippet, ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
-    DEFAULT_TIMEOUT_SECS,
+    extensions::Extensions,
+    http::{
+        server::{
+            auth::{RequestAuthorizers, SyncAuthorizer},
+            conn::{HttpServerMakeService, ServerHandshake, ServerState},
+            limit::RequestLimiters,
+        },
+        API_KEY_HEADER,
+    },
+    util::{
+        config::{
+            ensure_header_name, ensure_header_value, ensure_non_zero, ensure_sample_rate,
+            ConfigError,
+        },
+        BoxedFutureService, ResponseInterceptor,
+    },
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ProtocolError, ServiceError,
+    ServiceFuture, ServiceResponse, DEFAULT_TIMEOUT_SECS,
 };
 
-use super::util::serialize_to_http_response;
+use super::util::{serialize_to_http_response, DEFAULT_MAX_MESSAGE_BYTES};
 
 use super::{
-    generic_error, ModalHttpResponse, ProtocolHttpError, RequestHttpConvert, ResponseHttpConvert,
+    generic_error, MethodNotAllowedError, ModalHttpResponse, ProtocolHttpError, RequestHttpConvert,
+    ResponseHttpConvert,
 };
 
+/// The resolved address of the client that made the request.
+/// Inserted into the extensions of each [`HttpRequest`](super::HttpRequest), so it can be accessed by
+/// [`HttpAuthorizer`] implementations and [`RequestHttpConvert::from_http_request`].
+/// If [`HttpServerConfig::trust_forwarded_headers`] is enabled, the address is
+/// resolved from the forwarding headers set by the proxy. Also included in the request
+/// [`Extensions`] that are available to the service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientAddr(pub IpAddr);
+
+/// The socket address of the peer of the connection that the request was received on.
+/// Unlike [`ClientAddr`], forwarding headers are never considered, so this is the address of
+/// the proxy if the server is behind a reverse proxy. Inserted into the extensions of
+/// each [`HttpRequest`](super::HttpRequest), alongside [`ClientAddr`].
+/// Since the server does not terminate TLS, client certificate details are not available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Details of the HTTP request that a request was converted from. Provided to the
+/// request interceptor set via [`HttpServer::with_request_interceptor`], since the HTTP request
+/// is consumed by [`RequestHttpConvert::from_http_request`].
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    /// The HTTP method of the request.
+    pub method: Method,
+    /// The path of the request URI.
+    pub path: String,
+    /// The headers of the request.
+    pub headers: HeaderMap,
+    /// The correlation id of the request.
+    pub request_id: String,
+    /// The resolved address of the client. See [`ClientAddr`].
+    pub client_addr: IpAddr,
+    /// The extensions that will be available to the service while handling the request.
+    /// Includes [`ClientAddr`], [`RemoteAddr`], the verified `JwtClaims` if a JWT validator is
+    /// set, and the values provided via [`HttpServer::with_extension`]. Interceptors may insert
+    /// additional values, i.e. the identity of the caller.
+    pub extensions: Extensions,
+}
+
+/// Invoked for each converted request before dispatch. See [`HttpServer::with_request_interceptor`].
+type RequestInterceptor<Request> =
+    Arc<dyn Fn(&mut Request, &mut RequestContext) -> Result<(), ProtocolError> + Send + Sync>;
+
+/// The interceptors invoked before and after a request is handled by the service.
+struct Interceptors<Request, Response> {
+    request: Option<RequestInterceptor<Request>>,
+    response: Option<ResponseInterceptor<Response>>,
+}
+
+impl<Request, Response> Clone for Interceptors<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            request: self.request.clone(),
+            response: self.response.clone(),
+        }
+    }
+}
+
+impl<Request, Response> Default for Interceptors<Request, Response> {
+    fn default() -> Self {
+        Self {
+            request: None,
+            response: None,
+        }
+    }
+}
+
+/// A field that may be included in the access log line emitted
+/// for each handled request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogField {
+    /// The HTTP method of the request.
+    Method,
+    /// The URI of the request.
+    Uri,
+    /// The status code of the response.
+    Status,
+    /// The time taken to handle the request, in milliseconds.
+    Latency,
+    /// The address of the remote client.
+    RemoteAddr,
+    /// The correlation id of the request. The value of the `X-Request-Id` header
+    /// is used if provided by the client, otherwise a new id is generated.
+    RequestId,
+}
+
+impl AccessLogField {
+    /// Returns all access log fields.
+    pub fn all() -> HashSet<Self> {
+        HashSet::from([
+            Self::Method,
+            Self::Uri,
+            Self::Status,
+            Self::Latency,
+            Self::RemoteAddr,
+            Self::RequestId,
+        ])
+    }
+}
+
+/// Determines when the events of a streaming (server-sent events) response are
+/// written to the connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SseFlushMode {
+    /// Each event is written to the connection as soon as it is produced.
+    #[default]
+    Immediate,
+    /// Events produced within `window_ms` milliseconds of the first buffered event are
+    /// coalesced and written together. Reduces write overhead for chatty streams,
+    /// at the cost of added latency.
+    Coalesced { window_ms: u64 },
+}
+
 /// Configuration for the HTTP server.
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -32,9 +177,88 @@ pub struct HttpServerConfig {
     pub port: u16,
     /// An optional set of API keys for restricting access to the server.
     /// If omitted, an API key is not needed to make a request.
+    /// Keys are compared in constant time, to avoid leaking timing information.
     pub api_keys: HashSet<String>,
+    /// The name of the header that contains the API key. Defaults to `X-API-Key`.
+    pub api_key_header: String,
+    /// An optional map of API keys to request-per-second budgets.
+    /// Requests past the budget of a key will be rejected with
+    /// a "too many requests" error. Keys without a budget are not limited.
+    pub api_key_rate_limits: HashMap<String, f64>,
+    /// An optional map of API keys to the path prefixes they may access.
+    /// Requests for other paths will be rejected with a "forbidden" error,
+    /// once the request type has been identified. Keys without scopes may access
+    /// all paths. Scoped keys must also be included in `api_keys`.
+    pub api_key_scopes: HashMap<String, HashSet<String>>,
+    /// An optional set of secrets for verifying HMAC-SHA256 request signatures.
+    /// If provided, each request must include a signature produced with one of the secrets
+    /// in the `X-Signature` header, along with the signing time in the `X-Timestamp` header.
+    /// The signature check is performed in addition to the API key check.
+    pub hmac_secrets: HashSet<String>,
+    /// The maximum difference in seconds between the signing time of a request and
+    /// the current time. Requests outside of this window are rejected, to prevent replay.
+    pub hmac_max_age_secs: u64,
+    /// The maximum size in bytes of the body of a signed request. The body is buffered
+    /// to verify the signature, so larger requests are rejected with a "payload too large"
+    /// error. Only used if `hmac_secrets` is provided.
+    pub hmac_max_body_bytes: usize,
     /// Timeout for service requests in seconds.
     pub service_timeout_secs: u64,
+    /// If set, the maximum time in seconds for receiving the headers and body of a request.
+    /// Requests that are not received in time are rejected with a "request timeout" error, and
+    /// the connection is closed. Protects against clients that send requests very slowly
+    /// to exhaust server resources. Unbounded if omitted.
+    ///
+    /// The timeout only covers what is read before the request is dispatched to the service:
+    /// bodies that [`RequestHttpConvert::from_http_request`] passes on as a stream are not
+    /// covered once the request has been converted. For HTTP/2 connections, the time spent
+    /// receiving the headers is not covered either, since the header timeout of the
+    /// underlying server only applies to HTTP/1.
+    pub request_read_timeout_secs: Option<u64>,
+    /// If enabled, request and response body sizes will be included
+    /// in the log line for each handled request.
+    pub log_payload_sizes: bool,
+    /// If enabled, single responses will be compressed using gzip or deflate
+    /// if the client supports it, as indicated by the `Accept-Encoding` header.
+    /// Streaming responses are not compressed.
+    pub enable_compression: bool,
+    /// The minimum body size in bytes for a response to be compressed.
+    pub compression_min_bytes: usize,
+    /// The fields to include in the access log line for each request.
+    /// All fields are included by default.
+    pub access_log_fields: HashSet<AccessLogField>,
+    /// The fraction of handled requests to include in the access log,
+    /// between `0.0` and `1.0`. Can be used to reduce log volume at high throughput.
+    pub access_log_sample_rate: f64,
+    /// If enabled, requests that result in an error response are always
+    /// included in the access log, regardless of the sample rate.
+    pub access_log_all_errors: bool,
+    /// The maximum amount of requests that may be processed by the service
+    /// at the same time, across all connections. Requests past the limit will
+    /// be rejected with a "service unavailable" error. Streaming responses count
+    /// towards the limit until the stream completes. Unbounded if omitted.
+    pub max_concurrent_requests: Option<usize>,
+    /// The maximum amount of connections that may be open at the same time. Connections
+    /// past the limit are not accepted (i.e. they wait in the listen backlog) until another
+    /// connection closes. Unbounded if omitted.
+    pub max_connections: Option<usize>,
+    /// If enabled, the client address will be resolved from the `Forwarded` or
+    /// `X-Forwarded-For` headers, instead of the address of the connection.
+    /// The right-most entry is used, since it is appended by the proxy itself.
+    /// Should only be enabled when the server is behind a trusted reverse proxy,
+    /// since clients may spoof these headers.
+    pub trust_forwarded_headers: bool,
+    /// If enabled, HTTP/2 connections will be accepted in addition to HTTP/1.1.
+    /// Since the server does not terminate TLS, HTTP/2 clients must use
+    /// prior knowledge (h2c). Enabled by default; the protocol is detected per connection,
+    /// so HTTP/1.1 clients are unaffected.
+    pub http2: bool,
+    /// Determines when the events of streaming responses are written to the connection.
+    pub sse_flush_mode: SseFlushMode,
+    /// An application-defined version of the request and response schemas. If provided,
+    /// requests with a handshake from a client with a different schema version are rejected
+    /// with a "bad request" error. See [`Handshake`](crate::handshake::Handshake).
+    pub schema_version: Option<String>,
 }
 
 impl ConfigExampleSnippet for HttpServerConfig {
@@ -46,29 +270,168 @@ impl ConfigExampleSnippet for HttpServerConfig {
 # needed to make a request.
 # api_keys = ["key1", "key2", "key3"]
 
+# The name of the header that contains the API key.
+# api_key_header = "X-API-Key"
+
+# Optional requests-per-second budgets for API keys. Keys without a budget are not limited.
+# [api_key_rate_limits]
+# key1 = 10.0
+
+# Optional path prefixes that API keys are allowed to access. Keys without scopes
+# may access all paths.
+# [api_key_scopes]
+# key2 = ["/greet", "/admin/status"]
+
+# Secrets for verifying HMAC request signatures (optional). If provided, all requests
+# must be signed by the client with one of the secrets.
+# hmac_secrets = ["secret1"]
+
+# The maximum age in seconds of a signed request.
+# hmac_max_age_secs = 300
+
+# The maximum size in bytes of the body of a signed request. Defaults to 16 MiB.
+# hmac_max_body_bytes = 16777216
+
 # The timeout duration in seconds for the underlying backend service.
-# service_timeout_secs = 60"#
+# service_timeout_secs = 60
+
+# The maximum time in seconds for receiving the headers and body of a request.
+# Unbounded if omitted.
+# request_read_timeout_secs = 30
+
+# Include request and response body sizes in request logs.
+# log_payload_sizes = false
+
+# Compress responses if the client supports it.
+# enable_compression = false
+
+# The minimum response body size in bytes for compression to be applied.
+# compression_min_bytes = 1024
+
+# The fields to include in request logs. All fields are included by default.
+# access_log_fields = ["method", "uri", "status", "latency", "remote_addr", "request_id"]
+
+# The fraction of requests to include in request logs, between 0.0 and 1.0.
+# access_log_sample_rate = 1.0
+
+# Always include requests that result in an error in request logs, regardless of the sample rate.
+# access_log_all_errors = true
+
+# The maximum amount of requests processed at the same time. Unbounded if omitted.
+# max_concurrent_requests = 100
+
+# The maximum amount of connections open at the same time. Unbounded if omitted.
+# max_connections = 1000
+
+# Resolve the client address from the Forwarded/X-Forwarded-For headers.
+# Only enable if the server is behind a trusted reverse proxy.
+# trust_forwarded_headers = false
+
+# Accept cleartext HTTP/2 (h2c) connections with prior knowledge, in addition to HTTP/1.1.
+# http2 = true
+
+# When events of streaming responses are written to the connection. Events are written
+# immediately by default. Bursts of events may be coalesced within a time window instead.
+# sse_flush_mode = "immediate"
+# sse_flush_mode = { coalesced = { window_ms = 10 } }
+
+# The version of the request and response schemas. Requests with a handshake
+# from a client with a different schema version are rejected.
+# schema_version = "1""#
             .into()
     }
 }
 
+impl ConfigDeprecatedKeys for HttpServerConfig {}
+
+impl ConfigEnvPrefix for HttpServerConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_HTTP_SERVER"
+    }
+}
+
+impl HttpServerConfig {
+    /// Checks that the API key header name, rate limits, timeouts, connection limit,
+    /// sample rate and schema version are usable. A port of zero is allowed,
+    /// in which case the server listens on a port assigned by the OS.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ensure_header_name("api_key_header", &self.api_key_header)?;
+        if self
+            .api_key_rate_limits
+            .values()
+            .any(|requests_per_sec| !(requests_per_sec.is_finite() && *requests_per_sec > 0.0))
+        {
+            return Err(ConfigError::new(
+                "api_key_rate_limits",
+                "requests per second must be greater than zero",
+            ));
+        }
+        if let Some(schema_version) = self.schema_version.as_ref() {
+            ensure_header_value("schema_version", schema_version)?;
+        }
+        ensure_non_zero("service_timeout_secs", self.service_timeout_secs)?;
+        if let Some(request_read_timeout_secs) = self.request_read_timeout_secs {
+            ensure_non_zero("request_read_timeout_secs", request_read_timeout_secs)?;
+        }
+        if let Some(max_connections) = self.max_connections {
+            ensure_non_zero("max_connections", max_connections as u64)?;
+        }
+        if !self.hmac_secrets.is_empty() {
+            ensure_non_zero("hmac_max_age_secs", self.hmac_max_age_secs)?;
+            ensure_non_zero("hmac_max_body_bytes", self.hmac_max_body_bytes as u64)?;
+        }
+        ensure_sample_rate("access_log_sample_rate", self.access_log_sample_rate)
+    }
+}
+
 impl Default for HttpServerConfig {
     fn default() -> Self {
         Self {
             port: 8080,
             api_keys: HashSet::new(),
+            api_key_header: API_KEY_HEADER.to_string(),
+            api_key_rate_limits: HashMap::new(),
+            api_key_scopes: HashMap::new(),
+            hmac_secrets: HashSet::new(),
+            hmac_max_age_secs: 300,
+            hmac_max_body_bytes: DEFAULT_MAX_MESSAGE_BYTES,
             service_timeout_secs: DEFAULT_TIMEOUT_SECS,
+            request_read_timeout_secs: None,
+            log_payload_sizes: false,
+            enable_compression: false,
+            compression_min_bytes: 1024,
+            access_log_fields: AccessLogField::all(),
+            access_log_sample_rate: 1.0,
+            access_log_all_errors: true,
+            max_concurrent_requests: None,
+            max_connections: None,
+            trust_forwarded_headers: false,
+            http2: true,
+            sse_flush_mode: SseFlushMode::Immediate,
+            schema_version: None,
         }
     }
 }
 
 impl Into<HttpResponse<Body>> for ProtocolError {
     fn into(self) -> HttpResponse<Body> {
+        // The allowed methods are provided by validate_methods
+        let allow = self
+            .inner_error()
+            .downcast_ref::<MethodNotAllowedError>()
+            .and_then(|error| HeaderValue::from_str(&error.allow_header_value()).ok());
         let payload = ProtocolHttpError {
             error: self.error.to_string(),
+            error_type: Some(self.error_type.clone()),
+            code: self.code().map(str::to_string),
+            data: self.data().cloned(),
         };
-        serialize_to_http_response(&payload, self.error_type.into())
-            .expect("should serialize error into http response")
+        let mut response = serialize_to_http_response(&payload, self.error_type.into())
+            .expect("should serialize error into http response");
+        if let Some(allow) = allow {
+            response.headers_mut().insert(ALLOW, allow);
+        }
+        response
     }
 }
 
@@ -88,6 +451,12 @@ where
 {
     config: Arc<HttpServerConfig>,
     service: Timeout<S>,
+    limiters: RequestLimiters,
+    /// Holds a permit for each open connection, if the amount of connections is limited.
+    connection_limiter: Option<Arc<Semaphore>>,
+    authorizers: RequestAuthorizers,
+    interceptors: Interceptors<Request, Response>,
+    extensions: Extensions,
     request_phantom: PhantomData<Request>,
     response_phantom: PhantomData<Response>,
 }
@@ -109,30 +478,169 @@ where
     /// converted and forwarded to the `service`.
     pub fn new(service: S, config: HttpServerConfig) -> Self {
         let service = Timeout::new(service, Duration::from_secs(config.service_timeout_secs));
+        let limiters = RequestLimiters::new(&config);
+        let connection_limiter = config
+            .max_connections
+            .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
         Self {
             config: Arc::new(config),
             service,
+            limiters,
+            connection_limiter,
+            authorizers: RequestAuthorizers::default(),
+            interceptors: Interceptors::default(),
+            extensions: Extensions::new(),
             request_phantom: Default::default(),
             response_phantom: Default::default(),
         }
     }
 
+    /// Creates a new server for HTTP communication, after checking the configuration
+    /// via [`HttpServerConfig::validate`]. A [`ConfigError`] will be returned
+    /// if the configuration is invalid.
+    pub fn try_new(service: S, config: HttpServerConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::new(service, config))
+    }
+
+    /// Wraps the backend service with a `tower` [`Layer`], such as a rate or
+    /// concurrency limit. Layers are applied beneath the service timeout, and may be
+    /// stacked by calling this method multiple times. The outermost layer is the one
+    /// provided last.
+    pub fn with_layer<L>(
+        self,
+        layer: L,
+    ) -> HttpServer<Request, Response, BoxedFutureService<L::Service>>
+    where
+        L: Layer<S>,
+        L::Service: Service<Request, Response = ServiceResponse<Response>> + Send + Clone + 'static,
+        <L::Service as Service<Request>>::Error: Into<ServiceError>,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        let service = BoxedFutureService::new(layer.layer(self.service.into_inner()));
+        HttpServer {
+            service: Timeout::new(
+                service,
+                Duration::from_secs(self.config.service_timeout_secs),
+            ),
+            config: self.config,
+            limiters: self.limiters,
+            connection_limiter: self.connection_limiter,
+            authorizers: self.authorizers,
+            interceptors: self.interceptors,
+            extensions: self.extensions,
+            request_phantom: Default::default(),
+            response_phantom: Default::default(),
+        }
+    }
+
+    /// Sets an authorizer that will be invoked for each request after the API key check.
+    /// The authorizer can reject requests with an "unauthorized" or "forbidden" error.
+    /// Replaces any authorizer that was set previously.
+    pub fn with_authorizer<A: HttpAuthorizer + 'static>(mut self, authorizer: A) -> Self {
+        self.authorizers.authorizer = Some(Arc::new(SyncAuthorizer(authorizer)));
+        self
+    }
+
+    /// Sets an asynchronous authorizer that will be invoked for each request after the API
+    /// key check, i.e. for validating tokens against an external service. Requests that
+    /// exceed the rate limit of their API key are rejected before the authorizer is invoked.
+    /// Replaces any authorizer that was set previously.
+    pub fn with_async_authorizer<A: AsyncHttpAuthorizer + 'static>(
+        mut self,
+        authorizer: A,
+    ) -> Self {
+        self.authorizers.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Sets a validator for JWT bearer tokens, which will be invoked for each request
+    /// after the API key check, and before the authorizer. Requests without a valid token
+    /// are rejected. The verified claims are inserted into the request extensions as
+    /// [`JwtClaims`].
+    #[cfg(feature = "jwt")]
+    pub fn with_jwt_validator(mut self, jwt_validator: JwtValidator) -> Self {
+        self.authorizers.jwt_validator = Some(Arc::new(jwt_validator));
+        self
+    }
+
+    /// Sets an interceptor that will be invoked for each request after it has been converted
+    /// from the HTTP request, and before it is dispatched to the service. The interceptor may
+    /// modify the request (i.e. to inject a tenant id from the [`RequestContext`]), insert
+    /// request extensions, or reject the request by returning an error.
+    /// Replaces any interceptor that was set previously.
+    pub fn with_request_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut Request, &mut RequestContext) -> Result<(), ProtocolError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.interceptors.request = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Sets an interceptor that will be invoked for each service response before it is
+    /// converted into an HTTP response, i.e. for redacting fields or recording audit logs.
+    /// For streaming responses, the interceptor is invoked for each item of the stream,
+    /// as a single response. Errors returned by the interceptor replace the response (or the
+    /// stream item). Replaces any interceptor that was set previously.
+    pub fn with_response_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut ServiceResponse<Response>) -> Result<(), ProtocolError> + Send + Sync + 'static,
+    {
+        self.interceptors.response = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Inserts a value into the extensions of each request, i.e. shared state such as a
+    /// database pool. The value can be read by the service via
+    /// [`request_extension`](crate::extensions::request_extension).
+    pub fn with_extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
     /// Listens & processes requests from remote clients, until a [`hyper::Error`]
-    /// is encountered.
+    /// is encountered (i.e. if the port cannot be bound). Errors that occur while
+    /// accepting a connection (i.e. if the process has run out of file descriptors)
+    /// are logged, and connections are accepted again after a short delay.
+    /// Errors on individual connections do not stop the server.
     pub async fn run(self) -> Result<(), hyper::Error> {
-        let config_cl = self.config.clone();
-        let service_cl = self.service.clone();
-        let make_service = make_service_fn(move |conn: &AddrStream| {
-            let config = config_cl.clone();
-            let service = service_cl.clone();
-            let remote_addr = conn.remote_addr();
-            async move { Ok::<_, Infallible>(HttpServerConnService::new(config, service, remote_addr)) }
-        });
         let addr = SocketAddr::from(([0, 0, 0, 0], self.config.port));
+        let incoming = AddrIncoming::bind(&addr)?;
+        self.serve(incoming).await
+    }
+
+    /// Processes requests from remote clients that connect to `listener`, instead of binding
+    /// to the configured port. Useful for listening on a specific address, or on a port
+    /// assigned by the OS.
+    pub async fn run_with_listener(self, listener: TcpListener) -> Result<(), hyper::Error> {
+        let incoming = AddrIncoming::from_listener(listener)?;
+        self.serve(incoming).await
+    }
 
-        let server = Server::try_bind(&addr)?;
+    async fn serve(self, mut incoming: AddrIncoming) -> Result<(), hyper::Error> {
+        let state = ServerState {
+            handshake: ServerHandshake::new(&self.config),
+            config: self.config.clone(),
+            limiters: self.limiters,
+            authorizers: self.authorizers,
+            interceptors: self.interceptors,
+            extensions: self.extensions,
+        };
+        let make_service =
+            HttpServerMakeService::new(Arc::new(state), self.service, self.connection_limiter);
+        let local_addr = incoming.local_addr();
+        // Accept errors are logged and retried after a delay, instead of stopping the server
+        incoming.set_sleep_on_errors(true);
+        let mut server = Server::builder(incoming).http1_only(!self.config.http2);
+        if let Some(request_read_timeout_secs) = self.config.request_read_timeout_secs {
+            server =
+                server.http1_header_read_timeout(Duration::from_secs(request_read_timeout_secs));
+        }
 
-        info!("listening to http requests on port {}", self.config.port);
+        info!("listening to http requests on {}", local_addr);
 
         server.serve(make_service).await
     }
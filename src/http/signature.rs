@@ -0,0 +1,67 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::{body::Bytes, Method, Uri};
+use ring::hmac;
+
+pub(crate) const SIGNATURE_HEADER: &str = "X-Signature";
+pub(crate) const TIMESTAMP_HEADER: &str = "X-Timestamp";
+
+/// Returns the current unix timestamp in seconds.
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Builds the message covered by the signature, which consists of the timestamp,
+/// method, path (including the query) and body, separated by newlines.
+fn signed_message(timestamp: u64, method: &Method, uri: &Uri, body: &Bytes) -> Vec<u8> {
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let mut message = format!("{timestamp}\n{method}\n{path}\n").into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Produces a hex-encoded HMAC-SHA256 signature for a request.
+#[cfg(feature = "http-client")]
+pub(crate) fn sign(
+    secret: &str,
+    timestamp: u64,
+    method: &Method,
+    uri: &Uri,
+    body: &Bytes,
+) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, &signed_message(timestamp, method, uri, body));
+    tag.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(feature = "http-server")]
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Returns true if the hex-encoded signature was produced by one of the secrets.
+/// Signatures are compared in constant time.
+#[cfg(feature = "http-server")]
+pub(crate) fn verify<'a>(
+    secrets: impl IntoIterator<Item = &'a String>,
+    signature: &str,
+    timestamp: u64,
+    method: &Method,
+    uri: &Uri,
+    body: &Bytes,
+) -> bool {
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    let message = signed_message(timestamp, method, uri, body);
+    secrets.into_iter().any(|secret| {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        hmac::verify(&key, &message, &signature).is_ok()
+    })
+}
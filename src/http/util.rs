@@ -1,43 +1,220 @@
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
 
 use async_stream::stream;
-use futures::StreamExt;
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use futures::{stream::BoxStream, Stream, StreamExt};
 use hyper::{
-    body::to_bytes, header::CONTENT_TYPE, Body, Method, Request as HttpRequest,
-    Response as HttpResponse, StatusCode, Uri,
+    body::{to_bytes, Bytes},
+    header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG},
+    Body, HeaderMap, Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Uri,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use ring::digest;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
     error::ProtocolErrorType,
+    format::{SerializationFormat, JSON_CONTENT_TYPE},
     http::{
-        generic_error, HttpNotificationPayload, ModalHttpResponse, ResponseHttpConvert,
-        SSE_DATA_PREFIX,
+        generic_error,
+        query::{from_query, to_query_pairs},
+        HttpNotificationPayload, MethodNotAllowedError, ModalHttpResponse, ResponseHttpConvert,
+        SseEvent,
     },
     NotificationStream, ProtocolError, ServiceError, ServiceResponse,
 };
 
+const SSE_DATA_FIELD: &str = "data";
+const SSE_EVENT_FIELD: &str = "event";
+const SSE_ID_FIELD: &str = "id";
+/// The name of the final event sent by the server once a stream has completed.
+const SSE_END_EVENT: &str = "multilink-end";
+/// The name of the final event sent by the server if a stream has failed,
+/// which contains the error that ended the stream.
+const SSE_ERROR_END_EVENT: &str = "multilink-error";
+/// Present on streaming responses that will be terminated with an end event, so
+/// the client can detect truncated streams. Responses from older servers lack the header.
+const SSE_END_EVENT_HEADER: &str = "X-Sse-End-Event";
+
+/// Present on NDJSON streaming responses that will be terminated with an end record.
+const NDJSON_END_RECORD_HEADER: &str = "X-Ndjson-End-Record";
+
+/// The content type of request bodies created by [`serialize_stream_to_http_request`].
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+/// The default maximum size of a single line in a streamed body, in bytes.
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// A content encoding used for compressing HTTP bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Returns the header value for the content encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Parses a single encoding token (i.e. of the `Accept-Encoding` header).
+    /// Returns `None` if the encoding is unsupported.
+    pub(crate) fn from_token(token: &str) -> Option<Self> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Selects a supported encoding from the `Accept-Encoding` header, if present.
+    /// Gzip is preferred over deflate. Encodings with a quality value of zero are ignored.
+    pub fn from_accept_encoding(headers: &HeaderMap) -> Option<Self> {
+        let accepted = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let encoding = Self::from_token(parts.next()?)?;
+                let disabled = parts.any(|param| {
+                    param
+                        .trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map(|q| q == 0.0)
+                        .unwrap_or_default()
+                });
+                (!disabled).then_some(encoding)
+            })
+            .collect::<Vec<_>>();
+        [ContentEncoding::Gzip, ContentEncoding::Deflate]
+            .into_iter()
+            .find(|encoding| accepted.contains(encoding))
+    }
+
+    /// Parses the `Content-Encoding` header. Returns `None` if the header is missing
+    /// or set to `identity`, and a "bad request" error if the encoding is unsupported.
+    pub fn from_content_encoding(headers: &HeaderMap) -> Result<Option<Self>, ProtocolError> {
+        let value = match headers.get(CONTENT_ENCODING) {
+            None => return Ok(None),
+            Some(value) => value.to_str().unwrap_or_default().trim(),
+        };
+        if value.is_empty() || value.eq_ignore_ascii_case("identity") {
+            return Ok(None);
+        }
+        Self::from_token(value).map(Some).ok_or_else(|| {
+            ProtocolError::new(
+                ProtocolErrorType::BadRequest,
+                format!("unsupported content encoding: {value}").into(),
+            )
+        })
+    }
+}
+
+/// Compresses `bytes` using the given encoding. Returns an "internal" error
+/// if compression fails.
+pub fn compress_body(bytes: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>, ProtocolError> {
+    let result = match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).and_then(|_| encoder.finish())
+        }
+        ContentEncoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).and_then(|_| encoder.finish())
+        }
+    };
+    result.map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))
+}
+
+/// Decompresses `bytes` using the given encoding, or returns the bytes as-is if
+/// no encoding is provided. Returns a "bad request" error if decompression fails.
+pub fn decompress_body(
+    bytes: Bytes,
+    encoding: Option<ContentEncoding>,
+) -> Result<Bytes, ProtocolError> {
+    let mut decompressed = Vec::new();
+    let result = match encoding {
+        None => return Ok(bytes),
+        Some(ContentEncoding::Gzip) => {
+            GzDecoder::new(bytes.as_ref()).read_to_end(&mut decompressed)
+        }
+        Some(ContentEncoding::Deflate) => {
+            ZlibDecoder::new(bytes.as_ref()).read_to_end(&mut decompressed)
+        }
+    };
+    result
+        .map(|_| decompressed.into())
+        .map_err(|e| ProtocolError::new(ProtocolErrorType::BadRequest, Box::new(e)))
+}
+
+/// Returns the serialization format of a body, as indicated by the `Content-Type` header.
+/// JSON is assumed if the header is missing. Returns an "unsupported media type" error if
+/// the content type is unsupported.
+pub fn body_format(headers: &HeaderMap) -> Result<SerializationFormat, ProtocolError> {
+    match headers.get(CONTENT_TYPE) {
+        None => Ok(SerializationFormat::Json),
+        Some(value) => SerializationFormat::from_content_type(value.to_str().unwrap_or_default())
+            .map_err(Into::into),
+    }
+}
+
 /// Deserializes the body of [`HttpResponse<Body>`] into `T`.
-/// Returns a "bad request" error if JSON deserialization fails,
-/// and returns an "internal" error if raw data retrieval from the request fails.
+/// The body will be decompressed if the `Content-Encoding` header is set, and
+/// will be deserialized using the format indicated by the `Content-Type` header.
+/// Returns a "bad request" error if deserialization fails, an "unsupported media type" error
+/// if the content type is unsupported, and an "internal" error if raw data retrieval
+/// from the request fails.
 /// Can be useful for implementing [`ResponseHttpConvert::from_http_response`].
 pub async fn parse_response<T: DeserializeOwned>(
     response: HttpResponse<Body>,
 ) -> Result<T, ProtocolError> {
+    let encoding = ContentEncoding::from_content_encoding(response.headers())?;
+    let format = body_format(response.headers())?;
     let bytes = to_bytes(response)
         .await
         .map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))?;
-    parse_response_payload(bytes.as_ref())
+    let bytes = decompress_body(bytes, encoding)?;
+    format.deserialize(bytes.as_ref()).map_err(Into::into)
 }
 
-fn parse_response_payload<T: DeserializeOwned>(response: &[u8]) -> Result<T, ProtocolError> {
-    serde_json::from_slice(response)
-        .map_err(|e| ProtocolError::new(ProtocolErrorType::BadRequest, Box::new(e)))
+/// Joins the base URL with the request path. Any path prefix of the base URL
+/// (i.e. `/api/v1` in `https://example.com/api/v1`) is prepended to the request path.
+/// Returns an "internal" error if the base URL lacks a scheme or authority, or if the path is invalid.
+fn build_request_url(base_url: &Uri, path: &str) -> Result<Uri, ProtocolError> {
+    let (Some(scheme), Some(authority)) = (base_url.scheme(), base_url.authority()) else {
+        return Err(ProtocolError::new(
+            ProtocolErrorType::Internal,
+            format!("base url `{base_url}` should contain a scheme and authority").into(),
+        ));
+    };
+    Uri::builder()
+        .scheme(scheme.clone())
+        .authority(authority.clone())
+        .path_and_query(format!(
+            "{}/{}",
+            base_url.path().trim_end_matches('/'),
+            path.trim_start_matches('/')
+        ))
+        .build()
+        .map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))
 }
 
 /// Serializes `T` into [`HttpRequest<Body>`]. Returns an "internal" error if
-/// JSON serialization fails. Can be useful for
+/// JSON serialization fails, or if the request URL cannot be built from the base URL and path.
+/// Can be useful for
 /// implementing [`RequestHttpConvert::to_http_request`](crate::http::RequestHttpConvert::to_http_request).
 pub fn serialize_to_http_request<T: Serialize>(
     base_url: &Uri,
@@ -45,35 +222,213 @@ pub fn serialize_to_http_request<T: Serialize>(
     method: Method,
     request: &T,
 ) -> Result<HttpRequest<Body>, ProtocolError> {
-    let bytes = serde_json::to_vec(request)
+    serialize_to_http_request_with_format(
+        base_url,
+        path,
+        method,
+        request,
+        SerializationFormat::Json,
+    )
+}
+
+/// Serializes `T` into [`HttpRequest<Body>`], and appends the percent-encoded
+/// `query` pairs to the request path. Returns an "internal" error if JSON serialization
+/// fails, or if the request URL cannot be built. Can be useful for
+/// implementing [`RequestHttpConvert::to_http_request`](crate::http::RequestHttpConvert::to_http_request).
+pub fn serialize_to_http_request_with_query<T: Serialize>(
+    base_url: &Uri,
+    path: &str,
+    method: Method,
+    request: &T,
+    query: &[(&str, &str)],
+) -> Result<HttpRequest<Body>, ProtocolError> {
+    serialize_to_http_request(base_url, &append_query(path, query), method, request)
+}
+
+/// Creates a body-less [`HttpRequest<Body>`], with the fields of `T` encoded in the
+/// query string. Strings are used as-is, other scalar values are formatted as JSON, and
+/// nested arrays/objects are encoded as JSON strings. Useful for `GET` requests, since
+/// intermediaries may drop their bodies. Returns an "internal" error if `T` is not a struct
+/// or map, or if the request URL cannot be built. The server can deserialize the request
+/// with [`parse_request_query`].
+pub fn serialize_to_http_request_query<T: Serialize>(
+    base_url: &Uri,
+    path: &str,
+    method: Method,
+    request: &T,
+) -> Result<HttpRequest<Body>, ProtocolError> {
+    let query = to_query_pairs(request)
         .map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))?;
-    let url = Uri::builder()
-        .scheme(
-            base_url
-                .scheme()
-                .expect("base url should contain scheme")
-                .clone(),
-        )
-        .authority(
-            base_url
-                .authority()
-                .expect("base url should contain authority")
-                .clone(),
-        )
-        .path_and_query(path)
-        .build()
-        .expect("should be able to build url");
+    let query = query
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
     Ok(HttpRequest::builder()
         .method(method)
-        .uri(url)
-        .header(CONTENT_TYPE, "application/json")
+        .uri(build_request_url(base_url, &append_query(path, &query))?)
+        .body(Body::empty())
+        .expect("should be able to create http request"))
+}
+
+/// Appends percent-encoded query pairs to a path, which may already contain a query.
+fn append_query(path: &str, query: &[(&str, &str)]) -> String {
+    if query.is_empty() {
+        return path.to_string();
+    }
+    let encoded_query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(query)
+        .finish();
+    let separator = match path.contains('?') {
+        true => '&',
+        false => '?',
+    };
+    format!("{path}{separator}{encoded_query}")
+}
+
+/// Serializes `T` into [`HttpRequest<Body>`] using the given format, and sets
+/// the `Content-Type` header accordingly. Returns an "internal" error if
+/// serialization fails, or if the request URL cannot be built. Can be useful for
+/// implementing [`RequestHttpConvert::to_http_request`](crate::http::RequestHttpConvert::to_http_request).
+pub fn serialize_to_http_request_with_format<T: Serialize>(
+    base_url: &Uri,
+    path: &str,
+    method: Method,
+    request: &T,
+    format: SerializationFormat,
+) -> Result<HttpRequest<Body>, ProtocolError> {
+    let bytes = format
+        .serialize(request)
+        .map_err(Into::<ProtocolError>::into)?;
+    Ok(HttpRequest::builder()
+        .method(method)
+        .uri(build_request_url(base_url, path)?)
+        .header(CONTENT_TYPE, format.content_type())
         .body(bytes.into())
         .expect("should be able to create http request"))
 }
 
+/// Serializes a stream of `T` into a chunked [`HttpRequest<Body>`], with each item
+/// encoded as a line of JSON (`application/x-ndjson`). Items are serialized as they are
+/// sent, so the full payload is never buffered in memory. Can be useful for implementing
+/// [`RequestHttpConvert::to_http_request`](crate::http::RequestHttpConvert::to_http_request)
+/// for large uploads. The server can consume the body with [`parse_stream_request`].
+/// Returns an "internal" error if the request URL cannot be built.
+pub fn serialize_stream_to_http_request<T, S>(
+    base_url: &Uri,
+    path: &str,
+    method: Method,
+    request_stream: S,
+) -> Result<HttpRequest<Body>, ProtocolError>
+where
+    T: Serialize,
+    S: Stream<Item = T> + Send + 'static,
+{
+    let payload_stream = request_stream.map(|item| {
+        let mut bytes = serde_json::to_vec(&item)?;
+        bytes.push(b'\n');
+        Ok::<Vec<u8>, serde_json::Error>(bytes)
+    });
+    Ok(HttpRequest::builder()
+        .method(method)
+        .uri(build_request_url(base_url, path)?)
+        .header(CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+        .body(Body::wrap_stream(payload_stream))
+        .expect("should be able to create http request"))
+}
+
+/// Accumulates the fields of a server-sent event, until a blank line
+/// indicates that the event should be dispatched.
+#[derive(Default)]
+struct SseEventFields {
+    data: Option<String>,
+    name: Option<String>,
+    id: Option<String>,
+}
+
+impl SseEventFields {
+    fn handle_line(&mut self, line: &str) {
+        // Lines starting with a colon are comments
+        if line.starts_with(':') {
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            None => (line, ""),
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+        };
+        match field {
+            SSE_DATA_FIELD => match self.data.as_mut() {
+                None => self.data = Some(value.to_string()),
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+            },
+            SSE_EVENT_FIELD => self.name = Some(value.to_string()),
+            SSE_ID_FIELD => self.id = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    /// Returns true if the accumulated event marks the completion of the stream.
+    fn is_end(&self) -> bool {
+        self.name.as_deref() == Some(SSE_END_EVENT)
+    }
+
+    /// Returns the error that ended the stream, if the accumulated event marks the failure
+    /// of the stream.
+    fn take_end_error(&mut self) -> Option<ProtocolError> {
+        if self.name.as_deref() != Some(SSE_ERROR_END_EVENT) {
+            return None;
+        }
+        let data = std::mem::take(self).data.unwrap_or_default();
+        Some(
+            match serde_json::from_str::<HttpNotificationPayload>(&data) {
+                Ok(payload) => Into::<Result<Value, ProtocolError>>::into(payload).err(),
+                Err(e) => Some(ProtocolError::new(ProtocolErrorType::Internal, Box::new(e))),
+            }
+            .unwrap_or_else(|| generic_error(ProtocolErrorType::Internal)),
+        )
+    }
+
+    /// Returns the payload and metadata of the accumulated event, if data was received.
+    fn take(&mut self) -> Option<Result<ModalHttpResponse, ProtocolError>> {
+        let fields = std::mem::take(self);
+        let payload = serde_json::from_str::<HttpNotificationPayload>(&fields.data?).ok()?;
+        let result: Result<Value, ProtocolError> = payload.into();
+        Some(result.map(|value| match (fields.name, fields.id) {
+            (None, None) => ModalHttpResponse::Event(value),
+            (name, id) => ModalHttpResponse::SseEvent(SseEvent { name, id, value }),
+        }))
+    }
+}
+
+async fn convert_stream_event<Request, Response>(
+    event: Result<ModalHttpResponse, ProtocolError>,
+    original_request: &Request,
+) -> Result<Response, ProtocolError>
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+{
+    Response::from_http_response(event?, original_request)
+        .await
+        .and_then(|response| response.ok_or_else(|| generic_error(ProtocolErrorType::NotFound)))
+        .and_then(|response| match response {
+            ServiceResponse::Single(response) => Ok(response),
+            _ => Err(generic_error(ProtocolErrorType::NotFound)),
+        })
+}
+
 /// Converts an [`HttpResponse<Body>`] to a [`NotificationStream<Response>`] so
 /// server-side events can be consumed by the HTTP client. Can be useful for implementing
-/// [`ResponseHttpConvert::from_http_response`].
+/// [`ResponseHttpConvert::from_http_response`]. Events with an `event:` or `id:` field are
+/// passed to the converter as [`ModalHttpResponse::SseEvent`], all other events are passed
+/// as [`ModalHttpResponse::Event`]. Comment lines are ignored.
+///
+/// If the response was produced by [`notification_sse_response`], the stream will yield
+/// an error if the body ends before the server has signalled completion (i.e. if the server
+/// or connection failed mid-stream), instead of ending silently. If the server signals that
+/// the stream failed, the error that ended the stream is yielded as the final item.
 pub fn notification_sse_stream<Request, Response>(
     original_request: Request,
     http_response: HttpResponse<Body>,
@@ -82,9 +437,11 @@ where
     Request: Clone + Send + Sync + 'static,
     Response: ResponseHttpConvert<Request, Response> + Send + Sync + 'static,
 {
+    let expects_end = http_response.headers().contains_key(SSE_END_EVENT_HEADER);
     let mut body = http_response.into_body();
     stream! {
         let mut buffer = VecDeque::new();
+        let mut fields = SseEventFields::default();
         while let Some(bytes_result) = body.next().await {
             match bytes_result {
                 Err(e) => {
@@ -98,60 +455,267 @@ where
             }
             while let Some(linebreak_pos) = buffer.iter().position(|b| b == &b'\n') {
                 let line_bytes = buffer.drain(0..(linebreak_pos + 1)).collect::<Vec<_>>();
-                if let Ok(line) = std::str::from_utf8(&line_bytes) {
-                    if !line.starts_with(SSE_DATA_PREFIX) {
-                        continue;
-                    }
-                    if let Ok(payload) = serde_json::from_str::<HttpNotificationPayload>(&line[SSE_DATA_PREFIX.len()..]) {
-                        let result: Result<Value, ProtocolError> = payload.into();
-                        match result {
-                            Err(e) => yield Err(e),
-                            Ok(value) => {
-                                yield Response::from_http_response(ModalHttpResponse::Event(value), &original_request).await
-                                    .and_then(|response| response.ok_or_else(|| generic_error(ProtocolErrorType::NotFound)))
-                                    .and_then(|response| match response {
-                                        ServiceResponse::Single(response) => Ok(response),
-                                        _ => Err(generic_error(ProtocolErrorType::NotFound))
-                                    });
-                            }
-                        }
-                    }
+                let Ok(line) = std::str::from_utf8(&line_bytes) else {
+                    continue;
+                };
+                let line = line.trim_end_matches(['\n', '\r']);
+                if !line.is_empty() {
+                    fields.handle_line(line);
+                    continue;
+                }
+                if fields.is_end() {
+                    return;
+                }
+                if let Some(error) = fields.take_end_error() {
+                    yield Err(error);
+                    return;
+                }
+                if let Some(event) = fields.take() {
+                    yield convert_stream_event(event, &original_request).await;
                 }
             }
         }
-    }.boxed()
+        // Dispatch the last event, in case the stream ended without a blank line
+        if let Ok(line) = std::str::from_utf8(buffer.make_contiguous()) {
+            let line = line.trim_end_matches('\r');
+            if !line.is_empty() {
+                fields.handle_line(line);
+            }
+        }
+        if fields.is_end() {
+            return;
+        }
+        if let Some(error) = fields.take_end_error() {
+            yield Err(error);
+            return;
+        }
+        if let Some(event) = fields.take() {
+            yield convert_stream_event(event, &original_request).await;
+        }
+        if expects_end {
+            yield Err(ProtocolError::new(
+                ProtocolErrorType::Internal,
+                "event stream ended before completion".into(),
+            ));
+        }
+    }
+    .boxed()
 }
 
-/// Deserializes the body of [`HttpRequest<Body>`] into `T`.
-/// Returns a "bad request" error if JSON deserialization fails,
-/// and returns an "internal" error if raw data retrieval from the request fails.
+/// Converts an [`HttpResponse<Body>`] produced by [`notification_ndjson_response`] to a
+/// [`NotificationStream<Response>`], as an alternative to [`notification_sse_stream`].
+/// Each line of the body is passed to the converter as a [`ModalHttpResponse::Event`].
+/// Lines that cannot be deserialized will yield a "bad request" error. Can be useful for
+/// implementing [`ResponseHttpConvert::from_http_response`]. If the body ends without
+/// the end record, the stream yields an "internal" error, so that truncated streams are not
+/// mistaken for completed ones. If the stream failed, the error that ended the stream is
+/// yielded as the final item.
+pub fn notification_ndjson_stream<Request, Response>(
+    original_request: Request,
+    http_response: HttpResponse<Body>,
+) -> NotificationStream<Response>
+where
+    Request: Clone + Send + Sync + 'static,
+    Response: ResponseHttpConvert<Request, Response> + Send + Sync + 'static,
+{
+    let expects_end = http_response
+        .headers()
+        .contains_key(NDJSON_END_RECORD_HEADER);
+    let mut records =
+        parse_ndjson_body::<NdjsonRecord>(http_response.into_body(), DEFAULT_MAX_MESSAGE_BYTES);
+    stream! {
+        while let Some(record) = records.next().await {
+            let (payload, end) = match record {
+                Ok(NdjsonRecord { payload, end }) => (Ok(payload), end),
+                Err(e) => (Err(e), false),
+            };
+            if end {
+                if let Some(error) = payload.ok().and_then(|payload| payload.error) {
+                    yield Err(error.into());
+                }
+                return;
+            }
+            let event = payload
+                .and_then(Into::<Result<Value, ProtocolError>>::into)
+                .map(ModalHttpResponse::Event);
+            yield convert_stream_event(event, &original_request).await;
+        }
+        if expects_end {
+            yield Err(ProtocolError::new(
+                ProtocolErrorType::Internal,
+                "event stream ended before completion".into(),
+            ));
+        }
+    }
+    .boxed()
+}
+
+/// Deserializes the body of [`HttpRequest<Body>`] into `T`, using the format
+/// indicated by the `Content-Type` header (JSON is assumed if the header is missing).
+/// Returns a "bad request" error if deserialization fails, an "unsupported media type" error
+/// if the content type is unsupported, and an "internal" error if raw data retrieval
+/// from the request fails.
 /// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
 pub async fn parse_request<T: DeserializeOwned>(
     request: HttpRequest<Body>,
 ) -> Result<T, ProtocolError> {
+    let format = body_format(request.headers())?;
     let bytes = to_bytes(request)
         .await
         .map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))?;
-    serde_json::from_slice(bytes.as_ref())
+    format.deserialize(bytes.as_ref()).map_err(Into::into)
+}
+
+/// Deserializes the query string of [`HttpRequest<Body>`] into `T`. Values are parsed
+/// according to the field types of `T`, as encoded by [`serialize_to_http_request_query`].
+/// Returns a "bad request" error if deserialization fails.
+/// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
+pub fn parse_request_query<T: DeserializeOwned>(
+    request: &HttpRequest<Body>,
+) -> Result<T, ProtocolError> {
+    from_query(request.uri().query().unwrap_or_default())
+        .map_err(|e| ProtocolError::new(ProtocolErrorType::BadRequest, Box::new(e)))
+}
+
+/// Converts the body of an [`HttpRequest<Body>`] produced by [`serialize_stream_to_http_request`]
+/// into a stream of `T`, deserializing each line of JSON as it is received.
+/// The stream yields a "bad request" error if JSON deserialization fails,
+/// and an "internal" error if raw data retrieval from the request fails.
+/// Lines are limited to [`DEFAULT_MAX_MESSAGE_BYTES`]; see [`parse_stream_request_with_limit`].
+/// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
+pub fn parse_stream_request<T: DeserializeOwned + Send + 'static>(
+    request: HttpRequest<Body>,
+) -> BoxStream<'static, Result<T, ProtocolError>> {
+    parse_stream_request_with_limit(request, DEFAULT_MAX_MESSAGE_BYTES)
+}
+
+/// Same as [`parse_stream_request`], but with a custom maximum line size. If a line exceeds
+/// `max_message_bytes`, the stream yields a "payload too large" error and ends, instead
+/// of buffering the remainder of the line.
+pub fn parse_stream_request_with_limit<T: DeserializeOwned + Send + 'static>(
+    request: HttpRequest<Body>,
+    max_message_bytes: usize,
+) -> BoxStream<'static, Result<T, ProtocolError>> {
+    parse_ndjson_body(request.into_body(), max_message_bytes)
+}
+
+/// Deserializes each line of JSON in a body as it is received. Blank lines are ignored.
+fn parse_ndjson_body<T: DeserializeOwned + Send + 'static>(
+    mut body: Body,
+    max_message_bytes: usize,
+) -> BoxStream<'static, Result<T, ProtocolError>> {
+    stream! {
+        let mut buffer = Vec::new();
+        // Bytes at the start of the buffer that are known to not contain a newline
+        let mut searched = 0;
+        loop {
+            let bytes = match body.next().await {
+                None => break,
+                Some(Err(e)) => {
+                    yield Err(ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)));
+                    return;
+                }
+                Some(Ok(bytes)) => bytes,
+            };
+            buffer.extend_from_slice(&bytes);
+            let mut line_start = 0;
+            while let Some(linebreak_pos) = buffer[searched..].iter().position(|b| b == &b'\n') {
+                let line_end = searched + linebreak_pos + 1;
+                let line_bytes = &buffer[line_start..line_end];
+                line_start = line_end;
+                searched = line_end;
+                if line_bytes.len() > max_message_bytes {
+                    yield Err(line_too_large_error(max_message_bytes));
+                    return;
+                }
+                if line_bytes.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
+                yield parse_stream_request_item(line_bytes);
+            }
+            buffer.drain(..line_start);
+            searched = buffer.len();
+            if buffer.len() > max_message_bytes {
+                yield Err(line_too_large_error(max_message_bytes));
+                return;
+            }
+        }
+        // The last item may not be terminated with a newline
+        if buffer.iter().any(|b| !b.is_ascii_whitespace()) {
+            yield parse_stream_request_item(&buffer);
+        }
+    }
+    .boxed()
+}
+
+fn line_too_large_error(max_message_bytes: usize) -> ProtocolError {
+    ProtocolError::new(
+        ProtocolErrorType::PayloadTooLarge,
+        format!("stream line exceeds the maximum size of {max_message_bytes} bytes").into(),
+    )
+}
+
+fn parse_stream_request_item<T: DeserializeOwned>(line: &[u8]) -> Result<T, ProtocolError> {
+    serde_json::from_slice(line)
         .map_err(|e| ProtocolError::new(ProtocolErrorType::BadRequest, Box::new(e)))
 }
 
 /// Compares the request method with an expected method and returns
-/// [`ProtocolErrorType::HttpMethodNotAllowed`] if there is a mismatch.
+/// [`ProtocolErrorType::HttpMethodNotAllowed`] if there is a mismatch. The expected method
+/// is included in the `Allow` header of the resulting response.
 /// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
 pub fn validate_method(
     request: &HttpRequest<Body>,
     expected_method: Method,
 ) -> Result<(), ProtocolError> {
-    match request.method() == &expected_method {
+    validate_methods(request, &[expected_method])
+}
+
+/// Checks that the request method is one of the expected methods, and returns
+/// [`ProtocolErrorType::HttpMethodNotAllowed`] otherwise. Useful for paths that accept
+/// multiple methods, i.e. both `PUT` and `PATCH` for updating a resource. The expected methods
+/// are provided via [`MethodNotAllowedError`], and are included in the `Allow` header of the
+/// resulting response.
+/// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
+pub fn validate_methods(
+    request: &HttpRequest<Body>,
+    expected_methods: &[Method],
+) -> Result<(), ProtocolError> {
+    match expected_methods.contains(request.method()) {
         true => Ok(()),
-        false => Err(generic_error(ProtocolErrorType::HttpMethodNotAllowed).into()),
+        false => Err(ProtocolError::new(
+            ProtocolErrorType::HttpMethodNotAllowed,
+            Box::new(MethodNotAllowedError {
+                allowed_methods: expected_methods.to_vec(),
+            }),
+        )),
     }
 }
 
-fn serialize_response<T: Serialize>(response: &T) -> Result<Vec<u8>, ProtocolError> {
-    serde_json::to_vec(response)
-        .map_err(|e| ProtocolError::new(ProtocolErrorType::Internal, Box::new(e)))
+/// Compares the media type of the `Content-Type` header (ignoring any parameters, i.e. `charset`)
+/// with an expected media type, and returns [`ProtocolErrorType::UnsupportedMediaType`] if there
+/// is a mismatch or the header is missing.
+/// Can be useful for implementing [`RequestHttpConvert::from_http_request`](crate::http::RequestHttpConvert::from_http_request).
+pub fn validate_content_type(
+    request: &HttpRequest<Body>,
+    expected_content_type: &str,
+) -> Result<(), ProtocolError> {
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    match media_type.eq_ignore_ascii_case(expected_content_type) {
+        true => Ok(()),
+        false => Err(ProtocolError::new(
+            ProtocolErrorType::UnsupportedMediaType,
+            format!(
+                "unsupported content type: `{content_type}`, expected `{expected_content_type}`"
+            )
+            .into(),
+        )),
+    }
 }
 
 /// Serializes `T` into [`HttpResponse<Body>`]. Returns an "internal" error if
@@ -161,14 +725,254 @@ pub fn serialize_to_http_response<T: Serialize>(
     response: &T,
     status: StatusCode,
 ) -> Result<HttpResponse<Body>, ProtocolError> {
-    let bytes = serialize_response(response)?;
+    serialize_to_http_response_with_format(response, status, SerializationFormat::Json)
+}
+
+/// Serializes `T` into [`HttpResponse<Body>`], with the body sent in chunks of at most
+/// `max_chunk_bytes` bytes instead of a single oversized frame. The client receives the same
+/// body, so the response can be parsed with [`parse_response`] as usual. Returns an "internal"
+/// error if JSON serialization fails. Can be useful for implementing
+/// [`ResponseHttpConvert::to_http_response`] for large responses.
+pub fn serialize_to_chunked_http_response<T: Serialize>(
+    response: &T,
+    status: StatusCode,
+    max_chunk_bytes: usize,
+) -> Result<HttpResponse<Body>, ProtocolError> {
+    let bytes = Bytes::from(
+        SerializationFormat::Json
+            .serialize(response)
+            .map_err(Into::<ProtocolError>::into)?,
+    );
+    let max_chunk_bytes = max_chunk_bytes.max(1);
+    let chunks = (0..bytes.len())
+        .step_by(max_chunk_bytes)
+        .map(|start| {
+            let end = (start + max_chunk_bytes).min(bytes.len());
+            Ok::<Bytes, std::convert::Infallible>(bytes.slice(start..end))
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::builder()
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .status(status)
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))
+        .expect("should be able to create http response"))
+}
+
+/// Serializes `T` into [`HttpResponse<Body>`] using the given format, and sets
+/// the `Content-Type` header accordingly. Returns an "internal" error if
+/// serialization fails. Can be useful for
+/// implementing [`ResponseHttpConvert::to_http_response`].
+pub fn serialize_to_http_response_with_format<T: Serialize>(
+    response: &T,
+    status: StatusCode,
+    format: SerializationFormat,
+) -> Result<HttpResponse<Body>, ProtocolError> {
+    let bytes = format
+        .serialize(response)
+        .map_err(Into::<ProtocolError>::into)?;
+    Ok(HttpResponse::builder()
+        .header(CONTENT_TYPE, format.content_type())
+        .status(status)
+        .body(bytes.into())
+        .expect("should be able to create http response"))
+}
+
+/// Serializes `T` into [`HttpResponse<Body>`], and inserts the provided headers
+/// (i.e. `Location` or `Cache-Control`). The headers replace any default headers with the same
+/// name, including `Content-Type`. Returns an "internal" error if JSON serialization fails.
+/// Can be useful for implementing [`ResponseHttpConvert::to_http_response`].
+pub fn serialize_to_http_response_with_headers<T: Serialize>(
+    response: &T,
+    status: StatusCode,
+    headers: HeaderMap,
+) -> Result<HttpResponse<Body>, ProtocolError> {
+    let mut response = serialize_to_http_response(response, status)?;
+    response.headers_mut().extend(headers);
+    Ok(response)
+}
+
+/// Computes a weak entity tag (i.e. `W/"3f2a..."`) from a serialized body, for use in the
+/// `ETag` header. The tag is weak, since the server may compress the body.
+pub fn etag_for_bytes(bytes: &[u8]) -> String {
+    let digest = digest::digest(&digest::SHA256, bytes);
+    let hex = digest.as_ref()[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    format!("W/\"{hex}\"")
+}
+
+/// Serializes `T` into [`HttpResponse<Body>`], and inserts an `ETag` header
+/// computed from the serialized body via [`etag_for_bytes`]. Returns an "internal" error if
+/// JSON serialization fails. Can be useful for implementing [`ResponseHttpConvert::to_http_response`]
+/// for cacheable responses. The server responds with a body-less "304 Not Modified" response
+/// instead, if the `If-None-Match` header of a `GET` or `HEAD` request matches the entity tag.
+pub fn serialize_to_http_response_with_etag<T: Serialize>(
+    response: &T,
+    status: StatusCode,
+) -> Result<HttpResponse<Body>, ProtocolError> {
+    let bytes = SerializationFormat::Json
+        .serialize(response)
+        .map_err(Into::<ProtocolError>::into)?;
+    let etag = etag_for_bytes(&bytes);
     Ok(HttpResponse::builder()
-        .header(CONTENT_TYPE, "application/json")
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .header(ETAG, etag)
         .status(status)
         .body(bytes.into())
         .expect("should be able to create http response"))
 }
 
+/// Returns the SSE event name for a response.
+pub type SseEventNameFn<Response> = Box<dyn Fn(&Response) -> Option<String> + Send + Sync>;
+
+/// Options for the server-sent events produced by [`notification_sse_response_with_options`].
+pub struct SseResponseOptions<Response> {
+    /// Returns the event name for a response, which will be included in the
+    /// `event:` field. Events are unnamed if `None` is returned, or if this is unset.
+    pub event_name: Option<SseEventNameFn<Response>>,
+    /// If enabled, each event will include a sequential `id:` field,
+    /// starting from zero.
+    pub include_ids: bool,
+}
+
+impl<Response> Default for SseResponseOptions<Response> {
+    fn default() -> Self {
+        Self {
+            event_name: None,
+            include_ids: false,
+        }
+    }
+}
+
+/// Converts a streamed response into the payload of an event, along with the
+/// event name and id if the converter returned a [`ModalHttpResponse::SseEvent`].
+fn notification_event<Request, Response>(
+    result: Result<Response, ProtocolError>,
+) -> (HttpNotificationPayload, Option<String>, Option<String>)
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+{
+    let mut name = None;
+    let mut id = None;
+    let payload = HttpNotificationPayload::from(result.and_then(|response| {
+        Response::to_http_response(ServiceResponse::Single(response)).map(|opt| {
+            opt.and_then(|response| match response {
+                ModalHttpResponse::Event(value) => Some(value),
+                ModalHttpResponse::SseEvent(event) => {
+                    name = event.name;
+                    id = event.id;
+                    Some(event.value)
+                }
+                _ => None,
+            })
+        })
+    }));
+    (payload, name, id)
+}
+
+/// Converts a streamed response into the payload of an event.
+fn notification_payload<Request, Response>(
+    result: Result<Response, ProtocolError>,
+) -> HttpNotificationPayload
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response>,
+{
+    notification_event(result).0
+}
+
+/// A line of an NDJSON notification stream.
+#[derive(Serialize, Deserialize)]
+struct NdjsonRecord {
+    #[serde(flatten)]
+    payload: HttpNotificationPayload,
+    /// Set on the final record sent by the server once a stream has ended,
+    /// which contains the error that ended the stream, if any.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    end: bool,
+}
+
+/// An item of a notification stream, or the end of the stream.
+enum StreamFrame<Response> {
+    Item(Result<Response, ProtocolError>),
+    /// Contains the error that ended the stream, if any.
+    End(Option<ProtocolError>),
+}
+
+/// Maps the items of a notification stream, along with an end frame that is appended to the stream.
+/// Errors are held back until the next item is produced, so that an error that ends the stream
+/// is provided in the end frame instead. Items are mapped as they are produced, so that the
+/// resulting stream does not hold responses.
+fn map_stream_frames<Response: 'static, T>(
+    notification_stream: NotificationStream<Response>,
+    mut f: impl FnMut(StreamFrame<Response>) -> T,
+) -> impl Stream<Item = T> {
+    notification_stream
+        .map(Some)
+        .chain(futures::stream::once(async { None }))
+        .scan(None, move |pending_error, result| {
+            let mut mapped = Vec::new();
+            let previous_error = pending_error.take();
+            match result {
+                Some(result) => {
+                    mapped.extend(previous_error.map(|error| f(StreamFrame::Item(Err(error)))));
+                    match result {
+                        Ok(response) => mapped.push(f(StreamFrame::Item(Ok(response)))),
+                        Err(error) => *pending_error = Some(error),
+                    }
+                }
+                None => mapped.push(f(StreamFrame::End(previous_error))),
+            }
+            futures::future::ready(Some(futures::stream::iter(mapped)))
+        })
+        .flatten()
+}
+
+/// Converts a [`NotificationStream<Response>`] to an [`HttpResponse<Body>`], with each
+/// event encoded as a line of JSON (`application/x-ndjson`), as an alternative to
+/// [`notification_sse_response`] for clients that do not wish to parse server-sent events.
+/// Event names and ids are not supported. The final line is an end record
+/// (i.e. `{"result":null,"error":null,"end":true}`), which contains the error that ended
+/// the stream, if any. The client can consume the body
+/// with [`notification_ndjson_stream`]. Can be useful for implementing
+/// [`ResponseHttpConvert::to_http_response`].
+pub fn notification_ndjson_response<Request, Response>(
+    notification_stream: NotificationStream<Response>,
+) -> HttpResponse<Body>
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response> + 'static,
+{
+    let payload_stream = map_stream_frames(notification_stream, |frame| {
+        let record = match frame {
+            StreamFrame::Item(result) => NdjsonRecord {
+                payload: notification_payload(result),
+                end: false,
+            },
+            StreamFrame::End(error) => NdjsonRecord {
+                payload: HttpNotificationPayload {
+                    result: None,
+                    error: error.map(Into::into),
+                },
+                end: true,
+            },
+        };
+        let mut bytes = serde_json::to_vec(&record)?;
+        bytes.push(b'\n');
+        Ok::<Vec<u8>, serde_json::Error>(bytes)
+    });
+    let mut response = HttpResponse::new(Body::wrap_stream(payload_stream));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(NDJSON_CONTENT_TYPE));
+    response
+        .headers_mut()
+        .insert(NDJSON_END_RECORD_HEADER, HeaderValue::from_static("1"));
+    response
+}
+
 /// Converts a [`NotificationStream<Response>`] to an [`HttpResponse<Body>`] so
 /// server-side events can be produced by the HTTP server. Can be useful for implementing
 /// [`ResponseHttpConvert::to_http_response`].
@@ -179,17 +983,73 @@ where
     Request: Clone,
     Response: ResponseHttpConvert<Request, Response> + 'static,
 {
-    let payload_stream = notification_stream.map(|result| {
-        let payload = HttpNotificationPayload::from(result.and_then(|response| {
-            Response::to_http_response(ServiceResponse::Single(response)).map(|opt| {
-                opt.and_then(|response| match response {
-                    ModalHttpResponse::Event(value) => Some(value),
-                    _ => None,
-                })
-            })
-        }));
+    notification_sse_response_with_options(notification_stream, SseResponseOptions::default())
+}
+
+/// Converts a [`NotificationStream<Response>`] to an [`HttpResponse<Body>`] so
+/// server-side events can be produced by the HTTP server, with named events and/or
+/// event ids. Clients will receive events with names or ids as [`ModalHttpResponse::SseEvent`].
+/// If the converter returns a [`ModalHttpResponse::SseEvent`] for a streamed response, its
+/// name and id take precedence over the options. Can be useful for implementing [`ResponseHttpConvert::to_http_response`].
+///
+/// Once the notification stream completes, a final `multilink-end` event without data is sent,
+/// so that clients using [`notification_sse_stream`] can distinguish a completed
+/// stream from a truncated one. If the last item of the stream is an error, it is sent
+/// in a final `multilink-error` event instead, which indicates that the stream failed.
+pub fn notification_sse_response_with_options<Request, Response>(
+    notification_stream: NotificationStream<Response>,
+    options: SseResponseOptions<Response>,
+) -> HttpResponse<Body>
+where
+    Request: Clone,
+    Response: ResponseHttpConvert<Request, Response> + 'static,
+{
+    let mut next_id: u64 = 0;
+    let mut format_event = move |result: Result<Response, ProtocolError>| {
+        let mut event = String::new();
+        let options_name = result.as_ref().ok().and_then(|response| {
+            options
+                .event_name
+                .as_ref()
+                .and_then(|event_name| event_name(response))
+        });
+        let (payload, name, id) = notification_event(result);
+        let id = match options.include_ids {
+            true => {
+                next_id += 1;
+                Some(id.unwrap_or_else(|| (next_id - 1).to_string()))
+            }
+            false => id,
+        };
+        // Newlines would terminate the fields early, so they are removed
+        if let Some(id) = id {
+            let id = id.replace(['\n', '\r'], "");
+            event.push_str(&format!("{SSE_ID_FIELD}: {id}\n"));
+        }
+        if let Some(name) = name.or(options_name) {
+            let name = name.replace(['\n', '\r'], "");
+            event.push_str(&format!("{SSE_EVENT_FIELD}: {name}\n"));
+        }
         let payload_str = serde_json::to_string(&payload)?;
-        Ok::<String, serde_json::Error>(format!("data: {}\n\n", payload_str))
+        event.push_str(&format!("{SSE_DATA_FIELD}: {payload_str}\n\n"));
+        Ok::<String, serde_json::Error>(event)
+    };
+    let payload_stream = map_stream_frames(notification_stream, move |frame| match frame {
+        StreamFrame::Item(result) => format_event(result),
+        // The end event has no data, so that it is ignored by browsers
+        StreamFrame::End(None) => Ok(format!("{SSE_EVENT_FIELD}: {SSE_END_EVENT}\n\n")),
+        StreamFrame::End(Some(error)) => {
+            let payload = HttpNotificationPayload::from(Err::<Option<Value>, _>(error));
+            serde_json::to_string(&payload).map(|payload_str| {
+                format!(
+                    "{SSE_EVENT_FIELD}: {SSE_ERROR_END_EVENT}\n{SSE_DATA_FIELD}: {payload_str}\n\n"
+                )
+            })
+        }
     });
-    HttpResponse::new(Body::wrap_stream(payload_stream))
+    let mut response = HttpResponse::new(Body::wrap_stream(payload_stream));
+    response
+        .headers_mut()
+        .insert(SSE_END_EVENT_HEADER, HeaderValue::from_static("1"));
+    response
 }
@@ -1,5 +1,7 @@
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::Value;
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 
 use crate::error::{ProtocolErrorType, SerializableProtocolError};
 use crate::ProtocolError;
@@ -8,8 +10,34 @@ use crate::ProtocolError;
 pub const ID_KEY: &str = "id";
 /// The method field name used by the request and notification.
 pub const METHOD_KEY: &str = "method";
+/// The version field name used by all messages.
+pub const VERSION_KEY: &str = "jsonrpc";
+/// The params field name used by the request and notification.
+pub const PARAMS_KEY: &str = "params";
+/// The result field name used by the response.
+pub const RESULT_KEY: &str = "result";
+/// The error field name used by the response.
+pub const ERROR_KEY: &str = "error";
 /// The version of JSON-RPC used by this crate.
 pub const JSON_RPC_VERSION: &str = "2.0";
+/// The reserved notification method used for stdio keepalive heartbeats.
+/// The params of a heartbeat contain the id of the notification stream
+/// that is still active. Heartbeats are never passed to the response converter.
+pub const HEARTBEAT_METHOD: &str = "$/heartbeat";
+/// The reserved notification method sent by stdio clients to cancel a notification stream
+/// (i.e. a subscription) that is no longer consumed. The params contain the id of the stream.
+pub const UNSUBSCRIBE_METHOD: &str = "$/unsubscribe";
+/// The reserved request method sent by stdio clients to check that the server is responsive
+/// (i.e. during the handshake after spawning a child process). The params may contain the
+/// [`Handshake`](crate::handshake::Handshake) of the client. Servers respond with their own
+/// handshake, or with a "bad request" error if the handshake of the client is incompatible.
+pub const PING_METHOD: &str = "$/ping";
+/// The reserved notification method used by stdio servers to send a portion of a large result
+/// (see [`StdioServerConfig::response_chunk_bytes`](crate::stdio::server::StdioServerConfig::response_chunk_bytes)).
+/// The params contain the `id` of the request, and a piece of the serialized result as `data`.
+/// The chunks are followed by the response, which is marked as `chunked` instead of containing
+/// the result. Clients reassemble the result before passing the response to the converter.
+pub const CHUNK_METHOD: &str = "$/chunk";
 
 /// Data structure for a JSON-RPC request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +47,30 @@ pub struct JsonRpcRequest {
     pub method: String,
     pub params: Option<Value>,
     pub id: Value,
+    /// The trace context of the caller (i.e. W3C `traceparent` and `tracestate` fields),
+    /// so the server can continue the trace. Not part of the JSON-RPC specification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_context: Option<HashMap<String, String>>,
+    /// An optional correlation id for the request, used for logging. Responses and
+    /// notifications for the request will include the same id. Unlike `id`, it is not used
+    /// for matching responses to requests. Not part of the JSON-RPC specification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The remaining time in milliseconds that the caller will wait for a response.
+    /// The server will abort the request once the deadline elapses, and will reject the
+    /// request if the deadline has already elapsed. Not part of the JSON-RPC specification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// Fields that are not known to this crate (i.e. fields added by newer peers),
+    /// so that relayed messages are not stripped of them. Private, so that adding
+    /// fields does not break construction; see the `extra` accessors.
+    #[serde(
+        flatten,
+        default,
+        deserialize_with = "deserialize_extra",
+        skip_serializing_if = "Map::is_empty"
+    )]
+    pub(crate) extra: Map<String, Value>,
 }
 
 /// Data structure for a JSON-RPC response.
@@ -29,6 +81,19 @@ pub struct JsonRpcResponse {
     pub result: Option<Value>,
     pub error: Option<JsonRpcResponseError>,
     pub id: Value,
+    /// The correlation id of the associated request, if provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Fields that are not known to this crate (i.e. fields added by newer peers),
+    /// so that relayed messages are not stripped of them. Private, so that adding
+    /// fields does not break construction; see the `extra` accessors.
+    #[serde(
+        flatten,
+        default,
+        deserialize_with = "deserialize_extra",
+        skip_serializing_if = "Map::is_empty"
+    )]
+    pub(crate) extra: Map<String, Value>,
 }
 
 /// Data structure for a JSON-RPC notification.
@@ -38,6 +103,46 @@ pub struct JsonRpcNotification {
     pub jsonrpc_version: String,
     pub method: String,
     pub params: Option<Value>,
+    /// The correlation id of the associated request, if provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// True if the notification marks the end of a notification stream. Stream
+    /// termination notifications do not contain a response. Not part of the JSON-RPC specification.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub stream_complete: bool,
+    /// Fields that are not known to this crate (i.e. fields added by newer peers),
+    /// so that relayed messages are not stripped of them. Private, so that adding
+    /// fields does not break construction; see the `extra` accessors.
+    #[serde(
+        flatten,
+        default,
+        deserialize_with = "deserialize_extra",
+        skip_serializing_if = "Map::is_empty"
+    )]
+    pub(crate) extra: Map<String, Value>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Deserializes unknown fields, excluding the reserved keys of all message types
+/// (i.e. an `id` in a notification).
+fn deserialize_extra<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Map<String, Value>, D::Error> {
+    let mut extra = Map::deserialize(deserializer)?;
+    for key in [
+        ID_KEY,
+        METHOD_KEY,
+        VERSION_KEY,
+        RESULT_KEY,
+        ERROR_KEY,
+        PARAMS_KEY,
+    ] {
+        extra.remove(key);
+    }
+    Ok(extra)
 }
 
 /// Parameters used to return a result and error
@@ -56,7 +161,50 @@ pub struct JsonRpcResponseError {
     pub data: Option<Value>,
 }
 
-/// A subset of JSON-RPC error codes.
+/// The key of the stable error code in the `data` of a JSON-RPC error.
+const ERROR_CODE_KEY: &str = "error_code";
+/// The key of the structured error data in the `data` of a JSON-RPC error,
+/// if a stable error code is included.
+const ERROR_DETAILS_KEY: &str = "details";
+
+/// Includes the stable error code of a [`ProtocolError`] in the `data` of a JSON-RPC error, since
+/// the error object only contains a numeric code. If an error code is provided, the data is an object
+/// containing the error code and the structured error data (as `details`). Otherwise, the structured
+/// error data is used as is.
+fn encode_error_data(code: Option<&str>, data: Option<&Value>) -> Option<Value> {
+    let Some(code) = code else {
+        return data.cloned();
+    };
+    let mut object = Map::new();
+    object.insert(ERROR_CODE_KEY.to_string(), Value::String(code.to_string()));
+    if let Some(data) = data {
+        object.insert(ERROR_DETAILS_KEY.to_string(), data.clone());
+    }
+    Some(Value::Object(object))
+}
+
+/// Extracts the stable error code and structured error data from the `data` of a JSON-RPC error.
+/// See [`encode_error_data`].
+fn decode_error_data(data: Option<Value>) -> (Option<String>, Option<Value>) {
+    match data {
+        Some(Value::Object(mut object))
+            if object.get(ERROR_CODE_KEY).is_some_and(Value::is_string)
+                && object
+                    .keys()
+                    .all(|key| key == ERROR_CODE_KEY || key == ERROR_DETAILS_KEY) =>
+        {
+            let code = match object.remove(ERROR_CODE_KEY) {
+                Some(Value::String(code)) => Some(code),
+                _ => None,
+            };
+            (code, object.remove(ERROR_DETAILS_KEY))
+        }
+        data => (None, data),
+    }
+}
+
+/// A subset of JSON-RPC error codes, including implementation-defined
+/// server error codes (in the range of -32000 to -32099) used by multilink.
 #[derive(Clone, PartialEq, Debug)]
 #[repr(i32)]
 pub enum JsonRpcErrorCode {
@@ -65,6 +213,13 @@ pub enum JsonRpcErrorCode {
     MethodNotFound = -32601,
     InvalidParams = -32602,
     InternalError = -32603,
+    TooManyRequests = -32001,
+    ServiceUnavailable = -32002,
+    Forbidden = -32003,
+    Timeout = -32004,
+    RequestTimeout = -32005,
+    Tls = -32006,
+    ConnectionClosed = -32007,
 }
 
 impl From<i32> for JsonRpcErrorCode {
@@ -75,6 +230,13 @@ impl From<i32> for JsonRpcErrorCode {
             -32601 => Self::MethodNotFound,
             -32602 => Self::InvalidParams,
             -32603 => Self::InternalError,
+            -32001 => Self::TooManyRequests,
+            -32002 => Self::ServiceUnavailable,
+            -32003 => Self::Forbidden,
+            -32004 => Self::Timeout,
+            -32005 => Self::RequestTimeout,
+            -32006 => Self::Tls,
+            -32007 => Self::ConnectionClosed,
             _ => Self::InternalError,
         }
     }
@@ -86,6 +248,15 @@ impl From<ProtocolErrorType> for JsonRpcErrorCode {
             ProtocolErrorType::BadRequest => JsonRpcErrorCode::InvalidRequest,
             ProtocolErrorType::Unauthorized => JsonRpcErrorCode::InvalidRequest,
             ProtocolErrorType::Internal => JsonRpcErrorCode::InternalError,
+            ProtocolErrorType::TooManyRequests => JsonRpcErrorCode::TooManyRequests,
+            ProtocolErrorType::ServiceUnavailable => JsonRpcErrorCode::ServiceUnavailable,
+            ProtocolErrorType::Forbidden => JsonRpcErrorCode::Forbidden,
+            ProtocolErrorType::Timeout => JsonRpcErrorCode::Timeout,
+            ProtocolErrorType::RequestTimeout => JsonRpcErrorCode::RequestTimeout,
+            ProtocolErrorType::UnsupportedMediaType => JsonRpcErrorCode::InvalidRequest,
+            ProtocolErrorType::PayloadTooLarge => JsonRpcErrorCode::InvalidRequest,
+            ProtocolErrorType::Tls => JsonRpcErrorCode::Tls,
+            ProtocolErrorType::ConnectionClosed => JsonRpcErrorCode::ConnectionClosed,
             _ => JsonRpcErrorCode::InternalError,
         }
     }
@@ -99,11 +270,28 @@ impl Into<ProtocolErrorType> for JsonRpcErrorCode {
             Self::MethodNotFound => ProtocolErrorType::BadRequest,
             Self::InvalidParams => ProtocolErrorType::BadRequest,
             Self::InternalError => ProtocolErrorType::Internal,
+            Self::TooManyRequests => ProtocolErrorType::TooManyRequests,
+            Self::ServiceUnavailable => ProtocolErrorType::ServiceUnavailable,
+            Self::Forbidden => ProtocolErrorType::Forbidden,
+            Self::Timeout => ProtocolErrorType::Timeout,
+            Self::RequestTimeout => ProtocolErrorType::RequestTimeout,
+            Self::Tls => ProtocolErrorType::Tls,
+            Self::ConnectionClosed => ProtocolErrorType::ConnectionClosed,
         }
     }
 }
 
-/// All supported types of JSON-RPC messages.
+/// All supported types of JSON-RPC messages. Messages are serialized without a tag,
+/// using the fields of the inner message. When parsing, the type of message is determined
+/// by the fields that are present:
+///
+/// - A message with a `method` and a non-null `id` is a request.
+/// - A message with a `method`, and with a missing or null `id` is a notification.
+///   The `id` is ignored.
+/// - A message without a `method`, and with a `result` or `error` is a response.
+///   A missing `id` is treated as null (i.e. for error responses to unparseable requests).
+///
+/// Other messages are rejected.
 #[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub enum JsonRpcMessage {
@@ -119,22 +307,62 @@ impl JsonRpcRequest {
             method,
             params,
             id: Value::Null,
+            trace_context: None,
+            request_id: None,
+            extra: Map::new(),
+            deadline_ms: None,
         }
     }
 
-    /// Parses request parameters into `R`. Returns a "bad request" protocol error,
-    /// if deserialization fails.
+    /// Creates a request with positional (array) parameters.
+    pub fn new_positional(method: String, params: Vec<Value>) -> Self {
+        Self::new(method, Some(Value::Array(params)))
+    }
+
+    /// Parses request parameters into `R`. Both named (object) and positional (array)
+    /// parameters are supported; see [`deserialize_params`]. Returns a "bad request"
+    /// protocol error, if deserialization fails.
     pub fn parse_params<R: DeserializeOwned>(self) -> Result<R, SerializableProtocolError> {
-        let params = self.params.ok_or_else(|| SerializableProtocolError {
-            error_type: ProtocolErrorType::BadRequest,
-            description: "missing parameters".to_string(),
+        let params = self.params.ok_or_else(|| {
+            SerializableProtocolError::new(
+                ProtocolErrorType::BadRequest,
+                "missing parameters".to_string(),
+            )
         })?;
 
-        serde_json::from_value::<R>(params).map_err(|error| SerializableProtocolError {
-            error_type: ProtocolErrorType::BadRequest,
-            description: error.to_string(),
+        deserialize_params::<R>(params).map_err(|error| {
+            SerializableProtocolError::new(ProtocolErrorType::BadRequest, error.to_string())
         })
     }
+
+    /// Returns the fields that are not known to this crate.
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
+    /// Returns the fields that are not known to this crate, for modification.
+    /// Reserved keys (i.e. `id`) inserted here would conflict with the known fields.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        &mut self.extra
+    }
+}
+
+/// Deserializes named (object) or positional (array) parameters into `R`.
+/// Positional parameters are assigned to the fields of `R` in declaration order, so
+/// structs with named fields and tuple structs can be deserialized from either shape.
+/// If `params` is an array containing a single value that cannot be deserialized
+/// positionally (i.e. a single object wrapped in an array), the value itself is deserialized.
+pub fn deserialize_params<R: DeserializeOwned>(params: Value) -> Result<R, serde_json::Error> {
+    let error = match R::deserialize(&params) {
+        Ok(params) => return Ok(params),
+        Err(error) => error,
+    };
+    match params {
+        Value::Array(mut values) if values.len() == 1 => {
+            serde_json::from_value(values.remove(0)).map_err(|_| error)
+        }
+        _ => Err(error),
+    }
 }
 
 fn get_result_and_error(
@@ -147,7 +375,7 @@ fn get_result_and_error(
             Some(JsonRpcResponseError {
                 code: JsonRpcErrorCode::from(e.error_type.clone()) as i32,
                 message: e.to_string(),
-                data: None,
+                data: encode_error_data(e.code(), e.data()),
             }),
         ),
     }
@@ -161,6 +389,24 @@ impl JsonRpcResponse {
             result,
             error,
             id: id.into(),
+            request_id: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// Creates an error response with a specific JSON-RPC error code.
+    pub fn new_error(code: JsonRpcErrorCode, message: String, id: Value) -> Self {
+        JsonRpcResponse {
+            jsonrpc_version: JSON_RPC_VERSION.to_string(),
+            result: None,
+            error: Some(JsonRpcResponseError {
+                code: code as i32,
+                message,
+                data: None,
+            }),
+            id,
+            request_id: None,
+            extra: Map::new(),
         }
     }
 
@@ -168,14 +414,21 @@ impl JsonRpcResponse {
     /// a `Result`.
     pub fn get_result(self) -> Result<Value, SerializableProtocolError> {
         if let Some(error) = self.error {
-            let jsonrpc_error_type = JsonRpcErrorCode::from(error.code);
-            return Err(SerializableProtocolError {
-                error_type: jsonrpc_error_type.into(),
-                description: error.message,
-            });
+            return Err(error.into());
         }
         Ok(self.result.unwrap_or(Value::Null))
     }
+
+    /// Returns the fields that are not known to this crate.
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
+    /// Returns the fields that are not known to this crate, for modification.
+    /// Reserved keys (i.e. `id`) inserted here would conflict with the known fields.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        &mut self.extra
+    }
 }
 
 impl JsonRpcNotification {
@@ -184,6 +437,18 @@ impl JsonRpcNotification {
             jsonrpc_version: JSON_RPC_VERSION.to_string(),
             method,
             params,
+            request_id: None,
+            extra: Map::new(),
+            stream_complete: false,
+        }
+    }
+
+    /// Creates a notification that marks the end of a notification stream.
+    /// The `method` must be the id of the associated request.
+    pub fn new_stream_complete(method: String) -> Self {
+        JsonRpcNotification {
+            stream_complete: true,
+            ..Self::new(method, None)
         }
     }
 
@@ -195,6 +460,9 @@ impl JsonRpcNotification {
             jsonrpc_version: JSON_RPC_VERSION.to_string(),
             method,
             params: serde_json::to_value(JsonRpcNotificationResultParams::new(result)).ok(),
+            request_id: None,
+            extra: Map::new(),
+            stream_complete: false,
         }
     }
 
@@ -210,14 +478,34 @@ impl JsonRpcNotification {
             error: None,
         });
         if let Some(error) = params.error {
-            let jsonrpc_error_type = JsonRpcErrorCode::from(error.code);
-            return Err(SerializableProtocolError {
-                error_type: jsonrpc_error_type.into(),
-                description: error.message,
-            });
+            return Err(error.into());
         }
         Ok(params.result.unwrap_or(Value::Null))
     }
+
+    /// Returns the fields that are not known to this crate.
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
+    /// Returns the fields that are not known to this crate, for modification.
+    /// Reserved keys (i.e. `id`) inserted here would conflict with the known fields.
+    pub fn extra_mut(&mut self) -> &mut Map<String, Value> {
+        &mut self.extra
+    }
+}
+
+impl From<JsonRpcResponseError> for SerializableProtocolError {
+    fn from(error: JsonRpcResponseError) -> Self {
+        let jsonrpc_error_type = JsonRpcErrorCode::from(error.code);
+        let (code, data) = decode_error_data(error.data);
+        Self {
+            error_type: jsonrpc_error_type.into(),
+            description: error.message,
+            code,
+            data,
+        }
+    }
 }
 
 impl JsonRpcNotificationResultParams {
@@ -227,6 +515,84 @@ impl JsonRpcNotificationResultParams {
     }
 }
 
+impl JsonRpcMessage {
+    /// Sets the correlation id of the message.
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        match &mut self {
+            JsonRpcMessage::Request(request) => request.request_id = request_id,
+            JsonRpcMessage::Response(response) => response.request_id = request_id,
+            JsonRpcMessage::Notification(notification) => notification.request_id = request_id,
+        }
+        self
+    }
+
+    fn from_value(mut value: Value, validate_version: bool) -> Result<Self, serde_json::Error> {
+        match validate_version {
+            true => match value.get(VERSION_KEY) {
+                // A missing version is rejected during deserialization
+                None => (),
+                Some(Value::String(version)) if version == JSON_RPC_VERSION => (),
+                Some(version) => {
+                    return Err(serde::de::Error::custom(format!(
+                        "unsupported jsonrpc version {version}, expected \"{JSON_RPC_VERSION}\""
+                    )))
+                }
+            },
+            false => {
+                if let Value::Object(map) = &mut value {
+                    if !matches!(map.get(VERSION_KEY), Some(Value::String(_))) {
+                        map.insert(VERSION_KEY.to_string(), JSON_RPC_VERSION.into());
+                    }
+                }
+            }
+        }
+        let Value::Object(map) = &mut value else {
+            return Err(serde::de::Error::custom(
+                "json rpc message should be an object",
+            ));
+        };
+        if map.contains_key(METHOD_KEY) {
+            return Ok(match map.get(ID_KEY).is_some_and(|id| !id.is_null()) {
+                true => JsonRpcMessage::Request(serde_json::from_value(value)?),
+                false => JsonRpcMessage::Notification(serde_json::from_value(value)?),
+            });
+        }
+        if !map.contains_key(RESULT_KEY) && !map.contains_key(ERROR_KEY) {
+            return Err(serde::de::Error::custom(
+                "json rpc message should contain a method, result or error",
+            ));
+        }
+        map.entry(ID_KEY).or_insert(Value::Null);
+        Ok(JsonRpcMessage::Response(serde_json::from_value(value)?))
+    }
+
+    /// Parses a JSON-RPC message from a JSON payload. The `jsonrpc` field must be
+    /// present and equal to [`JSON_RPC_VERSION`]. See [`JsonRpcMessage`] for how the
+    /// type of message is determined.
+    pub fn from_slice(payload: &[u8]) -> Result<Self, serde_json::Error> {
+        Self::from_value(serde_json::from_slice(payload)?, true)
+    }
+
+    /// Serializes the message into a JSON payload. The payload can be parsed into an
+    /// equivalent message via [`JsonRpcMessage::from_slice`], as long as requests
+    /// have a non-null id.
+    pub fn to_vec(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+
+    /// Parses a JSON-RPC message. If `validate_version` is enabled, the `jsonrpc` field must be
+    /// present and equal to [`JSON_RPC_VERSION`]. Otherwise, messages with a missing or
+    /// different version are accepted. Returns a "bad request" protocol error if parsing fails.
+    pub fn parse(value: Value, validate_version: bool) -> Result<Self, SerializableProtocolError> {
+        Self::from_value(value, validate_version).map_err(|error| {
+            SerializableProtocolError::new(
+                ProtocolErrorType::BadRequest,
+                format!("invalid json rpc message: {error}"),
+            )
+        })
+    }
+}
+
 impl From<JsonRpcRequest> for JsonRpcMessage {
     fn from(value: JsonRpcRequest) -> Self {
         Self::Request(value)
@@ -248,13 +614,9 @@ impl From<JsonRpcNotification> for JsonRpcMessage {
 impl TryFrom<serde_json::Value> for JsonRpcMessage {
     type Error = serde_json::Error;
 
+    /// Parses a JSON-RPC message. The `jsonrpc` field must be present and
+    /// equal to [`JSON_RPC_VERSION`]. See [`JsonRpcMessage::parse`] for lenient parsing.
     fn try_from(value: serde_json::Value) -> Result<Self, serde_json::Error> {
-        Ok(match value.get(METHOD_KEY).is_some() {
-            true => match value.get(ID_KEY).is_some() {
-                true => JsonRpcMessage::Request(serde_json::from_value(value)?),
-                false => JsonRpcMessage::Notification(serde_json::from_value(value)?),
-            },
-            false => JsonRpcMessage::Response(serde_json::from_value(value)?),
-        })
+        Self::from_value(value, true)
     }
 }
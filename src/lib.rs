@@ -3,6 +3,9 @@
 //! - Local processes/stdio: JSON-RPC messages are passed between parent/child process via stdin/stdout
 //! - Remote processes/HTTP: HTTP requests/responses are passed between processes on remote hosts
 //!
+//! JSON-RPC messages may also be passed over a long-lived TCP or WebSocket connection, using the same
+//! conversion traits as stdio.
+//!
 //! Utilizes `tower` to handle RPC calls.
 //!
 //! ## Moving parts
@@ -16,26 +19,45 @@
 //!
 //! The caller of a multilink client will only use the protocol-agnostic request and response types, which allows seamless switching between protocols.
 
+#[cfg(feature = "blocking")]
+/// Blocking clients, for use outside of an async runtime.
+pub mod blocking;
 /// Protocol error types.
 pub mod error;
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+/// Typed request extensions, for passing context to services.
+pub mod extensions;
+/// Serialization formats for message payloads.
+pub mod format;
+/// Optional handshake for detecting incompatible peers.
+pub mod handshake;
 #[cfg(any(feature = "http-client", feature = "http-server"))]
 /// HTTP server and client.
 pub mod http;
 #[cfg(feature = "jsonrpc")]
 /// JSON-RPC types and methods.
 pub mod jsonrpc;
+/// Request metrics.
+pub mod metrics;
 #[cfg(any(feature = "stdio-client", feature = "stdio-server"))]
 /// JSON-RPC over stdio server and client.
 pub mod stdio;
+#[cfg(any(feature = "tcp-client", feature = "tcp-server"))]
+/// JSON-RPC over TCP server and client.
+pub mod tcp;
+mod trace;
 /// Miscellaneous utility functions.
 pub mod util;
+#[cfg(any(feature = "ws-client", feature = "ws-server"))]
+/// JSON-RPC over WebSocket server and client.
+pub mod ws;
 
 pub use error::ProtocolError;
 pub use tower;
 
 use std::{error::Error, pin::Pin};
 
-use futures::{Future, Stream};
+use futures::{stream, Future, Stream, StreamExt, TryStreamExt};
 use tower::Service;
 
 /// Default request timeout.
@@ -50,6 +72,26 @@ pub trait ConfigExampleSnippet {
     fn config_example_snippet() -> String;
 }
 
+/// A configuration data structure that may contain deprecated keys.
+/// Used by [`util::config::deserialize_config`] to report deprecated keys
+/// found in configuration files.
+pub trait ConfigDeprecatedKeys {
+    /// Returns a list of deprecated keys, along with a note for each key
+    /// (i.e. the key that should be used instead).
+    fn deprecated_config_keys() -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+}
+
+/// A configuration data structure whose fields may be overridden by environment variables.
+/// Used by [`util::config::apply_env_overrides`].
+pub trait ConfigEnvPrefix {
+    /// Returns the prefix of the environment variables that override fields of the
+    /// configuration. The variable for each field is named after the prefix and the
+    /// upper case field name, separated by an underscore (i.e. `MULTILINK_HTTP_CLIENT_TIMEOUT_SECS`).
+    fn config_env_prefix() -> &'static str;
+}
+
 /// A stream of multiple response results returned by the service.
 pub type NotificationStream<Response> =
     Pin<Box<dyn Stream<Item = Result<Response, ProtocolError>> + Send>>;
@@ -62,6 +104,45 @@ pub enum ServiceResponse<Response> {
     Multiple(NotificationStream<Response>),
 }
 
+impl<Response> ServiceResponse<Response> {
+    /// Converts the response into a stream, so that both variants can be consumed uniformly.
+    /// A single response is converted into a stream with one item.
+    pub fn into_stream(self) -> NotificationStream<Response>
+    where
+        Response: Send + 'static,
+    {
+        match self {
+            ServiceResponse::Single(response) => stream::iter([Ok(response)]).boxed(),
+            ServiceResponse::Multiple(stream) => stream,
+        }
+    }
+
+    /// Applies `f` to the single response, or to each response of the stream.
+    /// Responses of the stream are mapped lazily, as they are received; errors are passed through.
+    pub fn map<F, R2>(self, mut f: F) -> ServiceResponse<R2>
+    where
+        F: FnMut(Response) -> R2 + Send + 'static,
+        Response: 'static,
+        R2: Send + 'static,
+    {
+        match self {
+            ServiceResponse::Single(response) => ServiceResponse::Single(f(response)),
+            ServiceResponse::Multiple(stream) => {
+                ServiceResponse::Multiple(stream.map(move |result| result.map(&mut f)).boxed())
+            }
+        }
+    }
+
+    /// Collects all responses into a vector. A single response is collected into a vector
+    /// with one item. Returns the first error received from the stream, if any.
+    pub async fn collect_all(self) -> Result<Vec<Response>, ProtocolError> {
+        match self {
+            ServiceResponse::Single(response) => Ok(vec![response]),
+            ServiceResponse::Multiple(stream) => stream.try_collect().await,
+        }
+    }
+}
+
 /// A boxed error type that may be returned by service calls.
 pub type ServiceError = Box<dyn Error + Send + Sync + 'static>;
 /// A future that returns a result with a generic response and [`ServiceError`].
@@ -0,0 +1,127 @@
+//! Request metrics, recorded via the [`metrics`](https://docs.rs/metrics) crate facade
+//! if the `metrics` feature is enabled. An exporter (i.e. `metrics-exporter-prometheus`)
+//! must be installed by the application to collect the metrics.
+//!
+//! The following metrics are recorded for each request handled by the clients and servers:
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `multilink_requests_total` | Counter | `transport`, `method` |
+//! | `multilink_request_errors_total` | Counter | `transport`, `method`, `error_type` |
+//! | `multilink_request_duration_seconds` | Histogram | `transport`, `method` |
+//!
+//! The `transport` label is one of `http_client`, `http_server`, `stdio_client` or `stdio_server`.
+//! The `method` label is the method of the JSON-RPC request. For HTTP requests, it is the
+//! [`operation_name`](crate::http::RequestHttpConvert::operation_name) of the request, or the
+//! HTTP method (i.e. `GET`) if the request has no operation name or could not be converted.
+//! Paths are not used, since they may contain identifiers.
+//! The `error_type` label is the snake case name of the [`ProtocolErrorType`] (i.e. `not_found`).
+//! For streaming responses, the duration is measured until the stream is returned.
+
+use crate::error::ProtocolErrorType;
+
+/// The counter of handled requests.
+pub const REQUESTS_TOTAL: &str = "multilink_requests_total";
+/// The counter of requests that resulted in an error.
+pub const REQUEST_ERRORS_TOTAL: &str = "multilink_request_errors_total";
+/// The histogram of request durations, in seconds.
+pub const REQUEST_DURATION_SECONDS: &str = "multilink_request_duration_seconds";
+
+/// The `transport` label value for the HTTP client.
+pub const HTTP_CLIENT_TRANSPORT: &str = "http_client";
+/// The `transport` label value for the HTTP server.
+pub const HTTP_SERVER_TRANSPORT: &str = "http_server";
+/// The `transport` label value for the stdio client.
+pub const STDIO_CLIENT_TRANSPORT: &str = "stdio_client";
+/// The `transport` label value for the stdio server.
+pub const STDIO_SERVER_TRANSPORT: &str = "stdio_server";
+
+/// True if metrics are recorded. Can be used to skip work that
+/// is only needed for metric labels.
+#[cfg(feature = "stdio-client")]
+pub(crate) const ENABLED: bool = cfg!(feature = "metrics");
+
+impl ProtocolErrorType {
+    /// Returns the snake case name of the error type, as used in metric labels.
+    pub fn as_label(&self) -> &'static str {
+        match self {
+            ProtocolErrorType::NotFound => "not_found",
+            ProtocolErrorType::HttpMethodNotAllowed => "http_method_not_allowed",
+            ProtocolErrorType::BadRequest => "bad_request",
+            ProtocolErrorType::Unauthorized => "unauthorized",
+            ProtocolErrorType::Internal => "internal",
+            ProtocolErrorType::TooManyRequests => "too_many_requests",
+            ProtocolErrorType::ServiceUnavailable => "service_unavailable",
+            ProtocolErrorType::Forbidden => "forbidden",
+            ProtocolErrorType::Timeout => "timeout",
+            ProtocolErrorType::RequestTimeout => "request_timeout",
+            ProtocolErrorType::UnsupportedMediaType => "unsupported_media_type",
+            ProtocolErrorType::PayloadTooLarge => "payload_too_large",
+            ProtocolErrorType::Tls => "tls",
+            ProtocolErrorType::ConnectionClosed => "connection_closed",
+        }
+    }
+}
+
+/// Records the metrics for a handled request. `error_type` should be provided
+/// if the request resulted in an error.
+#[cfg(any(
+    feature = "http-client",
+    feature = "http-server",
+    feature = "stdio-client",
+    feature = "stdio-server"
+))]
+pub(crate) fn record_request(
+    transport: &'static str,
+    method: &str,
+    start: std::time::Instant,
+    error_type: Option<&ProtocolErrorType>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let labels = [
+            ("transport", transport.to_string()),
+            ("method", method.to_string()),
+        ];
+        metrics::increment_counter!(REQUESTS_TOTAL, &labels);
+        metrics::histogram!(
+            REQUEST_DURATION_SECONDS,
+            start.elapsed().as_secs_f64(),
+            &labels
+        );
+        if let Some(error_type) = error_type {
+            let [transport, method] = labels;
+            let labels = [
+                transport,
+                method,
+                ("error_type", error_type.as_label().to_string()),
+            ];
+            metrics::increment_counter!(REQUEST_ERRORS_TOTAL, &labels);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (transport, method, start, error_type);
+}
+
+/// Records the metrics for a request handled by a service.
+#[cfg(any(
+    feature = "http-client",
+    feature = "stdio-client",
+    feature = "stdio-server"
+))]
+pub(crate) fn record_result<T>(
+    transport: &'static str,
+    method: &str,
+    start: std::time::Instant,
+    result: &Result<T, crate::ServiceError>,
+) {
+    let error_type = result.as_ref().err().map(|e| {
+        e.downcast_ref::<crate::ProtocolError>()
+            .map(|e| e.error_type.clone())
+            .unwrap_or(match e.is::<tower::timeout::error::Elapsed>() {
+                true => ProtocolErrorType::Timeout,
+                false => ProtocolErrorType::Internal,
+            })
+    });
+    record_request(transport, method, start, error_type.as_ref());
+}
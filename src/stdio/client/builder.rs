@@ -0,0 +1,149 @@
+use std::{io, marker::PhantomData, sync::Arc};
+
+use crate::format::SerializationFormat;
+
+use super::{
+    super::{codec::default_codec, RequestJsonRpcConvert, ResponseJsonRpcConvert},
+    IdStrategy, StdioClient, StdioClientConfig, StdioCodec,
+};
+
+/// Builds a [`StdioClient`] that spawns a child process, starting from the default configuration.
+/// Created via [`StdioClient::builder`].
+pub struct StdioClientBuilder<Request, Response> {
+    program: String,
+    args: Vec<String>,
+    config: StdioClientConfig,
+    codec: Option<Arc<dyn StdioCodec>>,
+    request_phantom: PhantomData<Request>,
+    response_phantom: PhantomData<Response>,
+}
+
+impl<Request, Response> StdioClientBuilder<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    pub(super) fn new(program: String) -> Self {
+        Self {
+            program,
+            args: Vec::new(),
+            config: Default::default(),
+            codec: None,
+            request_phantom: Default::default(),
+            response_phantom: Default::default(),
+        }
+    }
+
+    /// Replaces the entire configuration. Subsequent calls will modify the provided configuration.
+    pub fn config(mut self, config: StdioClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Appends an argument for the child process.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Appends arguments for the child process.
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the codec used to frame messages. The child process must use the same codec.
+    /// See [`StdioClient::new_with_codec`].
+    pub fn codec<C: StdioCodec + 'static>(mut self, codec: C) -> Self {
+        self.codec = Some(Arc::new(codec));
+        self
+    }
+
+    /// See [`StdioClientConfig::bin_path`].
+    pub fn bin_path(mut self, bin_path: impl Into<String>) -> Self {
+        self.config.bin_path = Some(bin_path.into());
+        self
+    }
+
+    /// See [`StdioClientConfig::timeout_secs`].
+    pub fn timeout_secs(mut self, timeout_secs: u64) -> Self {
+        self.config.timeout_secs = timeout_secs;
+        self
+    }
+
+    /// See [`StdioClientConfig::idle_timeout_secs`].
+    pub fn idle_timeout_secs(mut self, idle_timeout_secs: u64) -> Self {
+        self.config.idle_timeout_secs = Some(idle_timeout_secs);
+        self
+    }
+
+    /// See [`StdioClientConfig::notification_channel_capacity`].
+    pub fn notification_channel_capacity(mut self, notification_channel_capacity: usize) -> Self {
+        self.config.notification_channel_capacity = Some(notification_channel_capacity);
+        self
+    }
+
+    /// See [`StdioClientConfig::serialization_format`].
+    pub fn serialization_format(mut self, serialization_format: SerializationFormat) -> Self {
+        self.config.serialization_format = serialization_format;
+        self
+    }
+
+    /// See [`StdioClientConfig::validate_jsonrpc_version`].
+    pub fn validate_jsonrpc_version(mut self, validate_jsonrpc_version: bool) -> Self {
+        self.config.validate_jsonrpc_version = validate_jsonrpc_version;
+        self
+    }
+
+    /// See [`StdioClientConfig::shutdown_grace_secs`].
+    pub fn shutdown_grace_secs(mut self, shutdown_grace_secs: u64) -> Self {
+        self.config.shutdown_grace_secs = shutdown_grace_secs;
+        self
+    }
+
+    /// See [`StdioClientConfig::max_message_bytes`].
+    pub fn max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.config.max_message_bytes = max_message_bytes;
+        self
+    }
+
+    /// See [`StdioClientConfig::pretty_json`].
+    pub fn pretty_json(mut self, pretty_json: bool) -> Self {
+        self.config.pretty_json = pretty_json;
+        self
+    }
+
+    /// See [`StdioClientConfig::id_strategy`].
+    pub fn id_strategy(mut self, id_strategy: IdStrategy) -> Self {
+        self.config.id_strategy = id_strategy;
+        self
+    }
+
+    /// See [`StdioClientConfig::handshake_timeout_secs`].
+    pub fn handshake_timeout_secs(mut self, handshake_timeout_secs: u64) -> Self {
+        self.config.handshake_timeout_secs = Some(handshake_timeout_secs);
+        self
+    }
+
+    /// See [`StdioClientConfig::schema_version`].
+    pub fn schema_version(mut self, schema_version: impl Into<String>) -> Self {
+        self.config.schema_version = Some(schema_version.into());
+        self
+    }
+
+    /// See [`StdioClientConfig::request_queue_capacity`].
+    pub fn request_queue_capacity(mut self, request_queue_capacity: usize) -> Self {
+        self.config.request_queue_capacity = request_queue_capacity;
+        self
+    }
+
+    /// Spawns the child process and creates the client.
+    /// A [`std::io::Error`] will be returned if spawning fails.
+    pub async fn build(self) -> io::Result<StdioClient<Request, Response>> {
+        let codec = self.codec.unwrap_or_else(|| {
+            default_codec(self.config.serialization_format, self.config.pretty_json)
+        });
+        let args = self.args.iter().map(String::as_str).collect::<Vec<_>>();
+        StdioClient::spawn(&self.program, &args, self.config, codec).await
+    }
+}
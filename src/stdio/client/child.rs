@@ -0,0 +1,181 @@
+use std::{io, time::Duration};
+
+use serde_json::Value;
+use tokio::{process::Child, runtime::Handle, sync::Mutex, time::timeout};
+use tracing::{debug, warn};
+
+use crate::{
+    error::SerializableProtocolError,
+    handshake::{Handshake, HandshakeError, HANDSHAKE_ERROR_CODE},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest, PING_METHOD},
+    stdio::{
+        codec::{FrameReader, FrameWriter},
+        StdioError,
+    },
+};
+
+/// The time to wait for the exit status of a child process that closed stdout
+/// during the handshake, so that the status can be included in the error.
+const EXIT_STATUS_WAIT: Duration = Duration::from_millis(100);
+
+/// Owns the child process of a stdio client. Once all clients have been dropped,
+/// the child is given a chance to exit on its own before it is killed.
+pub(super) struct ChildHandle {
+    child: Mutex<Option<Child>>,
+    shutdown_grace: Duration,
+}
+
+impl ChildHandle {
+    pub(super) fn new(child: Child, shutdown_grace: Duration) -> Self {
+        Self {
+            child: Mutex::new(Some(child)),
+            shutdown_grace,
+        }
+    }
+
+    /// Kills the child process immediately, and waits for it to exit.
+    pub(super) async fn kill(&self) -> io::Result<()> {
+        if let Some(child) = self.child.lock().await.as_mut() {
+            child.kill().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends a ping request to the newly spawned child process, and waits for the response
+/// within `handshake_timeout`. The ping carries the handshake of the client, which is rejected
+/// by multilink servers if the peers are incompatible. If the server responds with a result,
+/// it must be a valid handshake, which is also checked by the client. An error response
+/// (i.e. from servers that do not support pings) completes the handshake. Other messages received
+/// before the response are discarded. Returns an error containing a [`StdioError::Handshake`]
+/// if the child exits, or does not respond in time, and an error containing a
+/// [`StdioError::IncompatiblePeer`] if the peers are incompatible. Other I/O errors
+/// (i.e. a [`StdioError::MessageTooLarge`] error) are returned as is.
+pub(super) async fn handshake(
+    child: &mut Child,
+    writer: &mut FrameWriter,
+    reader: &mut FrameReader,
+    schema_version: Option<String>,
+    validate_jsonrpc_version: bool,
+    handshake_timeout: Duration,
+) -> io::Result<()> {
+    let handshake_error =
+        |kind, reason: String| io::Error::new(kind, StdioError::Handshake(reason));
+    let local = reader.handshake(schema_version);
+    let ping_id = Value::from(0);
+    let exchange = async {
+        let request = JsonRpcRequest {
+            id: ping_id.clone(),
+            ..JsonRpcRequest::new(PING_METHOD.to_string(), Some(serde_json::to_value(&local)?))
+        };
+        writer.write_message(&JsonRpcMessage::from(request)).await?;
+        loop {
+            if !reader.next_frame().await? {
+                return Ok::<_, io::Error>(None);
+            }
+            let Ok(value) = reader.parse_frame::<Value>() else {
+                continue;
+            };
+            if let Ok(JsonRpcMessage::Response(response)) =
+                JsonRpcMessage::parse(value, validate_jsonrpc_version)
+            {
+                if response.id == ping_id {
+                    return Ok(Some(response));
+                }
+            }
+        }
+    };
+    let response = match timeout(handshake_timeout, exchange).await {
+        Ok(Ok(Some(response))) => Some(response),
+        // Writing to stdin fails with a broken pipe if the child has exited
+        Ok(Ok(None)) => None,
+        Ok(Err(e)) if is_exit_error(&e) => None,
+        // Other errors (i.e. a response that exceeds the maximum message size)
+        // are not caused by the child exiting, so they are returned as is
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            return Err(handshake_error(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "no response within {} seconds",
+                    handshake_timeout.as_secs_f64()
+                ),
+            ))
+        }
+    };
+    let response = match response {
+        Some(response) => response,
+        None => {
+            let reason = match timeout(EXIT_STATUS_WAIT, child.wait()).await {
+                Ok(Ok(status)) => format!("child process exited before responding ({status})"),
+                _ => "child process closed stdout before responding".to_string(),
+            };
+            return Err(handshake_error(io::ErrorKind::UnexpectedEof, reason));
+        }
+    };
+    let incompatible_error =
+        |e: HandshakeError| io::Error::new(io::ErrorKind::InvalidData, StdioError::from(e));
+    let rejection = response
+        .error
+        .map(SerializableProtocolError::from)
+        .filter(|error| error.code.as_deref() == Some(HANDSHAKE_ERROR_CODE));
+    // Rejections include the handshake of the server, so that the mismatch
+    // can be described from the perspective of the client
+    let peer = match rejection.as_ref() {
+        Some(error) => error.data.clone(),
+        None => response.result,
+    };
+    if let Some(peer) = peer {
+        let peer = serde_json::from_value::<Handshake>(peer)
+            .map_err(|e| incompatible_error(HandshakeError::Invalid(e.to_string())))?;
+        local.negotiate(&peer).map_err(incompatible_error)?;
+    }
+    match rejection {
+        Some(error) => Err(incompatible_error(HandshakeError::Rejected(
+            error.description,
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Returns true if the error indicates that the pipes of the child process were closed.
+fn is_exit_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Waits for the child process to exit, once stdin has been closed by the comm task.
+/// If the child does not exit within the grace period, it is sent `SIGTERM` (on Unix),
+/// and is killed if it does not exit within another grace period.
+async fn shutdown(mut child: Child, grace: Duration) {
+    if timeout(grace, child.wait()).await.is_ok() {
+        return;
+    }
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        debug!("child process {pid} did not exit after stdin was closed, sending SIGTERM");
+        // SAFETY: the process has not been reaped, so the pid still belongs to the child
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+        if timeout(grace, child.wait()).await.is_ok() {
+            return;
+        }
+    }
+    warn!("child process did not exit within the shutdown grace period, killing process");
+    child.kill().await.ok();
+}
+
+impl Drop for ChildHandle {
+    fn drop(&mut self) {
+        let Some(child) = self.child.get_mut().take() else {
+            return;
+        };
+        // Without a runtime, the child is killed immediately once dropped
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(shutdown(child, self.shutdown_grace));
+        }
+    }
+}
@@ -1,24 +1,32 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
 use serde_json::Value;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::{ChildStdin, ChildStdout},
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    time::{interval, MissedTickBehavior},
 };
-use tokio_stream::wrappers::UnboundedReceiverStream;
-use tracing::{error, warn};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
 
 use crate::{
-    jsonrpc::{JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse},
-    stdio::StdioError,
-    ServiceResponse,
+    error::ProtocolErrorType,
+    jsonrpc::{
+        JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, CHUNK_METHOD,
+        HEARTBEAT_METHOD, UNSUBSCRIBE_METHOD,
+    },
+    stdio::{id_key, IdKey, ResponseChunkParams, StdioError, CHUNKED_RESPONSE_KEY},
+    ProtocolError, ServiceResponse,
 };
 
 use super::{
-    serialize_payload, ClientNotificationLink, ClientRequestTrx, RequestJsonRpcConvert,
-    ResponseJsonRpcConvert,
+    super::codec::{FrameReader, FrameWriter},
+    ClientNotificationLink, ClientRequestTrx, IdStrategy, NotificationSender, PushSubscribers,
+    RequestJsonRpcConvert, ResponseJsonRpcConvert, StdioClientConfig, UnsubscribeOnDrop,
 };
 
 pub(super) struct StdioClientCommTask<Request, Response>
@@ -26,13 +34,26 @@ where
     Request: RequestJsonRpcConvert<Request> + Send + 'static,
     Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
 {
-    stdin: ChildStdin,
-    stdout: BufReader<ChildStdout>,
-    pending_reqs: HashMap<u64, ClientRequestTrx<Request, Response>>,
-    notification_links: HashMap<u64, ClientNotificationLink<Request, Response>>,
-    to_child_rx: UnboundedReceiver<ClientRequestTrx<Request, Response>>,
-    to_child_tx: Option<UnboundedSender<ClientRequestTrx<Request, Response>>>,
+    /// Set to `None` once all clients have been dropped, which closes the writer
+    /// (i.e. so the child process receives EOF on stdin).
+    stdin: Option<FrameWriter>,
+    stdout: FrameReader,
+    pending_reqs: HashMap<IdKey, ClientRequestTrx<Request, Response>>,
+    notification_links: HashMap<IdKey, ClientNotificationLink<Request, Response>>,
+    /// The partial results of pending requests, received via chunk notifications.
+    chunked_results: HashMap<IdKey, String>,
+    to_child_rx: Receiver<ClientRequestTrx<Request, Response>>,
+    to_child_tx: Option<Sender<ClientRequestTrx<Request, Response>>>,
+    /// Receives the ids of notification streams that were dropped by the consumer.
+    unsubscribe_rx: UnboundedReceiver<IdKey>,
+    unsubscribe_tx: UnboundedSender<IdKey>,
+    id_strategy: IdStrategy,
     last_req_id: u64,
+    idle_timeout: Option<Duration>,
+    notification_channel_capacity: Option<usize>,
+    validate_jsonrpc_version: bool,
+    max_message_bytes: usize,
+    push_subscribers: Arc<StdMutex<PushSubscribers>>,
 }
 
 impl<Request, Response> StdioClientCommTask<Request, Response>
@@ -40,35 +61,94 @@ where
     Request: RequestJsonRpcConvert<Request> + Send + 'static,
     Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
 {
-    pub(super) fn new(stdin: ChildStdin, stdout: BufReader<ChildStdout>) -> Self {
+    /// Creates a new comm task. `stdin` is the writer for outgoing messages, and
+    /// `stdout` is the reader for incoming messages. These are usually the stdio
+    /// pipes of a child process, but may be any stream of frames. If an idle timeout
+    /// is configured, notification streams that do not receive any messages within the
+    /// timeout will be terminated with an error. If a notification channel capacity is configured,
+    /// notification streams with lagging consumers will be terminated with an error.
+    /// If version validation is enabled, incoming messages with a missing or
+    /// unsupported version will be rejected. Outgoing requests are queued in a channel
+    /// bounded by the request queue capacity, and ids are generated via the id strategy.
+    /// Notifications that are not associated with a request are forwarded to the `push_subscribers`.
+    pub(super) fn new(
+        stdin: FrameWriter,
+        stdout: FrameReader,
+        config: &StdioClientConfig,
+        push_subscribers: Arc<StdMutex<PushSubscribers>>,
+    ) -> Self {
         let (to_child_tx, to_child_rx) =
-            mpsc::unbounded_channel::<ClientRequestTrx<Request, Response>>();
+            mpsc::channel::<ClientRequestTrx<Request, Response>>(config.request_queue_capacity);
+        let (unsubscribe_tx, unsubscribe_rx) = mpsc::unbounded_channel();
         Self {
-            stdin,
+            stdin: Some(stdin),
             stdout,
             pending_reqs: HashMap::new(),
             notification_links: HashMap::new(),
+            chunked_results: HashMap::new(),
             to_child_rx,
             to_child_tx: Some(to_child_tx),
+            unsubscribe_rx,
+            unsubscribe_tx,
+            id_strategy: config.id_strategy.clone(),
             last_req_id: 0,
+            idle_timeout: config.idle_timeout_secs.map(Duration::from_secs),
+            notification_channel_capacity: config.notification_channel_capacity,
+            validate_jsonrpc_version: config.validate_jsonrpc_version,
+            max_message_bytes: config.max_message_bytes,
+            push_subscribers,
         }
     }
 
     async fn output_message(&mut self, message: JsonRpcMessage) {
-        let serialized_response = serialize_payload(&message);
-        self.stdin
-            .write_all(serialized_response.as_bytes())
-            .await
-            .ok();
+        if let Some(stdin) = self.stdin.as_mut() {
+            stdin.write_message(&message).await.ok();
+        }
     }
 
-    async fn handle_outgoing_request(&mut self, req_trx: ClientRequestTrx<Request, Response>) {
-        let mut jsonrpc_request = req_trx.request.into_jsonrpc_request();
-        let id = self.last_req_id + 1;
-        jsonrpc_request.id = serde_json::to_value(id).unwrap();
+    fn next_id(&mut self) -> Value {
+        match &self.id_strategy {
+            IdStrategy::Monotonic => {
+                self.last_req_id += 1;
+                self.last_req_id.into()
+            }
+            IdStrategy::Uuid => Uuid::new_v4().to_string().into(),
+            IdStrategy::Custom(generate) => generate(),
+        }
+    }
 
-        self.last_req_id = id;
-        self.pending_reqs.insert(id, req_trx);
+    async fn handle_outgoing_request(&mut self, mut req_trx: ClientRequestTrx<Request, Response>) {
+        let Some(mut jsonrpc_request) = req_trx.jsonrpc_request.take() else {
+            return;
+        };
+        jsonrpc_request.deadline_ms.get_or_insert_with(|| {
+            let remaining = req_trx.deadline.saturating_duration_since(Instant::now());
+            remaining.as_millis().try_into().unwrap_or(u64::MAX)
+        });
+        let request_id = jsonrpc_request
+            .request_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string());
+        debug!(
+            request_id = request_id.as_str(),
+            method = jsonrpc_request.method.as_str(),
+            "sending stdio request"
+        );
+        let id = self.next_id();
+        let key = id_key(&id);
+        if id.is_null()
+            || self.pending_reqs.contains_key(&key)
+            || self.notification_links.contains_key(&key)
+        {
+            error!("generated request id {id} is null or already in use");
+            let error = ProtocolError::new(
+                ProtocolErrorType::Internal,
+                format!("request id {id} is null or already in use").into(),
+            );
+            req_trx.response_tx.send(Err(error)).ok();
+            return;
+        }
+        jsonrpc_request.id = id;
+        self.pending_reqs.insert(key, req_trx);
 
         self.output_message(jsonrpc_request.into()).await;
     }
@@ -81,15 +161,71 @@ where
         .await
     }
 
-    fn handle_response(&mut self, response: JsonRpcResponse) {
-        match self
-            .pending_reqs
-            .remove(&serde_json::from_value(response.id.clone()).unwrap_or_default())
-        {
-            None => {
-                warn!("received response with unknown id, ignoring {:?}", response)
+    /// Appends a portion of a chunked result to the result of the pending request.
+    /// Chunks that would make the result exceed the maximum message size fail the request.
+    fn handle_chunk(&mut self, notification: JsonRpcNotification) {
+        let params = notification
+            .params
+            .and_then(|params| serde_json::from_value::<ResponseChunkParams>(params).ok());
+        let Some(ResponseChunkParams { id, data }) = params else {
+            warn!("received invalid response chunk, ignoring");
+            return;
+        };
+        let key = id_key(&id);
+        if !self.pending_reqs.contains_key(&key) {
+            warn!("received response chunk with unknown id {id}, ignoring");
+            return;
+        }
+        let result = self.chunked_results.entry(key.clone()).or_default();
+        if result.len() + data.len() > self.max_message_bytes {
+            self.chunked_results.remove(&key);
+            if let Some(trx) = self.pending_reqs.remove(&key) {
+                let error = StdioError::MessageTooLarge(self.max_message_bytes);
+                trx.response_tx.send(Err(error.into())).ok();
             }
+            return;
+        }
+        result.push_str(&data);
+    }
+
+    /// Replaces the result of a chunked response with the result reassembled from the chunks.
+    fn reassemble_chunked_response(
+        &mut self,
+        response: &mut JsonRpcResponse,
+    ) -> Result<(), ProtocolError> {
+        if response.extra.remove(CHUNKED_RESPONSE_KEY) != Some(Value::Bool(true)) {
+            return Ok(());
+        }
+        let serialized = self
+            .chunked_results
+            .remove(&id_key(&response.id))
+            .unwrap_or_default();
+        let result = serde_json::from_str(&serialized).map_err(|e| {
+            ProtocolError::new(
+                ProtocolErrorType::Internal,
+                format!("failed to reassemble chunked response: {e}").into(),
+            )
+        })?;
+        response.result = Some(result);
+        Ok(())
+    }
+
+    fn handle_response(&mut self, mut response: JsonRpcResponse) {
+        let reassembled = self.reassemble_chunked_response(&mut response);
+        match self.pending_reqs.remove(&id_key(&response.id)) {
+            None => match &response.error {
+                // i.e. the server could not parse the request, and could not recover the id
+                Some(error) => error!(
+                    "received error response with unknown id {}: {}",
+                    response.id, error.message
+                ),
+                None => warn!("received response with unknown id, ignoring {:?}", response),
+            },
             Some(trx) => {
+                if let Err(e) = reassembled {
+                    trx.response_tx.send(Err(e)).ok();
+                    return;
+                }
                 let result = match Response::from_jsonrpc_message(response.into(), &trx.request) {
                     Ok(response) => match response {
                         None => {
@@ -105,27 +241,63 @@ where
         }
     }
 
-    fn handle_notification(&mut self, notification: JsonRpcNotification) {
-        let id = notification.method.parse::<u64>().unwrap_or_default();
-        if let Some(trx) = self.pending_reqs.remove(&id) {
-            let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+    /// Returns the key of the request that is associated with a notification stream,
+    /// since the method of stream notifications is the id of the request (either the serialized
+    /// id, or the bare string for string ids). Numeric methods are always treated as stream
+    /// notifications, while other methods are only treated as such if a request with a
+    /// matching id is pending or streaming.
+    fn stream_key(&self, method: &str) -> Option<IdKey> {
+        if let Ok(id) = method.parse::<u64>() {
+            return Some(id_key(&id.into()));
+        }
+        [
+            method.to_string(),
+            id_key(&Value::String(method.to_string())),
+        ]
+        .into_iter()
+        .find(|key| {
+            self.pending_reqs.contains_key(key) || self.notification_links.contains_key(key)
+        })
+    }
+
+    /// Creates the notification stream for a pending request, once the server indicates that
+    /// the response is a stream (via the first notification or heartbeat for the request).
+    /// The idle timeout of the stream starts once it is registered.
+    fn register_stream(&mut self, key: &IdKey) {
+        if let Some(trx) = self.pending_reqs.remove(key) {
+            let (notification_tx, notification_stream) =
+                NotificationSender::channel(self.notification_channel_capacity);
+            let notification_stream = UnsubscribeOnDrop {
+                id: key.clone(),
+                stream: notification_stream,
+                unsubscribe_tx: Some(self.unsubscribe_tx.clone()),
+            };
             trx.response_tx
-                .send(Ok(ServiceResponse::Multiple(
-                    UnboundedReceiverStream::new(notification_rx).boxed(),
-                )))
+                .send(Ok(ServiceResponse::Multiple(notification_stream.boxed())))
                 .ok();
             self.notification_links.insert(
-                id,
+                key.clone(),
                 ClientNotificationLink {
+                    id: serde_json::from_str(key).unwrap_or_default(),
                     request: trx.request,
                     notification_tx,
+                    last_activity: Instant::now(),
                 },
             );
         }
-        match self.notification_links.get(&id) {
+    }
+
+    async fn handle_notification(&mut self, key: IdKey, notification: JsonRpcNotification) {
+        self.register_stream(&key);
+        match self.notification_links.get_mut(&key) {
             None => warn!("received notification with unknown id, ignoring"),
-            Some(link) => match notification.params.is_some() {
+            Some(link) => match notification.stream_complete {
                 true => {
+                    self.notification_links.remove(&key);
+                    self.pending_reqs.remove(&key);
+                }
+                false => {
+                    link.last_activity = Instant::now();
                     let result =
                         match Response::from_jsonrpc_message(notification.into(), &link.request) {
                             Ok(notification) => match notification {
@@ -137,44 +309,164 @@ where
                             },
                             Err(e) => Err(e.into()),
                         };
-                    link.notification_tx.send(result).ok();
-                }
-                false => {
-                    self.notification_links.remove(&id);
-                    self.pending_reqs.remove(&id);
+                    // Reads from the child are never paused for a single consumer, so a
+                    // stream is cancelled if its consumer has fallen behind or has been dropped
+                    if !link.notification_tx.try_send(result) {
+                        if let Some(capacity) = self.notification_channel_capacity {
+                            link.notification_tx
+                                .send_final_error(StdioError::StreamLagged(capacity).into());
+                        }
+                        self.unsubscribe(key).await;
+                    }
                 }
             },
         }
     }
 
+    /// Removes the notification stream, and asks the server to cancel it.
+    /// Only streams that the server has acknowledged (via a notification or heartbeat)
+    /// are unsubscribed, so this has no effect for pending requests, or for streams
+    /// that have already been removed.
+    async fn unsubscribe(&mut self, key: IdKey) {
+        if let Some(link) = self.notification_links.remove(&key) {
+            debug!("unsubscribing from notification stream {key}");
+            self.output_message(
+                JsonRpcNotification::new(UNSUBSCRIBE_METHOD.to_string(), Some(link.id)).into(),
+            )
+            .await;
+        }
+    }
+
+    /// Forwards a notification that is not associated with a request to the subscribers.
+    fn handle_push(&mut self, notification: JsonRpcNotification) {
+        let mut push_subscribers = self.push_subscribers.lock().unwrap();
+        if push_subscribers.senders.is_empty() {
+            debug!(
+                method = notification.method.as_str(),
+                "ignoring pushed notification without subscribers"
+            );
+        }
+        push_subscribers
+            .senders
+            .retain(|tx| tx.send(notification.clone()).is_ok());
+    }
+
+    fn handle_heartbeat(&mut self, notification: JsonRpcNotification) {
+        let key = id_key(&notification.params.unwrap_or_default());
+        // Heartbeats are only sent for streams, so a stream that has
+        // not emitted any items yet is registered by its first heartbeat
+        self.register_stream(&key);
+        if let Some(link) = self.notification_links.get_mut(&key) {
+            link.last_activity = Instant::now();
+        }
+    }
+
+    /// Terminates notification streams that have not received
+    /// any messages within the idle timeout.
+    fn expire_idle_links(&mut self) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        self.notification_links.retain(|id, link| {
+            if link.last_activity.elapsed() < idle_timeout {
+                return true;
+            }
+            warn!("notification stream {id} exceeded idle timeout, closing stream");
+            link.notification_tx
+                .send_final_error(StdioError::StreamIdleTimeout.into());
+            false
+        });
+    }
+
+    /// Fails all pending requests and active notification streams,
+    /// once the connection to the server has closed.
+    fn fail_pending(&mut self, error: impl Fn() -> StdioError) {
+        // Ends the push subscriber streams
+        let mut push_subscribers = self.push_subscribers.lock().unwrap();
+        push_subscribers.is_closed = true;
+        push_subscribers.senders.clear();
+        drop(push_subscribers);
+        self.chunked_results.clear();
+        for (_, trx) in self.pending_reqs.drain() {
+            trx.response_tx.send(Err(error().into())).ok();
+        }
+        for (_, link) in self.notification_links.drain() {
+            link.notification_tx.send_final_error(error().into());
+        }
+    }
+
     async fn run(mut self) {
+        let mut idle_check = interval(
+            self.idle_timeout
+                .map(|idle_timeout| (idle_timeout / 4).max(Duration::from_millis(100)))
+                .unwrap_or(Duration::from_secs(1)),
+        );
+        idle_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            let mut stdout_message = String::new();
             tokio::select! {
-                req_trx = self.to_child_rx.recv() => if let Some(req_trx) = req_trx {
-                    self.handle_outgoing_request(req_trx).await;
+                req_trx = self.to_child_rx.recv(), if self.stdin.is_some() => match req_trx {
+                    Some(req_trx) => self.handle_outgoing_request(req_trx).await,
+                    // All clients have been dropped, so the writer is closed to let the
+                    // child process know that it should exit. Reading continues until
+                    // the child closes stdout.
+                    None => self.stdin = None,
                 },
-                result = self.stdout.read_line(&mut stdout_message) => match result {
-                    Err(e) => error!("StdioClient i/o error reading line from stdout: {}" ,e),
-                    Ok(bytes_read) => {
-                        if bytes_read == 0 {
+                // Reading frames is cancel safe, so partially read messages
+                // will not be lost if another branch completes first.
+                result = self.stdout.next_frame() => match result {
+                    Err(e) => {
+                        error!("StdioClient i/o error reading frame from stdout: {}", e);
+                        let max_message_bytes = match StdioError::from_io_error(&e) {
+                            Some(StdioError::MessageTooLarge(max)) => Some(*max),
+                            _ => None,
+                        };
+                        self.fail_pending(|| match max_message_bytes {
+                            Some(max) => StdioError::MessageTooLarge(max),
+                            None => StdioError::ConnectionClosed,
+                        });
+                        return;
+                    }
+                    Ok(has_frame) => {
+                        if !has_frame {
+                            self.fail_pending(|| StdioError::ConnectionClosed);
                             return;
                         }
-                        match JsonRpcMessage::try_from(serde_json::from_str::<Value>(&stdout_message).unwrap_or_default()) {
+                        let value = match self.stdout.parse_frame::<Value>() {
+                            Ok(value) => value,
+                            Err(e) => {
+                                error!("failed to deserialize message from server: {}", e);
+                                continue;
+                            }
+                        };
+                        match JsonRpcMessage::parse(value, self.validate_jsonrpc_version) {
                             Err(e) => error!("failed to parse message from server: {}", e),
                             Ok(message) => match message {
                                 JsonRpcMessage::Request(request) => self.handle_incoming_request(request).await,
                                 JsonRpcMessage::Response(response) => self.handle_response(response),
-                                JsonRpcMessage::Notification(notification) => self.handle_notification(notification)
+                                JsonRpcMessage::Notification(notification) => {
+                                    if notification.method == HEARTBEAT_METHOD {
+                                        self.handle_heartbeat(notification);
+                                    } else if notification.method == CHUNK_METHOD {
+                                        self.handle_chunk(notification);
+                                    } else if let Some(key) = self.stream_key(&notification.method) {
+                                        // Notification streams use the request id as the method
+                                        self.handle_notification(key, notification).await;
+                                    } else {
+                                        self.handle_push(notification);
+                                    }
+                                }
                             }
                         }
                     }
-                }
+                },
+                // The task holds a sender, so the channel will not close
+                Some(id) = self.unsubscribe_rx.recv() => self.unsubscribe(id).await,
+                _ = idle_check.tick(), if self.idle_timeout.is_some() => self.expire_idle_links(),
             }
         }
     }
 
-    pub(super) fn start(mut self) -> UnboundedSender<ClientRequestTrx<Request, Response>> {
+    pub(super) fn start(mut self) -> Sender<ClientRequestTrx<Request, Response>> {
         let to_child_tx = self.to_child_tx.take().unwrap();
         tokio::spawn(async move {
             self.run().await;
@@ -1,30 +1,74 @@
+mod builder;
+mod child;
 mod comm;
 
+pub use builder::StdioClientBuilder;
+
 use std::{
     path::Path,
+    pin::Pin,
     process::Stdio,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use futures::{ready, stream::BoxStream, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::{
-    io::BufReader,
-    process::{Child, Command},
-    sync::{mpsc::UnboundedSender, oneshot},
-    time::timeout,
+    io::{AsyncRead, AsyncWrite},
+    process::Command,
+    sync::{
+        mpsc::{self, error::TrySendError, Sender, UnboundedSender},
+        oneshot,
+    },
+    time::timeout_at,
 };
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
+use tokio_util::sync::PollSender;
 use tower::Service;
 
 use crate::{
-    ConfigExampleSnippet, ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
-    DEFAULT_TIMEOUT_SECS,
+    error::ProtocolErrorType,
+    format::SerializationFormat,
+    jsonrpc::{JsonRpcNotification, JsonRpcRequest},
+    metrics, trace,
+    util::config::{ensure_non_zero, ConfigError},
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, NotificationStream, ProtocolError,
+    ServiceError, ServiceFuture, ServiceResponse, DEFAULT_TIMEOUT_SECS,
+};
+
+use self::{child::ChildHandle, comm::StdioClientCommTask};
+
+use super::codec::{
+    default_codec, FrameReader, FrameWriter, StdioCodec, DEFAULT_MAX_MESSAGE_BYTES,
 };
 
-use self::comm::StdioClientCommTask;
+use super::{IdKey, RequestJsonRpcConvert, ResponseJsonRpcConvert, StdioError};
 
-use super::{serialize_payload, RequestJsonRpcConvert, ResponseJsonRpcConvert, StdioError};
+/// The default maximum amount of requests that may be queued for writing to the child process.
+pub const DEFAULT_REQUEST_QUEUE_CAPACITY: usize = 1024;
+
+/// A generator of JSON-RPC request ids, used by [`IdStrategy::Custom`].
+pub type IdGenerator = Arc<dyn Fn() -> Value + Send + Sync>;
+
+/// The strategy used by the client to generate the JSON-RPC ids of outgoing requests.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// Numeric ids that increase monotonically, starting at 1.
+    #[default]
+    Monotonic,
+    /// Random UUID strings.
+    Uuid,
+    /// Ids produced by a custom generator (i.e. to forward ids from an upstream client).
+    /// Generated ids must be non-null and unique among in-flight requests; requests with
+    /// a duplicate id will fail with an "internal" error. Cannot be set via configuration files,
+    /// and cannot be serialized.
+    #[serde(skip)]
+    Custom(IdGenerator),
+}
 
 /// Configuration for the stdio client.
 #[derive(Clone, Serialize, Deserialize)]
@@ -33,8 +77,66 @@ pub struct StdioClientConfig {
     /// Optional binary path for spawning child processes.
     /// Defaults to PATH.
     pub bin_path: Option<String>,
-    /// Timeout for client requests in seconds.
+    /// Timeout for client requests in seconds. The remaining time is sent to the server
+    /// in the `deadline_ms` field of each request, so the server may abort requests that the
+    /// client will no longer wait for.
     pub timeout_secs: u64,
+    /// If set, a notification stream will fail with a timeout error if no messages
+    /// (including heartbeats) are received for the stream within this duration in seconds.
+    /// Should be greater than the server's heartbeat interval.
+    pub idle_timeout_secs: Option<u64>,
+    /// If set, the amount of notifications that may be buffered for each notification
+    /// stream. If the consumer of a stream falls behind, the stream fails with a
+    /// [`StdioError::StreamLagged`] error and the server is asked to cancel it, so that
+    /// other streams and responses are not delayed. Notifications are buffered without limit if unset.
+    pub notification_channel_capacity: Option<usize>,
+    /// The format used to encode messages. The child process must use the same format.
+    /// Unless a codec is provided via [`StdioClient::new_with_codec`], messages are
+    /// newline-delimited for JSON, and length-prefixed for binary formats.
+    pub serialization_format: SerializationFormat,
+    /// If enabled, messages from the child process must include a `jsonrpc` field equal to
+    /// [`JSON_RPC_VERSION`](crate::jsonrpc::JSON_RPC_VERSION). Can be disabled
+    /// for interoperability with servers that omit the field or use another version.
+    pub validate_jsonrpc_version: bool,
+    /// Once all clones of the client have been dropped, the child's stdin is closed,
+    /// and the child may exit on its own within this duration in seconds. Afterwards, the
+    /// child is sent `SIGTERM` (on Unix), and is killed if it does not exit within another
+    /// grace period.
+    pub shutdown_grace_secs: u64,
+    /// The maximum size of a single message from the child process in bytes. If a message
+    /// exceeds the limit, pending requests fail with a "payload too large" error, and the client
+    /// stops reading from the child process.
+    pub max_message_bytes: usize,
+    /// If enabled, JSON messages will be pretty-printed, which can be useful for inspecting
+    /// the protocol during development. Since pretty-printed messages contain newlines, messages
+    /// are length-prefixed (unless a codec is provided via [`StdioClient::new_with_codec`]).
+    /// The child process must use length-prefixed framing as well.
+    pub pretty_json: bool,
+    /// The strategy used to generate the JSON-RPC ids of outgoing requests.
+    /// Defaults to monotonically increasing numeric ids.
+    pub id_strategy: IdStrategy,
+    /// If set, the client sends a ping request to the child process once spawned, and waits
+    /// for a response within this duration in seconds. If the child exits (i.e. due to invalid
+    /// arguments) or does not respond in time, creating the client fails with an error containing
+    /// a [`StdioError::Handshake`]. The ping carries the [`Handshake`](crate::handshake::Handshake)
+    /// of the client, and multilink servers respond with their own handshake; if the peers are
+    /// incompatible (or if the result is not a valid handshake), creating the client fails with
+    /// a [`StdioError::IncompatiblePeer`]. Error responses complete the handshake, so servers
+    /// that do not support pings may be used.
+    /// Disabled by default, unless `schema_version` is set.
+    pub handshake_timeout_secs: Option<u64>,
+    /// An application-defined version of the request and response schemas, which is
+    /// sent during the handshake. If the server provides a different schema version,
+    /// the handshake fails. If set, the handshake is performed even if
+    /// `handshake_timeout_secs` is unset, in which case `timeout_secs` is used as
+    /// the handshake timeout.
+    pub schema_version: Option<String>,
+    /// The maximum amount of requests that may be queued for writing to the child process.
+    /// Once the queue is full (i.e. the child process is not reading its stdin), `poll_ready`
+    /// waits for capacity, so that callers are slowed down. Calls made without a preceding
+    /// `poll_ready` wait for capacity instead, and fail with a "timeout" error if the request
+    /// timeout elapses while waiting. Defaults to [`DEFAULT_REQUEST_QUEUE_CAPACITY`].
+    pub request_queue_capacity: usize,
 }
 
 impl ConfigExampleSnippet for StdioClientConfig {
@@ -43,16 +145,101 @@ impl ConfigExampleSnippet for StdioClientConfig {
 # bin_path = ""
 
 # The timeout duration in seconds for requests, defaults to 900
-# timeout_secs = 60"#
+# timeout_secs = 60
+
+# Fail notification streams that receive no messages or heartbeats
+# within this duration in seconds. Disabled by default.
+# idle_timeout_secs = 60
+
+# The amount of notifications buffered for each stream. Streams whose consumers
+# fall behind are cancelled with an error. Unbounded by default.
+# notification_channel_capacity = 64
+
+# The format used to encode messages: "json", "msgpack" or "cbor".
+# Binary formats require the associated crate feature.
+# serialization_format = "json"
+
+# Reject messages with a missing or unsupported "jsonrpc" version field.
+# validate_jsonrpc_version = true
+
+# The time in seconds that the child process may take to exit on its own after
+# stdin is closed, before it is terminated.
+# shutdown_grace_secs = 5
+
+# The maximum size of a single message from the child process in bytes.
+# max_message_bytes = 16777216
+
+# Pretty-print JSON messages for debugging. Messages will be length-prefixed
+# instead of newline-delimited.
+# pretty_json = false
+
+# The strategy used to generate request ids: "monotonic" or "uuid".
+# UUID ids require a server that supports string ids.
+# id_strategy = "monotonic"
+
+# Wait for the child process to respond to a ping within this duration in seconds
+# once spawned, so that a child that exits immediately is reported. Disabled by default.
+# handshake_timeout_secs = 5
+
+# The version of the request and response schemas, sent during the handshake.
+# The handshake fails if the child process uses a different schema version.
+# Enables the handshake, even if handshake_timeout_secs is unset.
+# schema_version = "1"
+
+# The maximum amount of requests queued for writing to the child process.
+# Clients wait for capacity once the queue is full.
+# request_queue_capacity = 1024"#
             .into()
     }
 }
 
+impl ConfigDeprecatedKeys for StdioClientConfig {}
+
+impl ConfigEnvPrefix for StdioClientConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_STDIO_CLIENT"
+    }
+}
+
+impl StdioClientConfig {
+    /// Checks that the bin path is non-empty (if provided),
+    /// and that the timeout, size limits and queue capacity are non-zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self
+            .bin_path
+            .as_ref()
+            .is_some_and(|bin_path| bin_path.is_empty())
+        {
+            return Err(ConfigError::new("bin_path", "bin path should not be empty"));
+        }
+        ensure_non_zero("timeout_secs", self.timeout_secs)?;
+        if let Some(capacity) = self.notification_channel_capacity {
+            ensure_non_zero("notification_channel_capacity", capacity as u64)?;
+        }
+        ensure_non_zero("max_message_bytes", self.max_message_bytes as u64)?;
+        if let Some(handshake_timeout_secs) = self.handshake_timeout_secs {
+            ensure_non_zero("handshake_timeout_secs", handshake_timeout_secs)?;
+        }
+        ensure_non_zero("request_queue_capacity", self.request_queue_capacity as u64)
+    }
+}
+
 impl Default for StdioClientConfig {
     fn default() -> Self {
         Self {
             bin_path: None,
             timeout_secs: DEFAULT_TIMEOUT_SECS,
+            idle_timeout_secs: None,
+            notification_channel_capacity: None,
+            serialization_format: SerializationFormat::Json,
+            validate_jsonrpc_version: true,
+            shutdown_grace_secs: 5,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            pretty_json: false,
+            id_strategy: IdStrategy::Monotonic,
+            handshake_timeout_secs: None,
+            schema_version: None,
+            request_queue_capacity: DEFAULT_REQUEST_QUEUE_CAPACITY,
         }
     }
 }
@@ -63,27 +250,180 @@ where
     Response: ResponseJsonRpcConvert<Request, Response> + Send,
 {
     request: Request,
+    /// The request converted by the caller, so that it is only converted once.
+    /// Taken by the comm task when the request is sent.
+    jsonrpc_request: Option<JsonRpcRequest>,
     response_tx: oneshot::Sender<Result<ServiceResponse<Response>, ProtocolError>>,
+    deadline: Instant,
+}
+
+enum NotificationSender<Response> {
+    Unbounded(UnboundedSender<Result<Response, ProtocolError>>),
+    /// Contains the sender, and the final error of the stream, which is yielded
+    /// after the buffered notifications if the channel was full when the stream ended.
+    Bounded(
+        Sender<Result<Response, ProtocolError>>,
+        Arc<StdMutex<Option<ProtocolError>>>,
+    ),
+}
+
+impl<Response> NotificationSender<Response> {
+    /// Creates a channel for a notification stream. If `capacity` is set,
+    /// the channel is bounded, and notifications will be rejected while the channel is full.
+    fn channel(capacity: Option<usize>) -> (Self, NotificationStream<Response>)
+    where
+        Response: Send + 'static,
+    {
+        match capacity {
+            None => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                (
+                    Self::Unbounded(tx),
+                    UnboundedReceiverStream::new(rx).boxed(),
+                )
+            }
+            Some(capacity) => {
+                let (tx, rx) = mpsc::channel(capacity.max(1));
+                let final_error = Arc::new(StdMutex::new(None));
+                let final_error_stream = {
+                    let final_error = final_error.clone();
+                    futures::stream::once(async move { final_error.lock().unwrap().take() })
+                        .filter_map(|error| async move { error.map(Err) })
+                };
+                (
+                    Self::Bounded(tx, final_error),
+                    ReceiverStream::new(rx).chain(final_error_stream).boxed(),
+                )
+            }
+        }
+    }
+
+    /// Sends a notification without waiting for capacity. Returns false if the
+    /// consumer has been dropped, or if the channel is full.
+    fn try_send(&self, result: Result<Response, ProtocolError>) -> bool {
+        match self {
+            Self::Unbounded(tx) => tx.send(result).is_ok(),
+            Self::Bounded(tx, _) => tx.try_send(result).is_ok(),
+        }
+    }
+
+    /// Sends the final error of the stream. If the channel is full, the error
+    /// is yielded once the consumer has received the buffered notifications.
+    /// The sender should be dropped afterwards, to end the stream.
+    fn send_final_error(&self, error: ProtocolError) {
+        match self {
+            Self::Unbounded(tx) => {
+                tx.send(Err(error)).ok();
+            }
+            Self::Bounded(tx, final_error) => {
+                if let Err(TrySendError::Full(error)) = tx.try_send(Err(error)) {
+                    *final_error.lock().unwrap() = error.err();
+                }
+            }
+        }
+    }
+}
+
+/// Wraps the notification stream returned to the consumer, so that the server
+/// is asked to cancel the stream if the consumer drops it before completion.
+struct UnsubscribeOnDrop<Response> {
+    id: IdKey,
+    stream: NotificationStream<Response>,
+    /// Set to `None` once the stream has completed.
+    unsubscribe_tx: Option<UnboundedSender<IdKey>>,
+}
+
+impl<Response> Stream for UnsubscribeOnDrop<Response> {
+    type Item = Result<Response, ProtocolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let result = self.stream.as_mut().poll_next(cx);
+        if let Poll::Ready(None) = result {
+            self.unsubscribe_tx = None;
+        }
+        result
+    }
+}
+
+impl<Response> Drop for UnsubscribeOnDrop<Response> {
+    fn drop(&mut self) {
+        if let Some(unsubscribe_tx) = self.unsubscribe_tx.take() {
+            unsubscribe_tx.send(std::mem::take(&mut self.id)).ok();
+        }
+    }
+}
+
+/// A notification stream for a long-lived subscription, created via
+/// [`StdioClient::subscribe`]. The server is asked to cancel the subscription once
+/// the handle is dropped, or once [`Subscription::unsubscribe`] is called.
+pub struct Subscription<Response> {
+    stream: NotificationStream<Response>,
+}
+
+impl<Response> Subscription<Response> {
+    /// Cancels the subscription. Notifications that have not been received yet are discarded.
+    pub fn unsubscribe(self) {}
+}
+
+impl<Response> Stream for Subscription<Response> {
+    type Item = Result<Response, ProtocolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+/// Subscribers of notifications pushed by the server, that are not associated with a request.
+#[derive(Default)]
+struct PushSubscribers {
+    senders: Vec<UnboundedSender<JsonRpcNotification>>,
+    /// Set once the connection has closed, so that new subscribers are not added.
+    is_closed: bool,
 }
 
 struct ClientNotificationLink<Request, Response> {
+    /// The raw id of the request, which identifies the stream when unsubscribing.
+    id: Value,
     request: Request,
-    notification_tx: UnboundedSender<Result<Response, ProtocolError>>,
+    notification_tx: NotificationSender<Response>,
+    last_activity: Instant,
 }
 
 /// Client for stdio communication via a child process.
 /// If cloned, this client will continue to communicate with the same child process.
-#[derive(Clone)]
 pub struct StdioClient<Request, Response>
 where
     Request: RequestJsonRpcConvert<Request> + Send + 'static,
     Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
 {
-    _child: Arc<Child>,
-    to_child_tx: UnboundedSender<ClientRequestTrx<Request, Response>>,
+    child: Option<Arc<ChildHandle>>,
+    child_id: Option<u32>,
+    /// Reserves a slot in the request queue in `poll_ready`, which is used by the next `call`.
+    to_child_tx: PollSender<ClientRequestTrx<Request, Response>>,
+    /// Set if a slot in the request queue was reserved by `poll_ready`.
+    has_reserved_slot: bool,
+    push_subscribers: Arc<StdMutex<PushSubscribers>>,
     config: StdioClientConfig,
 }
 
+impl<Request, Response> Clone for StdioClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    /// The clone does not share the slot reserved by this client, if any.
+    fn clone(&self) -> Self {
+        Self {
+            child: self.child.clone(),
+            child_id: self.child_id,
+            to_child_tx: self.to_child_tx.clone(),
+            has_reserved_slot: false,
+            push_subscribers: self.push_subscribers.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
 impl<Request, Response> Service<Request> for StdioClient<Request, Response>
 where
     Request: RequestJsonRpcConvert<Request> + Send + 'static,
@@ -93,25 +433,62 @@ where
     type Error = ServiceError;
     type Future = ServiceFuture<ServiceResponse<Response>>;
 
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Waits for capacity if the request queue is full (i.e. the child process is not
+        // reading its stdin), so that callers are slowed down. Fails if the comm task
+        // has stopped (i.e. the child process exited or closed stdout).
+        let result = ready!(self.to_child_tx.poll_reserve(cx)).map_err(|_| {
+            let error: ProtocolError = StdioError::SendRequestCommTask.into();
+            error.into()
+        });
+        self.has_reserved_slot = result.is_ok();
+        Poll::Ready(result)
     }
 
     fn call(&mut self, request: Request) -> Self::Future {
-        let to_child_tx = self.to_child_tx.clone();
-        let timeout_duration = Duration::from_secs(self.config.timeout_secs);
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(self.config.timeout_secs);
+        let mut jsonrpc_request = request.into_jsonrpc_request();
+        jsonrpc_request.trace_context = trace::current_trace_context();
+        let method = match metrics::ENABLED {
+            true => jsonrpc_request.method.clone(),
+            false => String::new(),
+        };
+        let (response_tx, response_rx) = oneshot::channel();
+        let trx = ClientRequestTrx {
+            request,
+            jsonrpc_request: Some(jsonrpc_request),
+            response_tx,
+            deadline,
+        };
+        // The request is queued via the slot reserved by `poll_ready`. If no slot was
+        // reserved, the future waits for capacity before the request is queued.
+        let unqueued = match std::mem::take(&mut self.has_reserved_slot) {
+            true => self
+                .to_child_tx
+                .send_item(trx)
+                .err()
+                .map(|e| e.into_inner().zip(self.to_child_tx.get_ref().cloned())),
+            false => Some(Some(trx).zip(self.to_child_tx.get_ref().cloned())),
+        };
         Box::pin(async move {
-            let (response_tx, response_rx) = oneshot::channel();
-            to_child_tx
-                .send(ClientRequestTrx {
-                    request,
-                    response_tx,
-                })
-                .map_err(|_| StdioError::SendRequestCommTask)?;
-            let response_result = timeout(timeout_duration, response_rx)
-                .await
-                .map_err(|_| StdioError::Timeout)?;
-            Ok(response_result.map_err(|_| StdioError::RecvResponseCommTask)??)
+            let result = async {
+                if let Some(unqueued) = unqueued {
+                    // The sender is closed if the comm task has stopped
+                    let (trx, to_child_tx) = unqueued.ok_or(StdioError::SendRequestCommTask)?;
+                    timeout_at(deadline.into(), to_child_tx.send(trx))
+                        .await
+                        .map_err(|_| StdioError::Timeout)?
+                        .map_err(|_| StdioError::SendRequestCommTask)?;
+                }
+                let response_result = timeout_at(deadline.into(), response_rx)
+                    .await
+                    .map_err(|_| StdioError::Timeout)?;
+                Ok(response_result.map_err(|_| StdioError::RecvResponseCommTask)??)
+            }
+            .await;
+            metrics::record_result(metrics::STDIO_CLIENT_TRANSPORT, &method, start, &result);
+            result
         })
     }
 }
@@ -123,11 +500,43 @@ where
 {
     /// Creates a new client for stdio communication. A new child process will be
     /// spawned, and a [`std::io::Error`] will be returned if spawning fails.
+    /// An error of kind [`std::io::ErrorKind::InvalidInput`] will be returned
+    /// if the configuration is invalid.
     pub async fn new(
         program: &str,
         args: &[&str],
         config: StdioClientConfig,
     ) -> std::io::Result<Self> {
+        let codec = default_codec(config.serialization_format, config.pretty_json);
+        Self::spawn(program, args, config, codec).await
+    }
+
+    /// Creates a new client for stdio communication, which will use `codec` to
+    /// frame messages. The child process must use the same codec.
+    pub async fn new_with_codec<C: StdioCodec + 'static>(
+        program: &str,
+        args: &[&str],
+        config: StdioClientConfig,
+        codec: C,
+    ) -> std::io::Result<Self> {
+        Self::spawn(program, args, config, Arc::new(codec)).await
+    }
+
+    /// Returns a builder for spawning `program` and configuring the client fluently,
+    /// as an alternative to providing a [`StdioClientConfig`] to [`StdioClient::new`].
+    pub fn builder(program: impl Into<String>) -> StdioClientBuilder<Request, Response> {
+        StdioClientBuilder::new(program.into())
+    }
+
+    async fn spawn(
+        program: &str,
+        args: &[&str],
+        config: StdioClientConfig,
+        codec: Arc<dyn StdioCodec>,
+    ) -> std::io::Result<Self> {
+        config
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
         let program_with_bin_path = config.bin_path.as_ref().map(|bin_path| {
             Path::new(bin_path)
                 .join(program)
@@ -147,13 +556,137 @@ where
         .kill_on_drop(true)
         .spawn()?;
         let stdin = child.stdin.take().unwrap();
-        let stdout = BufReader::new(child.stdout.take().unwrap());
-        let comm_task = StdioClientCommTask::new(stdin, stdout);
+        let stdout = child.stdout.take().unwrap();
+        let (mut writer, mut reader) = frames(Box::new(stdout), Box::new(stdin), &config, codec);
+        // Setting the schema version implies the handshake, since it would otherwise be unused
+        let handshake_timeout_secs = config
+            .handshake_timeout_secs
+            .or_else(|| config.schema_version.as_ref().map(|_| config.timeout_secs));
+        if let Some(handshake_timeout_secs) = handshake_timeout_secs {
+            child::handshake(
+                &mut child,
+                &mut writer,
+                &mut reader,
+                config.schema_version.clone(),
+                config.validate_jsonrpc_version,
+                Duration::from_secs(handshake_timeout_secs),
+            )
+            .await?;
+        }
+        let mut client = Self::from_frames(writer, reader, config);
+        client.child_id = child.id();
+        let shutdown_grace = Duration::from_secs(client.config.shutdown_grace_secs);
+        client.child = Some(Arc::new(ChildHandle::new(child, shutdown_grace)));
+        Ok(client)
+    }
+
+    /// Returns the process id of the child process. Returns `None` if the
+    /// client does not communicate with a child process (i.e. TCP clients).
+    pub fn child_id(&self) -> Option<u32> {
+        self.child_id
+    }
+
+    /// Kills the child process, and waits for it to exit. Pending requests and active
+    /// notification streams will fail with a "service unavailable" error, and new requests
+    /// will be rejected. May be called from any clone of the client. Has no effect if the
+    /// client does not communicate with a child process.
+    pub async fn kill(&self) -> std::io::Result<()> {
+        if let Some(child) = self.child.as_ref() {
+            child.kill().await?;
+        }
+        Ok(())
+    }
+
+    /// Creates a new client that reads messages from `reader` and writes requests
+    /// to `writer`, instead of spawning a child process. Useful for testing the client
+    /// with in-memory pipes (i.e. [`tokio::io::duplex`]), along with
+    /// [`StdioServer::with_io`](crate::stdio::server::StdioServer::with_io).
+    pub fn with_io<R, W>(reader: R, writer: W, config: StdioClientConfig) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let codec = default_codec(config.serialization_format, config.pretty_json);
+        Self::from_io(Box::new(reader), Box::new(writer), config, codec)
+    }
+
+    /// Creates a new client that communicates via JSON-RPC messages over the
+    /// provided reader and writer, instead of a child process.
+    pub(crate) fn from_io(
+        reader: Box<dyn AsyncRead + Send + Unpin>,
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        config: StdioClientConfig,
+        codec: Arc<dyn StdioCodec>,
+    ) -> Self {
+        let (writer, reader) = frames(reader, writer, &config, codec);
+        Self::from_frames(writer, reader, config)
+    }
+
+    fn from_frames(writer: FrameWriter, reader: FrameReader, config: StdioClientConfig) -> Self {
+        let push_subscribers = Arc::new(StdMutex::new(PushSubscribers::default()));
+        let comm_task = StdioClientCommTask::new(writer, reader, &config, push_subscribers.clone());
         let to_child_tx = comm_task.start();
-        Ok(Self {
-            _child: Arc::new(child),
-            to_child_tx,
+        Self {
+            child: None,
+            child_id: None,
+            to_child_tx: PollSender::new(to_child_tx),
+            has_reserved_slot: false,
+            push_subscribers,
             config,
-        })
+        }
     }
+
+    /// Sends a request that is expected to return a notification stream, and returns
+    /// a [`Subscription`] that receives the notifications until the server completes the
+    /// stream, or until the subscription is dropped. Returns a "bad request" error if the
+    /// server returns a single response instead of a stream.
+    pub async fn subscribe(
+        &mut self,
+        request: Request,
+    ) -> Result<Subscription<Response>, ServiceError> {
+        match self.call(request).await? {
+            ServiceResponse::Multiple(stream) => Ok(Subscription { stream }),
+            ServiceResponse::Single(_) => Err(ProtocolError::new(
+                ProtocolErrorType::BadRequest,
+                "server did not return a notification stream for the subscription".into(),
+            )
+            .into()),
+        }
+    }
+
+    /// Returns a stream of notifications pushed by the server that are not associated
+    /// with a request (i.e. via [`ServerPushHandle`](crate::stdio::server::ServerPushHandle)).
+    /// Each subscriber receives all notifications pushed after it subscribed. The stream
+    /// ends once the connection to the server has closed.
+    pub fn subscribe_push(&self) -> BoxStream<'static, JsonRpcNotification> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut push_subscribers = self.push_subscribers.lock().unwrap();
+        if !push_subscribers.is_closed {
+            push_subscribers.senders.push(tx);
+        }
+        UnboundedReceiverStream::new(rx).boxed()
+    }
+}
+
+/// Creates the frame writer and reader for communicating with the server.
+fn frames(
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    config: &StdioClientConfig,
+    codec: Arc<dyn StdioCodec>,
+) -> (FrameWriter, FrameReader) {
+    (
+        FrameWriter::new(
+            writer,
+            codec.clone(),
+            config.serialization_format,
+            config.pretty_json,
+        ),
+        FrameReader::new(
+            reader,
+            codec,
+            config.serialization_format,
+            config.max_message_bytes,
+        ),
+    )
 }
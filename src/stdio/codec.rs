@@ -0,0 +1,310 @@
+use std::{io, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    format::{FormatError, SerializationFormat},
+    handshake::{Handshake, FRAMING_FEATURE, SERIALIZATION_FORMAT_FEATURE},
+};
+
+use super::StdioError;
+
+/// Buffers that grow past this capacity (due to a large message) are
+/// released after use, instead of being retained for the next message.
+const MAX_RETAINED_BUFFER_CAPACITY: usize = 64 * 1024;
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The default maximum size of a single message in bytes.
+pub(crate) const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Defines how JSON-RPC messages are framed when they are read from or written
+/// to the underlying byte stream of the stdio transport (and transports based on it, such as TCP).
+/// A codec may also transform the payload of each frame (i.e. for compression or encryption).
+/// The same codec must be used by both the client and the server.
+pub trait StdioCodec: Send + Sync {
+    /// Attempts to decode a single frame from the start of `src`. If a complete
+    /// frame is available, the decoded payload should be appended to `dst`,
+    /// and the amount of bytes consumed from `src` should be returned.
+    /// Returns `None` if more data is needed to decode the frame.
+    fn decode(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<Option<usize>>;
+
+    /// Same as [`StdioCodec::decode`], but the first `searched` bytes of `src` are known to
+    /// not complete a frame, since a previous call returned `None` for them. Codecs that search
+    /// for a delimiter should override this to resume the search, so that a large frame
+    /// received over many reads is not scanned repeatedly.
+    fn decode_from(
+        &self,
+        src: &[u8],
+        searched: usize,
+        dst: &mut Vec<u8>,
+    ) -> io::Result<Option<usize>> {
+        let _ = searched;
+        self.decode(src, dst)
+    }
+
+    /// Encodes `payload` as a single frame, and appends the frame to `dst`.
+    fn encode(&self, payload: &[u8], dst: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Returns the name of the framing, which is compared with the framing of the peer
+    /// during the handshake. Unnamed codecs (the default) are not compared.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// A codec that delimits frames with a newline. Payloads must not contain newlines,
+/// so encoding fails for payloads that do (i.e. pretty-printed JSON).
+/// This is the default codec.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LineCodec;
+
+impl StdioCodec for LineCodec {
+    fn decode(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        self.decode_from(src, 0, dst)
+    }
+
+    fn decode_from(
+        &self,
+        src: &[u8],
+        searched: usize,
+        dst: &mut Vec<u8>,
+    ) -> io::Result<Option<usize>> {
+        let searched = searched.min(src.len());
+        Ok(src[searched..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|index| {
+                let index = searched + index;
+                dst.extend_from_slice(&src[..index]);
+                index + 1
+            }))
+    }
+
+    fn encode(&self, payload: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        if payload.contains(&b'\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload contains a newline, which is not supported by the line codec",
+            ));
+        }
+        dst.extend_from_slice(payload);
+        dst.push(b'\n');
+        Ok(())
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("line")
+    }
+}
+
+/// A codec that prefixes each frame with the length of the payload,
+/// as a 32-bit big-endian integer. Payloads may contain any bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthPrefixedCodec;
+
+impl StdioCodec for LengthPrefixedCodec {
+    fn decode(&self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        let Some(prefix) = src.get(..LENGTH_PREFIX_SIZE) else {
+            return Ok(None);
+        };
+        let length = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+        Ok(src
+            .get(LENGTH_PREFIX_SIZE..LENGTH_PREFIX_SIZE + length)
+            .map(|payload| {
+                dst.extend_from_slice(payload);
+                LENGTH_PREFIX_SIZE + length
+            }))
+    }
+
+    fn encode(&self, payload: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame is too large"))?;
+        dst.extend_from_slice(&length.to_be_bytes());
+        dst.extend_from_slice(payload);
+        Ok(())
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some("length_prefixed")
+    }
+}
+
+/// Returns the default codec for a serialization format. Newline-delimited framing
+/// is used for compact JSON, and length-prefixed framing is used for binary formats
+/// and pretty-printed JSON, since those payloads may contain newlines.
+pub(crate) fn default_codec(format: SerializationFormat, pretty_json: bool) -> Arc<dyn StdioCodec> {
+    match format.is_binary() || pretty_json {
+        true => Arc::new(LengthPrefixedCodec),
+        false => Arc::new(LineCodec),
+    }
+}
+
+/// Clears a message buffer so it can be reused for the next message.
+fn reset_buffer(buf: &mut Vec<u8>) {
+    match buf.capacity() > MAX_RETAINED_BUFFER_CAPACITY {
+        true => *buf = Vec::new(),
+        false => buf.clear(),
+    }
+}
+
+/// Reads frames from a byte stream using a [`StdioCodec`].
+/// Buffers are reused across frames to avoid allocating for each message.
+pub(crate) struct FrameReader {
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    codec: Arc<dyn StdioCodec>,
+    format: SerializationFormat,
+    max_frame_bytes: usize,
+    read_buf: Vec<u8>,
+    /// The start of the unconsumed bytes in `read_buf`. Consumed bytes are removed
+    /// before reading more data, rather than after each frame.
+    read_start: usize,
+    /// The amount of unconsumed bytes that the codec has searched without completing a frame.
+    searched: usize,
+    frame: Vec<u8>,
+}
+
+impl FrameReader {
+    pub(crate) fn new(
+        reader: Box<dyn AsyncRead + Send + Unpin>,
+        codec: Arc<dyn StdioCodec>,
+        format: SerializationFormat,
+        max_frame_bytes: usize,
+    ) -> Self {
+        Self {
+            reader,
+            codec,
+            format,
+            max_frame_bytes,
+            read_buf: Vec::new(),
+            read_start: 0,
+            searched: 0,
+            frame: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "stdio-server")]
+    pub(crate) fn set_codec(&mut self, codec: Arc<dyn StdioCodec>) {
+        self.codec = codec;
+    }
+
+    /// Returns the handshake for this end of the stream, which includes the serialization
+    /// format and the name of the codec (if any) as features.
+    pub(crate) fn handshake(&self, schema_version: Option<String>) -> Handshake {
+        let handshake = Handshake::new(schema_version)
+            .with_feature(SERIALIZATION_FORMAT_FEATURE, self.format.to_string());
+        match self.codec.name() {
+            Some(name) => handshake.with_feature(FRAMING_FEATURE, name),
+            None => handshake,
+        }
+    }
+
+    /// Reads the next frame, which can be accessed via [`FrameReader::frame`].
+    /// Returns false if the stream has ended. This method is cancel safe: partially
+    /// read frames are retained, so reading will resume on the next call.
+    /// Returns a [`StdioError::MessageTooLarge`] error (wrapped in an [`io::Error`]) if
+    /// a frame exceeds the maximum size, since the stream cannot be recovered afterwards.
+    pub(crate) async fn next_frame(&mut self) -> io::Result<bool> {
+        reset_buffer(&mut self.frame);
+        loop {
+            let unconsumed = &self.read_buf[self.read_start..];
+            if let Some(consumed) =
+                self.codec
+                    .decode_from(unconsumed, self.searched, &mut self.frame)?
+            {
+                self.read_start += consumed;
+                self.searched = 0;
+                if self.frame.len() > self.max_frame_bytes {
+                    return Err(self.too_large_error());
+                }
+                return Ok(true);
+            }
+            self.searched = unconsumed.len();
+            // The incomplete frame is buffered, so its size is bounded as it is read
+            if unconsumed.len() > self.max_frame_bytes + LENGTH_PREFIX_SIZE {
+                return Err(self.too_large_error());
+            }
+            self.read_buf.drain(..self.read_start);
+            self.read_start = 0;
+            if self.reader.read_buf(&mut self.read_buf).await? == 0 {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn too_large_error(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            StdioError::MessageTooLarge(self.max_frame_bytes),
+        )
+    }
+
+    /// Returns the payload of the last frame read.
+    #[cfg(any(feature = "stdio-server", feature = "ws-client", feature = "ws-server"))]
+    pub(crate) fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Deserializes the payload of the last frame read.
+    pub(crate) fn parse_frame<T: DeserializeOwned>(&self) -> Result<T, FormatError> {
+        self.format.deserialize(&self.frame)
+    }
+}
+
+/// Serializes messages using a [`SerializationFormat`], and writes them as frames to a byte stream
+/// using a [`StdioCodec`].
+/// Buffers are reused across frames to avoid allocating for each message.
+pub(crate) struct FrameWriter {
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    codec: Arc<dyn StdioCodec>,
+    format: SerializationFormat,
+    pretty_json: bool,
+    payload_buf: Vec<u8>,
+    frame_buf: Vec<u8>,
+}
+
+impl FrameWriter {
+    pub(crate) fn new(
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        codec: Arc<dyn StdioCodec>,
+        format: SerializationFormat,
+        pretty_json: bool,
+    ) -> Self {
+        Self {
+            writer,
+            codec,
+            format,
+            pretty_json,
+            payload_buf: Vec::new(),
+            frame_buf: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "stdio-server")]
+    pub(crate) fn set_codec(&mut self, codec: Arc<dyn StdioCodec>) {
+        self.codec = codec;
+    }
+
+    /// Serializes and writes a message as a single frame.
+    /// Returns the size of the frame in bytes.
+    pub(crate) async fn write_message<R: Serialize>(&mut self, message: &R) -> io::Result<usize> {
+        let result = self.write_message_inner(message).await;
+        reset_buffer(&mut self.payload_buf);
+        reset_buffer(&mut self.frame_buf);
+        result
+    }
+
+    async fn write_message_inner<R: Serialize>(&mut self, message: &R) -> io::Result<usize> {
+        match self.pretty_json && self.format == SerializationFormat::Json {
+            true => serde_json::to_writer_pretty(&mut self.payload_buf, message)?,
+            false => self
+                .format
+                .serialize_into(message, &mut self.payload_buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        }
+        self.codec.encode(&self.payload_buf, &mut self.frame_buf)?;
+        self.writer.write_all(&self.frame_buf).await?;
+        Ok(self.frame_buf.len())
+    }
+}
@@ -1,19 +1,41 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 
 use crate::{
     error::ProtocolErrorType,
+    handshake::{HandshakeError, HANDSHAKE_ERROR_CODE},
     jsonrpc::{JsonRpcMessage, JsonRpcRequest},
     ProtocolError,
 };
 
 #[cfg(feature = "stdio-client")]
 pub mod client;
+/// Framing codecs for the stdio transport.
+pub mod codec;
 
 #[cfg(feature = "stdio-server")]
 pub mod server;
 
+/// The raw JSON-RPC id of a request, serialized so that it can be used as a map key.
+/// Ids of different types are distinct (i.e. `1` and `"1"`).
+pub(crate) type IdKey = String;
+
+pub(crate) fn id_key(id: &Value) -> IdKey {
+    id.to_string()
+}
+
+/// The field of a response whose result was sent via [`CHUNK_METHOD`](crate::jsonrpc::CHUNK_METHOD)
+/// notifications.
+pub(crate) const CHUNKED_RESPONSE_KEY: &str = "chunked";
+
+/// The params of a [`CHUNK_METHOD`](crate::jsonrpc::CHUNK_METHOD) notification.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ResponseChunkParams {
+    pub id: Value,
+    pub data: String,
+}
+
 /// Errors that are specific to stdio communication.
 #[derive(Debug, Error)]
 pub enum StdioError {
@@ -25,19 +47,46 @@ pub enum StdioError {
     RecvResponseCommTask,
     #[error("client does not support serving request")]
     ClientRequestUnsupported,
+    #[error("notification stream timed out, no messages received within idle timeout")]
+    StreamIdleTimeout,
+    #[error("notification stream consumer fell behind, more than {0} notifications were buffered")]
+    StreamLagged(usize),
+    #[error("connection closed before the request completed (i.e. the child process exited)")]
+    ConnectionClosed,
+    #[error("message exceeds the maximum size of {0} bytes")]
+    MessageTooLarge(usize),
+    #[error("child process failed the handshake: {0}")]
+    Handshake(String),
+    #[error(transparent)]
+    IncompatiblePeer(#[from] HandshakeError),
+}
+
+impl StdioError {
+    /// Returns the stdio error contained in an [`std::io::Error`], if any.
+    pub(crate) fn from_io_error(error: &std::io::Error) -> Option<&Self> {
+        error.get_ref().and_then(|e| e.downcast_ref::<Self>())
+    }
 }
 
 impl Into<ProtocolError> for StdioError {
     fn into(self) -> ProtocolError {
         let error_type = match &self {
-            StdioError::SendRequestCommTask => ProtocolErrorType::Internal,
-            StdioError::Timeout => ProtocolErrorType::Internal,
-            StdioError::RecvResponseCommTask => ProtocolErrorType::Internal,
+            StdioError::SendRequestCommTask => ProtocolErrorType::ServiceUnavailable,
+            StdioError::Timeout => ProtocolErrorType::Timeout,
+            StdioError::RecvResponseCommTask => ProtocolErrorType::ServiceUnavailable,
             StdioError::ClientRequestUnsupported => ProtocolErrorType::BadRequest,
+            StdioError::StreamIdleTimeout => ProtocolErrorType::Timeout,
+            StdioError::StreamLagged(_) => ProtocolErrorType::Internal,
+            StdioError::ConnectionClosed => ProtocolErrorType::ServiceUnavailable,
+            StdioError::MessageTooLarge(_) => ProtocolErrorType::PayloadTooLarge,
+            StdioError::Handshake(_) => ProtocolErrorType::ServiceUnavailable,
+            StdioError::IncompatiblePeer(_) => ProtocolErrorType::BadRequest,
         };
-        ProtocolError {
-            error_type,
-            error: Box::new(self),
+        let is_incompatible_peer = matches!(self, StdioError::IncompatiblePeer(_));
+        let error = ProtocolError::new(error_type, Box::new(self));
+        match is_incompatible_peer {
+            true => error.with_code(HANDSHAKE_ERROR_CODE),
+            false => error,
         }
     }
 }
@@ -74,9 +123,3 @@ pub trait ResponseJsonRpcConvert<Request, Response> {
     /// Returns [`Value::Null`]
     fn into_jsonrpc_message(response: Response, id: Value) -> JsonRpcMessage;
 }
-
-fn serialize_payload<R: Serialize>(payload: &R) -> String {
-    let mut serialized = serde_json::to_string(payload).unwrap();
-    serialized.push_str("\n");
-    serialized
-}
@@ -1,24 +1,110 @@
-use std::pin::Pin;
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use futures::Future;
+use futures::{stream::abortable, Future, FutureExt, StreamExt};
 use serde_json::Value;
-use tokio::{
-    io::{AsyncWriteExt, Stdout},
-    sync::Mutex,
-};
-use tower::{timeout::future::ResponseFuture, Service};
-use tracing::error;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::timeout;
+use tower::Service;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
 use crate::{
-    jsonrpc::{JsonRpcMessage, JsonRpcNotification, JsonRpcResponse},
-    ServiceError, ServiceFuture, ServiceResponse,
+    error::ProtocolErrorType,
+    extensions::with_extensions,
+    handshake::{Handshake, HandshakeError},
+    jsonrpc::{
+        JsonRpcErrorCode, JsonRpcMessage, JsonRpcNotification, JsonRpcResponse, CHUNK_METHOD,
+        HEARTBEAT_METHOD, ID_KEY, PING_METHOD, UNSUBSCRIBE_METHOD,
+    },
+    metrics,
+    stdio::{ResponseChunkParams, CHUNKED_RESPONSE_KEY},
+    trace,
+    util::{
+        deadline_exceeded_error, intercept_response, service_timeout_error, should_sample_log,
+        split_into_chunks, with_deadline,
+    },
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
 };
 
 use super::{
-    serialize_payload, IdentifiedNotification, RequestJsonRpcConvert, ResponseJsonRpcConvert,
-    ServerNotificationLink, StdioServer,
+    super::codec::FrameWriter, ActiveRequestGuard, IdentifiedNotification, OutgoingMessage,
+    RequestJsonRpcConvert, ResponseJsonRpcConvert, ServerNotificationLink, StdioServer,
+    StdioServerConfig,
 };
 
+/// Context for a request that is being handled by the service.
+pub(super) struct RequestContext {
+    id: Value,
+    method: String,
+    request_id: Option<String>,
+    start: Instant,
+    span: Span,
+    active_guard: ActiveRequestGuard,
+    deadline: Option<Duration>,
+    service_timeout: Duration,
+}
+
+/// The error sent to the client if the service panics while handling request `id`.
+fn panic_error(id: &Value) -> ProtocolError {
+    error!("service panicked while handling request {id}");
+    ProtocolError::new(
+        ProtocolErrorType::Internal,
+        "service panicked while handling request".into(),
+    )
+}
+
+/// Splits the result of a response into [`CHUNK_METHOD`] notifications if the serialized
+/// result exceeds `max_chunk_bytes`. The notifications are followed by the response,
+/// which is marked as chunked instead of containing the result.
+fn chunk_response_message(message: JsonRpcMessage, max_chunk_bytes: usize) -> Vec<JsonRpcMessage> {
+    let JsonRpcMessage::Response(mut response) = message else {
+        return vec![message];
+    };
+    let Some(serialized) = response
+        .result
+        .as_ref()
+        .map(Value::to_string)
+        .filter(|serialized| serialized.len() > max_chunk_bytes)
+    else {
+        return vec![response.into()];
+    };
+    let mut messages = split_into_chunks(&serialized, max_chunk_bytes)
+        .into_iter()
+        .map(|data| {
+            let params = ResponseChunkParams {
+                id: response.id.clone(),
+                data: data.to_string(),
+            };
+            let params = serde_json::to_value(params).expect("chunk params should serialize");
+            JsonRpcMessage::from(JsonRpcNotification::new(
+                CHUNK_METHOD.to_string(),
+                Some(params),
+            ))
+            .with_request_id(response.request_id.clone())
+        })
+        .collect::<Vec<_>>();
+    response.result = None;
+    response
+        .extra
+        .insert(CHUNKED_RESPONSE_KEY.to_string(), Value::Bool(true));
+    messages.push(response.into());
+    messages
+}
+
+/// Fails the service future with a "timeout" error if it does not complete within `duration`.
+async fn with_service_timeout<T>(
+    future: impl Future<Output = Result<T, ServiceError>>,
+    duration: Duration,
+) -> Result<T, ServiceError> {
+    timeout(duration, future).await.unwrap_or_else(|_| {
+        warn!("service timed out after {} ms", duration.as_millis());
+        Err(service_timeout_error(duration).into())
+    })
+}
+
 impl<Request, Response, S> StdioServer<Request, Response, S>
 where
     Request: RequestJsonRpcConvert<Request> + Send + 'static,
@@ -31,112 +117,377 @@ where
         > + Send
         + 'static,
 {
-    async fn output_message(stdout: &Mutex<Stdout>, message: JsonRpcMessage) {
-        let serialized_message = serialize_payload(&message);
-        stdout
-            .lock()
-            .await
-            .write_all(serialized_message.as_bytes())
-            .await
-            .ok();
+    /// Writes queued messages to the client, until the server stops the task.
+    pub(super) async fn write_messages(
+        mut stdout: FrameWriter,
+        config: Arc<StdioServerConfig>,
+        mut outgoing_rx: Receiver<OutgoingMessage>,
+    ) {
+        while let Some(Some(message)) = outgoing_rx.recv().await {
+            let frame_bytes = stdout.write_message(&message).await;
+            if let Ok(frame_bytes) = frame_bytes {
+                if config.log_payload_sizes && should_sample_log(config.log_sample_rate) {
+                    info!(response_bytes = frame_bytes, "sent stdio message");
+                }
+            }
+        }
+    }
+
+    /// Queues a message to be written by the writer task.
+    pub(super) async fn output_message(
+        outgoing_tx: &Sender<OutgoingMessage>,
+        message: JsonRpcMessage,
+    ) {
+        outgoing_tx.send(Some(message)).await.ok();
     }
 
     pub(super) fn handle_response_future(
         &self,
-        result_future: ResponseFuture<
-            Pin<Box<dyn Future<Output = Result<ServiceResponse<Response>, ServiceError>> + Send>>,
-        >,
-        id: u64,
+        result_future: ServiceFuture<ServiceResponse<Response>>,
+        context: RequestContext,
     ) {
-        let stdout = self.stdout.clone();
+        let RequestContext {
+            id,
+            method,
+            request_id,
+            start,
+            span,
+            active_guard,
+            deadline,
+            service_timeout,
+        } = context;
+        let outgoing_tx = self
+            .outgoing_tx
+            .clone()
+            .expect("outgoing_tx should be initialized");
         let notification_streams_tx = self
             .notification_streams_tx
             .clone()
             .expect("notfication_streams_tx should be initialized");
+        let max_consecutive_stream_frames = self.config.max_consecutive_stream_frames;
+        let response_chunk_bytes = self.config.response_chunk_bytes;
+        let response_interceptor = self.response_interceptor.clone();
 
         tokio::spawn(async move {
-            let result = result_future.await;
+            let result_future = with_service_timeout(result_future, service_timeout);
+            // Catch panics from the service future and the response interceptor, so that the
+            // client receives an error response instead of waiting for the request to time out.
+            let result = AssertUnwindSafe(async {
+                let result = with_deadline(result_future, deadline)
+                    .instrument(span)
+                    .await;
+                intercept_response(result, response_interceptor.as_ref())
+            })
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|_| Err(panic_error(&id).into()));
+            metrics::record_result(metrics::STDIO_SERVER_TRANSPORT, &method, start, &result);
             match result {
                 Ok(response) => match response {
                     ServiceResponse::Single(response) => {
-                        let message = Response::into_jsonrpc_message(response, id.into());
-                        Self::output_message(stdout.as_ref(), message).await;
+                        let message = catch_unwind(AssertUnwindSafe(|| {
+                            Response::into_jsonrpc_message(response, id.clone())
+                        }))
+                        .unwrap_or_else(|_| {
+                            JsonRpcResponse::new(Err(panic_error(&id)), id.clone()).into()
+                        })
+                        .with_request_id(request_id);
+                        let messages = match response_chunk_bytes {
+                            Some(max_chunk_bytes) => {
+                                chunk_response_message(message, max_chunk_bytes)
+                            }
+                            None => vec![message],
+                        };
+                        for message in messages {
+                            Self::output_message(&outgoing_tx, message).await;
+                        }
                     }
                     ServiceResponse::Multiple(stream) => {
+                        // Panics are caught here as well, ending the stream with an error
+                        let panic_id = id.clone();
+                        let stream = AssertUnwindSafe(stream)
+                            .catch_unwind()
+                            .map(move |item| item.unwrap_or_else(|_| Err(panic_error(&panic_id))));
+                        // The stream is aborted if the client unsubscribes
+                        let (stream, abort_handle) = abortable(stream);
+                        active_guard.set_abort_handle(abort_handle.clone());
                         notification_streams_tx
-                            .send(ServerNotificationLink {
+                            .send(ServerNotificationLink::new(
                                 id,
-                                stream,
-                                is_complete: false,
-                            })
+                                request_id,
+                                stream.boxed(),
+                                Some(abort_handle),
+                                max_consecutive_stream_frames,
+                                Some(active_guard),
+                            ))
                             .ok();
+                        return;
                     }
                 },
                 Err(e) => {
                     Self::output_message(
-                        stdout.as_ref(),
-                        JsonRpcResponse::new(Err(e.into()), id.into()).into(),
+                        &outgoing_tx,
+                        JsonRpcMessage::from(JsonRpcResponse::new(Err(e.into()), id))
+                            .with_request_id(request_id),
                     )
                     .await
                 }
             }
+            drop(active_guard);
         });
     }
 
-    pub(super) fn handle_request(&mut self, serialized_request: String) {
-        let value: Value = serde_json::from_str(&serialized_request).unwrap_or_default();
-        let (result_future, id) = match JsonRpcMessage::try_from(value) {
+    /// Sends a response for a request that will not be handled by the service
+    /// (i.e. rejected requests, or pings).
+    fn respond_immediately(&self, response: JsonRpcResponse) {
+        let outgoing_tx = self
+            .outgoing_tx
+            .clone()
+            .expect("outgoing_tx should be initialized");
+        tokio::spawn(async move { Self::output_message(&outgoing_tx, response.into()).await });
+    }
+
+    /// Returns the handshake of the server in response to a ping. If the ping carries the
+    /// handshake of the client, and the peers are incompatible, an error containing the
+    /// handshake of the server is returned instead.
+    fn handshake_response(&self, params: Option<Value>) -> Result<Value, ProtocolError> {
+        let local = self.stdin.handshake(self.config.schema_version.clone());
+        let local_value = serde_json::to_value(&local).expect("handshake should serialize");
+        let Some(params) = params else {
+            return Ok(local_value);
+        };
+        let result = serde_json::from_value::<Handshake>(params)
+            .map_err(|e| HandshakeError::Invalid(e.to_string()))
+            .and_then(|peer| local.negotiate(&peer));
+        match result {
+            Ok(()) => Ok(local_value),
             Err(e) => {
-                error!("could not parse json rpc message from client: {e}, request: {serialized_request}");
+                warn!("rejecting handshake from client: {e}");
+                Err(ProtocolError::from(e).with_data(local_value))
+            }
+        }
+    }
+
+    pub(super) fn handle_request(&mut self) {
+        let start = Instant::now();
+        let serialized_request = self.stdin.frame();
+        if self.config.log_payload_sizes && should_sample_log(self.config.log_sample_rate) {
+            info!(
+                request_bytes = serialized_request.len(),
+                "received stdio request"
+            );
+        }
+        let value: Value = match self.stdin.parse_frame() {
+            Ok(value) => value,
+            Err(e) => {
+                error!("could not deserialize message from client: {e}");
+                // The id of a malformed message cannot be detected reliably,
+                // so a null id is used, as required by the JSON-RPC specification
+                self.respond_immediately(JsonRpcResponse::new_error(
+                    JsonRpcErrorCode::ParseError,
+                    e.to_string(),
+                    Value::Null,
+                ));
                 return;
             }
-            Ok(message) => match message {
-                JsonRpcMessage::Request(jsonrpc_request) => {
-                    let id = jsonrpc_request.id.as_u64().unwrap_or_default();
-                    match Request::from_jsonrpc_request(jsonrpc_request) {
-                        Err(e) => {
-                            error!("could not derive request enum from json rpc request: {e}");
+        };
+        let id_value = value.get(ID_KEY).cloned();
+        let (result_future, context) =
+            match JsonRpcMessage::parse(value, self.config.validate_jsonrpc_version) {
+                Err(e) => {
+                    error!(
+                        "could not parse json rpc message from client: {e}, request: {}",
+                        String::from_utf8_lossy(serialized_request)
+                    );
+                    // Responses and notifications from the client do not warrant a response,
+                    // but an id is likely to be present if the message was intended as a request
+                    if let Some(id) = id_value {
+                        self.respond_immediately(JsonRpcResponse::new_error(
+                            JsonRpcErrorCode::InvalidRequest,
+                            e.to_string(),
+                            id,
+                        ));
+                    }
+                    return;
+                }
+                Ok(message) => match message {
+                    JsonRpcMessage::Request(jsonrpc_request)
+                        if jsonrpc_request.method == PING_METHOD =>
+                    {
+                        // Pings are answered without invoking the service
+                        let response = self.handshake_response(jsonrpc_request.params);
+                        self.respond_immediately(JsonRpcResponse::new(
+                            response,
+                            jsonrpc_request.id,
+                        ));
+                        return;
+                    }
+                    JsonRpcMessage::Request(jsonrpc_request) => {
+                        let method = jsonrpc_request.method.clone();
+                        let request_id = jsonrpc_request.request_id.clone();
+                        // The full id is compared, so that distinct ids (i.e. `1` and `"1"`) do not collide
+                        let Some(active_guard) = self.active_ids.acquire(&jsonrpc_request.id)
+                        else {
+                            let raw_id = jsonrpc_request.id;
+                            warn!("rejecting request with duplicate id {raw_id}");
+                            let error = ProtocolError::new(
+                                ProtocolErrorType::BadRequest,
+                                format!("request id {raw_id} is already in use").into(),
+                            );
+                            self.respond_immediately(JsonRpcResponse {
+                                request_id,
+                                ..JsonRpcResponse::new(Err(error), raw_id)
+                            });
+                            return;
+                        };
+                        // The raw id is echoed in the response, since clients may use
+                        // non-numeric request ids
+                        let id = jsonrpc_request.id.clone();
+                        let deadline = jsonrpc_request.deadline_ms.map(Duration::from_millis);
+                        if deadline == Some(Duration::ZERO) {
+                            warn!("rejecting request {id} with expired deadline");
+                            self.respond_immediately(JsonRpcResponse {
+                                request_id,
+                                ..JsonRpcResponse::new(Err(deadline_exceeded_error()), id)
+                            });
                             return;
                         }
-                        Ok(request) => match request {
-                            None => {
-                                error!("unknown json rpc request received");
+                        let span = info_span!(
+                            "stdio_request",
+                            method = %method,
+                            id = %id,
+                            request_id = request_id.as_deref(),
+                        );
+                        trace::set_parent_from_trace_context(
+                            &span,
+                            jsonrpc_request.trace_context.as_ref(),
+                        );
+                        let request = catch_unwind(AssertUnwindSafe(|| {
+                            Request::from_jsonrpc_request(jsonrpc_request)
+                        }))
+                        .unwrap_or_else(|_| Err(panic_error(&id)));
+                        match request {
+                            Err(e) => {
+                                error!("could not derive request enum from json rpc request: {e}");
+                                self.respond_immediately(JsonRpcResponse {
+                                    request_id,
+                                    ..JsonRpcResponse::new(Err(e), id)
+                                });
                                 return;
                             }
-                            Some(request) => (self.service.call(request), id),
-                        },
+                            Ok(request) => match request {
+                                None => {
+                                    error!("unknown json rpc request received");
+                                    self.respond_immediately(JsonRpcResponse {
+                                        request_id,
+                                        ..JsonRpcResponse::new_error(
+                                            JsonRpcErrorCode::MethodNotFound,
+                                            format!("unknown method: {method}"),
+                                            id,
+                                        )
+                                    });
+                                    return;
+                                }
+                                Some(request) => {
+                                    let service_timeout = self.service_timeout(&request);
+                                    let extensions = self.extensions.clone();
+                                    // A panic while creating the future is reported
+                                    // in the same manner as a panic while polling it
+                                    let result_future = catch_unwind(AssertUnwindSafe(|| {
+                                        with_extensions(extensions, || {
+                                            span.in_scope(|| self.service.call(request))
+                                        })
+                                        .boxed()
+                                    }))
+                                    .unwrap_or_else(|_| {
+                                        futures::future::ready(Err(panic_error(&id).into())).boxed()
+                                    });
+                                    (
+                                        result_future,
+                                        RequestContext {
+                                            id,
+                                            method,
+                                            request_id,
+                                            start,
+                                            span,
+                                            active_guard,
+                                            deadline,
+                                            service_timeout,
+                                        },
+                                    )
+                                }
+                            },
+                        }
                     }
-                }
-                _ => {
-                    error!("ignoring non-request json rpc message from client");
-                    return;
-                }
-            },
+                    JsonRpcMessage::Notification(notification)
+                        if notification.method == UNSUBSCRIBE_METHOD =>
+                    {
+                        self.handle_unsubscribe(notification);
+                        return;
+                    }
+                    _ => {
+                        error!("ignoring non-request json rpc message from client");
+                        return;
+                    }
+                },
+            };
+        self.handle_response_future(result_future, context)
+    }
+
+    /// Cancels the notification stream identified by the params of the notification.
+    fn handle_unsubscribe(&self, notification: JsonRpcNotification) {
+        // The id is matched as a raw value, since clients may use non-numeric request ids
+        let Some(id) = notification.params.filter(|params| !params.is_null()) else {
+            warn!("ignoring unsubscribe notification without a stream id");
+            return;
         };
-        self.handle_response_future(result_future, id)
+        match self.active_ids.abort(&id) {
+            true => debug!("client unsubscribed from notification stream {id}"),
+            false => debug!("ignoring unsubscribe for inactive notification stream {id}"),
+        }
+    }
+
+    /// Sends a heartbeat for each of the provided notification stream ids.
+    pub(super) async fn send_heartbeats(outgoing_tx: &Sender<OutgoingMessage>, ids: Vec<Value>) {
+        for id in ids {
+            Self::output_message(
+                outgoing_tx,
+                JsonRpcNotification::new(HEARTBEAT_METHOD.to_string(), Some(id)).into(),
+            )
+            .await;
+        }
     }
 
     pub(super) async fn handle_notification(
-        &self,
+        outgoing_tx: &Sender<OutgoingMessage>,
         id_notification: IdentifiedNotification<Response>,
     ) {
         match id_notification.result {
             Some(result) => {
-                let id = id_notification.id.into();
+                let id = id_notification.id;
                 let message = match result {
-                    Ok(response) => Response::into_jsonrpc_message(response, id).into(),
+                    Ok(response) => catch_unwind(AssertUnwindSafe(|| {
+                        Response::into_jsonrpc_message(response, id.clone())
+                    }))
+                    .unwrap_or_else(|_| {
+                        let e = panic_error(&id);
+                        JsonRpcNotification::new_with_result_params(Err(e), id.to_string()).into()
+                    }),
                     Err(e) => {
                         JsonRpcNotification::new_with_result_params(Err(e), id.to_string()).into()
                     }
                 };
-                Self::output_message(self.stdout.as_ref(), message).await;
+                let message = message.with_request_id(id_notification.request_id);
+                Self::output_message(outgoing_tx, message).await;
             }
             None => {
-                // Send value with `None` params to let client know that the stream
-                // has terminated.
+                // Let the client know that the stream has terminated
                 Self::output_message(
-                    self.stdout.as_ref(),
-                    JsonRpcNotification::new(id_notification.id.to_string(), None).into(),
+                    outgoing_tx,
+                    JsonRpcMessage::from(JsonRpcNotification::new_stream_complete(
+                        id_notification.id.to_string(),
+                    ))
+                    .with_request_id(id_notification.request_id),
                 )
                 .await;
             }
@@ -1,60 +1,302 @@
 mod comm;
 
 use std::{
+    collections::HashMap,
     marker::PhantomData,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
     task::{Context, Poll},
     time::Duration,
 };
 
 use futures::{
-    stream::{pending, select_all, SelectAll},
+    stream::{pending, select_all, AbortHandle, SelectAll},
     Stream, StreamExt,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::{
-    io::{stdin, stdout, AsyncBufReadExt, BufReader, Stdin, Stdout},
-    sync::{
-        mpsc::{self, UnboundedSender},
-        Mutex,
-    },
+    io::{stdin, stdout, AsyncRead, AsyncWrite},
+    sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::{interval_at, timeout, Instant, MissedTickBehavior},
 };
-use tower::{timeout::Timeout, Service};
+use tower::{Layer, Service};
+use tracing::{error, warn};
 
 use crate::{
-    ConfigExampleSnippet, NotificationStream, ProtocolError, ServiceError, ServiceFuture,
-    ServiceResponse, DEFAULT_TIMEOUT_SECS,
+    error::ProtocolErrorType,
+    extensions::Extensions,
+    format::SerializationFormat,
+    jsonrpc::{
+        JsonRpcMessage, JsonRpcNotification, JsonRpcResponse, CHUNK_METHOD, HEARTBEAT_METHOD,
+    },
+    util::{
+        config::{ensure_non_zero, ensure_sample_rate, ConfigError},
+        BoxedFutureService, ResponseInterceptor,
+    },
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, NotificationStream, ProtocolError,
+    ServiceError, ServiceFuture, ServiceResponse, DEFAULT_TIMEOUT_SECS,
 };
 
-use super::{serialize_payload, RequestJsonRpcConvert, ResponseJsonRpcConvert};
+use super::{
+    codec::{default_codec, FrameReader, FrameWriter, StdioCodec, DEFAULT_MAX_MESSAGE_BYTES},
+    id_key, IdKey, RequestJsonRpcConvert, ResponseJsonRpcConvert, StdioError,
+};
+
+/// The maximum amount of outgoing messages that may be queued for the writer task.
+/// Responders wait for capacity once the queue is full, so that a slow consumer
+/// applies backpressure instead of growing the queue without limit.
+const OUTGOING_QUEUE_CAPACITY: usize = 256;
+
+/// Resolves the service timeout for a request. See [`StdioServer::with_timeout_policy`].
+type TimeoutPolicy<Request> = Arc<dyn Fn(&Request) -> Duration + Send + Sync>;
+
+/// A message queued for the writer task. `None` stops the task
+/// once all previously queued messages have been written.
+type OutgoingMessage = Option<JsonRpcMessage>;
+
+/// Sends notifications to the client that are not associated with a request
+/// (i.e. "config reloaded" events). Created via [`StdioServer::push_handle`], and may be
+/// cloned and used from any task. Clients can receive the notifications via
+/// [`StdioClient::subscribe_push`](crate::stdio::client::StdioClient::subscribe_push).
+#[derive(Clone)]
+pub struct ServerPushHandle {
+    push_tx: Sender<JsonRpcNotification>,
+}
+
+impl ServerPushHandle {
+    /// Sends a notification with the provided method name and params to the client.
+    /// The method name must not be numeric, since numeric method names are reserved for
+    /// notification streams. Returns a "bad request" error if the method name is reserved,
+    /// and a "service unavailable" error if the server has stopped.
+    pub async fn push(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<(), ProtocolError> {
+        let method = method.into();
+        if method.parse::<u64>().is_ok() || method == HEARTBEAT_METHOD || method == CHUNK_METHOD {
+            return Err(ProtocolError::new(
+                ProtocolErrorType::BadRequest,
+                format!("method name `{method}` is reserved").into(),
+            ));
+        }
+        self.push_tx
+            .send(JsonRpcNotification::new(method, params))
+            .await
+            .map_err(|_| {
+                ProtocolError::new(
+                    ProtocolErrorType::ServiceUnavailable,
+                    "server has stopped".into(),
+                )
+            })
+    }
+}
 
 /// Configuration for the stdio server.
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct StdioServerConfig {
-    /// Timeout for service requests in seconds.
+    /// Timeout for service requests in seconds. May be resolved per request
+    /// via [`StdioServer::with_timeout_policy`] instead.
     pub service_timeout_secs: u64,
+    /// If enabled, the sizes of incoming requests and outgoing
+    /// messages will be logged.
+    pub log_payload_sizes: bool,
+    /// The fraction of payload size log lines to emit, between `0.0` and `1.0`.
+    /// Can be used to reduce log volume at high throughput.
+    pub log_sample_rate: f64,
+    /// The maximum amount of time in seconds to wait for in-flight requests and
+    /// active notification streams to complete, once the parent process closes stdin.
+    pub shutdown_timeout_secs: u64,
+    /// If set, a heartbeat notification will be sent at this interval (in seconds)
+    /// for each active notification stream, so that clients can detect idle
+    /// or stalled streams. See [`crate::jsonrpc::HEARTBEAT_METHOD`].
+    pub heartbeat_interval_secs: Option<u64>,
+    /// The maximum amount of consecutive notifications that a single notification stream
+    /// may send, before yielding to other streams and incoming requests. Ensures that a chatty
+    /// stream does not starve other streams. Ordering within each stream is preserved.
+    pub max_consecutive_stream_frames: usize,
+    /// The format used to encode messages. The parent process must use the same format.
+    /// Unless a codec is provided via [`StdioServer::with_codec`], messages are newline-delimited
+    /// for JSON, and length-prefixed for binary formats.
+    pub serialization_format: SerializationFormat,
+    /// If enabled, requests must include a `jsonrpc` field equal to
+    /// [`JSON_RPC_VERSION`](crate::jsonrpc::JSON_RPC_VERSION). Can be disabled
+    /// for interoperability with clients that omit the field or use another version.
+    pub validate_jsonrpc_version: bool,
+    /// The maximum size of a single request in bytes. If a request exceeds the limit,
+    /// an error response is sent, and the server stops reading from stdin.
+    pub max_message_bytes: usize,
+    /// If enabled, JSON messages will be pretty-printed, which can be useful for inspecting
+    /// the protocol during development. Since pretty-printed messages contain newlines, messages
+    /// are length-prefixed (unless a codec is provided via [`StdioServer::with_codec`]). The
+    /// parent process must use length-prefixed framing as well.
+    pub pretty_json: bool,
+    /// An application-defined version of the request and response schemas. If provided,
+    /// handshakes from clients with a different schema version are rejected with a
+    /// "bad request" error. See [`Handshake`](crate::handshake::Handshake).
+    pub schema_version: Option<String>,
+    /// If set, the results of single responses that exceed this size in bytes (once serialized
+    /// as JSON) are sent as multiple [`CHUNK_METHOD`](crate::jsonrpc::CHUNK_METHOD) notifications,
+    /// which the client reassembles before converting the response. Avoids writing oversized
+    /// frames for large responses. The parent process must use a client that supports chunking.
+    pub response_chunk_bytes: Option<usize>,
 }
 
 impl ConfigExampleSnippet for StdioServerConfig {
     fn config_example_snippet() -> String {
         r#"# The timeout duration in seconds for the underlying backend service.
-# service_timeout_secs = 60"#
+# service_timeout_secs = 60
+
+# Log the sizes of incoming requests and outgoing messages.
+# log_payload_sizes = false
+
+# The fraction of payload size log lines to emit, between 0.0 and 1.0.
+# log_sample_rate = 1.0
+
+# The maximum time in seconds to wait for pending responses and streams
+# after stdin is closed.
+# shutdown_timeout_secs = 30
+
+# Send a heartbeat at this interval in seconds for each active notification
+# stream. Disabled by default.
+# heartbeat_interval_secs = 15
+
+# The maximum amount of consecutive notifications sent by one stream
+# before yielding to other streams and incoming requests.
+# max_consecutive_stream_frames = 16
+
+# The format used to encode messages: "json", "msgpack" or "cbor".
+# Binary formats require the associated crate feature.
+# serialization_format = "json"
+
+# Reject requests with a missing or unsupported "jsonrpc" version field.
+# validate_jsonrpc_version = true
+
+# The maximum size of a single request in bytes.
+# max_message_bytes = 16777216
+
+# Pretty-print JSON messages for debugging. Messages will be length-prefixed
+# instead of newline-delimited.
+# pretty_json = false
+
+# The version of the request and response schemas. Handshakes from clients
+# with a different schema version are rejected.
+# schema_version = "1"
+
+# Split the results of responses larger than this size in bytes into
+# multiple messages. Disabled by default.
+# response_chunk_bytes = 1048576"#
             .into()
     }
 }
 
+impl ConfigDeprecatedKeys for StdioServerConfig {}
+
+impl ConfigEnvPrefix for StdioServerConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_STDIO_SERVER"
+    }
+}
+
+impl StdioServerConfig {
+    /// Checks that the timeouts, intervals, size limits and sample rate are usable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ensure_non_zero("service_timeout_secs", self.service_timeout_secs)?;
+        if let Some(heartbeat_interval_secs) = self.heartbeat_interval_secs {
+            ensure_non_zero("heartbeat_interval_secs", heartbeat_interval_secs)?;
+        }
+        ensure_non_zero(
+            "max_consecutive_stream_frames",
+            self.max_consecutive_stream_frames as u64,
+        )?;
+        ensure_non_zero("max_message_bytes", self.max_message_bytes as u64)?;
+        if let Some(response_chunk_bytes) = self.response_chunk_bytes {
+            ensure_non_zero("response_chunk_bytes", response_chunk_bytes as u64)?;
+        }
+        ensure_sample_rate("log_sample_rate", self.log_sample_rate)
+    }
+}
+
 impl Default for StdioServerConfig {
     fn default() -> Self {
         Self {
             service_timeout_secs: DEFAULT_TIMEOUT_SECS,
+            log_payload_sizes: false,
+            log_sample_rate: 1.0,
+            shutdown_timeout_secs: 30,
+            heartbeat_interval_secs: None,
+            max_consecutive_stream_frames: 16,
+            serialization_format: SerializationFormat::Json,
+            validate_jsonrpc_version: true,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            pretty_json: false,
+            schema_version: None,
+            response_chunk_bytes: None,
+        }
+    }
+}
+
+/// Tracks the ids of in-flight requests and active notification streams,
+/// so that requests with duplicate ids can be rejected, and so that
+/// notification streams can be cancelled when the client unsubscribes.
+#[derive(Clone, Default)]
+struct ActiveRequestIds(Arc<StdMutex<HashMap<IdKey, Option<AbortHandle>>>>);
+
+impl ActiveRequestIds {
+    /// Marks the id as active until the returned guard is dropped.
+    /// Returns `None` if the id is already active.
+    fn acquire(&self, id: &Value) -> Option<ActiveRequestGuard> {
+        let key = id_key(id);
+        let mut ids = self.0.lock().unwrap();
+        if ids.contains_key(&key) {
+            return None;
+        }
+        ids.insert(key.clone(), None);
+        Some(ActiveRequestGuard {
+            ids: self.clone(),
+            key,
+        })
+    }
+
+    /// Aborts the notification stream for the id. Returns false
+    /// if there is no active notification stream for the id.
+    fn abort(&self, id: &Value) -> bool {
+        match self.0.lock().unwrap().get(&id_key(id)) {
+            Some(Some(abort_handle)) => {
+                abort_handle.abort();
+                true
+            }
+            _ => false,
         }
     }
 }
 
+struct ActiveRequestGuard {
+    ids: ActiveRequestIds,
+    key: IdKey,
+}
+
+impl ActiveRequestGuard {
+    /// Registers the abort handle of the notification stream for the guarded id.
+    fn set_abort_handle(&self, abort_handle: AbortHandle) {
+        if let Some(handle) = self.ids.0.lock().unwrap().get_mut(&self.key) {
+            *handle = Some(abort_handle);
+        }
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.ids.0.lock().unwrap().remove(&self.key);
+    }
+}
+
 struct IdentifiedNotification<Response> {
-    id: u64,
+    id: Value,
+    request_id: Option<String>,
     result: Option<Result<Response, ProtocolError>>,
 }
 
@@ -71,40 +313,106 @@ where
         > + Send
         + 'static,
 {
-    service: Timeout<S>,
-    stdin: BufReader<Stdin>,
-    stdout: Arc<Mutex<Stdout>>,
+    config: Arc<StdioServerConfig>,
+    service: S,
+    /// If unset, [`StdioServerConfig::service_timeout_secs`] is used for all requests.
+    timeout_policy: Option<TimeoutPolicy<Request>>,
+    response_interceptor: Option<ResponseInterceptor<Response>>,
+    /// Included in the extensions of each request.
+    extensions: Extensions,
+    stdin: FrameReader,
+    /// Moved into the writer task once the server runs.
+    stdout: Option<FrameWriter>,
+    outgoing_tx: Option<Sender<OutgoingMessage>>,
     notification_streams_tx: Option<UnboundedSender<ServerNotificationLink<Response>>>,
+    push_tx: Sender<JsonRpcNotification>,
+    /// Moved into the main loop once the server runs.
+    push_rx: Option<Receiver<JsonRpcNotification>>,
+    active_ids: ActiveRequestIds,
     request_phantom: PhantomData<Request>,
 }
 
 struct ServerNotificationLink<Response> {
-    id: u64,
+    /// The raw id of the request. Null for the placeholder stream of the server.
+    id: Value,
+    request_id: Option<String>,
     stream: NotificationStream<Response>,
+    /// Set if the stream may be cancelled by the client.
+    abort_handle: Option<AbortHandle>,
     is_complete: bool,
+    consecutive_frames: usize,
+    max_consecutive_frames: usize,
+    /// Keeps the request id active until the stream completes.
+    active_guard: Option<ActiveRequestGuard>,
+}
+
+impl<Response> ServerNotificationLink<Response> {
+    fn new(
+        id: Value,
+        request_id: Option<String>,
+        stream: NotificationStream<Response>,
+        abort_handle: Option<AbortHandle>,
+        max_consecutive_frames: usize,
+        active_guard: Option<ActiveRequestGuard>,
+    ) -> Self {
+        Self {
+            id,
+            request_id,
+            stream,
+            abort_handle,
+            active_guard,
+            is_complete: false,
+            consecutive_frames: 0,
+            max_consecutive_frames: max_consecutive_frames.max(1),
+        }
+    }
 }
 
 impl<Response> Stream for ServerNotificationLink<Response> {
     type Item = IdentifiedNotification<Response>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Yield after emitting the maximum amount of consecutive frames, so that
+        // other streams and incoming requests can make progress.
+        if self.consecutive_frames >= self.max_consecutive_frames {
+            self.consecutive_frames = 0;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
         match self.stream.as_mut().poll_next(cx) {
-            Poll::Pending => Poll::Pending,
+            Poll::Pending => {
+                self.consecutive_frames = 0;
+                Poll::Pending
+            }
             Poll::Ready(result) => match result {
                 None => match self.is_complete {
                     true => Poll::Ready(None),
+                    // The client unsubscribed, so it does not need to be notified
+                    false if self.abort_handle.as_ref().is_some_and(|h| h.is_aborted()) => {
+                        self.is_complete = true;
+                        self.active_guard = None;
+                        Poll::Ready(None)
+                    }
                     false => {
                         self.is_complete = true;
+                        // Release the id so it can be reused once the client
+                        // is notified that the stream has terminated
+                        self.active_guard = None;
                         Poll::Ready(Some(IdentifiedNotification {
-                            id: self.id,
+                            id: self.id.clone(),
+                            request_id: self.request_id.clone(),
                             result: None,
                         }))
                     }
                 },
-                Some(result) => Poll::Ready(Some(IdentifiedNotification {
-                    id: self.id,
-                    result: Some(result),
-                })),
+                Some(result) => {
+                    self.consecutive_frames += 1;
+                    Poll::Ready(Some(IdentifiedNotification {
+                        id: self.id.clone(),
+                        request_id: self.request_id.clone(),
+                        result: Some(result),
+                    }))
+                }
             },
         }
     }
@@ -125,46 +433,289 @@ where
     /// Creates a new server for stdio communication. Client requests will be
     /// converted and forwarded to the `service`.
     pub fn new(service: S, config: StdioServerConfig) -> Self {
+        Self::with_io(service, config, stdin(), stdout())
+    }
+
+    /// Creates a new server for stdio communication, after checking the configuration
+    /// via [`StdioServerConfig::validate`]. A [`ConfigError`] will be returned
+    /// if the configuration is invalid.
+    pub fn try_new(service: S, config: StdioServerConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::new(service, config))
+    }
+
+    /// Creates a new server that reads requests from `reader` and writes responses
+    /// to `writer`, instead of stdin/stdout. Useful for testing the server
+    /// with in-memory pipes (i.e. [`tokio::io::duplex`]).
+    pub fn with_io<R, W>(service: S, config: StdioServerConfig, reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let codec = default_codec(config.serialization_format, config.pretty_json);
+        Self::from_io(service, config, Box::new(reader), Box::new(writer), codec)
+    }
+
+    /// Creates a new server that communicates via JSON-RPC messages
+    /// over the provided reader and writer, instead of stdin/stdout.
+    pub(crate) fn from_io(
+        service: S,
+        config: StdioServerConfig,
+        reader: Box<dyn AsyncRead + Send + Unpin>,
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        codec: Arc<dyn StdioCodec>,
+    ) -> Self {
+        let format = config.serialization_format;
+        let (push_tx, push_rx) = mpsc::channel(OUTGOING_QUEUE_CAPACITY);
         Self {
-            service: Timeout::new(service, Duration::from_secs(config.service_timeout_secs)),
-            stdin: BufReader::new(stdin()),
-            stdout: Arc::new(Mutex::new(stdout())),
+            service,
+            timeout_policy: None,
+            response_interceptor: None,
+            extensions: Extensions::new(),
+            stdin: FrameReader::new(reader, codec.clone(), format, config.max_message_bytes),
+            stdout: Some(FrameWriter::new(writer, codec, format, config.pretty_json)),
+            config: Arc::new(config),
+            outgoing_tx: None,
             notification_streams_tx: None,
+            push_tx,
+            push_rx: Some(push_rx),
+            active_ids: Default::default(),
             request_phantom: Default::default(),
         }
     }
 
+    /// Wraps the backend service with a `tower` [`Layer`], such as a rate or
+    /// concurrency limit. Layers are applied beneath the service timeout, and may be
+    /// stacked by calling this method multiple times. The outermost layer is the one
+    /// provided last.
+    pub fn with_layer<L>(
+        self,
+        layer: L,
+    ) -> StdioServer<Request, Response, BoxedFutureService<L::Service>>
+    where
+        L: Layer<S>,
+        L::Service: Service<Request, Response = ServiceResponse<Response>> + Send + 'static,
+        <L::Service as Service<Request>>::Error: Into<ServiceError>,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        StdioServer {
+            service: BoxedFutureService::new(layer.layer(self.service)),
+            timeout_policy: self.timeout_policy,
+            response_interceptor: self.response_interceptor,
+            extensions: self.extensions,
+            config: self.config,
+            stdin: self.stdin,
+            stdout: self.stdout,
+            outgoing_tx: self.outgoing_tx,
+            notification_streams_tx: self.notification_streams_tx,
+            push_tx: self.push_tx,
+            push_rx: self.push_rx,
+            active_ids: self.active_ids,
+            request_phantom: Default::default(),
+        }
+    }
+
+    /// Sets a policy that resolves the service timeout for each request, so that
+    /// i.e. quick lookups and long-running generations may use different timeouts.
+    /// Replaces [`StdioServerConfig::service_timeout_secs`], which is used for all requests
+    /// by default. Deadlines sent by the client still apply, if they are shorter.
+    pub fn with_timeout_policy<F>(mut self, timeout_policy: F) -> Self
+    where
+        F: Fn(&Request) -> Duration + Send + Sync + 'static,
+    {
+        self.timeout_policy = Some(Arc::new(timeout_policy));
+        self
+    }
+
+    /// Sets an interceptor that will be invoked for each service response before it is
+    /// converted into a JSON-RPC message, i.e. for redacting fields or recording audit logs.
+    /// For streaming responses, the interceptor is invoked for each item of the stream,
+    /// as a single response. Errors returned by the interceptor replace the response (or the
+    /// stream item). Replaces any interceptor that was set previously.
+    pub fn with_response_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut ServiceResponse<Response>) -> Result<(), ProtocolError> + Send + Sync + 'static,
+    {
+        self.response_interceptor = Some(Arc::new(interceptor));
+        self
+    }
+
+    /// Inserts a value into the extensions of each request, i.e. shared state such as a
+    /// database pool. The value can be read by the service via
+    /// [`request_extension`](crate::extensions::request_extension).
+    pub fn with_extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// Inserts all of the extensions into the extensions of each request.
+    #[cfg(any(feature = "tcp-server", feature = "ws-server"))]
+    pub(crate) fn with_extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions.extend(extensions);
+        self
+    }
+
+    /// Returns the service timeout for the request.
+    fn service_timeout(&self, request: &Request) -> Duration {
+        match &self.timeout_policy {
+            Some(timeout_policy) => timeout_policy(request),
+            None => Duration::from_secs(self.config.service_timeout_secs),
+        }
+    }
+
+    /// Sets the codec used to frame messages. The parent process must use the same codec.
+    /// Newline-delimited framing ([`LineCodec`](super::codec::LineCodec)) is used by default
+    /// for JSON, and length-prefixed framing ([`LengthPrefixedCodec`](super::codec::LengthPrefixedCodec))
+    /// is used by default for binary formats.
+    pub fn with_codec<C: StdioCodec + 'static>(mut self, codec: C) -> Self {
+        let codec: Arc<dyn StdioCodec> = Arc::new(codec);
+        self.stdin.set_codec(codec.clone());
+        // The writer is only moved once the server runs, which consumes the server
+        if let Some(stdout) = self.stdout.as_mut() {
+            stdout.set_codec(codec);
+        }
+        self
+    }
+
+    /// Returns a handle for sending notifications to the client that are not associated
+    /// with a request. Notifications pushed before the server runs will be sent once it starts,
+    /// and notifications pushed once the server is shutting down will not be sent.
+    pub fn push_handle(&self) -> ServerPushHandle {
+        ServerPushHandle {
+            push_tx: self.push_tx.clone(),
+        }
+    }
+
+    /// Sends the remaining notifications for in-flight requests and active notification streams,
+    /// until all requests and streams are complete.
+    async fn drain(
+        outgoing_tx: &Sender<OutgoingMessage>,
+        notification_streams: &mut SelectAll<ServerNotificationLink<Response>>,
+        notification_stream_rx: &mut UnboundedReceiver<ServerNotificationLink<Response>>,
+    ) {
+        let mut is_rx_closed = false;
+        // The dummy notification stream will always remain in the set
+        while !is_rx_closed || notification_streams.len() > 1 {
+            tokio::select! {
+                id_notification = notification_streams.next() => {
+                    Self::handle_notification(outgoing_tx, id_notification.unwrap()).await;
+                }
+                stream = notification_stream_rx.recv(), if !is_rx_closed => match stream {
+                    Some(stream) => notification_streams.push(stream),
+                    None => is_rx_closed = true,
+                }
+            }
+        }
+    }
+
+    /// Stops the writer task once all queued messages have been written.
+    async fn stop_writer(outgoing_tx: &Sender<OutgoingMessage>, writer: JoinHandle<()>) {
+        outgoing_tx.send(None).await.ok();
+        writer.await.ok();
+    }
+
     /// Listens & processes requests from the parent process via stdin, until a [`std::io::Error`]
-    /// is encountered.
+    /// is encountered (including requests that exceed [`StdioServerConfig::max_message_bytes`]).
+    /// Once stdin is closed, new requests will not be accepted, and the server
+    /// will wait for in-flight requests and notification streams to complete (up to
+    /// [`StdioServerConfig::shutdown_timeout_secs`]) before returning.
     pub async fn run(mut self) -> std::io::Result<()> {
+        // All outgoing messages are written by a single task, so that responders
+        // do not contend for the writer
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(OUTGOING_QUEUE_CAPACITY);
+        let writer = tokio::spawn(Self::write_messages(
+            self.stdout
+                .take()
+                .expect("writer should not be moved before the server runs"),
+            self.config.clone(),
+            outgoing_rx,
+        ));
+        self.outgoing_tx = Some(outgoing_tx.clone());
+        let mut push_rx = self
+            .push_rx
+            .take()
+            .expect("push receiver should not be moved before the server runs");
+
         // insert dummy notification stream so that tokio::select (in main loop)
         // does not immediately return if no streams exist
         let (notification_stream_tx, mut notification_stream_rx) = mpsc::unbounded_channel();
         self.notification_streams_tx = Some(notification_stream_tx);
         let mut notification_streams: SelectAll<ServerNotificationLink<Response>> =
-            select_all([ServerNotificationLink {
-                id: u64::MAX,
-                stream: pending().boxed(),
-                is_complete: false,
-            }]);
+            select_all([ServerNotificationLink::new(
+                Value::Null,
+                None,
+                pending().boxed(),
+                None,
+                self.config.max_consecutive_stream_frames,
+                None,
+            )]);
+
+        let heartbeat_period = Duration::from_secs(
+            self.config
+                .heartbeat_interval_secs
+                .unwrap_or_default()
+                .max(1),
+        );
+        let mut heartbeat = interval_at(Instant::now() + heartbeat_period, heartbeat_period);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         loop {
-            let mut serialized_request = String::new();
             tokio::select! {
-                read_result = self.stdin.read_line(&mut serialized_request) => {
-                    if read_result? == 0 {
-                        break;
+                // Reading frames is cancel safe, so partially read requests
+                // will not be lost if another branch completes first.
+                read_result = self.stdin.next_frame() => match read_result {
+                    Ok(true) => self.handle_request(),
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("failed to read request from client: {e}");
+                        if let Some(StdioError::MessageTooLarge(max)) = StdioError::from_io_error(&e) {
+                            // The id of the request is unknown, since the request was not read
+                            let response =
+                                JsonRpcResponse::new(Err(StdioError::MessageTooLarge(*max).into()), Value::Null);
+                            Self::output_message(&outgoing_tx, response.into()).await;
+                        }
+                        Self::stop_writer(&outgoing_tx, writer).await;
+                        return Err(e);
                     }
-                    self.handle_request(serialized_request);
                 },
                 id_notification = notification_streams.next() => {
-                    self.handle_notification(id_notification.unwrap()).await;
+                    Self::handle_notification(&outgoing_tx, id_notification.unwrap()).await;
                 }
                 stream = notification_stream_rx.recv() => {
                     notification_streams.push(stream.unwrap());
                 }
+                // The server holds a sender, so the channel will not close
+                Some(notification) = push_rx.recv() => {
+                    Self::output_message(&outgoing_tx, notification.into()).await;
+                }
+                _ = heartbeat.tick(), if self.config.heartbeat_interval_secs.is_some() => {
+                    let ids = notification_streams
+                        .iter()
+                        .filter(|link| !link.id.is_null() && !link.is_complete)
+                        .map(|link| link.id.clone())
+                        .collect();
+                    Self::send_heartbeats(&outgoing_tx, ids).await;
+                }
             }
         }
+
+        // Drop the sender so that the receiver closes once all in-flight requests are complete
+        self.notification_streams_tx = None;
+        let shutdown_timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        if timeout(
+            shutdown_timeout,
+            Self::drain(
+                &outgoing_tx,
+                &mut notification_streams,
+                &mut notification_stream_rx,
+            ),
+        )
+        .await
+        .is_err()
+        {
+            warn!("timed out waiting for pending responses and notification streams to complete");
+        }
+        Self::stop_writer(&outgoing_tx, writer).await;
         Ok(())
     }
 }
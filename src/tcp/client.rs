@@ -0,0 +1,126 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tower::Service;
+
+use crate::{
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        codec::LineCodec,
+        RequestJsonRpcConvert, ResponseJsonRpcConvert,
+    },
+    util::config::{ensure_non_zero, ConfigError},
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ServiceError, ServiceFuture,
+    ServiceResponse, DEFAULT_TIMEOUT_SECS,
+};
+
+/// Configuration for the TCP client.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpClientConfig {
+    /// The address of the server (i.e. `127.0.0.1:8081`).
+    pub address: String,
+    /// Timeout for client requests in seconds.
+    pub timeout_secs: u64,
+}
+
+impl ConfigExampleSnippet for TcpClientConfig {
+    fn config_example_snippet() -> String {
+        r#"# The address of the server.
+# address = "127.0.0.1:8081"
+
+# The timeout duration in seconds for requests, defaults to 900
+# timeout_secs = 60"#
+            .into()
+    }
+}
+
+impl ConfigDeprecatedKeys for TcpClientConfig {}
+
+impl ConfigEnvPrefix for TcpClientConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_TCP_CLIENT"
+    }
+}
+
+impl TcpClientConfig {
+    /// Checks that the address is non-empty, and that the timeout is non-zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.address.is_empty() {
+            return Err(ConfigError::new("address", "address should not be empty"));
+        }
+        ensure_non_zero("timeout_secs", self.timeout_secs)
+    }
+}
+
+impl Default for TcpClientConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8081".to_string(),
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Client for JSON-RPC communication over a long-lived TCP connection.
+/// Messages are newline-delimited, and multiple requests may be in-flight
+/// at the same time, in the same manner as the [`StdioClient`].
+/// If cloned, this client will continue to use the same connection.
+#[derive(Clone)]
+pub struct TcpClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    inner: StdioClient<Request, Response>,
+}
+
+impl<Request, Response> TcpClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    /// Creates a new client for TCP communication. A [`std::io::Error`]
+    /// will be returned if the configuration is invalid, or if the connection to the server fails.
+    pub async fn new(config: TcpClientConfig) -> std::io::Result<Self> {
+        config
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let stream = TcpStream::connect(&config.address).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let inner = StdioClient::from_io(
+            Box::new(reader),
+            Box::new(writer),
+            StdioClientConfig {
+                bin_path: None,
+                timeout_secs: config.timeout_secs,
+                ..Default::default()
+            },
+            Arc::new(LineCodec),
+        );
+        Ok(Self { inner })
+    }
+}
+
+impl<Request, Response> Service<Request> for TcpClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.inner.call(request)
+    }
+}
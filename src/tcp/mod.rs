@@ -0,0 +1,6 @@
+/// TCP client components.
+#[cfg(feature = "tcp-client")]
+pub mod client;
+/// TCP server components.
+#[cfg(feature = "tcp-server")]
+pub mod server;
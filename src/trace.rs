@@ -0,0 +1,83 @@
+//! Trace context propagation across transports. If the `opentelemetry` feature is enabled,
+//! the context of the current span is injected into outgoing requests, and extracted from
+//! incoming requests to become the parent of the server-side span. The HTTP transport uses the
+//! headers defined by the global text map propagator (i.e. W3C `traceparent` and `tracestate`),
+//! and the stdio transport uses the `trace_context` field of the JSON-RPC request.
+//! Without the feature, these functions have no effect.
+
+#[cfg(any(feature = "stdio-client", feature = "stdio-server"))]
+use std::collections::HashMap;
+
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+use tracing::Span;
+
+#[cfg(all(
+    feature = "opentelemetry",
+    any(
+        feature = "http-client",
+        feature = "http-server",
+        feature = "stdio-client",
+        feature = "stdio-server"
+    )
+))]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Returns the trace context of the current span, to be included in a JSON-RPC request.
+#[cfg(feature = "stdio-client")]
+pub(crate) fn current_trace_context() -> Option<HashMap<String, String>> {
+    #[cfg(feature = "opentelemetry")]
+    {
+        let mut trace_context = HashMap::new();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&tracing::Span::current().context(), &mut trace_context)
+        });
+        (!trace_context.is_empty()).then_some(trace_context)
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    None
+}
+
+/// Sets the parent of `span` to the trace context included in a JSON-RPC request.
+#[cfg(feature = "stdio-server")]
+pub(crate) fn set_parent_from_trace_context(
+    span: &Span,
+    trace_context: Option<&HashMap<String, String>>,
+) {
+    #[cfg(feature = "opentelemetry")]
+    if let Some(trace_context) = trace_context {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(trace_context)
+        });
+        span.set_parent(parent);
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = (span, trace_context);
+}
+
+/// Injects the trace context of the current span into the headers of an HTTP request.
+#[cfg(feature = "http-client")]
+pub(crate) fn inject_http_headers(headers: &mut hyper::HeaderMap) {
+    #[cfg(feature = "opentelemetry")]
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &tracing::Span::current().context(),
+            &mut opentelemetry_http::HeaderInjector(headers),
+        )
+    });
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = headers;
+}
+
+/// Sets the parent of `span` to the trace context included in the headers of an HTTP request.
+#[cfg(feature = "http-server")]
+pub(crate) fn set_parent_from_http_headers(span: &Span, headers: &hyper::HeaderMap) {
+    #[cfg(feature = "opentelemetry")]
+    {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&opentelemetry_http::HeaderExtractor(headers))
+        });
+        span.set_parent(parent);
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = (span, headers);
+}
@@ -1,71 +1,630 @@
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+use std::{future::Future, sync::Arc, time::Duration};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    future::poll_fn,
+    stream::{FuturesOrdered, Peekable},
+    Stream, StreamExt,
+};
 #[cfg(any(feature = "stdio-server", feature = "stdio-client"))]
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 #[cfg(any(feature = "stdio-server", feature = "stdio-client"))]
 use serde_json::Value;
 
+use tower::Service;
+
+use crate::error::ProtocolErrorType;
 #[cfg(any(feature = "stdio-server", feature = "stdio-client"))]
-use crate::error::{ProtocolErrorType, SerializableProtocolError};
+use crate::error::SerializableProtocolError;
+use crate::{
+    BoxedService, NotificationStream, ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+};
 
 /// Parses/deserializes a [`serde_json::Value`] into `R`. Returns
-/// a "bad request" protocol error if deserialization fails. Can be useful for
-/// parsing events when implementing [`ResponseJsonRpcConvert::from_jsonrpc_message`](crate::stdio::ResponseJsonRpcConvert::from_jsonrpc_message).
+/// a "bad request" protocol error if deserialization fails. Objects and arrays are both
+/// accepted, in the same manner as [`JsonRpcRequest::parse_params`](crate::jsonrpc::JsonRpcRequest::parse_params).
+/// Can be useful for parsing events when implementing
+/// [`ResponseJsonRpcConvert::from_jsonrpc_message`](crate::stdio::ResponseJsonRpcConvert::from_jsonrpc_message).
 #[cfg(any(feature = "stdio-server", feature = "stdio-client"))]
 pub fn parse_from_value<R: DeserializeOwned>(value: Value) -> Result<R, SerializableProtocolError> {
-    serde_json::from_value::<R>(value).map_err(|error| SerializableProtocolError {
-        error_type: ProtocolErrorType::BadRequest,
-        description: error.to_string(),
+    crate::jsonrpc::deserialize_params::<R>(value).map_err(|error| {
+        SerializableProtocolError::new(ProtocolErrorType::BadRequest, error.to_string())
     })
 }
 
-/// Utility functions related to services.
-#[cfg(all(feature = "http-client", feature = "stdio-client"))]
-pub mod service {
-    use crate::{
-        http::{
-            client::{HttpClient, HttpClientConfig},
-            RequestHttpConvert, ResponseHttpConvert,
-        },
-        stdio::{
-            client::{StdioClient, StdioClientConfig},
-            RequestJsonRpcConvert, ResponseJsonRpcConvert,
-        },
-        BoxedService, ServiceError,
+/// Returns true if a log line should be emitted, given a sample rate between
+/// `0.0` (never) and `1.0` (always).
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+pub(crate) fn should_sample_log(sample_rate: f64) -> bool {
+    sample_rate >= 1.0 || (sample_rate > 0.0 && rand::random::<f64>() < sample_rate)
+}
+
+/// Returns the error for requests that have exceeded the deadline provided by the client.
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+pub(crate) fn deadline_exceeded_error() -> ProtocolError {
+    ProtocolError::new(
+        ProtocolErrorType::Timeout,
+        "request deadline exceeded".into(),
+    )
+}
+
+/// Returns the error for requests that were not handled by the service within the
+/// server's timeout.
+#[cfg(feature = "stdio-server")]
+pub(crate) fn service_timeout_error(timeout: Duration) -> ProtocolError {
+    ProtocolError::new(
+        ProtocolErrorType::Timeout,
+        format!("request timed out after {} ms", timeout.as_millis()).into(),
+    )
+}
+
+/// The delay before accepting connections again, after the listener fails to accept one.
+#[cfg(any(feature = "tcp-server", feature = "ws-server"))]
+const ACCEPT_ERROR_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Accepts the next connection from `listener`, and disables Nagle's algorithm for it.
+/// Errors are logged instead of returned, since they usually only affect a single connection,
+/// or are temporary (i.e. running out of file descriptors). The listener is retried
+/// after a delay in that case, so that a persistent error does not cause a busy loop.
+#[cfg(any(feature = "tcp-server", feature = "ws-server"))]
+pub(crate) async fn accept_connection(
+    listener: &tokio::net::TcpListener,
+) -> (tokio::net::TcpStream, std::net::SocketAddr) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, remote_addr)) => {
+                tracing::debug!("accepted tcp connection from {}", remote_addr);
+                if let Err(e) = stream.set_nodelay(true) {
+                    tracing::warn!(
+                        "failed to set TCP_NODELAY for connection from {remote_addr}: {e}"
+                    );
+                }
+                return (stream, remote_addr);
+            }
+            Err(e) => {
+                tracing::warn!("failed to accept tcp connection: {e}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Bounds a service future by the deadline provided by the client, in addition to
+/// the timeout of the server. Returns a timeout error if the deadline elapses first.
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+pub(crate) async fn with_deadline<T>(
+    future: impl Future<Output = Result<T, ServiceError>>,
+    deadline: Option<Duration>,
+) -> Result<T, ServiceError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout(deadline, future)
+            .await
+            .unwrap_or_else(|_| Err(deadline_exceeded_error().into())),
+        None => future.await,
+    }
+}
+
+/// Invoked for each service response before it is converted by a server.
+/// See [`HttpServer::with_response_interceptor`](crate::http::server::HttpServer::with_response_interceptor)
+/// and [`StdioServer::with_response_interceptor`](crate::stdio::server::StdioServer::with_response_interceptor).
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+pub(crate) type ResponseInterceptor<Response> =
+    Arc<dyn Fn(&mut ServiceResponse<Response>) -> Result<(), ProtocolError> + Send + Sync>;
+
+/// Applies the response interceptor to a service result, if an interceptor is set.
+/// The interceptor is applied to each item of a streaming response, as a single response.
+/// Errors returned by the interceptor replace the response (or the stream item).
+#[cfg(any(feature = "http-server", feature = "stdio-server"))]
+pub(crate) fn intercept_response<Response: Send + 'static>(
+    result: Result<ServiceResponse<Response>, ServiceError>,
+    interceptor: Option<&ResponseInterceptor<Response>>,
+) -> Result<ServiceResponse<Response>, ServiceError> {
+    let Some(interceptor) = interceptor else {
+        return result;
     };
+    match result? {
+        ServiceResponse::Single(response) => {
+            let mut response = ServiceResponse::Single(response);
+            interceptor(&mut response)?;
+            Ok(response)
+        }
+        ServiceResponse::Multiple(stream) => {
+            let interceptor = interceptor.clone();
+            Ok(ServiceResponse::Multiple(
+                stream
+                    .map(move |item| {
+                        let mut response = ServiceResponse::Single(item?);
+                        interceptor(&mut response)?;
+                        match response {
+                            ServiceResponse::Single(response) => Ok(response),
+                            ServiceResponse::Multiple(_) => Err(ProtocolError::new(
+                                ProtocolErrorType::Internal,
+                                "response interceptor returned a stream for a stream item".into(),
+                            )),
+                        }
+                    })
+                    .boxed(),
+            ))
+        }
+    }
+}
+
+/// An event emitted by a long-running request that reports progress
+/// before producing a final result.
+///
+/// A service can return a [`ServiceResponse::Multiple`] stream where each item
+/// wraps a `ProgressEvent`: zero or more [`ProgressEvent::Progress`] events, followed by a
+/// single [`ProgressEvent::Result`] event as the last item. Since the event is tagged
+/// when serialized, clients can distinguish progress from the final result
+/// regardless of the protocol used (i.e. server-side events over HTTP, or notifications over stdio).
+/// [`await_progress_result`] can be used by clients to consume the stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum ProgressEvent<P, R> {
+    /// Reports progress of the request.
+    Progress(P),
+    /// Contains the final result of the request.
+    Result(R),
+}
+
+/// Consumes a stream of responses that follows the [`ProgressEvent`] pattern.
+/// `extract` maps each response into a [`ProgressEvent`]; responses that map to `None`
+/// are ignored. `on_progress` is called for each progress event, and the final result is returned.
+/// Returns an "internal" error if the stream ends without a result.
+pub async fn await_progress_result<Response, P, R>(
+    mut stream: NotificationStream<Response>,
+    mut extract: impl FnMut(Response) -> Option<ProgressEvent<P, R>>,
+    mut on_progress: impl FnMut(P),
+) -> Result<R, ProtocolError> {
+    while let Some(response) = stream.next().await {
+        match extract(response?) {
+            Some(ProgressEvent::Progress(progress)) => on_progress(progress),
+            Some(ProgressEvent::Result(result)) => return Ok(result),
+            None => (),
+        }
+    }
+    Err(ProtocolError::new(
+        ProtocolErrorType::Internal,
+        "stream ended without a result".into(),
+    ))
+}
+
+/// Splits a serialized message into pieces of at most `max_chunk_bytes` bytes, so that
+/// large responses can be sent as multiple frames. Pieces are split at character boundaries,
+/// and include at least one character, even if it exceeds the limit.
+#[cfg(feature = "stdio-server")]
+pub(crate) fn split_into_chunks(serialized: &str, max_chunk_bytes: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = serialized;
+    while !rest.is_empty() {
+        let mut end = max_chunk_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map(char::len_utf8).unwrap_or_default();
+        }
+        let (piece, remaining) = rest.split_at(end);
+        pieces.push(piece);
+        rest = remaining;
+    }
+    pieces
+}
+
+/// A [`NotificationStream`] wrapper that allows inspecting the next
+/// notification without consuming it. Useful for lookahead parsing of event streams.
+pub struct PeekableNotificationStream<Response> {
+    inner: Peekable<NotificationStream<Response>>,
+}
+
+impl<Response> PeekableNotificationStream<Response> {
+    pub fn new(stream: NotificationStream<Response>) -> Self {
+        Self {
+            inner: stream.peekable(),
+        }
+    }
 
-    /// Creates a [`StdioClient`](crate::stdio::client::StdioClient) or
-    /// [`HttpClient`](crate::http::client::HttpClient) service depending
-    /// on the arguments provided. If `http_client_config` is `Some`, an
-    /// HTTP-based service will be created. If it is `None`, a stdio-based service
-    /// will be created.
-    pub async fn build_service_from_config<Request, Response>(
-        command_name: &str,
-        command_arguments: &[&str],
-        stdio_client_config: Option<StdioClientConfig>,
-        http_client_config: Option<HttpClientConfig>,
-    ) -> Result<BoxedService<Request, Response>, ServiceError>
+    /// Returns a reference to the next notification without consuming it.
+    /// Returns `None` if the stream has ended.
+    pub async fn peek(&mut self) -> Option<&Result<Response, ProtocolError>> {
+        Pin::new(&mut self.inner).peek().await
+    }
+
+    /// Consumes and returns the next notification only if `func` returns true for it.
+    pub async fn next_if(
+        &mut self,
+        func: impl FnOnce(&Result<Response, ProtocolError>) -> bool,
+    ) -> Option<Result<Response, ProtocolError>> {
+        Pin::new(&mut self.inner).next_if(func).await
+    }
+
+    /// Converts the wrapper back into a [`NotificationStream`], retaining any peeked notification.
+    pub fn into_stream(self) -> NotificationStream<Response>
     where
-        Request: RequestHttpConvert<Request>
-            + RequestJsonRpcConvert<Request>
-            + Clone
-            + Send
-            + Sync
-            + 'static,
-        Response: ResponseHttpConvert<Request, Response>
-            + ResponseJsonRpcConvert<Request, Response>
-            + Send
-            + Sync
-            + 'static,
+        Response: Send + 'static,
     {
-        Ok(match http_client_config {
-            Some(config) => Box::new(HttpClient::new(config)?),
-            None => Box::new(
-                StdioClient::new(
-                    command_name,
-                    command_arguments,
-                    stdio_client_config.unwrap_or_default(),
-                )
-                .await?,
-            ),
+        Box::pin(self.inner)
+    }
+}
+
+impl<Response> From<NotificationStream<Response>> for PeekableNotificationStream<Response> {
+    fn from(stream: NotificationStream<Response>) -> Self {
+        Self::new(stream)
+    }
+}
+
+impl<Response> Stream for PeekableNotificationStream<Response> {
+    type Item = Result<Response, ProtocolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Extension trait for dispatching a batch of requests to a multilink service.
+#[async_trait::async_trait]
+pub trait ServiceCallAll<Request, Response> {
+    /// Dispatches all `requests` concurrently and waits for every response.
+    /// The service's readiness is awaited before each request is dispatched, so
+    /// backpressure is respected. Requests that were already dispatched keep making progress
+    /// while readiness is awaited. Results are returned in the same order as the requests.
+    /// Stdio clients will multiplex the requests through the single child process,
+    /// and HTTP clients will use the connection pool.
+    async fn call_all(
+        &mut self,
+        requests: Vec<Request>,
+    ) -> Vec<Result<ServiceResponse<Response>, ServiceError>>;
+}
+
+#[async_trait::async_trait]
+impl<S, Request, Response> ServiceCallAll<Request, Response> for S
+where
+    S: Service<Request, Response = ServiceResponse<Response>, Error = ServiceError> + Send,
+    S::Future: Send,
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    async fn call_all(
+        &mut self,
+        requests: Vec<Request>,
+    ) -> Vec<Result<ServiceResponse<Response>, ServiceError>> {
+        let mut results = Vec::with_capacity(requests.len());
+        let mut pending = FuturesOrdered::new();
+        for request in requests {
+            // The dispatched requests are driven while waiting for readiness, since the
+            // service may only become ready once one of them completes (i.e. if the
+            // service is wrapped with a concurrency limit)
+            let readiness = poll_fn(|cx| loop {
+                if let Poll::Ready(result) = self.poll_ready(cx) {
+                    return Poll::Ready(result);
+                }
+                match pending.poll_next_unpin(cx) {
+                    Poll::Ready(Some(result)) => results.push(result),
+                    _ => return Poll::Pending,
+                }
+            })
+            .await;
+            let future = readiness.map(|()| self.call(request));
+            pending.push_back(async move {
+                match future {
+                    Ok(future) => future.await,
+                    Err(e) => Err(e),
+                }
+            });
+        }
+        while let Some(result) = pending.next().await {
+            results.push(result);
+        }
+        results
+    }
+}
+
+/// Wraps a service so that its future is boxed into a [`ServiceFuture`], and its
+/// error is converted into a [`ServiceError`]. This allows services produced by
+/// arbitrary `tower` layers to be used by multilink servers.
+#[derive(Clone)]
+pub struct BoxedFutureService<S> {
+    inner: S,
+}
+
+impl<S> BoxedFutureService<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the wrapper, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, Request, Response> Service<Request> for BoxedFutureService<S>
+where
+    S: Service<Request, Response = ServiceResponse<Response>>,
+    S::Error: Into<ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let future = self.inner.call(request);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+/// Adapts an ordinary `tower` service into a multilink service, by wrapping
+/// each response in a [`ServiceResponse::Single`] and converting errors into
+/// a [`ServiceError`]. This allows existing services that return a plain response
+/// type to be used with multilink clients and servers.
+#[derive(Clone)]
+pub struct SingleResponseService<S> {
+    inner: S,
+}
+
+impl<S> SingleResponseService<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the adapter, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Converts the adapter into a [`BoxedService`].
+    pub fn boxed<Request>(self) -> BoxedService<Request, S::Response>
+    where
+        S: Service<Request> + Send + Sync + 'static,
+        S::Error: Into<ServiceError>,
+        S::Future: Send + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<S, Request> Service<Request> for SingleResponseService<S>
+where
+    S: Service<Request>,
+    S::Error: Into<ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse<S::Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<S::Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            future
+                .await
+                .map(ServiceResponse::Single)
+                .map_err(Into::into)
         })
     }
 }
+
+/// Utility functions related to configuration loading.
+pub mod config {
+    use std::{env, fmt::Display};
+
+    use serde::{de::DeserializeOwned, Deserializer, Serialize};
+    use serde_json::Value;
+    use thiserror::Error;
+
+    use crate::{ConfigDeprecatedKeys, ConfigEnvPrefix};
+
+    /// A non-fatal issue found while loading a configuration.
+    #[derive(Clone, Debug, PartialEq, Eq, Error)]
+    pub enum ConfigWarning {
+        /// The key is not recognized by the configuration, and may be a typo.
+        #[error("unknown config key `{0}`")]
+        UnknownKey(String),
+        /// The key is deprecated and will be ignored.
+        #[error("deprecated config key `{key}`: {note}")]
+        DeprecatedKey { key: String, note: String },
+    }
+
+    /// Deserializes a configuration leniently, collecting any unknown or
+    /// deprecated keys as warnings instead of failing. Keys that are missing
+    /// from the configuration will use their default values, as usual.
+    pub fn deserialize_config<'de, T, D>(
+        deserializer: D,
+    ) -> Result<(T, Vec<ConfigWarning>), D::Error>
+    where
+        T: DeserializeOwned + ConfigDeprecatedKeys,
+        D: Deserializer<'de>,
+    {
+        let mut warnings = Vec::new();
+        let config = serde_ignored::deserialize(deserializer, |path| {
+            warnings.push(warning_for_key(path, T::deprecated_config_keys()))
+        })?;
+        Ok((config, warnings))
+    }
+
+    /// An error that occurs when a configuration contains an invalid value.
+    #[derive(Clone, Debug, PartialEq, Eq, Error)]
+    #[error("invalid value for config key `{key}`: {reason}")]
+    pub struct ConfigError {
+        /// The key of the invalid value.
+        pub key: &'static str,
+        /// The reason that the value is invalid.
+        pub reason: String,
+    }
+
+    impl ConfigError {
+        /// Creates an error for the value of `key`.
+        pub fn new(key: &'static str, reason: impl Into<String>) -> Self {
+            Self {
+                key,
+                reason: reason.into(),
+            }
+        }
+    }
+
+    /// Returns an error if a duration or size is zero.
+    #[cfg(any(
+        feature = "stdio-client",
+        feature = "stdio-server",
+        feature = "http-client",
+        feature = "http-server"
+    ))]
+    pub(crate) fn ensure_non_zero(key: &'static str, value: u64) -> Result<(), ConfigError> {
+        match value {
+            0 => Err(ConfigError::new(key, "value must be greater than zero")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if a log sample rate is not between 0.0 and 1.0.
+    #[cfg(any(feature = "stdio-server", feature = "http-server"))]
+    pub(crate) fn ensure_sample_rate(key: &'static str, value: f64) -> Result<(), ConfigError> {
+        match (0.0..=1.0).contains(&value) {
+            true => Ok(()),
+            false => Err(ConfigError::new(
+                key,
+                "sample rate must be between 0.0 and 1.0",
+            )),
+        }
+    }
+
+    /// Returns an error if a value is not a valid HTTP header name.
+    #[cfg(any(feature = "http-client", feature = "http-server"))]
+    pub(crate) fn ensure_header_name(key: &'static str, value: &str) -> Result<(), ConfigError> {
+        hyper::header::HeaderName::from_bytes(value.as_bytes())
+            .map(|_| ())
+            .map_err(|_| ConfigError::new(key, format!("`{value}` is not a valid header name")))
+    }
+
+    /// Returns an error if a value cannot be included in an HTTP header value
+    /// (i.e. it contains non-ASCII characters).
+    #[cfg(any(feature = "http-client", feature = "http-server"))]
+    pub(crate) fn ensure_header_value(key: &'static str, value: &str) -> Result<(), ConfigError> {
+        hyper::header::HeaderValue::from_str(value)
+            .map(|_| ())
+            .map_err(|_| ConfigError::new(key, format!("`{value}` is not a valid header value")))
+    }
+
+    /// An error that occurs while applying environment variable overrides to a configuration.
+    #[derive(Debug, Error)]
+    pub enum ConfigEnvError {
+        /// The configuration could not be represented as a map of fields.
+        #[error("config cannot be overridden by environment variables: {0}")]
+        Unsupported(String),
+        /// The value of the environment variable is not valid for the associated field.
+        #[error("invalid value for environment variable `{var}`: {error}")]
+        InvalidValue { var: String, error: String },
+    }
+
+    /// Overrides fields of a configuration with the values of environment variables,
+    /// using the prefix provided by [`ConfigEnvPrefix`]. Fields with unset environment
+    /// variables are left untouched. See [`apply_env_overrides_with_prefix`] for the
+    /// format of values.
+    pub fn apply_env_overrides<T>(config: T) -> Result<T, ConfigEnvError>
+    where
+        T: Serialize + DeserializeOwned + ConfigEnvPrefix,
+    {
+        apply_env_overrides_with_prefix(config, T::config_env_prefix())
+    }
+
+    /// Overrides fields of a configuration with the values of environment variables named
+    /// after `prefix` and the upper case field name, separated by an underscore
+    /// (i.e. `MYAPP_TIMEOUT_SECS` for the `timeout_secs` field, given a `MYAPP` prefix).
+    /// Values are parsed as JSON if possible (i.e. `60`, `true` or `["key1", "key2"]`),
+    /// otherwise they are used as strings. Fields with unset environment variables are left untouched.
+    pub fn apply_env_overrides_with_prefix<T>(config: T, prefix: &str) -> Result<T, ConfigEnvError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut value =
+            serde_json::to_value(config).map_err(|e| ConfigEnvError::Unsupported(e.to_string()))?;
+        let Value::Object(fields) = &mut value else {
+            return Err(ConfigEnvError::Unsupported(
+                "config is not a struct".to_string(),
+            ));
+        };
+        let keys = fields.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            let var = format!("{prefix}_{}", key.to_uppercase());
+            let Ok(raw_value) = env::var(&var) else {
+                continue;
+            };
+            let mut candidates = serde_json::from_str::<Value>(&raw_value)
+                .ok()
+                .into_iter()
+                .collect::<Vec<_>>();
+            // The raw value is preferred for string fields, so that i.e. numeric API keys
+            // are not parsed as numbers
+            match fields[&key].is_string() {
+                true => candidates.insert(0, Value::String(raw_value)),
+                false => candidates.push(Value::String(raw_value)),
+            }
+            let mut last_error = None;
+            for candidate in candidates {
+                fields.insert(key.clone(), candidate);
+                match T::deserialize(&Value::Object(fields.clone())) {
+                    Ok(_) => {
+                        last_error = None;
+                        break;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            if let Some(error) = last_error {
+                return Err(ConfigEnvError::InvalidValue {
+                    var,
+                    error: error.to_string(),
+                });
+            }
+        }
+        serde_json::from_value(value).map_err(|e| ConfigEnvError::Unsupported(e.to_string()))
+    }
+
+    fn warning_for_key(
+        path: impl Display,
+        deprecated_keys: &[(&'static str, &'static str)],
+    ) -> ConfigWarning {
+        let key = path.to_string();
+        match deprecated_keys
+            .iter()
+            .find(|(deprecated, _)| *deprecated == key)
+        {
+            Some((_, note)) => ConfigWarning::DeprecatedKey {
+                key,
+                note: note.to_string(),
+            },
+            None => ConfigWarning::UnknownKey(key),
+        }
+    }
+}
+
+/// Utility functions and wrappers related to services.
+pub mod service;
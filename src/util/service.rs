@@ -0,0 +1,624 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    channel::oneshot,
+    future::{poll_fn, FutureExt, Shared},
+};
+use serde_json::Value;
+use tower::Service;
+
+#[cfg(all(feature = "tcp-client", feature = "http-client"))]
+use crate::tcp::client::{TcpClient, TcpClientConfig};
+#[cfg(all(feature = "ws-client", feature = "http-client"))]
+use crate::ws::client::{WsClient, WsClientConfig};
+use crate::{
+    error::ProtocolErrorType, BoxedService, ProtocolError, ServiceError, ServiceFuture,
+    ServiceResponse,
+};
+#[cfg(all(feature = "http-client", feature = "stdio-client"))]
+use crate::{
+    http::{
+        client::{HttpClient, HttpClientConfig},
+        RequestHttpConvert, ResponseHttpConvert,
+    },
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        RequestJsonRpcConvert, ResponseJsonRpcConvert,
+    },
+};
+
+#[cfg(all(feature = "http-client", feature = "stdio-client"))]
+/// The transport, and associated configuration, used by a client
+/// created via [`build_service`]. Variants for other transports are available
+/// if their client features are enabled.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum ClientTransportConfig {
+    /// Spawns a child process, and communicates with it via stdio.
+    Stdio {
+        command_name: String,
+        command_arguments: Vec<String>,
+        config: StdioClientConfig,
+    },
+    /// Communicates with a remote HTTP server.
+    Http(HttpClientConfig),
+    /// Communicates with a remote server via JSON-RPC over TCP.
+    #[cfg(feature = "tcp-client")]
+    Tcp(TcpClientConfig),
+    /// Communicates with a remote server via JSON-RPC over WebSocket.
+    #[cfg(feature = "ws-client")]
+    Ws(WsClientConfig),
+}
+
+#[cfg(all(feature = "http-client", feature = "stdio-client"))]
+/// Creates a client service for the transport specified in `transport_config`
+/// (i.e. a [`StdioClient`] or [`HttpClient`]).
+pub async fn build_service<Request, Response>(
+    transport_config: ClientTransportConfig,
+) -> Result<BoxedService<Request, Response>, ServiceError>
+where
+    Request: RequestHttpConvert<Request>
+        + RequestJsonRpcConvert<Request>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    Response: ResponseHttpConvert<Request, Response>
+        + ResponseJsonRpcConvert<Request, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    Ok(match transport_config {
+        ClientTransportConfig::Stdio {
+            command_name,
+            command_arguments,
+            config,
+        } => {
+            let command_arguments: Vec<&str> =
+                command_arguments.iter().map(|arg| arg.as_str()).collect();
+            Box::new(StdioClient::new(&command_name, &command_arguments, config).await?)
+        }
+        ClientTransportConfig::Http(config) => Box::new(HttpClient::try_new(config)?),
+        #[cfg(feature = "tcp-client")]
+        ClientTransportConfig::Tcp(config) => Box::new(TcpClient::new(config).await?),
+        #[cfg(feature = "ws-client")]
+        ClientTransportConfig::Ws(config) => Box::new(WsClient::new(config).await?),
+    })
+}
+
+#[cfg(all(feature = "http-client", feature = "stdio-client"))]
+/// Creates a [`StdioClient`] or [`HttpClient`] service depending
+/// on the arguments provided. If `http_client_config` is `Some`, an
+/// HTTP-based service will be created. If it is `None`, a stdio-based service
+/// will be created.
+#[deprecated(note = "use `build_service` with a `ClientTransportConfig` instead")]
+pub async fn build_service_from_config<Request, Response>(
+    command_name: &str,
+    command_arguments: &[&str],
+    stdio_client_config: Option<StdioClientConfig>,
+    http_client_config: Option<HttpClientConfig>,
+) -> Result<BoxedService<Request, Response>, ServiceError>
+where
+    Request: RequestHttpConvert<Request>
+        + RequestJsonRpcConvert<Request>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    Response: ResponseHttpConvert<Request, Response>
+        + ResponseJsonRpcConvert<Request, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    let transport_config = match http_client_config {
+        Some(config) => ClientTransportConfig::Http(config),
+        None => ClientTransportConfig::Stdio {
+            command_name: command_name.to_string(),
+            command_arguments: command_arguments
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+            config: stdio_client_config.unwrap_or_default(),
+        },
+    };
+    build_service(transport_config).await
+}
+
+/// The readiness of a service wrapped by [`FallbackService`], as of the last call to `poll_ready`.
+enum Readiness {
+    /// The service has not been polled, or was not ready.
+    Unknown,
+    Ready,
+    /// The service failed to become ready, so it will be skipped.
+    Failed(ProtocolError),
+}
+
+/// A service that dispatches requests to an ordered list of services, falling back
+/// to the next service if a request fails due to a transport-level failure (i.e. a local
+/// stdio server crashed, or a remote server is unavailable). Failures are classified using
+/// [`ProtocolError::is_retryable`]; other errors (i.e. "bad request" or "not found")
+/// are returned immediately. "Timeout" errors only cause a fallback if enabled via
+/// [`FallbackService::with_fallback_on_timeout`], since the timed out service may have
+/// processed the request. Once a service returns a response, including the start of a
+/// response stream, no further fallback will occur for the request.
+///
+/// The service is ready once the first service that has not failed to become ready is ready.
+/// All services are polled, and services that fail to become ready are skipped.
+/// Readiness of the remaining services is awaited before falling back to them.
+/// If cloned, the clone will dispatch to the same services.
+pub struct FallbackService<Request, Response> {
+    services: Arc<Vec<Mutex<BoxedService<Request, Response>>>>,
+    readiness: Vec<Readiness>,
+    fallback_on_timeout: bool,
+    response_phantom: PhantomData<Response>,
+}
+
+impl<Request, Response> Clone for FallbackService<Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            services: self.services.clone(),
+            readiness: unknown_readiness(self.services.len()),
+            fallback_on_timeout: self.fallback_on_timeout,
+            response_phantom: Default::default(),
+        }
+    }
+}
+
+fn unknown_readiness(len: usize) -> Vec<Readiness> {
+    (0..len).map(|_| Readiness::Unknown).collect()
+}
+
+fn no_services_error() -> ProtocolError {
+    ProtocolError::new(
+        ProtocolErrorType::ServiceUnavailable,
+        "no services available".into(),
+    )
+}
+
+fn lock_service<Request, Response>(
+    service: &Mutex<BoxedService<Request, Response>>,
+) -> std::sync::MutexGuard<'_, BoxedService<Request, Response>> {
+    service
+        .lock()
+        .expect("fallback service lock should not be poisoned")
+}
+
+impl<Request, Response> FallbackService<Request, Response> {
+    /// Creates a new fallback service. Requests will be dispatched to
+    /// `services` in the order provided.
+    pub fn new(services: Vec<BoxedService<Request, Response>>) -> Self {
+        Self {
+            readiness: unknown_readiness(services.len()),
+            services: Arc::new(services.into_iter().map(Mutex::new).collect()),
+            fallback_on_timeout: false,
+            response_phantom: Default::default(),
+        }
+    }
+
+    /// Enables falling back to the next service if a request times out. Should only be
+    /// enabled if requests are idempotent, since the timed out service may have processed the request.
+    pub fn with_fallback_on_timeout(mut self, fallback_on_timeout: bool) -> Self {
+        self.fallback_on_timeout = fallback_on_timeout;
+        self
+    }
+}
+
+impl<Request, Response> Service<Request> for FallbackService<Request, Response>
+where
+    Request: Clone + Send + 'static,
+    Response: Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut result = None;
+        for (service, readiness) in self.services.iter().zip(self.readiness.iter_mut()) {
+            let poll = match readiness {
+                Readiness::Failed(_) => continue,
+                Readiness::Ready => Poll::Ready(Ok(())),
+                Readiness::Unknown => lock_service(service).poll_ready(cx),
+            };
+            match poll {
+                Poll::Ready(Ok(())) => {
+                    *readiness = Readiness::Ready;
+                    result.get_or_insert(Poll::Ready(Ok(())));
+                }
+                Poll::Ready(Err(e)) => *readiness = Readiness::Failed(ProtocolError::from(e)),
+                Poll::Pending => {
+                    result.get_or_insert(Poll::Pending);
+                }
+            }
+        }
+        result.unwrap_or_else(|| {
+            let readiness =
+                std::mem::replace(&mut self.readiness, unknown_readiness(self.services.len()));
+            let last_error = readiness
+                .into_iter()
+                .rev()
+                .find_map(|readiness| match readiness {
+                    Readiness::Failed(e) => Some(e),
+                    _ => None,
+                });
+            Poll::Ready(Err(last_error.unwrap_or_else(no_services_error).into()))
+        })
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let services = self.services.clone();
+        // Readiness is consumed by the call
+        let readiness = std::mem::replace(&mut self.readiness, unknown_readiness(services.len()));
+        let fallback_on_timeout = self.fallback_on_timeout;
+        Box::pin(async move {
+            let mut last_error = None;
+            for (service, readiness) in services.iter().zip(readiness) {
+                match readiness {
+                    Readiness::Failed(e) => {
+                        last_error = Some(e);
+                        continue;
+                    }
+                    Readiness::Ready => (),
+                    Readiness::Unknown => {
+                        let ready = poll_fn(|cx| lock_service(service).poll_ready(cx)).await;
+                        if let Err(e) = ready {
+                            last_error = Some(ProtocolError::from(e));
+                            continue;
+                        }
+                    }
+                }
+                let future = lock_service(service).call(request.clone());
+                match future.await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        let e = ProtocolError::from(e);
+                        let is_timeout = matches!(e.error_type, ProtocolErrorType::Timeout);
+                        if !e.is_retryable() || (is_timeout && !fallback_on_timeout) {
+                            return Err(e.into());
+                        }
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Err(last_error.unwrap_or_else(no_services_error).into())
+        })
+    }
+}
+
+/// Derives a key from a request. Requests that map to `None` are excluded.
+type RequestKeyFn<Request, K> = Arc<dyn Fn(&Request) -> Option<K> + Send + Sync>;
+
+struct CacheEntry<Response> {
+    response: Response,
+    inserted_at: Instant,
+}
+
+/// A service that caches single responses returned by the wrapped service.
+/// The cache key is derived from each request via the `key_fn` provided; requests
+/// that map to `None` are not cached. Entries expire after the configured TTL,
+/// and the oldest entry is evicted once the maximum amount of entries is reached.
+/// Streaming responses ([`ServiceResponse::Multiple`]) and errors are never cached.
+pub struct CachingService<Request, Response, K> {
+    inner: BoxedService<Request, Response>,
+    key_fn: RequestKeyFn<Request, K>,
+    entries: Arc<Mutex<HashMap<K, CacheEntry<Response>>>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl<Request, Response, K> CachingService<Request, Response, K>
+where
+    K: Hash + Eq,
+{
+    /// Creates a new caching service that wraps `inner`.
+    pub fn new(
+        inner: BoxedService<Request, Response>,
+        key_fn: impl Fn(&Request) -> Option<K> + Send + Sync + 'static,
+        ttl: Duration,
+        max_entries: usize,
+    ) -> Self {
+        Self {
+            inner,
+            key_fn: Arc::new(key_fn),
+            entries: Default::default(),
+            ttl,
+            max_entries,
+        }
+    }
+
+    fn get_cached(&self, key: &K) -> Option<Response>
+    where
+        Response: Clone,
+    {
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("cache lock should not be poisoned");
+        match entries.get(key) {
+            Some(entry) if entry.inserted_at.elapsed() < self.ttl => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+impl<Request, Response, K> Service<Request> for CachingService<Request, Response, K>
+where
+    Request: Send + 'static,
+    Response: Clone + Send + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let key = (self.key_fn)(&request);
+        if let Some(response) = key.as_ref().and_then(|key| self.get_cached(key)) {
+            return Box::pin(async move { Ok(ServiceResponse::Single(response)) });
+        }
+        let future = self.inner.call(request);
+        let entries = self.entries.clone();
+        let max_entries = self.max_entries;
+        Box::pin(async move {
+            let response = future.await?;
+            if let (Some(key), ServiceResponse::Single(response)) = (key, &response) {
+                let mut entries = entries.lock().expect("cache lock should not be poisoned");
+                if !entries.contains_key(&key) && entries.len() >= max_entries {
+                    let oldest_key = entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.inserted_at)
+                        .map(|(key, _)| key.clone());
+                    if let Some(oldest_key) = oldest_key {
+                        entries.remove(&oldest_key);
+                    }
+                }
+                if max_entries > 0 {
+                    entries.insert(
+                        key,
+                        CacheEntry {
+                            response: response.clone(),
+                            inserted_at: Instant::now(),
+                        },
+                    );
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// The error returned to each caller of a coalesced request that failed, if other
+/// callers were waiting for the request. Available via [`ProtocolError::inner_error`].
+/// Wraps the error returned by the wrapped service, which is shared by all callers and can be
+/// accessed via [`SharedError::inner`], i.e. to downcast it to its original type.
+/// The error type, code and data of the [`ProtocolError`] are preserved as well.
+#[derive(Clone, Debug)]
+pub struct SharedError(Arc<dyn std::error::Error + Send + Sync + 'static>);
+
+impl SharedError {
+    /// Returns the error returned by the wrapped service.
+    pub fn inner(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self.0.as_ref()
+    }
+}
+
+impl std::fmt::Display for SharedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+/// The parts of a failed coalesced request, used to create the error for each caller.
+#[derive(Clone)]
+struct SharedProtocolError {
+    error_type: ProtocolErrorType,
+    error: SharedError,
+    code: Option<String>,
+    data: Option<Value>,
+}
+
+impl SharedProtocolError {
+    fn new(error: ProtocolError) -> Self {
+        let (error_type, error, code, data) = error.into_parts();
+        Self {
+            error_type,
+            error: SharedError(Arc::from(error)),
+            code,
+            data,
+        }
+    }
+}
+
+impl From<SharedProtocolError> for ProtocolError {
+    fn from(error: SharedProtocolError) -> Self {
+        ProtocolError::new(error.error_type, Box::new(error.error))
+            .with_optional_details(error.code, error.data)
+    }
+}
+
+/// The outcome of a coalesced request, shared with all waiting callers.
+#[derive(Clone)]
+enum SingleFlightOutcome<Response> {
+    Single(Response),
+    Error(SharedProtocolError),
+    /// The response was a stream, which cannot be shared.
+    Stream,
+}
+
+type InFlightRequests<K, Response> =
+    Arc<Mutex<HashMap<K, Shared<oneshot::Receiver<SingleFlightOutcome<Response>>>>>>;
+
+/// Removes a key from the in-flight requests once the leading call completes or is dropped.
+struct InFlightGuard<K: Hash + Eq, Response> {
+    in_flight: InFlightRequests<K, Response>,
+    key: K,
+}
+
+impl<K: Hash + Eq, Response> Drop for InFlightGuard<K, Response> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+/// A service that coalesces concurrent identical requests, so that the wrapped service
+/// is only called once for all of them. Requests are considered identical if the `key_fn`
+/// provided derives the same key from them; requests that map to `None` are passed through.
+/// All waiting callers receive a clone of the single response, or a [`SharedError`] that
+/// wraps the error (the leading caller receives the original error if no other callers
+/// were waiting). Streaming
+/// responses cannot be shared, so waiting callers will send their own request if the
+/// leading request returns a stream.
+pub struct SingleFlightService<Request, Response, K: Hash + Eq> {
+    inner: Arc<Mutex<BoxedService<Request, Response>>>,
+    key_fn: RequestKeyFn<Request, K>,
+    in_flight: InFlightRequests<K, Response>,
+}
+
+impl<Request, Response, K: Hash + Eq> Clone for SingleFlightService<Request, Response, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            key_fn: self.key_fn.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<Request, Response, K: Hash + Eq> SingleFlightService<Request, Response, K> {
+    /// Creates a new single flight service that wraps `inner`.
+    pub fn new(
+        inner: BoxedService<Request, Response>,
+        key_fn: impl Fn(&Request) -> Option<K> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            key_fn: Arc::new(key_fn),
+            in_flight: Default::default(),
+        }
+    }
+}
+
+fn poll_shared_ready<Request, Response>(
+    inner: &Mutex<BoxedService<Request, Response>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), ServiceError>> {
+    inner
+        .lock()
+        .expect("single flight service lock should not be poisoned")
+        .poll_ready(cx)
+}
+
+fn call_shared<Request, Response>(
+    inner: &Mutex<BoxedService<Request, Response>>,
+    request: Request,
+) -> ServiceFuture<ServiceResponse<Response>> {
+    inner
+        .lock()
+        .expect("single flight service lock should not be poisoned")
+        .call(request)
+}
+
+impl<Request, Response, K> Service<Request> for SingleFlightService<Request, Response, K>
+where
+    Request: Send + 'static,
+    Response: Clone + Send + Sync + 'static,
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        poll_shared_ready(&self.inner, cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let Some(key) = (self.key_fn)(&request) else {
+            return call_shared(&self.inner, request);
+        };
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("single flight lock should not be poisoned");
+        if let Some(outcome_rx) = in_flight.get(&key).cloned() {
+            drop(in_flight);
+            let inner = self.inner.clone();
+            return Box::pin(async move {
+                match outcome_rx.await {
+                    Ok(SingleFlightOutcome::Single(response)) => {
+                        Ok(ServiceResponse::Single(response))
+                    }
+                    Ok(SingleFlightOutcome::Error(e)) => Err(ProtocolError::from(e).into()),
+                    // The leading request returned a stream or was cancelled,
+                    // so the request must be sent separately
+                    Ok(SingleFlightOutcome::Stream) | Err(_) => {
+                        poll_fn(|cx| poll_shared_ready(&inner, cx)).await?;
+                        call_shared(&inner, request).await
+                    }
+                }
+            });
+        }
+        let (outcome_tx, outcome_rx) = oneshot::channel();
+        let outcome_rx = outcome_rx.shared();
+        in_flight.insert(key.clone(), outcome_rx.clone());
+        drop(in_flight);
+        let guard = InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            key,
+        };
+        let future = call_shared(&self.inner, request);
+        Box::pin(async move {
+            let result = future.await;
+            // Remove the in-flight request before sending the outcome,
+            // so that subsequent requests are sent to the service
+            drop(guard);
+            let (outcome, result) = match result {
+                Ok(ServiceResponse::Single(response)) => (
+                    SingleFlightOutcome::Single(response.clone()),
+                    Ok(ServiceResponse::Single(response)),
+                ),
+                Ok(ServiceResponse::Multiple(stream)) => (
+                    SingleFlightOutcome::Stream,
+                    Ok(ServiceResponse::Multiple(stream)),
+                ),
+                // The original error is returned if no other callers are waiting,
+                // since the receiver of this call is the only one left
+                Err(e) if outcome_rx.strong_count() == Some(1) => return Err(e),
+                Err(e) => {
+                    let shared_error = SharedProtocolError::new(ProtocolError::from(e));
+                    (
+                        SingleFlightOutcome::Error(shared_error.clone()),
+                        Err(ProtocolError::from(shared_error).into()),
+                    )
+                }
+            };
+            outcome_tx.send(outcome).ok();
+            result
+        })
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{http::Uri, Error as WsError},
+};
+use tower::Service;
+
+use crate::{
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        codec::LengthPrefixedCodec,
+        RequestJsonRpcConvert, ResponseJsonRpcConvert,
+    },
+    util::config::{ensure_non_zero, ConfigError},
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ServiceError, ServiceFuture,
+    ServiceResponse, DEFAULT_TIMEOUT_SECS,
+};
+
+use super::bridge_websocket;
+
+/// Configuration for the WebSocket client.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WsClientConfig {
+    /// The WebSocket URL of the server (i.e. `ws://localhost:8082`).
+    pub url: String,
+    /// Timeout for client requests in seconds.
+    pub timeout_secs: u64,
+}
+
+impl ConfigExampleSnippet for WsClientConfig {
+    fn config_example_snippet() -> String {
+        r#"# The WebSocket URL of the server.
+# url = "ws://localhost:8082"
+
+# The timeout duration in seconds for requests, defaults to 900
+# timeout_secs = 60"#
+            .into()
+    }
+}
+
+impl ConfigDeprecatedKeys for WsClientConfig {}
+
+impl ConfigEnvPrefix for WsClientConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_WS_CLIENT"
+    }
+}
+
+impl WsClientConfig {
+    /// Checks that the URL contains a scheme and authority, and that the timeout is non-zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let uri = self
+            .url
+            .parse::<Uri>()
+            .map_err(|e| ConfigError::new("url", e.to_string()))?;
+        if uri.scheme().is_none() || uri.authority().is_none() {
+            return Err(ConfigError::new(
+                "url",
+                format!("url `{}` should contain a scheme and authority", self.url),
+            ));
+        }
+        ensure_non_zero("timeout_secs", self.timeout_secs)
+    }
+}
+
+impl Default for WsClientConfig {
+    fn default() -> Self {
+        Self {
+            url: "ws://localhost:8082".to_string(),
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Client for JSON-RPC communication over a WebSocket connection.
+/// Each WebSocket frame contains a single JSON-RPC message, and streaming
+/// responses are received as notification frames, in the same manner as the [`StdioClient`].
+/// If cloned, this client will continue to use the same connection.
+#[derive(Clone)]
+pub struct WsClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    inner: StdioClient<Request, Response>,
+}
+
+impl<Request, Response> WsClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    /// Creates a new client for WebSocket communication. An error
+    /// will be returned if the configuration is invalid, or if the connection to the server fails.
+    pub async fn new(config: WsClientConfig) -> Result<Self, WsError> {
+        config
+            .validate()
+            .map_err(|e| WsError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+        let (websocket, _) = connect_async(config.url.as_str()).await?;
+        let (reader, writer) = bridge_websocket(websocket);
+        let inner = StdioClient::from_io(
+            reader,
+            writer,
+            StdioClientConfig {
+                bin_path: None,
+                timeout_secs: config.timeout_secs,
+                ..Default::default()
+            },
+            Arc::new(LengthPrefixedCodec),
+        );
+        Ok(Self { inner })
+    }
+}
+
+impl<Request, Response> Service<Request> for WsClient<Request, Response>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+{
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.inner.call(request)
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::{duplex, split, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tracing::{debug, warn};
+
+use crate::{
+    format::SerializationFormat,
+    stdio::codec::{FrameReader, LengthPrefixedCodec, StdioCodec},
+};
+
+/// WebSocket client components.
+#[cfg(feature = "ws-client")]
+pub mod client;
+/// WebSocket server components.
+#[cfg(feature = "ws-server")]
+pub mod server;
+
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+/// The maximum size of a message sent by the comm tasks, which matches the default
+/// maximum message size of the WebSocket connection.
+const MAX_BRIDGED_MESSAGE_BYTES: usize = 64 << 20;
+
+/// Bridges a WebSocket connection to a byte stream of frames, so that the connection can be
+/// used by the JSON-RPC comm tasks with the [`LengthPrefixedCodec`]. Each WebSocket message contains
+/// a single JSON-RPC message. Length-prefixed framing is used since messages from the peer
+/// may contain newlines (i.e. pretty-printed JSON). Each direction is forwarded by a separate task,
+/// so that a full buffer in one direction cannot stall the other.
+/// The socket is closed once the returned writer is dropped.
+fn bridge_websocket<S>(
+    websocket: WebSocketStream<S>,
+) -> (
+    Box<dyn AsyncRead + Send + Unpin>,
+    Box<dyn AsyncWrite + Send + Unpin>,
+)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (local, remote) = duplex(BRIDGE_BUFFER_SIZE);
+    let (local_reader, local_writer) = split(local);
+    let (remote_reader, mut remote_writer) = split(remote);
+    let (mut ws_tx, mut ws_rx) = websocket.split();
+    tokio::spawn(async move {
+        let mut frames = FrameReader::new(
+            Box::new(remote_reader),
+            Arc::new(LengthPrefixedCodec),
+            SerializationFormat::default(),
+            MAX_BRIDGED_MESSAGE_BYTES,
+        );
+        loop {
+            match frames.next_frame().await {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => {
+                    warn!("failed to read outgoing websocket message: {}", e);
+                    break;
+                }
+            }
+            let message = match std::str::from_utf8(frames.frame()) {
+                Ok(text) => Message::Text(text.to_string()),
+                Err(_) => Message::Binary(frames.frame().to_vec()),
+            };
+            if let Err(e) = ws_tx.send(message).await {
+                warn!("failed to send websocket message: {}", e);
+                break;
+            }
+        }
+        ws_tx.close().await.ok();
+    });
+    tokio::spawn(async move {
+        let mut frame = Vec::new();
+        while let Some(message) = ws_rx.next().await {
+            let payload = match message {
+                Ok(Message::Text(text)) => text.into_bytes(),
+                Ok(Message::Binary(bytes)) => bytes,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!("websocket connection error: {}", e);
+                    break;
+                }
+            };
+            frame.clear();
+            if LengthPrefixedCodec.encode(&payload, &mut frame).is_err()
+                || remote_writer.write_all(&frame).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    (Box::new(local_reader), Box::new(local_writer))
+}
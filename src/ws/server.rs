@@ -0,0 +1,195 @@
+use std::{marker::PhantomData, net::SocketAddr, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_tungstenite::accept_async;
+use tower::Service;
+use tracing::{info, warn};
+
+use crate::{
+    extensions::{Extensions, PeerAddr},
+    stdio::{
+        codec::LengthPrefixedCodec,
+        server::{StdioServer, StdioServerConfig},
+        RequestJsonRpcConvert, ResponseJsonRpcConvert,
+    },
+    util::{
+        accept_connection,
+        config::{ensure_non_zero, ConfigError},
+    },
+    ConfigDeprecatedKeys, ConfigEnvPrefix, ConfigExampleSnippet, ServiceError, ServiceFuture,
+    ServiceResponse, DEFAULT_TIMEOUT_SECS,
+};
+
+use super::bridge_websocket;
+
+/// Configuration for the WebSocket server.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WsServerConfig {
+    /// Port to listen on.
+    pub port: u16,
+    /// Timeout for service requests in seconds.
+    pub service_timeout_secs: u64,
+    /// If enabled, the sizes of incoming requests and outgoing
+    /// messages will be logged.
+    pub log_payload_sizes: bool,
+}
+
+impl ConfigExampleSnippet for WsServerConfig {
+    fn config_example_snippet() -> String {
+        r#"# The port number on which the server listens.
+# port = 8082
+
+# The timeout duration in seconds for the underlying backend service.
+# service_timeout_secs = 60
+
+# Log the sizes of incoming requests and outgoing messages.
+# log_payload_sizes = false"#
+            .into()
+    }
+}
+
+impl ConfigDeprecatedKeys for WsServerConfig {}
+
+impl ConfigEnvPrefix for WsServerConfig {
+    fn config_env_prefix() -> &'static str {
+        "MULTILINK_WS_SERVER"
+    }
+}
+
+impl WsServerConfig {
+    /// Checks that the timeout is non-zero. A port of zero is allowed,
+    /// in which case the server listens on a port assigned by the OS.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ensure_non_zero("service_timeout_secs", self.service_timeout_secs)
+    }
+}
+
+impl Default for WsServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 8082,
+            service_timeout_secs: DEFAULT_TIMEOUT_SECS,
+            log_payload_sizes: false,
+        }
+    }
+}
+
+/// Server for JSON-RPC communication with remote clients over WebSocket connections.
+/// Each connection is handled in the same manner as the [`StdioServer`]:
+/// each frame contains a single JSON-RPC message, multiple requests may be in-flight at the
+/// same time, and streaming responses are sent as notification frames.
+pub struct WsServer<Request, Response, S>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    config: WsServerConfig,
+    service: S,
+    /// Included in the extensions of each request.
+    extensions: Extensions,
+    request_phantom: PhantomData<Request>,
+    response_phantom: PhantomData<Response>,
+}
+
+impl<Request, Response, S> WsServer<Request, Response, S>
+where
+    Request: RequestJsonRpcConvert<Request> + Send + 'static,
+    Response: ResponseJsonRpcConvert<Request, Response> + Send + 'static,
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    /// Creates a new server for WebSocket communication. Client requests will be
+    /// converted and forwarded to the `service`.
+    pub fn new(service: S, config: WsServerConfig) -> Self {
+        Self {
+            config,
+            service,
+            extensions: Extensions::new(),
+            request_phantom: Default::default(),
+            response_phantom: Default::default(),
+        }
+    }
+
+    /// Creates a new server for WebSocket communication, after checking the configuration
+    /// via [`WsServerConfig::validate`]. A [`ConfigError`] will be returned
+    /// if the configuration is invalid.
+    pub fn try_new(service: S, config: WsServerConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::new(service, config))
+    }
+
+    /// Inserts a value into the extensions of each request, i.e. shared state such as a
+    /// database pool. The [`PeerAddr`] of the connection is included in the extensions as well.
+    pub fn with_extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// Listens & processes requests from remote clients. Only returns if the configured
+    /// port cannot be bound, since errors while accepting connections are logged and retried.
+    pub async fn run(self) -> std::io::Result<()> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.config.port));
+        let listener = TcpListener::bind(addr).await?;
+        self.run_with_listener(listener).await
+    }
+
+    /// Processes requests from remote clients that connect to `listener`, instead of binding
+    /// to the configured port. Useful for listening on a specific address, or on a port
+    /// assigned by the OS.
+    pub async fn run_with_listener(self, listener: TcpListener) -> std::io::Result<()> {
+        info!(
+            "listening to websocket connections on {}",
+            listener.local_addr()?
+        );
+
+        let stdio_config = StdioServerConfig {
+            service_timeout_secs: self.config.service_timeout_secs,
+            log_payload_sizes: self.config.log_payload_sizes,
+            ..Default::default()
+        };
+        loop {
+            let (stream, remote_addr) = accept_connection(&listener).await;
+            let service = self.service.clone();
+            let stdio_config = stdio_config.clone();
+            let mut extensions = self.extensions.clone();
+            extensions.insert(PeerAddr(remote_addr));
+            tokio::spawn(async move {
+                let websocket = match accept_async(stream).await {
+                    Ok(websocket) => websocket,
+                    Err(e) => {
+                        warn!("websocket handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                let (reader, writer) = bridge_websocket(websocket);
+                let server = StdioServer::from_io(
+                    service,
+                    stdio_config,
+                    reader,
+                    writer,
+                    Arc::new(LengthPrefixedCodec),
+                )
+                .with_extensions(extensions);
+                if let Err(e) = server.run().await {
+                    warn!("websocket connection from {} failed: {}", remote_addr, e);
+                }
+            });
+        }
+    }
+}
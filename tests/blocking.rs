@@ -0,0 +1,113 @@
+mod common;
+
+use std::{net::SocketAddr, sync::mpsc, thread};
+
+use multilink::{
+    blocking::{BlockingClient, BlockingError, BlockingServiceResponse},
+    http::server::HttpServer,
+    ProtocolError, ServiceError,
+};
+use tokio::runtime::Runtime;
+
+use common::{
+    bind_listener, http_client,
+    protocol::{Request, Response, SayHelloRequest},
+    stdio_pair, GreetingService,
+};
+
+/// Runs an HTTP server on a separate thread, with its own runtime.
+fn spawn_http_server_thread() -> SocketAddr {
+    let (addr_tx, addr_rx) = mpsc::channel();
+    thread::spawn(move || {
+        Runtime::new().unwrap().block_on(async move {
+            let (listener, addr) = bind_listener().await;
+            addr_tx.send(addr).unwrap();
+            HttpServer::new(GreetingService, Default::default())
+                .run_with_listener(listener)
+                .await
+        })
+    });
+    addr_rx.recv().unwrap()
+}
+
+fn say_hello_request(name: &str) -> Request {
+    Request::SayHello(SayHelloRequest {
+        name: name.to_string(),
+    })
+}
+
+fn say_hello_stream_request(name: &str) -> Request {
+    Request::SayHelloStream(SayHelloRequest {
+        name: name.to_string(),
+    })
+}
+
+fn assert_blocking_calls(client: &mut BlockingClient<Request, Response>) {
+    match client.call(say_hello_request("a")).unwrap() {
+        BlockingServiceResponse::Single(Response::SayHello(response)) => {
+            assert_eq!(response.result, "Hello, a!")
+        }
+        _ => panic!("unexpected response"),
+    }
+
+    let BlockingServiceResponse::Multiple(iter) =
+        client.call(say_hello_stream_request("b")).unwrap()
+    else {
+        panic!("expected stream response");
+    };
+    let greeting: String = iter
+        .map(|item| match item {
+            Ok(Response::SayHelloStream(response)) => response.character,
+            _ => panic!("unexpected stream item"),
+        })
+        .collect();
+    assert_eq!(greeting, "Hello, b!");
+}
+
+fn assert_within_runtime(error: ProtocolError) {
+    assert!(error
+        .inner_error()
+        .downcast_ref::<BlockingError>()
+        .is_some_and(|e| matches!(e, BlockingError::WithinRuntime)));
+}
+
+#[test]
+fn blocking_http_client_calls_outside_a_runtime() {
+    let addr = spawn_http_server_thread();
+    let mut client = BlockingClient::new(http_client(addr)).unwrap();
+    assert_blocking_calls(&mut client);
+}
+
+#[test]
+fn blocking_stdio_client_calls_outside_a_runtime() {
+    let mut client = BlockingClient::build(async {
+        Ok::<_, ServiceError>(stdio_pair(
+            GreetingService,
+            Default::default(),
+            Default::default(),
+        ))
+    })
+    .unwrap();
+    assert_blocking_calls(&mut client);
+}
+
+#[test]
+fn blocking_clients_cannot_be_used_within_a_runtime() {
+    let addr = spawn_http_server_thread();
+    let mut client = BlockingClient::new(http_client(addr)).unwrap();
+    let BlockingServiceResponse::Multiple(mut iter) =
+        client.call(say_hello_stream_request("a")).unwrap()
+    else {
+        panic!("expected stream response");
+    };
+
+    Runtime::new().unwrap().block_on(async move {
+        assert_within_runtime(BlockingClient::new(http_client(addr)).err().unwrap());
+        assert_within_runtime(client.call(say_hello_request("a")).err().unwrap());
+        assert_within_runtime(iter.next().unwrap().err().unwrap());
+        assert!(iter.next().is_none());
+        // Dropping the client and iterator within a runtime must not panic
+        drop(iter);
+        drop(client);
+    });
+}
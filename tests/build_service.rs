@@ -0,0 +1,71 @@
+mod common;
+
+use multilink::{
+    http::server::HttpServer,
+    tcp::{client::TcpClientConfig, server::TcpServer},
+    util::service::{build_service, ClientTransportConfig},
+    ws::{client::WsClientConfig, server::WsServer},
+};
+
+use common::{
+    bind_listener, http_client_config,
+    protocol::{Request, Response},
+    say_hello, spawn_http_server, GreetingService,
+};
+
+#[tokio::test]
+async fn http_transport() {
+    let addr = spawn_http_server(HttpServer::new(GreetingService, Default::default())).await;
+    let transport_config = ClientTransportConfig::Http(http_client_config(addr));
+    let mut service = build_service::<Request, Response>(transport_config)
+        .await
+        .unwrap();
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+}
+
+#[tokio::test]
+async fn tcp_transport() {
+    let (listener, addr) = bind_listener().await;
+    let server = TcpServer::new(GreetingService, Default::default());
+    tokio::spawn(server.run_with_listener(listener));
+    let transport_config = ClientTransportConfig::Tcp(TcpClientConfig {
+        address: addr.to_string(),
+        timeout_secs: 5,
+    });
+    let mut service = build_service::<Request, Response>(transport_config)
+        .await
+        .unwrap();
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+}
+
+#[tokio::test]
+async fn ws_transport() {
+    let (listener, addr) = bind_listener().await;
+    let server = WsServer::new(GreetingService, Default::default());
+    tokio::spawn(server.run_with_listener(listener));
+    let transport_config = ClientTransportConfig::Ws(WsClientConfig {
+        url: format!("ws://{addr}"),
+        timeout_secs: 5,
+    });
+    let mut service = build_service::<Request, Response>(transport_config)
+        .await
+        .unwrap();
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+}
+
+#[tokio::test]
+async fn stdio_transport_spawns_the_command() {
+    let transport_config = ClientTransportConfig::Stdio {
+        command_name: "multilink-test-missing-command".to_string(),
+        command_arguments: Vec::new(),
+        config: Default::default(),
+    };
+    let error = build_service::<Request, Response>(transport_config)
+        .await
+        .err()
+        .expect("missing command should fail to spawn");
+    let error = error
+        .downcast::<std::io::Error>()
+        .expect("spawn error should be an io error");
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+}
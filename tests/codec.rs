@@ -0,0 +1,95 @@
+mod common;
+
+use futures::future::poll_fn;
+use multilink::stdio::{
+    client::{StdioClient, StdioClientConfig},
+    codec::{LengthPrefixedCodec, LineCodec, StdioCodec},
+    server::StdioServerConfig,
+};
+use serde_json::Value;
+use tokio::io::{duplex, split, AsyncReadExt};
+use tower::Service;
+
+use common::{
+    protocol::{Request, Response, SayHelloRequest},
+    say_hello, say_hello_stream, stdio_pair, GreetingService,
+};
+
+#[test]
+fn line_codec_resumes_the_search() {
+    let mut frame = Vec::new();
+    assert_eq!(LineCodec.decode_from(b"abc", 3, &mut frame).unwrap(), None);
+    assert_eq!(
+        LineCodec.decode_from(b"abc\ndef\n", 3, &mut frame).unwrap(),
+        Some(4)
+    );
+    assert_eq!(frame, b"abc");
+}
+
+#[test]
+fn length_prefixed_codec_round_trips() {
+    let mut encoded = Vec::new();
+    LengthPrefixedCodec.encode(b"a\nb", &mut encoded).unwrap();
+    let mut frame = Vec::new();
+    assert_eq!(
+        LengthPrefixedCodec
+            .decode(&encoded[..5], &mut frame)
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        LengthPrefixedCodec.decode(&encoded, &mut frame).unwrap(),
+        Some(encoded.len())
+    );
+    assert_eq!(frame, b"a\nb");
+}
+
+#[tokio::test]
+async fn large_messages_are_read_over_many_reads() {
+    let mut client = stdio_pair(GreetingService, Default::default(), Default::default());
+    let name = "a".repeat(4 * 1024 * 1024);
+    let greeting = say_hello(&mut client, &name).await.unwrap();
+    assert_eq!(greeting.len(), name.len() + 8);
+    // Messages after the large message are unaffected
+    assert_eq!(say_hello(&mut client, "b").await.unwrap(), "Hello, b!");
+}
+
+#[tokio::test]
+async fn pretty_json_round_trips_with_length_prefixes() {
+    let mut client = stdio_pair(
+        GreetingService,
+        StdioServerConfig {
+            pretty_json: true,
+            ..Default::default()
+        },
+        StdioClientConfig {
+            pretty_json: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+    assert_eq!(say_hello_stream(&mut client, "b").await, "Hello, b!");
+
+    // Requests are written as length-prefixed, pretty-printed JSON
+    let (client_io, mut server_io) = duplex(64 * 1024);
+    let (client_reader, client_writer) = split(client_io);
+    let mut client: StdioClient<Request, Response> = StdioClient::with_io(
+        client_reader,
+        client_writer,
+        StdioClientConfig {
+            pretty_json: true,
+            ..Default::default()
+        },
+    );
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let _response = tokio::spawn(client.call(Request::SayHello(SayHelloRequest {
+        name: "a".to_string(),
+    })));
+    let length = server_io.read_u32().await.unwrap() as usize;
+    let mut payload = vec![0; length];
+    server_io.read_exact(&mut payload).await.unwrap();
+    assert!(payload.contains(&b'\n'));
+    let request: Value = serde_json::from_slice(&payload).unwrap();
+    assert_eq!(request["method"], "sayHello");
+    assert_eq!(request["params"]["name"], "a");
+}
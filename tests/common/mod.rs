@@ -0,0 +1,298 @@
+#![allow(dead_code)]
+
+#[allow(clippy::enum_variant_names)]
+#[path = "../../examples/protocol/mod.rs"]
+pub mod protocol;
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_stream::stream;
+use futures::StreamExt;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request as HttpRequest, Response as HttpResponse, Server,
+};
+use multilink::{
+    http::{
+        client::{HttpClient, HttpClientConfig},
+        server::HttpServer,
+    },
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        server::{StdioServer, StdioServerConfig},
+    },
+    util::ProgressEvent,
+    ServiceError, ServiceFuture, ServiceResponse,
+};
+use protocol::{GreetingProgress, GreetingResponse, GreetingStreamResponse, Request, Response};
+use serde_json::Value;
+use tokio::{
+    io::{
+        duplex, split, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+    },
+    net::TcpListener,
+    time::sleep,
+};
+use tower::Service;
+
+/// The delay between the items of streaming responses.
+pub const STREAM_ITEM_DELAY: Duration = Duration::from_millis(10);
+
+/// Sends greetings, in the same manner as the greeting server example.
+#[derive(Clone)]
+pub struct GreetingService;
+
+impl Service<Request> for GreetingService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        Box::pin(async move {
+            Ok(match req {
+                Request::SayHello(request) => {
+                    ServiceResponse::Single(Response::SayHello(GreetingResponse {
+                        result: format!("Hello, {}!", request.name),
+                    }))
+                }
+                Request::SayCustomGreeting(request) => {
+                    ServiceResponse::Single(Response::SayCustomGreeting(GreetingResponse {
+                        result: format!("{}, {}!", request.greeting, request.name),
+                    }))
+                }
+                Request::SayHelloStream(request) => ServiceResponse::Multiple(
+                    stream! {
+                        let result = format!("Hello, {}!", request.name);
+                        for character in result.chars() {
+                            yield Ok(Response::SayHelloStream(GreetingStreamResponse { character }));
+                            sleep(STREAM_ITEM_DELAY).await;
+                        }
+                    }
+                    .boxed(),
+                ),
+                Request::SayHelloWithProgress(request) => ServiceResponse::Multiple(
+                    stream! {
+                        for percent in [0, 50] {
+                            yield Ok(Response::SayHelloWithProgress(ProgressEvent::Progress(GreetingProgress { percent })));
+                            sleep(STREAM_ITEM_DELAY).await;
+                        }
+                        yield Ok(Response::SayHelloWithProgress(ProgressEvent::Result(GreetingResponse {
+                            result: format!("Hello, {}!", request.name),
+                        })));
+                    }
+                    .boxed(),
+                ),
+            })
+        })
+    }
+}
+
+/// Waits for the delay before handling each request via [`GreetingService`].
+#[derive(Clone)]
+pub struct SlowService(pub Duration);
+
+impl Service<Request> for SlowService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let delay = self.0;
+        Box::pin(async move {
+            sleep(delay).await;
+            GreetingService.call(req).await
+        })
+    }
+}
+
+/// Binds a listener to a port assigned by the OS.
+pub async fn bind_listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// Runs the HTTP server in the background, and returns the address it listens on.
+pub async fn spawn_http_server<S>(server: HttpServer<Request, Response, S>) -> SocketAddr
+where
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + Clone
+        + 'static,
+{
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(server.run_with_listener(listener));
+    addr
+}
+
+/// Runs a plain hyper server in the background, which responds to each request
+/// via `handler`. Returns the address it listens on.
+pub async fn spawn_raw_http_server<F>(handler: F) -> SocketAddr
+where
+    F: Fn(HttpRequest<Body>) -> HttpResponse<Body> + Clone + Send + Sync + 'static,
+{
+    let (listener, addr) = bind_listener().await;
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handler(request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    let server = Server::from_tcp(listener.into_std().unwrap())
+        .unwrap()
+        .serve(make_service);
+    tokio::spawn(server);
+    addr
+}
+
+/// Returns the config for an HTTP client that sends requests to `addr`.
+pub fn http_client_config(addr: SocketAddr) -> HttpClientConfig {
+    HttpClientConfig {
+        base_url: format!("http://{addr}"),
+        timeout_secs: 5,
+        ..Default::default()
+    }
+}
+
+pub fn http_client(addr: SocketAddr) -> HttpClient<Request, Response> {
+    HttpClient::try_new(http_client_config(addr)).unwrap()
+}
+
+/// One end of an in-memory connection, which reads and writes raw JSON-RPC messages.
+pub struct RawPeer {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+impl RawPeer {
+    fn new(io: DuplexStream) -> Self {
+        let (reader, writer) = split(io);
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    pub async fn read_message(&mut self) -> Value {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    pub async fn write_message(&mut self, message: Value) {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    pub async fn write_raw(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).await.unwrap();
+    }
+}
+
+/// Connects a stdio client to a [`RawPeer`] that acts as the server.
+pub fn raw_server(client_config: StdioClientConfig) -> (StdioClient<Request, Response>, RawPeer) {
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (client_reader, client_writer) = split(client_io);
+    let client = StdioClient::with_io(client_reader, client_writer, client_config);
+    (client, RawPeer::new(server_io))
+}
+
+/// Connects a [`RawPeer`] that acts as the client to an in-process stdio server.
+pub fn raw_client<S>(service: S, server_config: StdioServerConfig) -> RawPeer
+where
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + 'static,
+{
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let server = StdioServer::with_io(service, server_config, server_reader, server_writer);
+    tokio::spawn(server.run());
+    RawPeer::new(client_io)
+}
+
+/// Connects a stdio client to an in-process stdio server via in-memory pipes.
+pub fn stdio_pair<S>(
+    service: S,
+    server_config: StdioServerConfig,
+    client_config: StdioClientConfig,
+) -> StdioClient<Request, Response>
+where
+    S: Service<
+            Request,
+            Response = ServiceResponse<Response>,
+            Error = ServiceError,
+            Future = ServiceFuture<ServiceResponse<Response>>,
+        > + Send
+        + 'static,
+{
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let (client_reader, client_writer) = split(client_io);
+    let server = StdioServer::with_io(service, server_config, server_reader, server_writer);
+    tokio::spawn(server.run());
+    StdioClient::with_io(client_reader, client_writer, client_config)
+}
+
+/// Sends a `SayHello` request, and returns the greeting.
+pub async fn say_hello<S>(client: &mut S, name: &str) -> Result<String, ServiceError>
+where
+    S: Service<Request, Response = ServiceResponse<Response>, Error = ServiceError>,
+{
+    let request = Request::SayHello(protocol::SayHelloRequest {
+        name: name.to_string(),
+    });
+    futures::future::poll_fn(|cx| client.poll_ready(cx)).await?;
+    match client.call(request).await? {
+        ServiceResponse::Single(Response::SayHello(response)) => Ok(response.result),
+        _ => panic!("unexpected response"),
+    }
+}
+
+/// Sends a `SayHelloStream` request, and returns the greeting assembled from the stream.
+pub async fn say_hello_stream<S>(client: &mut S, name: &str) -> String
+where
+    S: Service<Request, Response = ServiceResponse<Response>, Error = ServiceError>,
+{
+    let request = Request::SayHelloStream(protocol::SayHelloRequest {
+        name: name.to_string(),
+    });
+    futures::future::poll_fn(|cx| client.poll_ready(cx))
+        .await
+        .unwrap();
+    let ServiceResponse::Multiple(stream) = client.call(request).await.unwrap() else {
+        panic!("expected stream response");
+    };
+    stream
+        .map(|item| match item {
+            Ok(Response::SayHelloStream(response)) => response.character,
+            _ => panic!("unexpected stream item"),
+        })
+        .collect()
+        .await
+}
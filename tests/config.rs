@@ -0,0 +1,400 @@
+mod common;
+
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+use common::{
+    protocol::{Request, Response},
+    say_hello, GreetingService,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    http::{
+        client::{HttpClient, HttpClientConfig},
+        server::{HttpServer, HttpServerConfig},
+    },
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        server::StdioServerConfig,
+    },
+    tcp::{client::TcpClientConfig, server::TcpServerConfig},
+    util::config::{
+        apply_env_overrides, apply_env_overrides_with_prefix, ConfigEnvError, ConfigError,
+    },
+    ws::{client::WsClientConfig, server::WsServerConfig},
+    ProtocolError,
+};
+
+fn invalid_key(result: Result<(), ConfigError>) -> &'static str {
+    result.expect_err("config should be invalid").key
+}
+
+fn http_client_config(base_url: &str) -> HttpClientConfig {
+    HttpClientConfig {
+        base_url: base_url.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn http_client_config_rejects_invalid_values() {
+    assert!(http_client_config("http://localhost:8080")
+        .validate()
+        .is_ok());
+    assert_eq!(
+        invalid_key(http_client_config("localhost").validate()),
+        "base_url"
+    );
+    assert_eq!(
+        invalid_key(http_client_config("/api").validate()),
+        "base_url"
+    );
+    assert_eq!(
+        invalid_key(http_client_config("http://").validate()),
+        "base_url"
+    );
+    let config = HttpClientConfig {
+        base_urls: vec!["http://a:8080".to_string(), "b:8080".to_string()],
+        ..http_client_config("http://localhost:8080")
+    };
+    assert_eq!(invalid_key(config.validate()), "base_urls");
+    let config = HttpClientConfig {
+        timeout_secs: 0,
+        ..http_client_config("http://localhost:8080")
+    };
+    assert_eq!(invalid_key(config.validate()), "timeout_secs");
+    let config = HttpClientConfig {
+        api_key_header: "Api Token".to_string(),
+        ..http_client_config("http://localhost:8080")
+    };
+    assert_eq!(invalid_key(config.validate()), "api_key_header");
+    let config = HttpClientConfig {
+        schema_version: Some("1\n".to_string()),
+        ..http_client_config("http://localhost:8080")
+    };
+    assert_eq!(invalid_key(config.validate()), "schema_version");
+}
+
+#[test]
+fn http_client_try_new_rejects_invalid_config() {
+    let result = HttpClient::<Request, Response>::try_new(http_client_config("localhost"));
+    assert_eq!(result.err().unwrap().key, "base_url");
+    let result = HttpClient::<Request, Response>::builder()
+        .base_url("localhost")
+        .build();
+    assert_eq!(result.err().unwrap().key, "base_url");
+}
+
+#[tokio::test]
+async fn http_client_new_reports_invalid_config_per_request() {
+    assert!(HttpClient::<Request, Response>::new(http_client_config("http://[::1")).is_err());
+    let mut client = HttpClient::<Request, Response>::new(http_client_config("localhost"))
+        .expect("only unparseable base urls should be rejected");
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(error.to_string().contains("base_url"));
+}
+
+#[tokio::test]
+async fn http_client_builder_matches_config() {
+    let server_config = HttpServerConfig {
+        api_keys: ["secret".to_string()].into(),
+        api_key_header: "Api-Token".to_string(),
+        ..Default::default()
+    };
+    let addr =
+        common::spawn_http_server(HttpServer::try_new(GreetingService, server_config).unwrap())
+            .await;
+    let base_url = format!("http://{addr}");
+
+    let config = HttpClientConfig {
+        api_key: Some("secret".to_string()),
+        api_key_header: "Api-Token".to_string(),
+        timeout_secs: 5,
+        ..http_client_config(&base_url)
+    };
+    let mut config_client = HttpClient::<Request, Response>::try_new(config).unwrap();
+    let mut builder_client = HttpClient::<Request, Response>::builder()
+        .base_url(&base_url)
+        .api_key("secret")
+        .api_key_header("Api-Token")
+        .timeout_secs(5)
+        .build()
+        .unwrap();
+    for client in [&mut config_client, &mut builder_client] {
+        assert_eq!(say_hello(client, "a").await.unwrap(), "Hello, a!");
+    }
+
+    // Both clients omit the key if it was not set, so the server rejects their requests
+    let mut config_client =
+        HttpClient::<Request, Response>::try_new(http_client_config(&base_url)).unwrap();
+    let mut builder_client = HttpClient::<Request, Response>::builder()
+        .base_url(&base_url)
+        .build()
+        .unwrap();
+    for client in [&mut config_client, &mut builder_client] {
+        let error = ProtocolError::from(say_hello(client, "a").await.unwrap_err());
+        assert!(matches!(error.error_type, ProtocolErrorType::Unauthorized));
+    }
+}
+
+#[test]
+fn http_server_config_rejects_invalid_values() {
+    let config = HttpServerConfig {
+        port: 0,
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let config = HttpServerConfig {
+        service_timeout_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "service_timeout_secs");
+    for requests_per_sec in [0.0, -1.0, f64::NAN] {
+        let config = HttpServerConfig {
+            api_key_rate_limits: [("key".to_string(), requests_per_sec)].into(),
+            ..Default::default()
+        };
+        assert_eq!(invalid_key(config.validate()), "api_key_rate_limits");
+    }
+    let config = HttpServerConfig {
+        request_read_timeout_secs: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "request_read_timeout_secs");
+    let config = HttpServerConfig {
+        max_connections: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "max_connections");
+    let config = HttpServerConfig {
+        hmac_secrets: ["secret".to_string()].into(),
+        hmac_max_age_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "hmac_max_age_secs");
+    let config = HttpServerConfig {
+        access_log_sample_rate: 1.5,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "access_log_sample_rate");
+    let config = HttpServerConfig {
+        api_key_header: String::new(),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "api_key_header");
+    let config = HttpServerConfig {
+        schema_version: Some("\u{7f}".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "schema_version");
+}
+
+#[test]
+fn http_server_try_new_rejects_invalid_config() {
+    let config = HttpServerConfig {
+        service_timeout_secs: 0,
+        ..Default::default()
+    };
+    let result = HttpServer::<Request, Response, _>::try_new(common::GreetingService, config);
+    assert_eq!(result.err().unwrap().key, "service_timeout_secs");
+}
+
+#[test]
+fn stdio_config_rejects_invalid_values() {
+    assert!(StdioClientConfig::default().validate().is_ok());
+    let config = StdioClientConfig {
+        bin_path: Some(String::new()),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "bin_path");
+    let config = StdioClientConfig {
+        timeout_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "timeout_secs");
+    let config = StdioClientConfig {
+        notification_channel_capacity: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        invalid_key(config.validate()),
+        "notification_channel_capacity"
+    );
+    let config = StdioClientConfig {
+        max_message_bytes: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "max_message_bytes");
+    let config = StdioClientConfig {
+        request_queue_capacity: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "request_queue_capacity");
+
+    assert!(StdioServerConfig::default().validate().is_ok());
+    let config = StdioServerConfig {
+        service_timeout_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "service_timeout_secs");
+    let config = StdioServerConfig {
+        heartbeat_interval_secs: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "heartbeat_interval_secs");
+    let config = StdioServerConfig {
+        log_sample_rate: -0.1,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "log_sample_rate");
+}
+
+#[tokio::test]
+async fn stdio_client_rejects_invalid_config() {
+    let config = StdioClientConfig {
+        timeout_secs: 0,
+        ..Default::default()
+    };
+    let error = StdioClient::<Request, Response>::new("true", &[], config)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+/// Replies to the first request (with id 1), and never responds to subsequent requests.
+#[cfg(unix)]
+const STDIO_CHILD_SCRIPT: &str = r#"read -r line; echo '{"jsonrpc":"2.0","id":1,"result":{"result":"Hello, a!"}}'; cat >/dev/null"#;
+
+#[cfg(unix)]
+#[tokio::test]
+async fn stdio_client_builder_matches_config() {
+    let config = StdioClientConfig {
+        timeout_secs: 1,
+        ..Default::default()
+    };
+    let config_client =
+        StdioClient::<Request, Response>::new("sh", &["-c", STDIO_CHILD_SCRIPT], config)
+            .await
+            .unwrap();
+    let builder_client = StdioClient::<Request, Response>::builder("sh")
+        .args(["-c", STDIO_CHILD_SCRIPT])
+        .timeout_secs(1)
+        .build()
+        .await
+        .unwrap();
+    for mut client in [config_client, builder_client] {
+        assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+        let start = Instant::now();
+        let error = ProtocolError::from(say_hello(&mut client, "b").await.unwrap_err());
+        assert!(error.to_string().contains("timed out"), "{error}");
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    // Invalid values are rejected by both paths
+    let error = StdioClient::<Request, Response>::builder("true")
+        .timeout_secs(0)
+        .build()
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn tcp_and_ws_configs_reject_invalid_values() {
+    let config = TcpServerConfig {
+        port: 0,
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let config = TcpServerConfig {
+        service_timeout_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "service_timeout_secs");
+    let config = TcpClientConfig {
+        address: String::new(),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "address");
+
+    let config = WsServerConfig {
+        port: 0,
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let config = WsClientConfig {
+        url: "localhost:8082".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "url");
+    let config = WsClientConfig {
+        timeout_secs: 0,
+        ..Default::default()
+    };
+    assert_eq!(invalid_key(config.validate()), "timeout_secs");
+}
+
+#[test]
+fn http_client_config_env_overrides() {
+    std::env::set_var("MULTILINK_HTTP_CLIENT_TIMEOUT_SECS", "5");
+    std::env::set_var("MULTILINK_HTTP_CLIENT_BASE_URL", "http://127.0.0.1:9000/");
+    std::env::set_var("MULTILINK_HTTP_CLIENT_POOL_IDLE_TIMEOUT_SECS", "30");
+    let config = apply_env_overrides(HttpClientConfig {
+        api_key: Some("key".to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(config.timeout_secs, 5);
+    assert_eq!(config.base_url, "http://127.0.0.1:9000/");
+    assert_eq!(config.pool_idle_timeout_secs, Some(30));
+    // Fields without environment variables are left untouched
+    assert_eq!(config.api_key.as_deref(), Some("key"));
+    assert_eq!(
+        config.api_key_header,
+        HttpClientConfig::default().api_key_header
+    );
+}
+
+#[test]
+fn http_server_config_env_overrides() {
+    std::env::set_var("MULTILINK_HTTP_SERVER_PORT", "9123");
+    std::env::set_var("MULTILINK_HTTP_SERVER_SERVICE_TIMEOUT_SECS", "7");
+    std::env::set_var("MULTILINK_HTTP_SERVER_API_KEYS", r#"["1234"]"#);
+    let config = apply_env_overrides(HttpServerConfig::default()).unwrap();
+    assert_eq!(config.port, 9123);
+    assert_eq!(config.service_timeout_secs, 7);
+    assert_eq!(config.api_keys, ["1234".to_string()].into());
+    assert_eq!(config.max_connections, None);
+}
+
+#[test]
+fn stdio_config_env_overrides() {
+    std::env::set_var("MULTILINK_STDIO_CLIENT_TIMEOUT_SECS", "3");
+    std::env::set_var("MULTILINK_STDIO_CLIENT_BIN_PATH", "/usr/bin/example");
+    let config = apply_env_overrides(StdioClientConfig::default()).unwrap();
+    assert_eq!(config.timeout_secs, 3);
+    assert_eq!(config.bin_path.as_deref(), Some("/usr/bin/example"));
+
+    let default_server_config = StdioServerConfig::default();
+    let config = apply_env_overrides(StdioServerConfig::default()).unwrap();
+    assert_eq!(
+        config.service_timeout_secs,
+        default_server_config.service_timeout_secs
+    );
+}
+
+#[test]
+fn invalid_env_overrides_are_rejected() {
+    std::env::set_var("MULTILINK_TEST_INVALID_PORT", "not-a-port");
+    let Err(error) =
+        apply_env_overrides_with_prefix(HttpServerConfig::default(), "MULTILINK_TEST_INVALID")
+    else {
+        panic!("override should be invalid");
+    };
+    assert!(
+        matches!(&error, ConfigEnvError::InvalidValue { var, .. } if var == "MULTILINK_TEST_INVALID_PORT"),
+        "{error}"
+    );
+}
@@ -0,0 +1,231 @@
+mod common;
+
+use std::{
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request as HttpRequest, Response as HttpResponse, Server, StatusCode,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    http::{server::HttpServer, ProtocolHttpError},
+    jsonrpc::{JsonRpcErrorCode, JsonRpcResponse},
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+};
+use serde_json::{json, Value};
+use tokio::io::AsyncReadExt;
+use tower::Service;
+
+use common::{
+    bind_listener, http_client,
+    protocol::{Request, Response},
+    say_hello, spawn_http_server, stdio_pair,
+};
+
+/// Fails every request with an error that carries a code and structured data.
+#[derive(Clone)]
+struct FailingService;
+
+impl Service<Request> for FailingService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request) -> Self::Future {
+        Box::pin(async {
+            Err(
+                ProtocolError::new(ProtocolErrorType::BadRequest, "invalid name".into())
+                    .with_code("request.invalid_name")
+                    .with_data(json!({"field": "name"}))
+                    .into(),
+            )
+        })
+    }
+}
+
+fn assert_error_details(error: ServiceError) {
+    let error = ProtocolError::from(error);
+    assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    assert_eq!(error.to_string(), "invalid name");
+    assert_eq!(error.code(), Some("request.invalid_name"));
+    assert_eq!(error.data(), Some(&json!({"field": "name"})));
+}
+
+#[test]
+fn error_details_do_not_change_the_error() {
+    let error = ProtocolError::new(ProtocolErrorType::Internal, "failed".into());
+    assert_eq!(error.code(), None);
+    assert_eq!(error.data(), None);
+    let error = error.with_data(json!(1)).with_code("a").with_code("b");
+    assert_eq!(error.to_string(), "failed");
+    assert_eq!(error.code(), Some("b"));
+    assert_eq!(error.data(), Some(&json!(1)));
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("original")]
+struct OriginalError;
+
+#[test]
+fn error_details_do_not_wrap_the_error() {
+    let error = ProtocolError::new(ProtocolErrorType::Internal, Box::new(OriginalError))
+        .with_code("a")
+        .with_data(json!(1));
+    assert!(error.error.downcast_ref::<OriginalError>().is_some());
+    assert!(error.inner_error().is::<OriginalError>());
+    assert_eq!(error.to_string(), "original");
+}
+
+#[test]
+fn jsonrpc_error_code_is_carried_in_data() {
+    let error = ProtocolError::new(ProtocolErrorType::BadRequest, "invalid name".into())
+        .with_code("request.invalid_name")
+        .with_data(json!({"field": "name"}));
+    let response = serde_json::to_value(JsonRpcResponse::new(Err(error), Value::from(1))).unwrap();
+    let error = &response["error"];
+    assert_eq!(
+        error.as_object().unwrap().keys().collect::<Vec<_>>(),
+        ["code", "data", "message"]
+    );
+    assert_eq!(
+        error["data"],
+        json!({"error_code": "request.invalid_name", "details": {"field": "name"}})
+    );
+
+    // Data without a code is sent as is, and is not mistaken for a code
+    let error = ProtocolError::new(ProtocolErrorType::BadRequest, "invalid name".into())
+        .with_data(json!({"error_code": 1}));
+    let response = JsonRpcResponse::new(Err(error), Value::from(1));
+    let error = response.get_result().unwrap_err();
+    assert_eq!(error.code, None);
+    assert_eq!(error.data, Some(json!({"error_code": 1})));
+}
+
+#[tokio::test]
+async fn error_details_are_preserved_over_http() {
+    let addr = spawn_http_server(HttpServer::new(FailingService, Default::default())).await;
+    let mut client = http_client(addr);
+    assert_error_details(say_hello(&mut client, "a").await.unwrap_err());
+}
+
+#[tokio::test]
+async fn error_details_are_preserved_over_stdio() {
+    let mut client = stdio_pair(FailingService, Default::default(), Default::default());
+    assert_error_details(say_hello(&mut client, "a").await.unwrap_err());
+}
+
+#[test]
+fn timeout_and_unavailable_errors_round_trip() {
+    for (error_type, status, code) in [
+        (
+            ProtocolErrorType::RequestTimeout,
+            StatusCode::REQUEST_TIMEOUT,
+            JsonRpcErrorCode::RequestTimeout,
+        ),
+        (
+            ProtocolErrorType::ServiceUnavailable,
+            StatusCode::SERVICE_UNAVAILABLE,
+            JsonRpcErrorCode::ServiceUnavailable,
+        ),
+    ] {
+        let mapped_status: StatusCode = error_type.clone().into();
+        assert_eq!(mapped_status, status);
+        let from_status = ProtocolErrorType::from(status);
+        assert_eq!(format!("{from_status:?}"), format!("{error_type:?}"));
+
+        let mapped_code = JsonRpcErrorCode::from(error_type.clone());
+        assert_eq!(mapped_code, code);
+        let from_code: ProtocolErrorType = JsonRpcErrorCode::from(code as i32).into();
+        assert_eq!(format!("{from_code:?}"), format!("{error_type:?}"));
+
+        assert!(error_type.is_retryable());
+    }
+}
+
+#[test]
+fn unknown_http_error_types_are_ignored() {
+    let error: ProtocolHttpError =
+        serde_json::from_value(json!({"error": "slow down", "error_type": "TooManyRequests"}))
+            .unwrap();
+    assert!(matches!(
+        error.error_type,
+        Some(ProtocolErrorType::TooManyRequests)
+    ));
+    for body in [
+        json!({"error": "teapot", "error_type": "Teapot"}),
+        json!({"error": "teapot", "error_type": {"Teapot": 418}}),
+        json!({"error": "teapot", "error_type": null}),
+        json!({"error": "teapot"}),
+    ] {
+        let error: ProtocolHttpError = serde_json::from_value(body).unwrap();
+        assert_eq!(error.error, "teapot");
+        assert!(error.error_type.is_none());
+    }
+}
+
+#[tokio::test]
+async fn unknown_http_error_types_fall_back_to_the_status() {
+    let (listener, addr) = bind_listener().await;
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_: HttpRequest<Body>| async {
+            let body = json!({"error": "out of stock", "error_type": "OutOfStock"});
+            let response = HttpResponse::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    let server = Server::from_tcp(listener.into_std().unwrap())
+        .unwrap()
+        .serve(make_service);
+    tokio::spawn(server);
+
+    let mut client = http_client(addr);
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(matches!(error.error_type, ProtocolErrorType::NotFound));
+    assert_eq!(error.to_string(), "out of stock");
+}
+
+#[tokio::test]
+async fn connection_failures_are_retryable() {
+    // The listener is dropped, so that nothing is listening on the port
+    let (_, addr) = bind_listener().await;
+    let mut client = http_client(addr);
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::ServiceUnavailable
+    ));
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn closed_connections_are_not_retryable() {
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(async move {
+        // Read the request, then close the connection without responding
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let _ = stream.read(&mut buf).await.unwrap();
+    });
+    let mut client = http_client(addr);
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(
+        matches!(error.error_type, ProtocolErrorType::ConnectionClosed),
+        "{:?}",
+        error.error_type
+    );
+    assert!(!error.is_retryable());
+
+    let code = JsonRpcErrorCode::from(ProtocolErrorType::ConnectionClosed);
+    let from_code: ProtocolErrorType = JsonRpcErrorCode::from(code as i32).into();
+    assert!(matches!(from_code, ProtocolErrorType::ConnectionClosed));
+}
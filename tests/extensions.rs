@@ -0,0 +1,102 @@
+mod common;
+
+use std::task::{Context, Poll};
+
+use hyper::{body::to_bytes, Body, Client, Request as HttpRequest};
+use multilink::{
+    extensions::request_extension,
+    http::server::{HttpServer, HttpServerConfig},
+    stdio::{client::StdioClient, server::StdioServer},
+    ServiceError, ServiceFuture, ServiceResponse,
+};
+use serde_json::Value;
+use tokio::io::{duplex, split};
+use tower::Service;
+
+use common::{
+    http_client,
+    protocol::{GreetingResponse, Request, Response},
+    say_hello, spawn_http_server, GreetingService,
+};
+
+/// The identity of the caller, injected by the server.
+#[derive(Clone)]
+struct Identity(String);
+
+/// Greets the caller by its identity, if one was injected.
+#[derive(Clone)]
+struct IdentityService;
+
+impl Service<Request> for IdentityService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let Request::SayHello(request) = req else {
+            return GreetingService.call(req);
+        };
+        Box::pin(async move {
+            // Extensions are not available in spawned tasks, so they must be read beforehand
+            let spawned_identity = tokio::spawn(async { request_extension::<Identity>() })
+                .await
+                .unwrap();
+            assert!(spawned_identity.is_none());
+            let result = match request_extension::<Identity>() {
+                Some(Identity(identity)) => format!("Hello, {}! ({identity})", request.name),
+                None => format!("Hello, {}!", request.name),
+            };
+            Ok(ServiceResponse::Single(Response::SayHello(
+                GreetingResponse { result },
+            )))
+        })
+    }
+}
+
+#[tokio::test]
+async fn http_services_read_identities_injected_by_interceptors() {
+    let server = HttpServer::new(IdentityService, HttpServerConfig::default())
+        .with_request_interceptor(|_, context| {
+            if let Some(user) = context.headers.get("X-User") {
+                let user = user.to_str().unwrap_or_default().to_string();
+                context.extensions.insert(Identity(user));
+            }
+            Ok(())
+        });
+    let addr = spawn_http_server(server).await;
+    let mut client = http_client(addr);
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-User", "admin")
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap().into_body();
+    let body: Value = serde_json::from_slice(&to_bytes(response).await.unwrap()).unwrap();
+    assert_eq!(body["result"], "Hello, a! (admin)");
+}
+
+#[tokio::test]
+async fn stdio_services_read_server_extensions() {
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let (client_reader, client_writer) = split(client_io);
+    let server = StdioServer::with_io(
+        IdentityService,
+        Default::default(),
+        server_reader,
+        server_writer,
+    )
+    .with_extension(Identity("parent".to_string()));
+    tokio::spawn(server.run());
+    let mut client: StdioClient<Request, Response> =
+        StdioClient::with_io(client_reader, client_writer, Default::default());
+    assert_eq!(
+        say_hello(&mut client, "a").await.unwrap(),
+        "Hello, a! (parent)"
+    );
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Method, Uri,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    format::{SerializationFormat, JSON_CONTENT_TYPE},
+    http::util::{body_format, parse_request, serialize_to_http_request_with_format},
+    jsonrpc::JsonRpcRequest,
+    stdio::{client::StdioClientConfig, server::StdioServerConfig},
+};
+use serde_json::{json, Value};
+
+use common::{say_hello, say_hello_stream, stdio_pair, GreetingService};
+
+const FORMATS: [SerializationFormat; 3] = [
+    SerializationFormat::Json,
+    SerializationFormat::MessagePack,
+    SerializationFormat::Cbor,
+];
+
+#[test]
+fn jsonrpc_messages_round_trip_in_each_format() {
+    let request: JsonRpcRequest = serde_json::from_value(json!({
+        "jsonrpc": "2.0",
+        "method": "sayHello",
+        "params": {"name": "a", "tags": [1, 2.5, null, true]},
+        "id": 7,
+    }))
+    .unwrap();
+    for format in FORMATS {
+        let bytes = format.serialize(&request).unwrap();
+        let decoded: JsonRpcRequest = format.deserialize(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&request).unwrap(),
+            "{format}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn stdio_requests_round_trip_in_each_format() {
+    for format in FORMATS {
+        let mut client = stdio_pair(
+            GreetingService,
+            StdioServerConfig {
+                serialization_format: format,
+                ..Default::default()
+            },
+            StdioClientConfig {
+                serialization_format: format,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            say_hello(&mut client, "a").await.unwrap(),
+            "Hello, a!",
+            "{format}"
+        );
+        assert_eq!(
+            say_hello_stream(&mut client, "ab").await,
+            "Hello, ab!",
+            "{format}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn http_bodies_round_trip_in_each_format() {
+    let base_url = Uri::from_static("http://localhost");
+    let body = json!({"name": "a", "count": 3});
+    for format in FORMATS {
+        let request =
+            serialize_to_http_request_with_format(&base_url, "/test", Method::POST, &body, format)
+                .unwrap();
+        assert_eq!(body_format(request.headers()).unwrap(), format);
+        let parsed: Value = parse_request(request).await.unwrap();
+        assert_eq!(parsed, body, "{format}");
+    }
+}
+
+#[tokio::test]
+async fn mismatched_http_body_formats_fail_with_an_error() {
+    let base_url = Uri::from_static("http://localhost");
+    let mut request = serialize_to_http_request_with_format(
+        &base_url,
+        "/test",
+        Method::POST,
+        &json!({"name": "a"}),
+        SerializationFormat::MessagePack,
+    )
+    .unwrap();
+    request
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE));
+    let error = parse_request::<Value>(request).await.unwrap_err();
+    assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    assert!(
+        error
+            .to_string()
+            .contains("failed to deserialize json payload"),
+        "{error}"
+    );
+
+    let mut request = serialize_to_http_request_with_format(
+        &base_url,
+        "/test",
+        Method::POST,
+        &json!({}),
+        SerializationFormat::Json,
+    )
+    .unwrap();
+    request
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+    let error = parse_request::<Value>(request).await.unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::UnsupportedMediaType
+    ));
+}
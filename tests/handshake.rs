@@ -0,0 +1,264 @@
+mod common;
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING},
+    Body, Client, Request as HttpRequest, StatusCode,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    handshake::{Handshake, HandshakeError, COMPRESSION_FEATURE, HANDSHAKE_ERROR_CODE},
+    http::{
+        client::{HttpClient, HttpClientConfig},
+        server::{HttpServer, HttpServerConfig},
+    },
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        StdioError,
+    },
+    ProtocolError,
+};
+
+use common::{
+    http_client_config,
+    protocol::{Request, Response},
+    say_hello, spawn_http_server, GreetingService,
+};
+
+const HANDSHAKE_HEADER: &str = "X-Multilink-Handshake";
+
+/// Runs an HTTP server with compression enabled for all responses.
+async fn spawn_handshake_server(schema_version: &str) -> SocketAddr {
+    let config = HttpServerConfig {
+        schema_version: Some(schema_version.to_string()),
+        enable_compression: true,
+        compression_min_bytes: 0,
+        ..Default::default()
+    };
+    spawn_http_server(HttpServer::new(GreetingService, config)).await
+}
+
+fn handshake_client(addr: SocketAddr, schema_version: &str) -> HttpClient<Request, Response> {
+    HttpClient::try_new(HttpClientConfig {
+        handshake: true,
+        schema_version: Some(schema_version.to_string()),
+        ..http_client_config(addr)
+    })
+    .unwrap()
+}
+
+/// Sends a raw request with the given client handshake, and returns the
+/// `Content-Encoding` of the response, along with the handshake of the server.
+async fn get_with_handshake(
+    addr: SocketAddr,
+    handshake: &Handshake,
+) -> (Option<String>, Handshake) {
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header(ACCEPT_ENCODING, "gzip, deflate")
+        .header(HANDSHAKE_HEADER, serde_json::to_string(handshake).unwrap())
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap().to_string());
+    let server_handshake =
+        serde_json::from_slice(response.headers()[HANDSHAKE_HEADER].as_bytes()).unwrap();
+    (encoding, server_handshake)
+}
+
+#[tokio::test]
+async fn http_handshakes_succeed_between_compatible_peers() {
+    let addr = spawn_handshake_server("v1").await;
+    let mut client = handshake_client(addr, "v1");
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+}
+
+#[tokio::test]
+async fn http_schema_version_mismatches_are_rejected() {
+    let addr = spawn_handshake_server("v1").await;
+    let mut client = handshake_client(addr, "v2");
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    assert_eq!(error.code(), Some(HANDSHAKE_ERROR_CODE));
+    assert!(error.to_string().contains("schema version"), "{error}");
+}
+
+#[tokio::test]
+async fn http_compression_is_negotiated_via_the_handshake() {
+    let addr = spawn_handshake_server("v1").await;
+    let handshake = Handshake::new(Some("v1".to_string()));
+
+    // Without the feature, the Accept-Encoding header is used
+    let (encoding, server_handshake) = get_with_handshake(addr, &handshake).await;
+    assert_eq!(encoding.as_deref(), Some("gzip"));
+    assert_eq!(
+        server_handshake.features[COMPRESSION_FEATURE],
+        "gzip,deflate"
+    );
+
+    let deflate = handshake
+        .clone()
+        .with_feature(COMPRESSION_FEATURE, "deflate");
+    let (encoding, _) = get_with_handshake(addr, &deflate).await;
+    assert_eq!(encoding.as_deref(), Some("deflate"));
+
+    let identity = handshake.with_feature(COMPRESSION_FEATURE, "identity");
+    let (encoding, _) = get_with_handshake(addr, &identity).await;
+    assert_eq!(encoding, None);
+}
+
+#[tokio::test]
+async fn http_servers_without_compression_advertise_identity() {
+    let config = HttpServerConfig {
+        schema_version: Some("v1".to_string()),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let handshake =
+        Handshake::new(Some("v1".to_string())).with_feature(COMPRESSION_FEATURE, "gzip,deflate");
+    let (encoding, server_handshake) = get_with_handshake(addr, &handshake).await;
+    assert_eq!(encoding, None);
+    assert_eq!(server_handshake.features[COMPRESSION_FEATURE], "identity");
+}
+
+/// Spawns a shell script as the child process, with the handshake enabled.
+#[cfg(unix)]
+async fn spawn_script(
+    script: &str,
+    config: StdioClientConfig,
+) -> io::Result<StdioClient<Request, Response>> {
+    let config = StdioClientConfig {
+        handshake_timeout_secs: Some(5),
+        ..config
+    };
+    StdioClient::new("sh", &["-c", script], config).await
+}
+
+fn stdio_error(error: &io::Error) -> &StdioError {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<StdioError>())
+        .unwrap_or_else(|| panic!("expected stdio error, got {error}"))
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn children_that_exit_immediately_fail_the_handshake() {
+    let start = Instant::now();
+    let error = spawn_script("exit 3", Default::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    let StdioError::Handshake(reason) = stdio_error(&error) else {
+        panic!("expected handshake error, got {error}");
+    };
+    assert!(reason.contains("exited"), "{reason}");
+
+    // The handshake is opt-in
+    let config = StdioClientConfig {
+        handshake_timeout_secs: None,
+        ..Default::default()
+    };
+    assert!(
+        StdioClient::<Request, Response>::new("sh", &["-c", "exit 3"], config)
+            .await
+            .is_ok()
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn handshake_read_errors_are_reported() {
+    let script = "head -c 4096 /dev/zero | tr '\\0' a; sleep 5";
+    let config = StdioClientConfig {
+        max_message_bytes: 1024,
+        ..Default::default()
+    };
+    let error = spawn_script(script, config).await.err().unwrap();
+    assert!(
+        matches!(stdio_error(&error), StdioError::MessageTooLarge(1024)),
+        "{error}"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unresponsive_children_fail_the_handshake() {
+    let config = StdioClientConfig {
+        handshake_timeout_secs: Some(1),
+        ..Default::default()
+    };
+    let error = StdioClient::<Request, Response>::new("sh", &["-c", "sleep 5"], config)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    assert!(matches!(stdio_error(&error), StdioError::Handshake(_)));
+}
+
+/// Returns a script for a child that replies to the handshake ping with `result`,
+/// and then ignores all further requests.
+#[cfg(unix)]
+fn handshake_script(result: &str) -> String {
+    format!(
+        "read line; echo '{{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":{result}}}'; cat >/dev/null"
+    )
+}
+
+#[cfg(unix)]
+async fn spawn_with_schema_version(
+    result: &str,
+    schema_version: &str,
+) -> io::Result<StdioClient<Request, Response>> {
+    // The handshake is implied by the schema version, without a dedicated timeout
+    let config = StdioClientConfig {
+        schema_version: Some(schema_version.to_string()),
+        handshake_timeout_secs: None,
+        timeout_secs: 5,
+        ..Default::default()
+    };
+    StdioClient::new("sh", &["-c", &handshake_script(result)], config).await
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn stdio_schema_versions_are_checked_during_the_handshake() {
+    let result = r#"{"protocol_version":1,"schema_version":"v1"}"#;
+    assert!(spawn_with_schema_version(result, "v1").await.is_ok());
+
+    let error = spawn_with_schema_version(result, "v2").await.err().unwrap();
+    assert!(
+        matches!(
+            stdio_error(&error),
+            StdioError::IncompatiblePeer(HandshakeError::SchemaVersion { .. })
+        ),
+        "{error}"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn invalid_stdio_handshakes_are_rejected() {
+    let error = spawn_with_schema_version(r#"{"protocol_version":"one"}"#, "v1")
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        matches!(
+            stdio_error(&error),
+            StdioError::IncompatiblePeer(HandshakeError::Invalid(_))
+        ),
+        "{error}"
+    );
+}
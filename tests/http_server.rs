@@ -0,0 +1,989 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::join_all;
+use hyper::{
+    body::to_bytes,
+    client::HttpConnector,
+    header::{ALLOW, ETAG, IF_NONE_MATCH, LOCATION},
+    Body, Client, HeaderMap, Method, Request as HttpRequest, Response as HttpResponse, StatusCode,
+    Uri, Version,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    http::{
+        client::{HttpClient, HttpClientConfig, HttpVersion},
+        server::{HttpServer, HttpServerConfig},
+        util::{
+            etag_for_bytes, parse_response, serialize_to_http_request_query,
+            serialize_to_http_response_with_etag, serialize_to_http_response_with_headers,
+            validate_method, validate_methods,
+        },
+        MethodNotAllowedError, ModalHttpResponse, RequestHttpConvert, ResponseHttpConvert,
+    },
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tower::Service;
+use tracing_test::traced_test;
+
+use common::{
+    bind_listener, http_client, http_client_config,
+    protocol::{Request, Response, SayCustomGreetingRequest},
+    say_hello, say_hello_stream, spawn_http_server, spawn_raw_http_server, GreetingService,
+    SlowService,
+};
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+#[tokio::test]
+#[traced_test]
+async fn access_log_includes_rejected_requests() {
+    let config = HttpServerConfig {
+        api_keys: ["key".to_string()].into(),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let client = Client::new();
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header(REQUEST_ID_HEADER, "rejected-request")
+        .body(Body::empty())
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "rejected-request");
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-API-Key", "key")
+        .body(Body::empty())
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+
+    assert!(logs_contain("status=401"));
+    assert!(logs_contain("request_id=\"rejected-request\""));
+    assert!(logs_contain("status=200"));
+    assert!(logs_contain("method=\"GET\""));
+    assert!(logs_contain("latency_ms="));
+}
+
+async fn get_with_api_key(
+    client: &Client<HttpConnector>,
+    addr: SocketAddr,
+    key: &str,
+) -> StatusCode {
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-API-Key", key)
+        .body(Body::empty())
+        .unwrap();
+    client.request(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn rate_limits_are_applied_per_api_key() {
+    let config = HttpServerConfig {
+        api_keys: [
+            "limited".to_string(),
+            "generous".to_string(),
+            "unlimited".to_string(),
+        ]
+        .into(),
+        api_key_rate_limits: [
+            ("limited".to_string(), 1.0),
+            ("generous".to_string(), 1000.0),
+        ]
+        .into(),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let client = Client::new();
+
+    assert_eq!(
+        get_with_api_key(&client, addr, "limited").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        get_with_api_key(&client, addr, "limited").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    for _ in 0..5 {
+        assert_eq!(
+            get_with_api_key(&client, addr, "generous").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_with_api_key(&client, addr, "unlimited").await,
+            StatusCode::OK
+        );
+    }
+}
+
+async fn post_stream_with_api_key(
+    client: &Client<HttpConnector>,
+    addr: SocketAddr,
+    key: &str,
+) -> StatusCode {
+    let request = HttpRequest::post(format!("http://{addr}/say_hello_stream"))
+        .header("X-API-Key", key)
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"name":"a"}"#))
+        .unwrap();
+    client.request(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn scoped_api_keys_may_only_access_their_paths() {
+    let config = HttpServerConfig {
+        api_keys: ["scoped".to_string(), "unscoped".to_string()].into(),
+        api_key_scopes: [("scoped".to_string(), ["/say_hello".to_string()].into())].into(),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let client = Client::new();
+
+    assert_eq!(
+        get_with_api_key(&client, addr, "scoped").await,
+        StatusCode::OK
+    );
+    // Prefixes only match whole path segments
+    assert_eq!(
+        post_stream_with_api_key(&client, addr, "scoped").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        post_stream_with_api_key(&client, addr, "unscoped").await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+#[traced_test]
+async fn forwarded_headers_use_the_address_appended_by_the_proxy() {
+    let config = HttpServerConfig {
+        trust_forwarded_headers: true,
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let client = Client::new();
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-Forwarded-For", "10.0.0.1, 192.0.2.7")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        client.request(request).await.unwrap().status(),
+        StatusCode::OK
+    );
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("Forwarded", "for=10.0.0.2, for=\"192.0.2.8\";proto=http")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        client.request(request).await.unwrap().status(),
+        StatusCode::OK
+    );
+
+    assert!(logs_contain("remote_addr=192.0.2.7"));
+    assert!(logs_contain("remote_addr=192.0.2.8"));
+    assert!(!logs_contain("remote_addr=10.0.0."));
+}
+
+#[tokio::test]
+async fn http2_prior_knowledge_is_accepted_by_default() {
+    let addr = spawn_http_server(HttpServer::new(GreetingService, Default::default())).await;
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .body(Body::empty())
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), Version::HTTP_2);
+
+    // Concurrent streaming responses are multiplexed over the connection
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        http_version: HttpVersion::Http2,
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    let greetings = join_all((0..8).map(|i| {
+        let mut client = client.clone();
+        async move { say_hello_stream(&mut client, &i.to_string()).await }
+    }))
+    .await;
+    for (i, greeting) in greetings.into_iter().enumerate() {
+        assert_eq!(greeting, format!("Hello, {i}!"));
+    }
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+}
+
+const HMAC_SECRET: &str = "secret";
+
+fn signed_request(
+    addr: SocketAddr,
+    body: &str,
+    signed_body: &str,
+    timestamp: u64,
+) -> HttpRequest<Body> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, HMAC_SECRET.as_bytes());
+    let message = format!("{timestamp}\nPOST\n/say_greeting\n{signed_body}");
+    let signature = hmac::sign(&key, message.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    HttpRequest::post(format!("http://{addr}/say_greeting"))
+        .header("Content-Type", "application/json")
+        .header("X-Signature", signature)
+        .header("X-Timestamp", timestamp)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn say_greeting(
+    client: &mut HttpClient<Request, Response>,
+    name: &str,
+) -> Result<(), ProtocolError> {
+    futures::future::poll_fn(|cx| client.poll_ready(cx))
+        .await
+        .map_err(ProtocolError::from)?;
+    let request = Request::SayCustomGreeting(SayCustomGreetingRequest {
+        greeting: "Hi".to_string(),
+        name: name.to_string(),
+    });
+    client
+        .call(request)
+        .await
+        .map(|_| ())
+        .map_err(ProtocolError::from)
+}
+
+#[tokio::test]
+async fn signed_requests_are_verified() {
+    let config = HttpServerConfig {
+        hmac_secrets: [HMAC_SECRET.to_string()].into(),
+        hmac_max_body_bytes: 64,
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let mut client = HttpClient::try_new(HttpClientConfig {
+        hmac_secret: Some(HMAC_SECRET.to_string()),
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+    say_greeting(&mut client, "a").await.unwrap();
+
+    // Bodies that exceed the limit are rejected before they are fully buffered
+    let error = say_greeting(&mut client, &"a".repeat(100))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::PayloadTooLarge
+    ));
+
+    let mut unsigned_client = HttpClient::try_new(HttpClientConfig {
+        hmac_secret: Some("other".to_string()),
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    let error = ProtocolError::from(say_hello(&mut unsigned_client, "a").await.unwrap_err());
+    assert!(matches!(error.error_type, ProtocolErrorType::Unauthorized));
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let body = r#"{"greeting":"Hi","name":"a"}"#;
+    let client = Client::new();
+    let response = client
+        .request(signed_request(addr, body, body, now))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let tampered_body = r#"{"greeting":"Hi","name":"b"}"#;
+    let response = client
+        .request(signed_request(addr, tampered_body, body, now))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .request(signed_request(addr, body, body, now - 3600))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn unsupported_content_types_are_rejected() {
+    let addr = spawn_http_server(HttpServer::new(GreetingService, Default::default())).await;
+    let client = Client::new();
+
+    for content_type in ["text/plain", "application/xml"] {
+        let request = HttpRequest::post(format!("http://{addr}/say_greeting"))
+            .header("Content-Type", content_type)
+            .body(Body::from(r#"{"greeting":"Hi","name":"a"}"#))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    let request = HttpRequest::post(format!("http://{addr}/say_greeting"))
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(Body::from(r#"{"greeting":"Hi","name":"a"}"#))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn conditional_requests_receive_not_modified_responses() {
+    let addr = spawn_http_server(HttpServer::new(GreetingService, Default::default())).await;
+    let client = Client::new();
+    let get = |name: &str, etag: Option<&str>| {
+        let mut request = HttpRequest::get(format!("http://{addr}/say_hello?name={name}"));
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        client.request(request.body(Body::empty()).unwrap())
+    };
+
+    let response = get("a", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[ETAG].to_str().unwrap().to_string();
+
+    let response = get("a", Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[ETAG], etag.as_str());
+    assert!(to_bytes(response.into_body()).await.unwrap().is_empty());
+
+    let response = get("a", Some(&format!("\"other\", {etag}"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // A changed response has a different entity tag, so the full body is returned
+    let response = get("b", Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[ETAG], etag.as_str());
+    let body = to_bytes(response.into_body()).await.unwrap();
+    assert!(
+        String::from_utf8_lossy(&body).contains("Hello, b!"),
+        "{body:?}"
+    );
+}
+
+/// The path for updating the document, which accepts `PUT` and `PATCH` requests.
+const DOCUMENT_UPDATE_PATH: &str = "/update";
+
+/// A request for a cacheable document, which includes the entity tag of the cached copy.
+#[derive(Clone)]
+struct DocumentRequest {
+    cached_etag: Option<String>,
+}
+
+enum DocumentResponse {
+    Document(String),
+    NotModified(Option<String>),
+}
+
+#[async_trait::async_trait]
+impl RequestHttpConvert<DocumentRequest> for DocumentRequest {
+    async fn from_http_request(
+        request: HttpRequest<Body>,
+    ) -> Result<Option<DocumentRequest>, ProtocolError> {
+        // The document may be replaced or partially updated
+        if request.uri().path() == DOCUMENT_UPDATE_PATH {
+            validate_methods(&request, &[Method::PUT, Method::PATCH])?;
+        }
+        Ok(Some(DocumentRequest { cached_etag: None }))
+    }
+
+    fn to_http_request(&self, base_url: &Uri) -> Result<Option<HttpRequest<Body>>, ProtocolError> {
+        let mut request = serialize_to_http_request_query(base_url, "/", Method::GET, &json!({}))?;
+        if let Some(etag) = &self.cached_etag {
+            request
+                .headers_mut()
+                .insert(IF_NONE_MATCH, etag.parse().unwrap());
+        }
+        Ok(Some(request))
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseHttpConvert<DocumentRequest, DocumentResponse> for DocumentResponse {
+    async fn from_http_response(
+        response: ModalHttpResponse,
+        _original_request: &DocumentRequest,
+    ) -> Result<Option<ServiceResponse<DocumentResponse>>, ProtocolError> {
+        Ok(Some(ServiceResponse::Single(match response {
+            ModalHttpResponse::Single(response) => {
+                DocumentResponse::Document(parse_response(response).await?)
+            }
+            ModalHttpResponse::NotModified { etag } => DocumentResponse::NotModified(etag),
+            _ => return Ok(None),
+        })))
+    }
+
+    fn to_http_response(
+        response: ServiceResponse<DocumentResponse>,
+    ) -> Result<Option<ModalHttpResponse>, ProtocolError> {
+        Ok(match response {
+            ServiceResponse::Single(DocumentResponse::Document(document)) => {
+                Some(ModalHttpResponse::Single(
+                    serialize_to_http_response_with_etag(&document, StatusCode::OK)?,
+                ))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct DocumentService;
+
+impl Service<DocumentRequest> for DocumentService {
+    type Response = ServiceResponse<DocumentResponse>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<DocumentResponse>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: DocumentRequest) -> Self::Future {
+        Box::pin(async {
+            Ok(ServiceResponse::Single(DocumentResponse::Document(
+                "document".to_string(),
+            )))
+        })
+    }
+}
+
+async fn get_document(
+    client: &mut HttpClient<DocumentRequest, DocumentResponse>,
+    cached_etag: Option<String>,
+) -> DocumentResponse {
+    let request = DocumentRequest { cached_etag };
+    match client.call(request).await.unwrap() {
+        ServiceResponse::Single(response) => response,
+        ServiceResponse::Multiple(_) => panic!("expected a single response"),
+    }
+}
+
+#[tokio::test]
+async fn clients_receive_not_modified_responses() {
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(HttpServer::new(DocumentService, Default::default()).run_with_listener(listener));
+    let mut client = HttpClient::try_new(http_client_config(addr)).unwrap();
+
+    let DocumentResponse::Document(document) = get_document(&mut client, None).await else {
+        panic!("expected the document");
+    };
+    assert_eq!(document, "document");
+
+    let etag = etag_for_bytes(&serde_json::to_vec(&document).unwrap());
+    let DocumentResponse::NotModified(not_modified_etag) =
+        get_document(&mut client, Some(etag.clone())).await
+    else {
+        panic!("expected a not modified response");
+    };
+    assert_eq!(not_modified_etag, Some(etag));
+
+    let response = get_document(&mut client, Some("\"stale\"".to_string())).await;
+    assert!(matches!(response, DocumentResponse::Document(_)));
+}
+
+const JOBS_PATH: &str = "/jobs";
+
+/// A request to create a job, which the server accepts for asynchronous processing.
+#[derive(Clone)]
+struct CreateJobRequest;
+
+#[derive(Serialize, Deserialize)]
+struct Job {
+    id: u64,
+}
+
+/// The accepted job, along with the status and headers of the HTTP response.
+struct CreateJobResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    job: Job,
+}
+
+#[async_trait::async_trait]
+impl RequestHttpConvert<CreateJobRequest> for CreateJobRequest {
+    async fn from_http_request(
+        request: HttpRequest<Body>,
+    ) -> Result<Option<CreateJobRequest>, ProtocolError> {
+        if request.uri().path() != JOBS_PATH {
+            return Ok(None);
+        }
+        validate_method(&request, Method::POST)?;
+        Ok(Some(CreateJobRequest))
+    }
+
+    fn to_http_request(&self, base_url: &Uri) -> Result<Option<HttpRequest<Body>>, ProtocolError> {
+        serialize_to_http_request_query(base_url, JOBS_PATH, Method::POST, &json!({})).map(Some)
+    }
+}
+
+#[async_trait::async_trait]
+impl ResponseHttpConvert<CreateJobRequest, CreateJobResponse> for CreateJobResponse {
+    async fn from_http_response(
+        response: ModalHttpResponse,
+        _original_request: &CreateJobRequest,
+    ) -> Result<Option<ServiceResponse<CreateJobResponse>>, ProtocolError> {
+        let ModalHttpResponse::Single(response) = response else {
+            return Ok(None);
+        };
+        Ok(Some(ServiceResponse::Single(CreateJobResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            job: parse_response(response).await?,
+        })))
+    }
+
+    fn to_http_response(
+        response: ServiceResponse<CreateJobResponse>,
+    ) -> Result<Option<ModalHttpResponse>, ProtocolError> {
+        let ServiceResponse::Single(response) = response else {
+            return Ok(None);
+        };
+        let mut headers = response.headers;
+        let location = format!("{JOBS_PATH}/{}", response.job.id);
+        headers.insert(LOCATION, location.parse().unwrap());
+        Ok(Some(ModalHttpResponse::Single(
+            serialize_to_http_response_with_headers(&response.job, response.status, headers)?,
+        )))
+    }
+}
+
+#[derive(Clone)]
+struct JobService;
+
+impl Service<CreateJobRequest> for JobService {
+    type Response = ServiceResponse<CreateJobResponse>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<CreateJobResponse>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: CreateJobRequest) -> Self::Future {
+        Box::pin(async {
+            Ok(ServiceResponse::Single(CreateJobResponse {
+                status: StatusCode::ACCEPTED,
+                headers: HeaderMap::new(),
+                job: Job { id: 7 },
+            }))
+        })
+    }
+}
+
+async fn create_job(
+    client: &mut HttpClient<CreateJobRequest, CreateJobResponse>,
+) -> CreateJobResponse {
+    match client.call(CreateJobRequest).await.unwrap() {
+        ServiceResponse::Single(response) => response,
+        ServiceResponse::Multiple(_) => panic!("expected a single response"),
+    }
+}
+
+#[tokio::test]
+async fn custom_response_headers_reach_the_client() {
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(HttpServer::new(JobService, Default::default()).run_with_listener(listener));
+    let mut client = HttpClient::try_new(http_client_config(addr)).unwrap();
+
+    let response = create_job(&mut client).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    assert_eq!(response.headers[LOCATION], "/jobs/7");
+    assert_eq!(response.job.id, 7);
+}
+
+#[tokio::test]
+async fn non_200_success_responses_reach_the_converter() {
+    let addr = spawn_raw_http_server(|_| {
+        HttpResponse::builder()
+            .status(StatusCode::ACCEPTED)
+            .header("Content-Type", "application/json")
+            .header("X-Queue-Position", "3")
+            .body(Body::from(r#"{"id":7}"#))
+            .unwrap()
+    })
+    .await;
+    let mut client = HttpClient::try_new(http_client_config(addr)).unwrap();
+
+    let response = create_job(&mut client).await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    assert_eq!(response.headers["X-Queue-Position"], "3");
+    assert_eq!(response.job.id, 7);
+}
+
+/// Sends a `SayHello` request over a raw connection, and returns the start of the response.
+async fn send_raw_say_hello(stream: &mut TcpStream) -> String {
+    stream
+        .write_all(b"GET /say_hello?name=a HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    read_raw_response(stream).await
+}
+
+async fn read_raw_response(stream: &mut TcpStream) -> String {
+    let mut buffer = vec![0; 1024];
+    let len = stream.read(&mut buffer).await.unwrap();
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[tokio::test]
+async fn connections_beyond_the_limit_wait_for_a_connection_to_close() {
+    let config = HttpServerConfig {
+        max_connections: Some(2),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+
+    let mut connections = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        assert!(send_raw_say_hello(&mut stream)
+            .await
+            .starts_with("HTTP/1.1 200"));
+        connections.push(stream);
+    }
+
+    // The connection waits in the listen backlog, since the server stops accepting
+    let mut excess = TcpStream::connect(addr).await.unwrap();
+    excess
+        .write_all(b"GET /say_hello?name=a HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    assert!(
+        timeout(Duration::from_millis(500), read_raw_response(&mut excess))
+            .await
+            .is_err(),
+        "excess connection should not be served"
+    );
+
+    // Closing a connection frees a permit, and the waiting connection is served
+    drop(connections.remove(0));
+    let response = timeout(Duration::from_secs(5), read_raw_response(&mut excess))
+        .await
+        .expect("waiting connection should be served once a connection closes");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    // The remaining connection is still served
+    assert!(send_raw_say_hello(&mut connections[0])
+        .await
+        .starts_with("HTTP/1.1 200"));
+}
+
+#[tokio::test]
+async fn slow_request_bodies_are_rejected_after_the_read_timeout() {
+    let config = HttpServerConfig {
+        request_read_timeout_secs: Some(1),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::new(GreetingService, config)).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let body = br#"{"name": "a", "greeting": "Hi"}"#;
+    stream
+        .write_all(
+            format!(
+                "POST /say_greeting HTTP/1.1\r\nHost: localhost\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    // Trickles the body, so that it is not received within the read timeout
+    let writer = async {
+        for byte in body {
+            if stream.write_all(&[*byte]).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    };
+    let _ = timeout(Duration::from_millis(1500), writer).await;
+    let response = timeout(Duration::from_secs(5), read_raw_response(&mut stream))
+        .await
+        .expect("server should respond once the read timeout elapses");
+    assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+    assert!(
+        response.to_lowercase().contains("connection: close"),
+        "{response}"
+    );
+
+    // The connection is closed by the server
+    let mut buffer = [0; 16];
+    let len = timeout(Duration::from_secs(5), stream.read(&mut buffer))
+        .await
+        .expect("connection should be closed")
+        .unwrap_or_default();
+    assert_eq!(len, 0);
+}
+
+#[tokio::test]
+async fn api_keys_are_accepted_under_a_custom_header() {
+    let config = HttpServerConfig {
+        api_keys: ["secret".to_string()].into(),
+        api_key_header: "Api-Token".to_string(),
+        ..Default::default()
+    };
+    let addr = spawn_http_server(HttpServer::try_new(GreetingService, config).unwrap()).await;
+
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        api_key: Some("secret".to_string()),
+        api_key_header: "Api-Token".to_string(),
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    // The key is not accepted under the default header
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        api_key: Some("secret".to_string()),
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+    assert!(matches!(error.error_type, ProtocolErrorType::Unauthorized));
+}
+
+#[tokio::test]
+async fn rate_limited_requests_do_not_invoke_the_async_authorizer() {
+    let config = HttpServerConfig {
+        api_keys: ["key".to_string()].into(),
+        api_key_rate_limits: [("key".to_string(), 1.0)].into(),
+        ..Default::default()
+    };
+    let authorizations = Arc::new(AtomicUsize::new(0));
+    let authorizations_cl = authorizations.clone();
+    let server = HttpServer::try_new(GreetingService, config)
+        .unwrap()
+        .with_async_authorizer(move |_: &HttpRequest<Body>| {
+            authorizations_cl.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        });
+    let addr = spawn_http_server(server).await;
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        api_key: Some("key".to_string()),
+        ..http_client_config(addr)
+    })
+    .unwrap();
+
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+    for _ in 0..5 {
+        let error = ProtocolError::from(say_hello(&mut client, "a").await.unwrap_err());
+        assert!(matches!(
+            error.error_type,
+            ProtocolErrorType::TooManyRequests
+        ));
+    }
+    assert_eq!(authorizations.load(Ordering::SeqCst), 1);
+}
+
+async fn request_document_update(addr: SocketAddr, method: Method) -> HttpResponse<Body> {
+    let request = HttpRequest::builder()
+        .method(method)
+        .uri(format!("http://{addr}{DOCUMENT_UPDATE_PATH}"))
+        .body(Body::empty())
+        .unwrap();
+    Client::new().request(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn paths_may_accept_multiple_methods() {
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(HttpServer::new(DocumentService, Default::default()).run_with_listener(listener));
+    for method in [Method::PUT, Method::PATCH] {
+        let response = request_document_update(addr, method).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = request_document_update(addr, Method::GET).await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn method_not_allowed_responses_include_the_allowed_methods() {
+    let (listener, addr) = bind_listener().await;
+    tokio::spawn(HttpServer::new(DocumentService, Default::default()).run_with_listener(listener));
+    let response = request_document_update(addr, Method::GET).await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()[ALLOW], "PUT, PATCH");
+    // The allowed methods are not included in the error data
+    let body: Value =
+        serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert!(body.get("data").is_none(), "{body}");
+
+    let request = HttpRequest::post("http://localhost/")
+        .body(Body::empty())
+        .unwrap();
+    let error = validate_method(&request, Method::GET).unwrap_err();
+    let error_type = error.error_type.clone();
+    let allowed_methods = &error
+        .inner_error()
+        .downcast_ref::<MethodNotAllowedError>()
+        .unwrap()
+        .allowed_methods;
+    assert_eq!(allowed_methods, &[Method::GET]);
+    assert!(matches!(
+        error_type,
+        ProtocolErrorType::HttpMethodNotAllowed
+    ));
+    let response: HttpResponse<Body> = error.into();
+    assert_eq!(response.headers()[ALLOW], "GET");
+}
+
+#[tokio::test]
+async fn requests_are_aborted_once_the_client_deadline_elapses() {
+    let server = HttpServer::new(SlowService(Duration::from_secs(5)), Default::default());
+    let addr = spawn_http_server(server).await;
+    let start = Instant::now();
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-Deadline-Ms", "200")
+        .body(Body::empty())
+        .unwrap();
+    let response = timeout(Duration::from_secs(2), Client::new().request(request))
+        .await
+        .expect("server should abort the request before the service completes")
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = to_bytes(response.into_body()).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("deadline exceeded"));
+}
+
+#[tokio::test]
+async fn clients_send_their_timeout_as_the_deadline() {
+    let (deadline_tx, mut deadline_rx) = tokio::sync::mpsc::unbounded_channel();
+    let addr = spawn_raw_http_server(move |request| {
+        deadline_tx
+            .send(request.headers().get("X-Deadline-Ms").cloned())
+            .unwrap();
+        HttpResponse::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap()
+    })
+    .await;
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        timeout_secs: 1,
+        ..http_client_config(addr)
+    })
+    .unwrap();
+    say_hello(&mut client, "a").await.unwrap_err();
+    let deadline = deadline_rx.recv().await.unwrap().unwrap();
+    assert_eq!(deadline, "1000");
+}
+
+/// Runs a stub server that greets every request, and counts the requests it receives.
+async fn spawn_counting_stub(calls: Arc<AtomicUsize>) -> SocketAddr {
+    spawn_raw_http_server(move |_| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"result":"Hello, a!"}"#))
+            .unwrap()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn requests_are_distributed_across_replicas() {
+    let calls = [Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0))];
+    let mut base_urls = Vec::new();
+    for calls in &calls {
+        let addr = spawn_counting_stub(calls.clone()).await;
+        base_urls.push(format!("http://{addr}"));
+    }
+    let mut client = HttpClient::<Request, Response>::try_new(HttpClientConfig {
+        base_urls,
+        timeout_secs: 5,
+        ..Default::default()
+    })
+    .unwrap();
+    for _ in 0..4 {
+        assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+    }
+    assert_eq!(calls[0].load(Ordering::SeqCst), 2);
+    assert_eq!(calls[1].load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn request_interceptors_may_reject_requests() {
+    let server = HttpServer::new(GreetingService, Default::default()).with_request_interceptor(
+        |request, _| match request {
+            Request::SayHello(request) if request.name == "mallory" => Err(ProtocolError::new(
+                ProtocolErrorType::Forbidden,
+                "name is blocked".into(),
+            )),
+            _ => Ok(()),
+        },
+    );
+    let addr = spawn_http_server(server).await;
+    let mut client = http_client(addr);
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    let error = ProtocolError::from(say_hello(&mut client, "mallory").await.unwrap_err());
+    assert!(matches!(error.error_type, ProtocolErrorType::Forbidden));
+    assert_eq!(error.to_string(), "name is blocked");
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=mallory"))
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn services_observe_requests_modified_by_interceptors() {
+    let server = HttpServer::new(GreetingService, Default::default()).with_request_interceptor(
+        |request, context| {
+            if let (Request::SayHello(request), Some(title)) =
+                (request, context.headers.get("X-Title"))
+            {
+                let title = title.to_str().unwrap_or_default();
+                request.name = format!("{title} {}", request.name);
+            }
+            Ok(())
+        },
+    );
+    let addr = spawn_http_server(server).await;
+    let mut client = http_client(addr);
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("X-Title", "Dr.")
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    let body: Value =
+        serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body["result"], "Hello, Dr. a!");
+}
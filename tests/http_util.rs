@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+
+use futures::StreamExt;
+use hyper::{
+    body::to_bytes, header::CONTENT_TYPE, Body, Method, Request as HttpRequest,
+    Response as HttpResponse, StatusCode, Uri,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    http::{
+        util::{
+            notification_ndjson_response, notification_ndjson_stream, notification_sse_response,
+            notification_sse_stream, parse_request_query, parse_response, parse_stream_request,
+            parse_stream_request_with_limit, serialize_to_chunked_http_response,
+            serialize_to_http_request, serialize_to_http_request_query,
+            serialize_to_http_request_with_query, NDJSON_CONTENT_TYPE,
+        },
+        ModalHttpResponse, ResponseHttpConvert, SseEvent,
+    },
+    NotificationStream, ProtocolError, ServiceResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// A streamed response that is sent as a named server-sent event with an id.
+#[derive(Clone, Debug, PartialEq)]
+struct Tick(u64);
+
+#[async_trait::async_trait]
+impl ResponseHttpConvert<(), Tick> for Tick {
+    async fn from_http_response(
+        response: ModalHttpResponse,
+        _original_request: &(),
+    ) -> Result<Option<ServiceResponse<Tick>>, ProtocolError> {
+        Ok(match response {
+            ModalHttpResponse::SseEvent(SseEvent {
+                name: Some(name),
+                id: Some(id),
+                value,
+            }) if name == "tick" && id.parse().ok() == value.as_u64() => {
+                Some(ServiceResponse::Single(Tick(value.as_u64().unwrap())))
+            }
+            _ => None,
+        })
+    }
+
+    fn to_http_response(
+        response: ServiceResponse<Tick>,
+    ) -> Result<Option<ModalHttpResponse>, ProtocolError> {
+        Ok(match response {
+            ServiceResponse::Single(Tick(n)) => Some(ModalHttpResponse::SseEvent(SseEvent {
+                name: Some("tick".to_string()),
+                id: Some(n.to_string()),
+                value: json!(n),
+            })),
+            ServiceResponse::Multiple(_) => None,
+        })
+    }
+}
+
+fn stream_request(chunks: Vec<&'static str>) -> HttpRequest<Body> {
+    let chunks = chunks.into_iter().map(Ok::<_, std::io::Error>);
+    HttpRequest::new(Body::wrap_stream(futures::stream::iter(chunks)))
+}
+
+#[tokio::test]
+async fn stream_request_lines_are_parsed_across_chunks() {
+    let request = stream_request(vec!["{\"a\":", "1}\n\n{\"b\"", ":2}\n{\"c\":3}"]);
+    let items = parse_stream_request::<Value>(request)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        items,
+        vec![json!({"a": 1}), json!({"b": 2}), json!({"c": 3})]
+    );
+}
+
+#[tokio::test]
+async fn oversized_stream_request_lines_are_rejected() {
+    let request = stream_request(vec!["{\"a\":1}\n", "{\"b\":\"", "0123456789", "0123456789"]);
+    let mut items = parse_stream_request_with_limit::<Value>(request, 16);
+    assert_eq!(items.next().await.unwrap().unwrap(), json!({"a": 1}));
+    let error = items.next().await.unwrap().unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::PayloadTooLarge
+    ));
+    assert!(items.next().await.is_none());
+}
+
+#[tokio::test]
+async fn oversized_terminated_stream_request_lines_are_rejected() {
+    let request = stream_request(vec!["{\"b\":\"01234567890123456789\"}\n{\"a\":1}\n"]);
+    let mut items = parse_stream_request_with_limit::<Value>(request, 16);
+    let error = items.next().await.unwrap().unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::PayloadTooLarge
+    ));
+    assert!(items.next().await.is_none());
+}
+
+#[tokio::test]
+async fn sse_event_names_and_ids_from_the_converter_are_written() {
+    let ticks = futures::stream::iter([Ok(Tick(5)), Ok(Tick(6))]).boxed();
+    let response = notification_sse_response::<(), Tick>(ticks);
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.starts_with("id: 5\nevent: tick\ndata: "), "{body}");
+    assert!(body.contains("id: 6\nevent: tick\ndata: "), "{body}");
+
+    let ticks = futures::stream::iter([Ok(Tick(5)), Ok(Tick(6))]).boxed();
+    let response = notification_sse_response::<(), Tick>(ticks);
+    let events = notification_sse_stream::<(), Tick>((), response)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(events, vec![Tick(5), Tick(6)]);
+}
+
+async fn sse_body(items: Vec<Result<Tick, ProtocolError>>) -> String {
+    let response = notification_sse_response::<(), Tick>(futures::stream::iter(items).boxed());
+    let body = to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+fn backend_error() -> ProtocolError {
+    ProtocolError::new(
+        ProtocolErrorType::ServiceUnavailable,
+        "backend failed".into(),
+    )
+}
+
+#[tokio::test]
+async fn completed_sse_streams_end_with_an_event_without_data() {
+    let body = sse_body(vec![Ok(Tick(1))]).await;
+    assert!(
+        body.ends_with("data: {\"result\":1,\"error\":null}\n\nevent: multilink-end\n\n"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn failed_sse_streams_end_with_the_error() {
+    let items = vec![
+        Ok(Tick(1)),
+        Err(backend_error()),
+        Ok(Tick(2)),
+        Err(backend_error()),
+    ];
+    let body = sse_body(items).await;
+    assert!(body.contains("event: multilink-error\ndata: "), "{body}");
+    assert!(!body.contains("multilink-end"), "{body}");
+
+    let items = vec![
+        Ok(Tick(1)),
+        Err(backend_error()),
+        Ok(Tick(2)),
+        Err(backend_error()),
+    ];
+    let response = notification_sse_response::<(), Tick>(futures::stream::iter(items).boxed());
+    let items = notification_sse_stream::<(), Tick>((), response)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(items.len(), 4);
+    assert_eq!(items[0].as_ref().unwrap(), &Tick(1));
+    assert!(items[1].is_err());
+    assert_eq!(items[2].as_ref().unwrap(), &Tick(2));
+    let Err(error) = &items[3] else {
+        panic!("expected the error that ended the stream");
+    };
+    assert!(matches!(
+        error.error_type,
+        ProtocolErrorType::ServiceUnavailable
+    ));
+    assert!(error.to_string().contains("backend failed"), "{error}");
+}
+
+#[test]
+fn malformed_base_urls_are_reported_as_errors() {
+    for base_url in ["/api/v1", "localhost:8080", "localhost"] {
+        let base_url = base_url.parse::<Uri>().unwrap();
+        let Err(error) =
+            serialize_to_http_request(&base_url, "/say_hello", Method::POST, &json!({}))
+        else {
+            panic!("request with base url `{base_url}` should fail");
+        };
+        assert!(
+            matches!(error.error_type, ProtocolErrorType::Internal),
+            "{error}"
+        );
+    }
+
+    let base_url = Uri::from_static("http://localhost:8080");
+    let Err(error) = serialize_to_http_request(&base_url, "/say hello", Method::POST, &json!({}))
+    else {
+        panic!("request with an invalid path should fail");
+    };
+    assert!(
+        matches!(error.error_type, ProtocolErrorType::Internal),
+        "{error}"
+    );
+}
+
+#[test]
+fn base_url_path_prefixes_are_prepended() {
+    for (base_url, path) in [
+        ("https://example.com/api/v1", "/say_hello"),
+        ("https://example.com/api/v1/", "/say_hello"),
+        ("https://example.com/api/v1/", "say_hello"),
+        ("https://example.com/api/v1", "say_hello"),
+    ] {
+        let base_url = base_url.parse::<Uri>().unwrap();
+        let request = serialize_to_http_request(&base_url, path, Method::POST, &json!({})).unwrap();
+        assert_eq!(
+            request.uri().path(),
+            "/api/v1/say_hello",
+            "{base_url} {path}"
+        );
+        assert_eq!(request.uri().host(), Some("example.com"));
+    }
+
+    let base_url = Uri::from_static("https://example.com");
+    let request =
+        serialize_to_http_request(&base_url, "/say_hello", Method::POST, &json!({})).unwrap();
+    assert_eq!(request.uri().path(), "/say_hello");
+}
+
+#[test]
+fn query_values_are_percent_encoded() {
+    let base_url = Uri::from_static("https://example.com/api");
+    let request = serialize_to_http_request_with_query(
+        &base_url,
+        "/say_hello",
+        Method::GET,
+        &json!({}),
+        &[("name", "a b&c=d/é?#"), ("plain", "value")],
+    )
+    .unwrap();
+    assert_eq!(request.uri().path(), "/api/say_hello");
+    assert_eq!(
+        request.uri().query(),
+        Some("name=a+b%26c%3Dd%2F%C3%A9%3F%23&plain=value")
+    );
+
+    let request = serialize_to_http_request_with_query(
+        &base_url,
+        "/say_hello?existing=1",
+        Method::GET,
+        &json!({}),
+        &[("name", "100%")],
+    )
+    .unwrap();
+    assert_eq!(request.uri().query(), Some("existing=1&name=100%25"));
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct QueryRequest {
+    name: String,
+    count: u32,
+    enabled: bool,
+    tags: Vec<String>,
+    nickname: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
+}
+
+#[tokio::test]
+async fn get_requests_round_trip_through_the_query_string() {
+    let request = QueryRequest {
+        name: "123".to_string(),
+        count: 3,
+        enabled: true,
+        tags: vec!["a b".to_string(), "c&d".to_string()],
+        nickname: None,
+        extra: [
+            ("flag".to_string(), json!(false)),
+            ("ratio".to_string(), json!(-1.5)),
+            ("label".to_string(), json!("x=y")),
+        ]
+        .into(),
+    };
+    let base_url = Uri::from_static("https://example.com");
+    let http_request =
+        serialize_to_http_request_query(&base_url, "/say_hello", Method::GET, &request).unwrap();
+    let parsed = parse_request_query::<QueryRequest>(&http_request).unwrap();
+    assert_eq!(parsed, request);
+    assert!(to_bytes(http_request.into_body()).await.unwrap().is_empty());
+
+    let http_request = HttpRequest::get("/?a=1&b=true&c=text&d=-2")
+        .body(Body::empty())
+        .unwrap();
+    let parsed = parse_request_query::<Value>(&http_request).unwrap();
+    assert_eq!(parsed, json!({"a": 1, "b": true, "c": "text", "d": -2}));
+}
+
+/// A streamed response that is sent as a plain event.
+#[derive(Clone, Debug, PartialEq)]
+struct Count(u64);
+
+#[async_trait::async_trait]
+impl ResponseHttpConvert<(), Count> for Count {
+    async fn from_http_response(
+        response: ModalHttpResponse,
+        _original_request: &(),
+    ) -> Result<Option<ServiceResponse<Count>>, ProtocolError> {
+        Ok(match response {
+            ModalHttpResponse::Event(value)
+            | ModalHttpResponse::SseEvent(SseEvent { value, .. }) => {
+                value.as_u64().map(|n| ServiceResponse::Single(Count(n)))
+            }
+            _ => None,
+        })
+    }
+
+    fn to_http_response(
+        response: ServiceResponse<Count>,
+    ) -> Result<Option<ModalHttpResponse>, ProtocolError> {
+        Ok(match response {
+            ServiceResponse::Single(Count(n)) => Some(ModalHttpResponse::Event(json!(n))),
+            ServiceResponse::Multiple(_) => None,
+        })
+    }
+}
+
+fn count_stream(items: &[Result<u64, ProtocolErrorType>]) -> NotificationStream<Count> {
+    let items = items
+        .iter()
+        .map(|item| match item {
+            Ok(n) => Ok(Count(*n)),
+            Err(error_type) => Err(ProtocolError::new(
+                error_type.clone(),
+                "backend failed".into(),
+            )),
+        })
+        .collect::<Vec<_>>();
+    futures::stream::iter(items).boxed()
+}
+
+/// Collects the items of a decoded stream, with errors replaced by their type.
+async fn collect_counts(stream: NotificationStream<Count>) -> Vec<Result<u64, ProtocolErrorType>> {
+    stream
+        .map(|item| item.map(|Count(n)| n).map_err(|e| e.error_type))
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn ndjson_streams_match_sse_streams() {
+    let items = [
+        Ok(1),
+        Ok(2),
+        Err(ProtocolErrorType::BadRequest),
+        Ok(3),
+        Err(ProtocolErrorType::ServiceUnavailable),
+    ];
+    let response = notification_ndjson_response::<(), Count>(count_stream(&items));
+    assert_eq!(response.headers()[CONTENT_TYPE], NDJSON_CONTENT_TYPE);
+    let ndjson_items = collect_counts(notification_ndjson_stream((), response)).await;
+    let response = notification_sse_response::<(), Count>(count_stream(&items));
+    let sse_items = collect_counts(notification_sse_stream((), response)).await;
+    assert_eq!(format!("{ndjson_items:?}"), format!("{items:?}"));
+    assert_eq!(format!("{sse_items:?}"), format!("{items:?}"));
+}
+
+#[tokio::test]
+async fn ndjson_streams_end_with_an_end_record() {
+    let response = notification_ndjson_response::<(), Count>(count_stream(&[Ok(1), Ok(2)]));
+    let (parts, body) = response.into_parts();
+    let body = String::from_utf8(to_bytes(body).await.unwrap().to_vec()).unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{body}");
+    let end_record = serde_json::from_str::<Value>(lines[2]).unwrap();
+    assert_eq!(end_record["end"], json!(true));
+    assert_eq!(end_record["error"], Value::Null);
+
+    // A body that is cut off before the end record is reported as incomplete
+    let truncated_body = format!("{}\n{}\n", lines[0], lines[1]);
+    let response = HttpResponse::from_parts(parts, Body::from(truncated_body));
+    let items = collect_counts(notification_ndjson_stream((), response)).await;
+    assert_eq!(
+        format!("{items:?}"),
+        format!("{:?}", [Ok(1), Ok(2), Err(ProtocolErrorType::Internal)])
+    );
+}
+
+#[tokio::test]
+async fn chunked_responses_are_reassembled() {
+    let value = json!({"items": (0..100).map(|i| format!("item-{i}")).collect::<Vec<_>>()});
+    let response = serialize_to_chunked_http_response(&value, StatusCode::OK, 64).unwrap();
+    let mut body = response.into_body();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = body.next().await {
+        chunks.push(chunk.unwrap());
+    }
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 64));
+
+    let response = serialize_to_chunked_http_response(&value, StatusCode::OK, 64).unwrap();
+    assert_eq!(parse_response::<Value>(response).await.unwrap(), value);
+}
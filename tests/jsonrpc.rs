@@ -0,0 +1,184 @@
+use multilink::{
+    error::ProtocolErrorType,
+    jsonrpc::{JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse},
+};
+use serde_json::{json, Value};
+
+/// Parses the payload, and checks that the message survives a round trip.
+fn round_trip(payload: Value) -> JsonRpcMessage {
+    let message = JsonRpcMessage::from_slice(payload.to_string().as_bytes()).unwrap();
+    let serialized = message.to_vec().unwrap();
+    let reparsed = JsonRpcMessage::from_slice(&serialized).unwrap();
+    assert_eq!(reparsed.to_vec().unwrap(), serialized);
+    assert_eq!(
+        std::mem::discriminant(&reparsed),
+        std::mem::discriminant(&message)
+    );
+    message
+}
+
+#[test]
+fn requests_round_trip() {
+    let JsonRpcMessage::Request(request) = round_trip(json!({
+        "jsonrpc": "2.0",
+        "method": "sayHello",
+        "params": {"name": "a"},
+        "id": "b3c1",
+        "request_id": "abc",
+    })) else {
+        panic!("expected request");
+    };
+    assert_eq!(request.method, "sayHello");
+    assert_eq!(request.id, "b3c1");
+    assert_eq!(request.params, Some(json!({"name": "a"})));
+    assert_eq!(request.request_id.as_deref(), Some("abc"));
+}
+
+#[test]
+fn notifications_round_trip() {
+    let JsonRpcMessage::Notification(notification) = round_trip(json!({
+        "jsonrpc": "2.0",
+        "method": "progress",
+        "params": [1, 2],
+    })) else {
+        panic!("expected notification");
+    };
+    assert_eq!(notification.method, "progress");
+    assert_eq!(notification.params, Some(json!([1, 2])));
+    assert!(!notification.stream_complete);
+
+    let JsonRpcMessage::Notification(notification) =
+        round_trip(json!({"jsonrpc": "2.0", "method": "7", "stream_complete": true}))
+    else {
+        panic!("expected notification");
+    };
+    assert!(notification.stream_complete);
+    assert_eq!(notification.params, None);
+}
+
+#[test]
+fn notifications_with_a_null_id_round_trip() {
+    let JsonRpcMessage::Notification(notification) =
+        round_trip(json!({"jsonrpc": "2.0", "method": "progress", "id": null}))
+    else {
+        panic!("expected notification");
+    };
+    // The id is not part of a notification
+    assert!(notification.extra().is_empty());
+    let serialized: Value =
+        serde_json::from_slice(&JsonRpcMessage::from(notification).to_vec().unwrap()).unwrap();
+    assert!(serialized.get("id").is_none());
+}
+
+#[test]
+fn responses_round_trip() {
+    let JsonRpcMessage::Response(response) =
+        round_trip(json!({"jsonrpc": "2.0", "result": {"result": "Hello, a!"}, "id": 3}))
+    else {
+        panic!("expected response");
+    };
+    assert_eq!(response.id, 3);
+    assert_eq!(response.result, Some(json!({"result": "Hello, a!"})));
+    assert!(response.error.is_none());
+}
+
+#[test]
+fn responses_with_only_an_error_round_trip() {
+    let JsonRpcMessage::Response(response) = round_trip(json!({
+        "jsonrpc": "2.0",
+        "error": {"code": -32700, "message": "parse error"},
+    })) else {
+        panic!("expected response");
+    };
+    assert_eq!(response.id, Value::Null);
+    assert!(response.result.is_none());
+    let error = response.error.unwrap();
+    assert_eq!(error.code, -32700);
+    assert_eq!(error.message, "parse error");
+}
+
+#[test]
+fn constructed_messages_round_trip() {
+    // Requests must have a non-null id to be parsed as requests
+    let mut request = JsonRpcRequest::new("sayHello".to_string(), Some(json!({"name": "a"})));
+    request.id = json!(1);
+    let messages: [JsonRpcMessage; 3] = [
+        request.into(),
+        JsonRpcResponse::new(Ok(json!("a")), json!(1)).into(),
+        JsonRpcNotification::new_stream_complete("1".to_string()).into(),
+    ];
+    for message in messages {
+        let serialized = message.to_vec().unwrap();
+        let reparsed = JsonRpcMessage::from_slice(&serialized).unwrap();
+        assert_eq!(reparsed.to_vec().unwrap(), serialized);
+    }
+}
+
+#[test]
+fn ambiguous_messages_are_rejected() {
+    for payload in [
+        json!({"jsonrpc": "2.0", "id": 1}),
+        json!({"jsonrpc": "1.0", "method": "sayHello", "id": 1}),
+        json!([{"jsonrpc": "2.0", "method": "sayHello", "id": 1}]),
+    ] {
+        assert!(
+            JsonRpcMessage::from_slice(payload.to_string().as_bytes()).is_err(),
+            "{payload}"
+        );
+    }
+}
+
+#[test]
+fn jsonrpc_versions_are_validated_if_enabled() {
+    let request = |version: Option<&str>| {
+        let mut request = json!({"method": "sayHello", "id": 1});
+        if let Some(version) = version {
+            request["jsonrpc"] = version.into();
+        }
+        request
+    };
+    assert!(JsonRpcMessage::parse(request(Some("2.0")), true).is_ok());
+    for version in [Some("1.0"), None] {
+        let error = JsonRpcMessage::parse(request(version), true).err().unwrap();
+        assert!(
+            matches!(error.error_type, ProtocolErrorType::BadRequest),
+            "{version:?}"
+        );
+        assert!(
+            JsonRpcMessage::parse(request(version), false).is_ok(),
+            "{version:?}"
+        );
+    }
+}
+
+#[test]
+fn unknown_fields_round_trip() {
+    for payload in [
+        json!({"jsonrpc": "2.0", "method": "a", "id": 1, "priority": "high"}),
+        json!({"jsonrpc": "2.0", "result": 1, "error": null, "id": 1, "priority": "high"}),
+        json!({"jsonrpc": "2.0", "method": "a", "params": null, "priority": "high"}),
+    ] {
+        let message = JsonRpcMessage::from_slice(payload.to_string().as_bytes()).unwrap();
+        let extra = match &message {
+            JsonRpcMessage::Request(request) => request.extra(),
+            JsonRpcMessage::Response(response) => response.extra(),
+            JsonRpcMessage::Notification(notification) => notification.extra(),
+        };
+        // Reserved keys are not captured
+        assert_eq!(extra.len(), 1, "{extra:?}");
+        assert_eq!(extra["priority"], "high");
+        let serialized: Value = serde_json::from_slice(&message.to_vec().unwrap()).unwrap();
+        assert_eq!(serialized["priority"], "high");
+    }
+}
+
+#[test]
+fn unknown_fields_can_be_added() {
+    let mut notification = JsonRpcNotification::new("a".to_string(), None);
+    notification
+        .extra_mut()
+        .insert("priority".to_string(), json!("high"));
+    let serialized: Value =
+        serde_json::from_slice(&JsonRpcMessage::from(notification).to_vec().unwrap()).unwrap();
+    assert_eq!(serialized["priority"], "high");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Client, Request as HttpRequest, Response as HttpResponse, Server, StatusCode,
+};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use multilink::http::server::{HttpServer, HttpServerConfig, JwtConfig, JwtValidator};
+use serde_json::{json, Value};
+
+use common::{bind_listener, spawn_http_server, GreetingService};
+
+const SECRET: &[u8] = b"multilink-test-secret-0123456789";
+/// The base64url encoding of [`SECRET`].
+const ENCODED_SECRET: &str = "bXVsdGlsaW5rLXRlc3Qtc2VjcmV0LTAxMjM0NTY3ODk";
+const KEY_ID: &str = "test-key";
+
+/// Serves a key set containing the symmetric test key, and returns its URL.
+async fn spawn_jwks_server() -> String {
+    let (listener, addr) = bind_listener().await;
+    let jwks = json!({
+        "keys": [{"kty": "oct", "kid": KEY_ID, "alg": "HS256", "k": ENCODED_SECRET}]
+    })
+    .to_string();
+    let make_service = make_service_fn(move |_| {
+        let jwks = jwks.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_: HttpRequest<Body>| {
+                let jwks = jwks.clone();
+                async move { Ok::<_, Infallible>(HttpResponse::new(Body::from(jwks))) }
+            }))
+        }
+    });
+    let server = Server::from_tcp(listener.into_std().unwrap())
+        .unwrap()
+        .serve(make_service);
+    tokio::spawn(server);
+    format!("http://{addr}/jwks.json")
+}
+
+fn sign(claims: Value) -> String {
+    let header = Header {
+        kid: Some(KEY_ID.to_string()),
+        ..Default::default()
+    };
+    encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn expires_in(secs: i64) -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() as i64 + secs
+}
+
+async fn get_with_token(token: &str, addr: std::net::SocketAddr) -> StatusCode {
+    let request = HttpRequest::get(format!("http://{addr}/say_hello?name=a"))
+        .header("Authorization", format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap();
+    Client::new().request(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn bearer_tokens_are_validated() {
+    let jwt_validator = JwtValidator::new(JwtConfig {
+        jwks_url: spawn_jwks_server().await,
+        required_claims: HashSet::from(["sub".to_string()]),
+        leeway_secs: 0,
+        ..Default::default()
+    })
+    .unwrap();
+    let server = HttpServer::new(GreetingService, HttpServerConfig::default())
+        .with_jwt_validator(jwt_validator);
+    let addr = spawn_http_server(server).await;
+
+    let valid = sign(json!({"sub": "user", "exp": expires_in(600)}));
+    assert_eq!(get_with_token(&valid, addr).await, StatusCode::OK);
+
+    let expired = sign(json!({"sub": "user", "exp": expires_in(-600)}));
+    assert_eq!(
+        get_with_token(&expired, addr).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let missing_claim = sign(json!({"exp": expires_in(600)}));
+    assert_eq!(
+        get_with_token(&missing_claim, addr).await,
+        StatusCode::FORBIDDEN
+    );
+
+    let tampered = format!("{valid}x");
+    assert_eq!(
+        get_with_token(&tampered, addr).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use hyper::{Body, Client, Request as HttpRequest, StatusCode};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use multilink::{
+    http::server::{HttpServer, HttpServerConfig},
+    metrics::REQUESTS_TOTAL,
+};
+
+use common::{http_client, say_hello, spawn_http_server, stdio_pair, GreetingService};
+
+/// Returns the value of the request counter with the given labels.
+fn requests_total(snapshotter: &Snapshotter, transport: &str, method: &str) -> u64 {
+    snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .find_map(|(key, _, _, value)| {
+            let key = key.key();
+            let has_labels = key
+                .labels()
+                .any(|label| label.key() == "transport" && label.value() == transport)
+                && key
+                    .labels()
+                    .any(|label| label.key() == "method" && label.value() == method);
+            match (key.name() == REQUESTS_TOTAL && has_labels, value) {
+                (true, DebugValue::Counter(count)) => Some(count),
+                _ => None,
+            }
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_operation_name() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    let addr = spawn_http_server(HttpServer::new(
+        GreetingService,
+        HttpServerConfig::default(),
+    ))
+    .await;
+    let mut client = http_client(addr);
+    say_hello(&mut client, "a").await.unwrap();
+    say_hello(&mut client, "b").await.unwrap();
+    assert_eq!(requests_total(&snapshotter, "http_client", "sayHello"), 2);
+    assert_eq!(requests_total(&snapshotter, "http_server", "sayHello"), 2);
+
+    // Paths are not used as labels, since they may contain identifiers
+    let response = Client::new()
+        .request(
+            HttpRequest::get(format!("http://{addr}/unknown/123"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(requests_total(&snapshotter, "http_server", "GET"), 1);
+
+    let mut client = stdio_pair(GreetingService, Default::default(), Default::default());
+    say_hello(&mut client, "a").await.unwrap();
+    assert_eq!(requests_total(&snapshotter, "stdio_client", "sayHello"), 1);
+    assert_eq!(requests_total(&snapshotter, "stdio_server", "sayHello"), 1);
+}
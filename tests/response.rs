@@ -0,0 +1,100 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use multilink::{error::ProtocolErrorType, ProtocolError, ServiceResponse};
+
+fn multiple(items: Vec<Result<u32, ProtocolError>>) -> ServiceResponse<u32> {
+    ServiceResponse::Multiple(stream::iter(items).boxed())
+}
+
+#[tokio::test]
+async fn single_responses_collect_into_one_item() {
+    let response = ServiceResponse::Single(1);
+    assert_eq!(response.collect_all().await.unwrap(), [1]);
+}
+
+#[tokio::test]
+async fn stream_responses_collect_all_items() {
+    let response = multiple(vec![Ok(1), Ok(2), Ok(3)]);
+    assert_eq!(response.collect_all().await.unwrap(), [1, 2, 3]);
+
+    let response = multiple(vec![]);
+    assert!(response.collect_all().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn collecting_stops_at_the_first_error() {
+    let response = multiple(vec![
+        Ok(1),
+        Err(ProtocolError::new(
+            ProtocolErrorType::BadRequest,
+            "first".into(),
+        )),
+        Err(ProtocolError::new(
+            ProtocolErrorType::Internal,
+            "second".into(),
+        )),
+        Ok(2),
+    ]);
+    let error = response.collect_all().await.unwrap_err();
+    assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    assert_eq!(error.to_string(), "first");
+}
+
+#[tokio::test]
+async fn both_variants_convert_into_streams() {
+    let items: Vec<u32> = ServiceResponse::Single(1)
+        .into_stream()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(items, [1]);
+
+    let items: Vec<u32> = multiple(vec![Ok(1), Ok(2)])
+        .into_stream()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(items, [1, 2]);
+}
+
+#[test]
+fn map_applies_to_single_responses() {
+    let response = ServiceResponse::Single(1).map(|n| n.to_string());
+    assert!(matches!(&response, ServiceResponse::Single(s) if s == "1"));
+}
+
+#[tokio::test]
+async fn map_applies_lazily_to_each_stream_item() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let map_calls = calls.clone();
+    let response = multiple(vec![
+        Ok(1),
+        Err(ProtocolError::new(
+            ProtocolErrorType::BadRequest,
+            "bad".into(),
+        )),
+        Ok(2),
+    ])
+    .map(move |n| {
+        map_calls.fetch_add(1, Ordering::SeqCst);
+        n * 10
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let ServiceResponse::Multiple(mut stream) = response else {
+        panic!("expected stream response");
+    };
+    assert_eq!(stream.next().await.unwrap().unwrap(), 10);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    // Errors are passed through without calling the function
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(stream.next().await.unwrap().unwrap(), 20);
+    assert!(stream.next().await.is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
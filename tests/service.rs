@@ -0,0 +1,345 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    future::{join_all, poll_fn},
+    FutureExt,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    util::{
+        service::{CachingService, FallbackService, SharedError, SingleFlightService},
+        ServiceCallAll,
+    },
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+};
+use tokio::time::{sleep, timeout};
+use tower::{limit::ConcurrencyLimit, Service};
+
+use common::{
+    protocol::{Request, Response, SayHelloRequest},
+    say_hello, say_hello_stream, stdio_pair, GreetingService,
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error("mock failure")]
+struct MockError;
+
+/// Fails each request with a [`MockError`] of the configured error type, or greets if none
+/// is configured. Counts the calls it receives, and responds after the configured delay.
+#[derive(Clone, Default)]
+struct MockService {
+    error_type: Option<ProtocolErrorType>,
+    delay: Duration,
+    pending: bool,
+    fails_to_become_ready: bool,
+    calls: Arc<AtomicUsize>,
+}
+
+impl MockService {
+    fn failing(error_type: ProtocolErrorType) -> Self {
+        Self {
+            error_type: Some(error_type),
+            ..Default::default()
+        }
+    }
+
+    fn pending() -> Self {
+        Self {
+            pending: true,
+            ..Default::default()
+        }
+    }
+
+    fn fails_to_become_ready() -> Self {
+        Self {
+            fails_to_become_ready: true,
+            ..Default::default()
+        }
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl Service<Request> for MockService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.fails_to_become_ready {
+            return Poll::Ready(Err(ProtocolError::new(
+                ProtocolErrorType::ServiceUnavailable,
+                "closed".into(),
+            )
+            .into()));
+        }
+        match self.pending {
+            true => Poll::Pending,
+            false => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let error_type = self.error_type.clone();
+        let delay = self.delay;
+        Box::pin(async move {
+            sleep(delay).await;
+            match error_type {
+                Some(error_type) => Err(ProtocolError::new(error_type, Box::new(MockError))
+                    .with_code("mock.failed")
+                    .into()),
+                None => GreetingService.call(req).await,
+            }
+        })
+    }
+}
+
+fn fallback(primary: &MockService, secondary: &MockService) -> FallbackService<Request, Response> {
+    FallbackService::new(vec![Box::new(primary.clone()), Box::new(secondary.clone())])
+}
+
+fn error_type(error: ServiceError) -> ProtocolErrorType {
+    ProtocolError::from(error).error_type
+}
+
+#[tokio::test]
+async fn falls_back_on_transport_failures() {
+    let primary = MockService::failing(ProtocolErrorType::ServiceUnavailable);
+    let secondary = MockService::default();
+    let mut service = fallback(&primary, &secondary);
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+    assert_eq!((primary.calls(), secondary.calls()), (1, 1));
+}
+
+#[tokio::test]
+async fn does_not_fall_back_on_other_errors() {
+    let primary = MockService::failing(ProtocolErrorType::BadRequest);
+    let secondary = MockService::default();
+    let mut service = fallback(&primary, &secondary);
+    let error = say_hello(&mut service, "a").await.unwrap_err();
+    assert!(matches!(error_type(error), ProtocolErrorType::BadRequest));
+    assert_eq!(secondary.calls(), 0);
+}
+
+#[tokio::test]
+async fn falls_back_on_timeouts_if_enabled() {
+    let primary = MockService::failing(ProtocolErrorType::Timeout);
+    let secondary = MockService::default();
+    let mut service = fallback(&primary, &secondary);
+    let error = say_hello(&mut service, "a").await.unwrap_err();
+    assert!(matches!(error_type(error), ProtocolErrorType::Timeout));
+    assert_eq!(secondary.calls(), 0);
+
+    let mut service = fallback(&primary, &secondary).with_fallback_on_timeout(true);
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+    assert_eq!(secondary.calls(), 1);
+}
+
+#[tokio::test]
+async fn readiness_reflects_the_inner_services() {
+    let secondary = MockService::default();
+    let mut service = fallback(&MockService::pending(), &secondary);
+    assert!(poll_fn(|cx| service.poll_ready(cx))
+        .now_or_never()
+        .is_none());
+
+    // Services that fail to become ready are skipped
+    let mut service = fallback(&MockService::fails_to_become_ready(), &secondary);
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+    assert_eq!(secondary.calls(), 1);
+
+    let mut service = fallback(
+        &MockService::fails_to_become_ready(),
+        &MockService::fails_to_become_ready(),
+    );
+    let error = poll_fn(|cx| service.poll_ready(cx)).await.unwrap_err();
+    assert!(matches!(
+        error_type(error),
+        ProtocolErrorType::ServiceUnavailable
+    ));
+}
+
+fn single_flight(inner: &MockService) -> SingleFlightService<Request, Response, String> {
+    SingleFlightService::new(Box::new(inner.clone()), |request: &Request| match request {
+        Request::SayHello(request) => Some(request.name.clone()),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn single_flight_coalesces_identical_requests() {
+    let inner = MockService {
+        delay: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let service = single_flight(&inner);
+    let results = join_all((0..5).map(|_| {
+        let mut service = service.clone();
+        async move { say_hello(&mut service, "a").await.unwrap() }
+    }))
+    .await;
+    assert!(results.iter().all(|result| result == "Hello, a!"));
+    assert_eq!(inner.calls(), 1);
+
+    // Requests with other keys are not coalesced
+    let mut service = service.clone();
+    say_hello(&mut service, "b").await.unwrap();
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test]
+async fn single_flight_preserves_errors() {
+    let inner = MockService {
+        delay: Duration::from_millis(50),
+        ..MockService::failing(ProtocolErrorType::BadRequest)
+    };
+    let service = single_flight(&inner);
+    let errors = join_all((0..3).map(|_| {
+        let mut service = service.clone();
+        async move { ProtocolError::from(say_hello(&mut service, "a").await.unwrap_err()) }
+    }))
+    .await;
+    assert_eq!(inner.calls(), 1);
+    for error in errors {
+        assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+        assert_eq!(error.code(), Some("mock.failed"));
+        let shared = error.inner_error().downcast_ref::<SharedError>().unwrap();
+        assert!(shared.inner().is::<MockError>());
+    }
+
+    // Errors that are not shared are returned as is
+    let mut service = service.clone();
+    let error = ProtocolError::from(say_hello(&mut service, "a").await.unwrap_err());
+    assert!(error.inner_error().is::<MockError>());
+}
+
+#[tokio::test]
+async fn single_flight_readiness_reflects_the_inner_service() {
+    let mut service = single_flight(&MockService::pending());
+    assert!(poll_fn(|cx| service.poll_ready(cx))
+        .now_or_never()
+        .is_none());
+}
+
+fn caching(
+    inner: &MockService,
+    ttl: Duration,
+    max_entries: usize,
+) -> CachingService<Request, Response, String> {
+    CachingService::new(
+        Box::new(inner.clone()),
+        |request: &Request| match request {
+            Request::SayHello(request) | Request::SayHelloStream(request) => {
+                Some(request.name.clone())
+            }
+            _ => None,
+        },
+        ttl,
+        max_entries,
+    )
+}
+
+#[tokio::test]
+async fn caching_returns_cached_responses() {
+    let inner = MockService::default();
+    let mut service = caching(&inner, Duration::from_secs(60), 10);
+    for _ in 0..3 {
+        assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+    }
+    assert_eq!(inner.calls(), 1);
+
+    say_hello(&mut service, "b").await.unwrap();
+    assert_eq!(inner.calls(), 2);
+
+    // Streaming responses are never cached
+    for _ in 0..2 {
+        assert_eq!(say_hello_stream(&mut service, "c").await, "Hello, c!");
+    }
+    assert_eq!(inner.calls(), 4);
+}
+
+#[tokio::test]
+async fn caching_does_not_cache_errors() {
+    let inner = MockService::failing(ProtocolErrorType::Internal);
+    let mut service = caching(&inner, Duration::from_secs(60), 10);
+    for _ in 0..2 {
+        say_hello(&mut service, "a").await.unwrap_err();
+    }
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test]
+async fn caching_expires_entries_after_the_ttl() {
+    let inner = MockService::default();
+    let mut service = caching(&inner, Duration::from_millis(50), 10);
+    say_hello(&mut service, "a").await.unwrap();
+    say_hello(&mut service, "a").await.unwrap();
+    assert_eq!(inner.calls(), 1);
+
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(say_hello(&mut service, "a").await.unwrap(), "Hello, a!");
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test]
+async fn caching_evicts_the_oldest_entry() {
+    let inner = MockService::default();
+    let mut service = caching(&inner, Duration::from_secs(60), 2);
+    for name in ["a", "b", "c"] {
+        say_hello(&mut service, name).await.unwrap();
+    }
+    assert_eq!(inner.calls(), 3);
+
+    // "a" was evicted once "c" was inserted
+    for name in ["b", "c"] {
+        say_hello(&mut service, name).await.unwrap();
+    }
+    assert_eq!(inner.calls(), 3);
+    say_hello(&mut service, "a").await.unwrap();
+    assert_eq!(inner.calls(), 4);
+
+    // Nothing is cached if the maximum is zero
+    let mut service = caching(&inner, Duration::from_secs(60), 0);
+    say_hello(&mut service, "a").await.unwrap();
+    say_hello(&mut service, "a").await.unwrap();
+    assert_eq!(inner.calls(), 6);
+}
+
+#[tokio::test]
+async fn call_all_respects_concurrency_limits() {
+    let client = stdio_pair(GreetingService, Default::default(), Default::default());
+    let mut client = ConcurrencyLimit::new(client, 1);
+    let names = ["a", "b", "c", "d", "e"];
+    let requests = names
+        .iter()
+        .map(|name| {
+            Request::SayHello(SayHelloRequest {
+                name: name.to_string(),
+            })
+        })
+        .collect();
+    let results = timeout(Duration::from_secs(5), client.call_all(requests))
+        .await
+        .expect("batch should not stall on the concurrency limit");
+    assert_eq!(results.len(), names.len());
+    for (name, result) in names.iter().zip(results) {
+        match result.unwrap() {
+            ServiceResponse::Single(Response::SayHello(response)) => {
+                assert_eq!(response.result, format!("Hello, {name}!"))
+            }
+            _ => panic!("unexpected response"),
+        }
+    }
+}
@@ -0,0 +1,464 @@
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{
+    future::{join_all, poll_fn},
+    StreamExt,
+};
+use multilink::{
+    error::ProtocolErrorType,
+    stdio::{
+        client::{IdStrategy, StdioClient, StdioClientConfig},
+        server::StdioServer,
+    },
+    ProtocolError, ServiceResponse,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{duplex, split},
+    time::{sleep, timeout},
+};
+use tower::{limit::ConcurrencyLimit, Service};
+
+use common::{
+    protocol::{Request, Response, SayHelloRequest},
+    raw_server, say_hello, say_hello_stream, stdio_pair, GreetingService,
+};
+
+fn say_hello_stream_request() -> Request {
+    Request::SayHelloStream(SayHelloRequest {
+        name: "a".to_string(),
+    })
+}
+
+#[tokio::test]
+async fn idle_timeout_applies_to_streams_without_items() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        idle_timeout_secs: Some(1),
+        ..Default::default()
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let response = tokio::spawn(client.call(say_hello_stream_request()));
+
+    let request = server.read_message().await;
+    // The server registers the stream, but never sends an item or another heartbeat
+    server
+        .write_message(json!({"jsonrpc": "2.0", "method": "$/heartbeat", "params": request["id"]}))
+        .await;
+
+    let ServiceResponse::Multiple(mut stream) = response.await.unwrap().unwrap() else {
+        panic!("expected stream response");
+    };
+    let item = timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("stream should fail after the idle timeout");
+    let Some(Err(error)) = item else {
+        panic!("expected idle timeout error");
+    };
+    assert!(matches!(error.error_type, ProtocolErrorType::Timeout));
+}
+
+fn stream_item(id: &Value, character: char) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": id.to_string(),
+        "params": {"result": {"character": character}},
+    })
+}
+
+#[tokio::test]
+async fn lagging_streams_are_cancelled_without_blocking_other_streams() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        notification_channel_capacity: Some(2),
+        ..Default::default()
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let lagging_response = tokio::spawn(client.call(say_hello_stream_request()));
+    let lagging_id = server.read_message().await["id"].clone();
+    let other_response = tokio::spawn(client.call(say_hello_stream_request()));
+    let other_id = server.read_message().await["id"].clone();
+
+    // The consumer of the first stream does not read, so the third item overflows
+    for character in ['a', 'b', 'c', 'd'] {
+        server
+            .write_message(stream_item(&lagging_id, character))
+            .await;
+    }
+    server.write_message(stream_item(&other_id, 'z')).await;
+
+    let unsubscribe = server.read_message().await;
+    assert_eq!(unsubscribe["method"], "$/unsubscribe");
+    assert_eq!(unsubscribe["params"], lagging_id);
+
+    let ServiceResponse::Multiple(mut other_stream) = other_response.await.unwrap().unwrap() else {
+        panic!("expected stream response");
+    };
+    let item = timeout(Duration::from_secs(5), other_stream.next())
+        .await
+        .expect("other stream should not be blocked by the lagging stream");
+    let Some(Ok(Response::SayHelloStream(item))) = item else {
+        panic!("expected stream item");
+    };
+    assert_eq!(item.character, 'z');
+
+    let ServiceResponse::Multiple(lagging_stream) = lagging_response.await.unwrap().unwrap() else {
+        panic!("expected stream response");
+    };
+    let items = lagging_stream.collect::<Vec<_>>().await;
+    assert_eq!(items.len(), 3);
+    assert!(items[..2].iter().all(|item| item.is_ok()));
+    let Err(error) = &items[2] else {
+        panic!("expected lagged error");
+    };
+    assert!(error.to_string().contains("fell behind"), "{error}");
+}
+
+#[tokio::test]
+async fn idle_timeout_error_is_delivered_when_the_channel_is_full() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        idle_timeout_secs: Some(1),
+        notification_channel_capacity: Some(1),
+        ..Default::default()
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let response = tokio::spawn(client.call(say_hello_stream_request()));
+    let id = server.read_message().await["id"].clone();
+    server.write_message(stream_item(&id, 'a')).await;
+
+    let ServiceResponse::Multiple(stream) = response.await.unwrap().unwrap() else {
+        panic!("expected stream response");
+    };
+    // Waits for the idle timeout while the buffered item fills the channel
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let items = timeout(Duration::from_secs(5), stream.collect::<Vec<_>>())
+        .await
+        .unwrap();
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_ok());
+    let Err(error) = &items[1] else {
+        panic!("expected idle timeout error");
+    };
+    assert!(matches!(error.error_type, ProtocolErrorType::Timeout));
+}
+
+#[tokio::test]
+async fn server_pushes_are_received_without_a_request() {
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let (client_reader, client_writer) = split(client_io);
+    let server = StdioServer::with_io(
+        GreetingService,
+        Default::default(),
+        server_reader,
+        server_writer,
+    );
+    let push_handle = server.push_handle();
+    tokio::spawn(server.run());
+    let mut client =
+        StdioClient::<Request, Response>::with_io(client_reader, client_writer, Default::default());
+    let mut pushes = client.subscribe_push();
+
+    push_handle
+        .push("configReloaded", Some(json!({"version": 2})))
+        .await
+        .unwrap();
+    let push = timeout(Duration::from_secs(5), pushes.next())
+        .await
+        .expect("push should be received")
+        .unwrap();
+    assert_eq!(push.method, "configReloaded");
+    assert_eq!(push.params, Some(json!({"version": 2})));
+
+    // Requests are unaffected by pushes
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    for reserved_method in ["1", "$/heartbeat"] {
+        let error = push_handle.push(reserved_method, None).await.unwrap_err();
+        assert!(matches!(error.error_type, ProtocolErrorType::BadRequest));
+    }
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_unsubscribe_independently() {
+    let (client, mut server) = raw_server(StdioClientConfig::default());
+    let subscribe = |client: &StdioClient<Request, Response>| {
+        let mut client = client.clone();
+        tokio::spawn(async move {
+            poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+            client.subscribe(say_hello_stream_request()).await
+        })
+    };
+    let mut subscriptions = Vec::new();
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        subscriptions.push(subscribe(&client));
+        ids.push(server.read_message().await["id"].clone());
+    }
+    // The server never acknowledges this request as a stream
+    let unacknowledged = subscribe(&client);
+    server.read_message().await;
+
+    for (id, character) in ids.iter().zip(['a', 'b', 'c']) {
+        server.write_message(stream_item(id, character)).await;
+    }
+    let mut subscriptions = futures::future::join_all(subscriptions)
+        .await
+        .into_iter()
+        .map(|subscription| subscription.unwrap().unwrap())
+        .collect::<Vec<_>>();
+    for (subscription, expected) in subscriptions.iter_mut().zip(['a', 'b', 'c']) {
+        let Some(Ok(Response::SayHelloStream(item))) = subscription.next().await else {
+            panic!("expected stream item");
+        };
+        assert_eq!(item.character, expected);
+    }
+
+    subscriptions.remove(1).unsubscribe();
+    let unsubscribe = server.read_message().await;
+    assert_eq!(unsubscribe["method"], "$/unsubscribe");
+    assert_eq!(unsubscribe["params"], ids[1]);
+
+    // Dropping a request that was never acknowledged does not unsubscribe
+    unacknowledged.abort();
+    for (id, character) in [(&ids[0], 'x'), (&ids[2], 'z')] {
+        server.write_message(stream_item(id, character)).await;
+    }
+    for (subscription, expected) in subscriptions.iter_mut().zip(['x', 'z']) {
+        let Some(Ok(Response::SayHelloStream(item))) = subscription.next().await else {
+            panic!("other subscriptions should continue");
+        };
+        assert_eq!(item.character, expected);
+    }
+    drop(subscriptions.remove(1));
+    let unsubscribe = server.read_message().await;
+    assert_eq!(unsubscribe["method"], "$/unsubscribe");
+    assert_eq!(unsubscribe["params"], ids[2]);
+}
+
+/// Sends concurrent single and stream requests, and checks that each response
+/// is matched to its request.
+async fn assert_responses_match_requests(client: &StdioClient<Request, Response>) {
+    let names = ["a", "b", "c", "d"];
+    let greetings = join_all(names.iter().map(|name| {
+        let mut client = client.clone();
+        async move { say_hello(&mut client, name).await.unwrap() }
+    }))
+    .await;
+    let streamed_greetings = join_all(names.iter().map(|name| {
+        let mut client = client.clone();
+        async move { say_hello_stream(&mut client, name).await }
+    }))
+    .await;
+    for ((name, greeting), streamed_greeting) in names.iter().zip(greetings).zip(streamed_greetings)
+    {
+        assert_eq!(greeting, format!("Hello, {name}!"));
+        assert_eq!(streamed_greeting, format!("Hello, {name}!"));
+    }
+}
+
+#[tokio::test]
+async fn uuid_ids_are_matched_with_responses() {
+    let client = stdio_pair(
+        GreetingService,
+        Default::default(),
+        StdioClientConfig {
+            id_strategy: IdStrategy::Uuid,
+            ..Default::default()
+        },
+    );
+    assert_responses_match_requests(&client).await;
+}
+
+#[tokio::test]
+async fn custom_ids_are_matched_with_responses() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let client = stdio_pair(
+        GreetingService,
+        Default::default(),
+        StdioClientConfig {
+            id_strategy: IdStrategy::Custom(Arc::new(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                json!(format!("upstream-{n}"))
+            })),
+            ..Default::default()
+        },
+    );
+    assert_responses_match_requests(&client).await;
+}
+
+#[tokio::test]
+async fn flooding_a_slow_child_applies_backpressure() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        request_queue_capacity: 2,
+        ..Default::default()
+    });
+    // The server does not read, so the pipe fills up once a few requests are written
+    let name = "a".repeat(16 * 1024);
+    let mut responses = Vec::new();
+    loop {
+        let ready = timeout(
+            Duration::from_millis(200),
+            poll_fn(|cx| client.poll_ready(cx)),
+        )
+        .await;
+        match ready {
+            Ok(ready) => ready.unwrap(),
+            Err(_) => break,
+        }
+        responses.push(client.call(Request::SayHello(SayHelloRequest { name: name.clone() })));
+        assert!(
+            responses.len() < 32,
+            "requests were queued without backpressure"
+        );
+    }
+    assert!(responses.len() >= 2, "{} requests queued", responses.len());
+
+    // Reading a request frees capacity in the queue
+    server.read_message().await;
+    timeout(Duration::from_secs(5), poll_fn(|cx| client.poll_ready(cx)))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn concurrency_limits_wait_for_responses() {
+    let (client, mut server) = raw_server(Default::default());
+    let mut client = ConcurrencyLimit::new(client, 1);
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let response = tokio::spawn(client.call(Request::SayHello(SayHelloRequest {
+        name: "a".to_string(),
+    })));
+    let request = server.read_message().await;
+
+    // The limit is reached until the in-flight request completes
+    let ready = timeout(
+        Duration::from_millis(200),
+        poll_fn(|cx| client.poll_ready(cx)),
+    )
+    .await;
+    assert!(ready.is_err(), "client should not be ready");
+
+    server
+        .write_message(
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": {"result": "Hello, a!"}}),
+        )
+        .await;
+    match response.await.unwrap().unwrap() {
+        ServiceResponse::Single(Response::SayHello(response)) => {
+            assert_eq!(response.result, "Hello, a!")
+        }
+        _ => panic!("unexpected response"),
+    }
+    timeout(Duration::from_secs(5), poll_fn(|cx| client.poll_ready(cx)))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn killing_the_child_fails_in_flight_requests() {
+    let config = StdioClientConfig {
+        timeout_secs: 5,
+        ..Default::default()
+    };
+    let mut client = StdioClient::<Request, Response>::new("sh", &["-c", "cat >/dev/null"], config)
+        .await
+        .unwrap();
+    let pid = client.child_id().expect("child should have a pid");
+    let is_running = || {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    };
+    assert!(is_running());
+
+    // The child never responds, so the request stays in-flight until the child is killed
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let response = tokio::spawn(client.call(say_hello_stream_request()));
+    client.kill().await.unwrap();
+    assert!(!is_running());
+
+    let error = timeout(Duration::from_secs(5), response)
+        .await
+        .unwrap()
+        .unwrap()
+        .err()
+        .unwrap();
+    assert!(matches!(
+        ProtocolError::from(error).error_type,
+        ProtocolErrorType::ServiceUnavailable
+    ));
+
+    // Clients without a child process have no pid
+    let (client, _server) = raw_server(Default::default());
+    assert_eq!(client.child_id(), None);
+    client.kill().await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn children_that_exit_on_stdin_eof_are_not_terminated() {
+    let path = std::env::temp_dir().join(format!("multilink-shutdown-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    // The trap records a SIGTERM, and the final line is only written if the child
+    // exits on its own after stdin is closed
+    let script = format!(
+        "trap 'echo terminated >> {path}; exit 1' TERM; cat >/dev/null; sleep 0.2; echo exited >> {path}",
+        path = path.display()
+    );
+    let config = StdioClientConfig {
+        shutdown_grace_secs: 1,
+        ..Default::default()
+    };
+    let client = StdioClient::<Request, Response>::new("sh", &["-c", &script], config)
+        .await
+        .unwrap();
+    drop(client);
+
+    // Waits for longer than both grace periods, so that a SIGTERM or kill would be observed
+    sleep(Duration::from_millis(2500)).await;
+    let output = std::fs::read_to_string(&path).unwrap_or_default();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(output, "exited\n");
+}
+
+#[tokio::test]
+async fn over_long_messages_fail_pending_requests() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        max_message_bytes: 1024,
+        ..Default::default()
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let response = tokio::spawn(client.call(Request::SayHello(SayHelloRequest {
+        name: "a".to_string(),
+    })));
+    let request = server.read_message().await;
+    let result = "a".repeat(2048);
+    server
+        .write_message(json!({"jsonrpc": "2.0", "id": request["id"], "result": {"result": result}}))
+        .await;
+
+    let error = timeout(Duration::from_secs(5), response)
+        .await
+        .unwrap()
+        .unwrap()
+        .err()
+        .unwrap();
+    let error = ProtocolError::from(error);
+    assert!(
+        matches!(error.error_type, ProtocolErrorType::PayloadTooLarge),
+        "{error}"
+    );
+}
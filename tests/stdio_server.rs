@@ -0,0 +1,492 @@
+mod common;
+
+use std::{
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_stream::stream;
+use futures::{future::poll_fn, StreamExt};
+use multilink::{
+    error::ProtocolErrorType,
+    jsonrpc::{JsonRpcErrorCode, CHUNK_METHOD},
+    stdio::{
+        client::{StdioClient, StdioClientConfig},
+        server::{StdioServer, StdioServerConfig},
+    },
+    ProtocolError, ServiceError, ServiceFuture, ServiceResponse,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{duplex, split},
+    time::timeout,
+};
+use tower::Service;
+
+use common::{
+    protocol::{GreetingStreamResponse, Request, Response, SayHelloRequest},
+    raw_client, raw_server, say_hello, say_hello_stream, stdio_pair, GreetingService, RawPeer,
+    SlowService,
+};
+
+/// Panics while creating the future for `SayHello` requests, and after the first item of
+/// `SayHelloStream` responses. Other requests are handled by [`GreetingService`].
+struct PanickingService;
+
+impl Service<Request> for PanickingService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match req {
+            Request::SayHello(_) => panic!("service panicked"),
+            Request::SayHelloStream(_) => Box::pin(async {
+                Ok(ServiceResponse::Multiple(
+                    stream! {
+                        yield Ok(Response::SayHelloStream(GreetingStreamResponse { character: 'H' }));
+                        panic!("stream panicked");
+                    }
+                    .boxed(),
+                ))
+            }),
+            req => GreetingService.call(req),
+        }
+    }
+}
+
+fn assert_internal(error: impl Into<ProtocolError>) {
+    let error = error.into();
+    assert!(matches!(error.error_type, ProtocolErrorType::Internal));
+}
+
+#[tokio::test]
+async fn panics_are_reported_to_the_client() {
+    let mut client = stdio_pair(PanickingService, Default::default(), Default::default());
+    assert_internal(say_hello(&mut client, "a").await.unwrap_err());
+
+    let request = Request::SayHelloStream(SayHelloRequest {
+        name: "a".to_string(),
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let ServiceResponse::Multiple(mut stream) = client.call(request).await.unwrap() else {
+        panic!("expected stream response");
+    };
+    assert!(matches!(
+        stream.next().await,
+        Some(Ok(Response::SayHelloStream(GreetingStreamResponse {
+            character: 'H'
+        })))
+    ));
+    let Some(Err(error)) = stream.next().await else {
+        panic!("expected error after panic");
+    };
+    assert_internal(error);
+    assert!(stream.next().await.is_none());
+
+    // The server keeps handling requests after a panic
+    let request = Request::SayCustomGreeting(common::protocol::SayCustomGreetingRequest {
+        greeting: "Hi".to_string(),
+        name: "a".to_string(),
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    assert!(matches!(
+        client.call(request).await.unwrap(),
+        ServiceResponse::Single(Response::SayCustomGreeting(_))
+    ));
+}
+
+fn stream_request(id: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": "sayHelloStream", "params": {"name": "abc"}, "id": id})
+}
+
+/// Reads messages until `count` streams have completed, and returns the messages.
+async fn read_until_streams_complete(client: &mut RawPeer, count: usize) -> Vec<Value> {
+    let mut messages = Vec::new();
+    while messages
+        .iter()
+        .filter(|message: &&Value| message["stream_complete"] == true)
+        .count()
+        < count
+    {
+        messages.push(client.read_message().await);
+    }
+    messages
+}
+
+#[tokio::test]
+async fn duplicate_request_ids_are_rejected() {
+    let mut client = raw_client(GreetingService, Default::default());
+    client.write_message(stream_request(json!(1))).await;
+    client.write_message(stream_request(json!(1))).await;
+
+    let messages = read_until_streams_complete(&mut client, 1).await;
+    let rejections = messages
+        .iter()
+        .filter(|message| message.get("error").is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(rejections.len(), 1, "{messages:?}");
+    assert_eq!(rejections[0]["id"], 1);
+    assert!(rejections[0]["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("already in use"));
+
+    // The id may be reused once the first request has completed
+    client.write_message(stream_request(json!(1))).await;
+    let messages = read_until_streams_complete(&mut client, 1).await;
+    assert!(messages
+        .iter()
+        .all(|message| message.get("error").is_none()));
+}
+
+#[tokio::test]
+async fn distinct_non_numeric_ids_are_not_duplicates() {
+    let mut client = raw_client(GreetingService, Default::default());
+    for id in [json!("a"), json!("b"), json!(1), json!("1")] {
+        client.write_message(stream_request(id)).await;
+    }
+    let messages = read_until_streams_complete(&mut client, 4).await;
+    assert!(
+        messages
+            .iter()
+            .all(|message| message.get("error").is_none()),
+        "{messages:?}"
+    );
+}
+
+#[tokio::test]
+async fn non_numeric_ids_are_echoed() {
+    let mut client = raw_client(GreetingService, Default::default());
+    let id = json!("b7c1f3a2-request");
+    client
+        .write_message(json!({
+            "jsonrpc": "2.0",
+            "method": "sayHello",
+            "params": {"name": "a"},
+            "id": id,
+        }))
+        .await;
+    let response = client.read_message().await;
+    assert_eq!(response["id"], id, "{response}");
+    assert_eq!(response["result"]["result"], "Hello, a!");
+
+    client.write_message(stream_request(id.clone())).await;
+    let messages = read_until_streams_complete(&mut client, 1).await;
+    // Stream notifications use the serialized id as the method
+    let stream_method = id.to_string();
+    assert!(
+        messages
+            .iter()
+            .all(|message| message["method"] == stream_method.as_str()),
+        "{messages:?}"
+    );
+}
+
+#[tokio::test]
+async fn streams_with_non_numeric_ids_can_be_unsubscribed() {
+    let mut client = raw_client(GreetingService, Default::default());
+    let name = "a".repeat(500);
+    client
+        .write_message(json!({
+            "jsonrpc": "2.0",
+            "method": "sayHelloStream",
+            "params": {"name": name},
+            "id": "subscription",
+        }))
+        .await;
+    // Waits for the first item, so that the stream is active
+    client.read_message().await;
+    client
+        .write_message(
+            json!({"jsonrpc": "2.0", "method": "$/unsubscribe", "params": "subscription"}),
+        )
+        .await;
+
+    // The client is not notified once the stream is cancelled, so items stop arriving
+    let mut received = 1;
+    while timeout(Duration::from_millis(200), client.read_message())
+        .await
+        .is_ok()
+    {
+        received += 1;
+        assert!(received < name.len(), "stream was not cancelled");
+    }
+
+    // The id is released, so it may be reused
+    client
+        .write_message(stream_request(json!("subscription")))
+        .await;
+    let messages = read_until_streams_complete(&mut client, 1).await;
+    assert!(
+        messages
+            .iter()
+            .all(|message| message.get("error").is_none()),
+        "{messages:?}"
+    );
+}
+
+#[tokio::test]
+async fn malformed_messages_receive_a_parse_error_with_a_null_id() {
+    let mut client = raw_client(GreetingService, Default::default());
+    // The id appears in the payload, but cannot be recovered reliably
+    client
+        .write_raw(b"{\"jsonrpc\": \"2.0\", \"method\": \"sayHello\", \"id\": 5, \"params\": {\n")
+        .await;
+    let response = timeout(Duration::from_secs(5), client.read_message())
+        .await
+        .expect("parse error should be sent promptly");
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    // The server continues to handle messages
+    client
+        .write_message(
+            json!({"jsonrpc": "2.0", "method": "sayHello", "params": {"name": "a"}, "id": 6}),
+        )
+        .await;
+    let response = client.read_message().await;
+    assert_eq!(response["id"], 6);
+    assert_eq!(response["result"]["result"], "Hello, a!");
+}
+
+#[tokio::test]
+async fn large_responses_are_chunked_and_reassembled() {
+    let server_config = StdioServerConfig {
+        response_chunk_bytes: Some(16),
+        ..Default::default()
+    };
+    let name = "é-large-name-".repeat(50);
+    let mut client = stdio_pair(GreetingService, server_config.clone(), Default::default());
+    assert_eq!(
+        say_hello(&mut client, &name).await.unwrap(),
+        format!("Hello, {name}!")
+    );
+    // Small responses are not chunked
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    let mut client = raw_client(GreetingService, server_config);
+    client
+        .write_message(
+            json!({"jsonrpc": "2.0", "method": "sayHello", "params": {"name": name}, "id": 7}),
+        )
+        .await;
+    let mut data = String::new();
+    let response = loop {
+        let message = client.read_message().await;
+        if message["method"] != CHUNK_METHOD {
+            break message;
+        }
+        assert_eq!(message["params"]["id"], 7);
+        let chunk = message["params"]["data"].as_str().unwrap();
+        assert!(chunk.len() <= 16);
+        data.push_str(chunk);
+    };
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["chunked"], true);
+    assert_eq!(response["result"], Value::Null);
+    let result = serde_json::from_str::<Value>(&data).unwrap();
+    assert_eq!(result["result"], format!("Hello, {name}!"));
+}
+
+fn assert_timeout(error: impl Into<ProtocolError>) {
+    let error = error.into();
+    assert!(
+        matches!(error.error_type, ProtocolErrorType::Timeout),
+        "{error}"
+    );
+    assert!(error.to_string().contains("timed out"), "{error}");
+}
+
+#[tokio::test]
+async fn service_timeouts_are_resolved_per_request() {
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let (client_reader, client_writer) = split(client_io);
+    let server = StdioServer::with_io(
+        SlowService(Duration::from_millis(300)),
+        Default::default(),
+        server_reader,
+        server_writer,
+    )
+    .with_timeout_policy(|request| match request {
+        Request::SayHello(_) => Duration::from_millis(100),
+        _ => Duration::from_secs(5),
+    });
+    tokio::spawn(server.run());
+    let mut client: StdioClient<Request, Response> =
+        StdioClient::with_io(client_reader, client_writer, Default::default());
+
+    // The same delay exceeds the tight timeout, but not the generous one
+    assert_timeout(say_hello(&mut client, "a").await.unwrap_err());
+    assert_eq!(say_hello_stream(&mut client, "a").await, "Hello, a!");
+}
+
+#[tokio::test]
+async fn slow_services_return_a_timeout_error() {
+    let mut client = stdio_pair(
+        SlowService(Duration::from_secs(3)),
+        StdioServerConfig {
+            service_timeout_secs: 1,
+            ..Default::default()
+        },
+        Default::default(),
+    );
+    let error = timeout(Duration::from_secs(2), say_hello(&mut client, "a"))
+        .await
+        .expect("server should time out before the client")
+        .unwrap_err();
+    assert_timeout(error);
+}
+
+/// Connects a stdio client to a server with the given response interceptor.
+fn intercepted_pair<F>(interceptor: F) -> StdioClient<Request, Response>
+where
+    F: Fn(&mut ServiceResponse<Response>) -> Result<(), ProtocolError> + Send + Sync + 'static,
+{
+    let (client_io, server_io) = duplex(64 * 1024);
+    let (server_reader, server_writer) = split(server_io);
+    let (client_reader, client_writer) = split(client_io);
+    let server = StdioServer::with_io(
+        GreetingService,
+        Default::default(),
+        server_reader,
+        server_writer,
+    )
+    .with_response_interceptor(interceptor);
+    tokio::spawn(server.run());
+    StdioClient::with_io(client_reader, client_writer, Default::default())
+}
+
+#[tokio::test]
+async fn response_interceptors_redact_single_and_stream_responses() {
+    let mut client = intercepted_pair(|response| {
+        match response {
+            ServiceResponse::Single(Response::SayHello(response)) => {
+                response.result = "[redacted]".to_string()
+            }
+            ServiceResponse::Single(Response::SayHelloStream(response)) => response.character = '*',
+            _ => (),
+        }
+        Ok(())
+    });
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "[redacted]");
+    assert_eq!(say_hello_stream(&mut client, "a").await, "*********");
+}
+
+#[tokio::test]
+async fn response_interceptor_panics_are_reported_to_the_client() {
+    let mut client = intercepted_pair(|response| match response {
+        ServiceResponse::Single(Response::SayHello(_)) => panic!("interceptor panicked"),
+        _ => Ok(()),
+    });
+    let error = timeout(Duration::from_secs(5), say_hello(&mut client, "a"))
+        .await
+        .expect("client should receive an error response")
+        .unwrap_err();
+    assert_internal(error);
+
+    // The server continues to handle requests
+    assert_eq!(say_hello_stream(&mut client, "a").await, "Hello, a!");
+}
+
+#[tokio::test]
+async fn requests_are_aborted_once_the_client_deadline_elapses() {
+    let mut peer = raw_client(SlowService(Duration::from_secs(5)), Default::default());
+    let start = Instant::now();
+    peer.write_message(json!({
+        "jsonrpc": "2.0",
+        "method": "sayHello",
+        "params": {"name": "a"},
+        "id": 1,
+        "deadline_ms": 200,
+    }))
+    .await;
+    let response = timeout(Duration::from_secs(2), peer.read_message())
+        .await
+        .expect("server should abort the request before the service completes");
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["error"]["code"], JsonRpcErrorCode::Timeout as i64);
+    assert!(response["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("deadline exceeded"));
+}
+
+#[tokio::test]
+async fn clients_send_their_remaining_time_as_the_deadline() {
+    let (mut client, mut server) = raw_server(StdioClientConfig {
+        timeout_secs: 1,
+        ..Default::default()
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let _response = tokio::spawn(client.call(Request::SayHello(SayHelloRequest {
+        name: "a".to_string(),
+    })));
+    let request = server.read_message().await;
+    let deadline_ms = request["deadline_ms"].as_u64().unwrap();
+    assert!(deadline_ms > 0 && deadline_ms <= 1000, "{deadline_ms}");
+}
+
+#[tokio::test]
+async fn over_long_requests_are_rejected() {
+    let mut client = raw_client(
+        GreetingService,
+        StdioServerConfig {
+            max_message_bytes: 1024,
+            ..Default::default()
+        },
+    );
+    client
+        .write_message(json!({
+            "jsonrpc": "2.0",
+            "method": "sayHello",
+            "params": {"name": "a".repeat(2048)},
+            "id": 1,
+        }))
+        .await;
+    let response = timeout(Duration::from_secs(5), client.read_message())
+        .await
+        .unwrap();
+    // The id of the request is unknown, since the request was not read
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(
+        response["error"]["code"],
+        JsonRpcErrorCode::InvalidRequest as i64
+    );
+    assert!(response["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("maximum size of 1024 bytes"));
+}
+
+#[tokio::test]
+async fn jsonrpc_versions_are_validated_by_the_server() {
+    let request = |version: &str| json!({"jsonrpc": version, "method": "sayHello", "params": {"name": "a"}, "id": 1});
+    let mut client = raw_client(GreetingService, Default::default());
+    client.write_message(request("1.0")).await;
+    let response = client.read_message().await;
+    assert_eq!(
+        response["error"]["code"],
+        JsonRpcErrorCode::InvalidRequest as i64
+    );
+    client.write_message(request("2.0")).await;
+    let response = client.read_message().await;
+    assert_eq!(response["result"]["result"], "Hello, a!");
+
+    let mut client = raw_client(
+        GreetingService,
+        StdioServerConfig {
+            validate_jsonrpc_version: false,
+            ..Default::default()
+        },
+    );
+    client.write_message(request("1.0")).await;
+    let response = client.read_message().await;
+    assert_eq!(response["result"]["result"], "Hello, a!");
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use futures::{
+    future::{join_all, poll_fn},
+    StreamExt,
+};
+use multilink::{
+    tcp::{
+        client::{TcpClient, TcpClientConfig},
+        server::TcpServer,
+    },
+    ServiceResponse,
+};
+use tower::Service;
+
+use common::{
+    bind_listener,
+    protocol::{GreetingStreamResponse, Request, Response, SayHelloRequest},
+    say_hello, GreetingService,
+};
+
+async fn tcp_client() -> TcpClient<Request, Response> {
+    let (listener, addr) = bind_listener().await;
+    let server = TcpServer::new(GreetingService, Default::default());
+    tokio::spawn(server.run_with_listener(listener));
+    TcpClient::new(TcpClientConfig {
+        address: addr.to_string(),
+        timeout_secs: 5,
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_one_connection() {
+    let mut client = tcp_client().await;
+
+    // The stream stays in-flight while the other requests are sent on the same connection
+    let request = Request::SayHelloStream(SayHelloRequest {
+        name: "stream".to_string(),
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let ServiceResponse::Multiple(stream) = client.call(request).await.unwrap() else {
+        panic!("expected stream response");
+    };
+
+    let names = ["a", "b", "c", "d", "e"];
+    let greetings = join_all(names.iter().map(|name| {
+        let mut client = client.clone();
+        async move { say_hello(&mut client, name).await.unwrap() }
+    }))
+    .await;
+    for (name, greeting) in names.iter().zip(greetings) {
+        assert_eq!(greeting, format!("Hello, {name}!"));
+    }
+
+    let characters = stream
+        .map(|item| match item {
+            Ok(Response::SayHelloStream(GreetingStreamResponse { character })) => character,
+            _ => panic!("unexpected stream item"),
+        })
+        .collect::<String>()
+        .await;
+    assert_eq!(characters, "Hello, stream!");
+}
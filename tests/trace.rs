@@ -0,0 +1,105 @@
+mod common;
+
+use std::{
+    sync::Once,
+    task::{Context, Poll},
+};
+
+use multilink::{
+    http::server::{HttpServer, HttpServerConfig},
+    ServiceError, ServiceFuture, ServiceResponse,
+};
+use opentelemetry::{
+    sdk::{propagation::TraceContextPropagator, trace::TracerProvider},
+    trace::{TraceContextExt, TracerProvider as _},
+};
+use tower::Service;
+use tracing::{info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+use common::{
+    http_client,
+    protocol::{GreetingResponse, Request, Response},
+    say_hello, spawn_http_server, stdio_pair,
+};
+
+static INIT: Once = Once::new();
+
+/// Installs a subscriber which assigns OpenTelemetry contexts to spans, along with the
+/// W3C trace context propagator.
+fn init_tracing() {
+    INIT.call_once(|| {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("multilink-test");
+        // The provider is kept alive globally, since tracers only hold weak references to it
+        opentelemetry::global::set_tracer_provider(provider);
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+    });
+}
+
+/// Returns the trace id of the given span.
+fn trace_id(span: &Span) -> String {
+    span.context().span().span_context().trace_id().to_string()
+}
+
+/// Responds with the trace id of the span that the request is handled in.
+#[derive(Clone)]
+struct TraceIdService;
+
+impl Service<Request> for TraceIdService {
+    type Response = ServiceResponse<Response>;
+    type Error = ServiceError;
+    type Future = ServiceFuture<ServiceResponse<Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request) -> Self::Future {
+        let result = trace_id(&Span::current());
+        Box::pin(async move {
+            Ok(ServiceResponse::Single(Response::SayHello(
+                GreetingResponse { result },
+            )))
+        })
+    }
+}
+
+#[tokio::test]
+async fn http_server_spans_continue_the_client_trace() {
+    init_tracing();
+    let addr =
+        spawn_http_server(HttpServer::new(TraceIdService, HttpServerConfig::default())).await;
+    let mut client = http_client(addr);
+
+    let span = info_span!("client");
+    let server_trace_id = say_hello(&mut client, "a")
+        .instrument(span.clone())
+        .await
+        .unwrap();
+    assert_eq!(server_trace_id, trace_id(&span));
+
+    // Requests made outside of the span start a separate trace
+    let server_trace_id = say_hello(&mut client, "a").await.unwrap();
+    assert_ne!(server_trace_id, trace_id(&span));
+}
+
+#[tokio::test]
+async fn stdio_server_spans_continue_the_client_trace() {
+    init_tracing();
+    let mut client = stdio_pair(TraceIdService, Default::default(), Default::default());
+
+    let span = info_span!("client");
+    let server_trace_id = say_hello(&mut client, "a")
+        .instrument(span.clone())
+        .await
+        .unwrap();
+    assert_eq!(server_trace_id, trace_id(&span));
+
+    let server_trace_id = say_hello(&mut client, "a").await.unwrap();
+    assert_ne!(server_trace_id, trace_id(&span));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use futures::{future::poll_fn, SinkExt, StreamExt};
+use multilink::{
+    ws::{
+        client::{WsClient, WsClientConfig},
+        server::WsServer,
+    },
+    ServiceResponse,
+};
+use serde_json::{json, Value};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tower::Service;
+
+use common::{
+    bind_listener,
+    protocol::{GreetingStreamResponse, Request, Response, SayHelloRequest},
+    say_hello, GreetingService,
+};
+
+async fn spawn_ws_server() -> SocketAddr {
+    let (listener, addr) = bind_listener().await;
+    let server = WsServer::new(GreetingService, Default::default());
+    tokio::spawn(server.run_with_listener(listener));
+    addr
+}
+
+async fn ws_client(addr: SocketAddr) -> WsClient<Request, Response> {
+    WsClient::new(WsClientConfig {
+        url: format!("ws://{addr}"),
+        timeout_secs: 5,
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn single_and_streamed_calls() {
+    let addr = spawn_ws_server().await;
+    let mut client = ws_client(addr).await;
+    assert_eq!(say_hello(&mut client, "a").await.unwrap(), "Hello, a!");
+
+    let request = Request::SayHelloStream(SayHelloRequest {
+        name: "a".to_string(),
+    });
+    poll_fn(|cx| client.poll_ready(cx)).await.unwrap();
+    let ServiceResponse::Multiple(stream) = client.call(request).await.unwrap() else {
+        panic!("expected stream response");
+    };
+    let characters = stream
+        .map(|item| match item {
+            Ok(Response::SayHelloStream(GreetingStreamResponse { character })) => character,
+            _ => panic!("unexpected stream item"),
+        })
+        .collect::<String>()
+        .await;
+    assert_eq!(characters, "Hello, a!");
+}
+
+#[tokio::test]
+async fn pretty_printed_messages_are_accepted() {
+    let addr = spawn_ws_server().await;
+    let (mut websocket, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "sayHello",
+        "params": {"name": "a"},
+        "id": 1,
+    });
+    let message = serde_json::to_string_pretty(&request).unwrap();
+    assert!(message.contains('\n'));
+    websocket.send(Message::Text(message)).await.unwrap();
+
+    let Some(Ok(Message::Text(response))) = websocket.next().await else {
+        panic!("expected text response");
+    };
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["result"], "Hello, a!");
+}
+
+#[tokio::test]
+async fn concurrent_large_messages_do_not_stall_the_connection() {
+    let addr = spawn_ws_server().await;
+    let client = ws_client(addr).await;
+    // Each greeting exceeds the buffer of the bridge, so requests and responses
+    // are forwarded at the same time
+    let name = "a".repeat(100 * 1024);
+    let results = futures::future::join_all((0..16).map(|_| {
+        let mut client = client.clone();
+        let name = name.clone();
+        async move { say_hello(&mut client, &name).await.unwrap() }
+    }))
+    .await;
+    assert!(results.iter().all(|result| result.len() == name.len() + 8));
+}